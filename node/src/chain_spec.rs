@@ -2,12 +2,13 @@
 
 use cumulus_primitives::ParaId;
 use parachain_runtime::{
-    AccountId, DexXCMPConfig, GenesisConfig, Signature, SudoConfig, SystemConfig, WASM_BINARY,
+    AccountId, DexPriceFeedConfig, DexXCMPConfig, GenesisConfig, Signature, SudoConfig,
+    SystemConfig, TechnicalCommitteeConfig, WASM_BINARY,
 };
-use sc_chain_spec::{ChainSpecExtension, ChainSpecGroup};
-use sc_service::ChainType;
+use sc_chain_spec::{ChainSpecExtension, ChainSpecGroup, Properties};
+use sc_service::{config::TelemetryEndpoints, ChainType};
 use serde::{Deserialize, Serialize};
-use sp_core::{sr25519, Pair, Public};
+use sp_core::{crypto::Ss58Codec, sr25519, Pair, Public};
 use sp_runtime::traits::{IdentifyAccount, Verify};
 
 /// Specialized `ChainSpec`. This is a specialization of the general Substrate ChainSpec type.
@@ -47,41 +48,218 @@ where
     AccountPublic::from(get_from_seed::<TPublic>(seed)).into_account()
 }
 
-pub fn get_chain_spec(id: ParaId) -> ChainSpec {
-    ChainSpec::from_genesis(
-        "Subdex Parachain Network",
-        "local_testnet",
-        ChainType::Local,
-        move || testnet_genesis(get_account_id_from_seed::<sr25519::Public>("Alice"), id),
-        vec![],
-        None,
-        None,
-        None,
-        Extensions {
-            relay_chain: "local_testnet".into(),
-            para_id: id.into(),
-        },
-    )
+/// Who holds root, the technical committee and the price signer key of a preset.
+enum Authorities {
+    /// `//Alice` as root and price signer, `//Alice`, `//Bob` and `//Charlie` in the technical
+    /// committee, for test chains only.
+    Development,
+    /// SS58 addresses read from `<prefix>ROOT_KEY`, `<prefix>TECHNICAL_COMMITTEE` (comma
+    /// separated) and `<prefix>PRICE_SIGNER` when the chain spec is built.
+    Configured { env_prefix: &'static str },
 }
 
-pub fn staging_test_net(id: ParaId) -> ChainSpec {
-    ChainSpec::from_genesis(
-        "Subdex Staging Testnet",
-        "staging_testnet",
-        ChainType::Live,
-        move || testnet_genesis(get_account_id_from_seed::<sr25519::Public>("Alice"), id),
-        Vec::new(),
-        None,
-        None,
-        None,
-        Extensions {
-            relay_chain: "rococo_local_testnet".into(),
-            para_id: id.into(),
-        },
-    )
+/// Root key, price signer and technical committee of a chain spec.
+type GenesisAuthorities = (AccountId, sr25519::Public, Vec<AccountId>);
+
+/// Network settings, authorities and token properties of a named chain spec.
+struct Preset {
+    name: &'static str,
+    id: &'static str,
+    chain_type: ChainType,
+    /// Chain spec id or name of the relay chain.
+    relay_chain: &'static str,
+    protocol_id: &'static str,
+    /// Telemetry endpoints and their verbosity.
+    telemetry: &'static [(&'static str, u8)],
+    boot_nodes: &'static [&'static str],
+    authorities: Authorities,
+    token_symbol: &'static str,
+    token_decimals: u8,
+    ss58_format: u8,
+}
+
+const DEV: Preset = Preset {
+    name: "Subdex Development",
+    id: "dev",
+    chain_type: ChainType::Development,
+    relay_chain: "rococo-local",
+    protocol_id: "subdex-dev",
+    telemetry: &[],
+    boot_nodes: &[],
+    authorities: Authorities::Development,
+    token_symbol: "UNIT",
+    token_decimals: 12,
+    ss58_format: 42,
+};
+
+const LOCAL: Preset = Preset {
+    name: "Subdex Parachain Network",
+    id: "local_testnet",
+    chain_type: ChainType::Local,
+    relay_chain: "local_testnet",
+    protocol_id: "subdex-local",
+    telemetry: &[],
+    boot_nodes: &[],
+    authorities: Authorities::Development,
+    token_symbol: "UNIT",
+    token_decimals: 12,
+    ss58_format: 42,
+};
+
+const STAGING: Preset = Preset {
+    name: "Subdex Staging Testnet",
+    id: "staging_testnet",
+    chain_type: ChainType::Live,
+    relay_chain: "rococo_local_testnet",
+    protocol_id: "subdex-staging",
+    telemetry: &[("wss://telemetry.polkadot.io/submit/", 0)],
+    boot_nodes: &[],
+    authorities: Authorities::Configured {
+        env_prefix: "SUBDEX_STAGING_",
+    },
+    token_symbol: "SDX",
+    token_decimals: 12,
+    ss58_format: 42,
+};
+
+const ROCOCO: Preset = Preset {
+    name: "Subdex Rococo",
+    id: "subdex_rococo",
+    chain_type: ChainType::Live,
+    relay_chain: "rococo",
+    protocol_id: "subdex-rococo",
+    telemetry: &[("wss://telemetry.polkadot.io/submit/", 0)],
+    boot_nodes: &[],
+    authorities: Authorities::Configured {
+        env_prefix: "SUBDEX_ROCOCO_",
+    },
+    token_symbol: "SDX",
+    token_decimals: 12,
+    ss58_format: 42,
+};
+
+/// Chain spec of the preset named `name` for the parachain `id`: `dev`, `local` (also the
+/// default, `""`), `staging` or `rococo`.
+///
+/// Fails when the authorities of a live preset are not configured.
+pub fn preset(name: &str, id: ParaId) -> Result<Option<ChainSpec>, String> {
+    let preset = match name {
+        "dev" => &DEV,
+        "" | "local" => &LOCAL,
+        "staging" => &STAGING,
+        "rococo" => &ROCOCO,
+        _ => return Ok(None),
+    };
+    preset.chain_spec(id).map(Some)
+}
+
+impl Authorities {
+    fn genesis_authorities(&self) -> Result<GenesisAuthorities, String> {
+        let env_prefix = match self {
+            Authorities::Development => {
+                return Ok((
+                    get_account_id_from_seed::<sr25519::Public>("Alice"),
+                    get_from_seed::<sr25519::Public>("Alice"),
+                    ["Alice", "Bob", "Charlie"]
+                        .iter()
+                        .map(|seed| get_account_id_from_seed::<sr25519::Public>(seed))
+                        .collect(),
+                ))
+            }
+            Authorities::Configured { env_prefix } => env_prefix,
+        };
+        let var = |name: &str| {
+            let key = format!("{}{}", env_prefix, name);
+            std::env::var(&key).map_err(|_| format!("{} must be set to build this chain spec", key))
+        };
+        let invalid = |name: &str, address: &str| {
+            format!("Invalid SS58 address {} in {}{}", address, env_prefix, name)
+        };
+
+        let root_key = var("ROOT_KEY")?;
+        let root_key =
+            AccountId::from_ss58check(&root_key).map_err(|_| invalid("ROOT_KEY", &root_key))?;
+        let price_signer = var("PRICE_SIGNER")?;
+        let price_signer = sr25519::Public::from_ss58check(&price_signer)
+            .map_err(|_| invalid("PRICE_SIGNER", &price_signer))?;
+        let technical_committee = var("TECHNICAL_COMMITTEE")?
+            .split(',')
+            .map(str::trim)
+            .filter(|member| !member.is_empty())
+            .map(|member| {
+                AccountId::from_ss58check(member)
+                    .map_err(|_| invalid("TECHNICAL_COMMITTEE", member))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if technical_committee.is_empty() {
+            return Err(format!(
+                "{}TECHNICAL_COMMITTEE must name at least one member",
+                env_prefix
+            ));
+        }
+        Ok((root_key, price_signer, technical_committee))
+    }
 }
 
-fn testnet_genesis(root_key: AccountId, _id: ParaId) -> GenesisConfig {
+impl Preset {
+    fn chain_spec(&self, id: ParaId) -> Result<ChainSpec, String> {
+        let (root_key, price_signer, technical_committee) =
+            self.authorities.genesis_authorities()?;
+        let boot_nodes = self
+            .boot_nodes
+            .iter()
+            .map(|node| {
+                node.parse()
+                    .expect("Boot nodes of the presets are valid; qed")
+            })
+            .collect();
+        let telemetry = if self.telemetry.is_empty() {
+            None
+        } else {
+            let endpoints = self
+                .telemetry
+                .iter()
+                .map(|(url, verbosity)| (url.to_string(), *verbosity))
+                .collect();
+            Some(
+                TelemetryEndpoints::new(endpoints)
+                    .expect("Telemetry endpoints of the presets are valid; qed"),
+            )
+        };
+        let mut properties = Properties::new();
+        properties.insert("tokenSymbol".into(), self.token_symbol.into());
+        properties.insert("tokenDecimals".into(), self.token_decimals.into());
+        properties.insert("ss58Format".into(), self.ss58_format.into());
+        Ok(ChainSpec::from_genesis(
+            self.name,
+            self.id,
+            self.chain_type.clone(),
+            move || {
+                testnet_genesis(
+                    root_key.clone(),
+                    price_signer,
+                    technical_committee.clone(),
+                    id,
+                )
+            },
+            boot_nodes,
+            telemetry,
+            Some(self.protocol_id),
+            Some(properties),
+            Extensions {
+                relay_chain: self.relay_chain.into(),
+                para_id: id.into(),
+            },
+        ))
+    }
+}
+
+fn testnet_genesis(
+    root_key: AccountId,
+    price_signer: sr25519::Public,
+    technical_committee: Vec<AccountId>,
+    _id: ParaId,
+) -> GenesisConfig {
     GenesisConfig {
         frame_system: Some(SystemConfig {
             code: WASM_BINARY.to_vec(),
@@ -91,5 +269,13 @@ fn testnet_genesis(root_key: AccountId, _id: ParaId) -> GenesisConfig {
             key: root_key.clone(),
         }),
         pallet_subdex_xcmp: Some(DexXCMPConfig { next_asset_id: 1 }),
+        pallet_subdex_price_feed: Some(DexPriceFeedConfig {
+            update_fee: 1_000_000,
+            price_signer: Some(price_signer),
+        }),
+        pallet_collective_Instance1: Some(TechnicalCommitteeConfig {
+            members: technical_committee,
+            phantom: Default::default(),
+        }),
     }
 }
@@ -18,6 +18,18 @@ pub enum Subcommand {
     /// Export the genesis wasm of the parachain.
     #[structopt(name = "export-genesis-wasm")]
     ExportGenesisWasm(ExportGenesisWasmCommand),
+
+    /// Dry-run an upgrade to a runtime wasm file against the state in the local database.
+    #[structopt(name = "try-upgrade")]
+    TryUpgrade(TryUpgradeCommand),
+
+    /// Export the state of a block into a raw chain spec, to fork the chain locally.
+    #[structopt(name = "fork-state")]
+    ForkState(ForkStateCommand),
+
+    /// Export the DEX state and the DEX events of a block range from the local database.
+    #[structopt(name = "export-dex")]
+    ExportDex(ExportDexCommand),
 }
 
 /// Command for exporting the genesis state of the parachain
@@ -48,6 +60,139 @@ pub struct ExportGenesisWasmCommand {
     pub chain: Option<String>,
 }
 
+/// Command for dry-running a runtime upgrade.
+#[derive(Debug, StructOpt)]
+pub struct TryUpgradeCommand {
+    /// Runtime wasm file to upgrade to. Build it with the `try-runtime` feature to run the
+    /// checks of the storage migrations.
+    #[structopt(parse(from_os_str))]
+    pub wasm: PathBuf,
+
+    /// Hash of the block whose state is upgraded, the best block if unspecified.
+    #[structopt(long)]
+    pub at: Option<String>,
+
+    #[allow(missing_docs)]
+    #[structopt(flatten)]
+    pub shared_params: sc_cli::SharedParams,
+
+    #[allow(missing_docs)]
+    #[structopt(flatten)]
+    pub import_params: sc_cli::ImportParams,
+}
+
+/// Command for forking the chain into a new chain spec.
+#[derive(Debug, StructOpt)]
+pub struct ForkStateCommand {
+    /// Output file name or stdout if unspecified.
+    #[structopt(parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// Hash of the block whose state is exported, the best block if unspecified.
+    #[structopt(long)]
+    pub at: Option<String>,
+
+    /// Sudo key of the fork, as an SS58 address or the secret URI of an sr25519 key.
+    #[structopt(long, default_value = "//Alice")]
+    pub sudo: String,
+
+    /// Members of the technical committee of the fork, as comma separated SS58 addresses or
+    /// secret URIs of sr25519 keys.
+    #[structopt(long, use_delimiter = true, default_value = "//Alice,//Bob,//Charlie")]
+    pub technical_committee: Vec<String>,
+
+    /// Key signing the price feed of the fork, as an SS58 address or the secret URI of an
+    /// sr25519 key.
+    #[structopt(long, default_value = "//Alice")]
+    pub price_signer: String,
+
+    /// Runtime wasm file replacing the code of the forked chain.
+    #[structopt(long, parse(from_os_str))]
+    pub wasm: Option<PathBuf>,
+
+    /// Relay chain of the fork, the relay chain of the forked chain if unspecified.
+    #[structopt(long)]
+    pub relay_chain: Option<String>,
+
+    #[allow(missing_docs)]
+    #[structopt(flatten)]
+    pub shared_params: sc_cli::SharedParams,
+
+    #[allow(missing_docs)]
+    #[structopt(flatten)]
+    pub import_params: sc_cli::ImportParams,
+}
+
+/// File format of exported tables.
+#[derive(Debug, Clone, Copy)]
+pub enum ExportFormat {
+    /// JSON Lines, one object per row.
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("Unknown export format {}", s)),
+        }
+    }
+}
+
+/// Format of the node logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Colored text, as logged by Substrate.
+    Text,
+    /// JSON Lines, one object per log record.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown log format {}", s)),
+        }
+    }
+}
+
+/// Command for exporting the DEX for analytics.
+#[derive(Debug, StructOpt)]
+pub struct ExportDexCommand {
+    /// Directory the tables are written to.
+    #[structopt(parse(from_os_str))]
+    pub output: PathBuf,
+
+    /// First block whose events are exported.
+    #[structopt(long, default_value = "0")]
+    pub from: u32,
+
+    /// Last block whose events are exported, and block whose state is exported. The best
+    /// block if unspecified.
+    #[structopt(long)]
+    pub to: Option<u32>,
+
+    /// Format of the tables.
+    #[structopt(long, default_value = "json", possible_values = &["json", "csv"])]
+    pub format: ExportFormat,
+
+    #[allow(missing_docs)]
+    #[structopt(flatten)]
+    pub shared_params: sc_cli::SharedParams,
+
+    #[allow(missing_docs)]
+    #[structopt(flatten)]
+    pub import_params: sc_cli::ImportParams,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct RunCmd {
     #[structopt(flatten)]
@@ -56,6 +201,32 @@ pub struct RunCmd {
     /// Id of the parachain this collator collates for.
     #[structopt(long)]
     pub parachain_id: Option<u32>,
+
+    /// Index the DEX activity into the SQLite database at this path, and serve it over RPC.
+    #[structopt(long, parse(from_os_str))]
+    pub dex_index: Option<PathBuf>,
+
+    /// Index finalized blocks only, instead of following the best chain.
+    #[structopt(long, requires = "dex-index")]
+    pub dex_index_finalized: bool,
+
+    /// Serve the collator status on `GET /health` of this port.
+    #[structopt(long)]
+    pub health_port: Option<u16>,
+
+    /// Listen for health requests on all interfaces, instead of localhost only.
+    #[structopt(long, requires = "health-port")]
+    pub health_external: bool,
+
+    /// Number of blocks the last included block may lag behind before the node is reported
+    /// unhealthy, 10 by default.
+    #[structopt(long, requires = "health-port")]
+    pub health_max_lag: Option<u32>,
+
+    /// Format of the logs of both chains: `text`, or `json` for one JSON object per line with
+    /// the chain it comes from.
+    #[structopt(long, default_value = "text")]
+    pub log_format: LogFormat,
 }
 
 impl std::ops::Deref for RunCmd {
@@ -0,0 +1,279 @@
+//! Status of the collator, served over RPC and on an HTTP health endpoint.
+//!
+//! The parachain blocks are followed through import and finality notifications: the last
+//! block built by this node is the last collated one, and finalized blocks are the ones
+//! included in the relay chain. The relay chain best and finalized blocks are read from the
+//! client of the embedded relay chain node, which [`KeepRelayChainClient`] takes from the relay
+//! chain node builder of collators. Full nodes start their relay chain node in
+//! `cumulus_service`, which keeps its client to itself, so they do not report them.
+//!
+//! The health endpoint answers `GET /health` with the status, and with `503 Service
+//! Unavailable` when the last included block lags more than the allowed number of blocks
+//! behind the last collated one, or the best one when not collating.
+
+use crate::logging::COLLATOR_STATUS_TARGET;
+use futures::{future, StreamExt};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use jsonrpc_core::Result as RpcResult;
+use jsonrpc_derive::rpc;
+use log::warn;
+use parachain_runtime::{opaque::Block, BlockNumber};
+use parking_lot::RwLock;
+use polkadot_collator::{BuildParachainContext, Network};
+use polkadot_parachain::primitives::AccountIdConversion;
+use polkadot_service::{AbstractClient, ExecuteWithClient, RuntimeApiCollection};
+use sc_client_api::BlockchainEvents;
+use sc_service::SpawnTaskHandle;
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_consensus::{BlockOrigin, SyncOracle};
+use sp_core::traits::SpawnNamed;
+use sp_runtime::traits::{BlakeTwo256, Header as _};
+use std::{net::SocketAddr, sync::Arc};
+
+/// Number of blocks the last included block may lag behind by default.
+pub const DEFAULT_MAX_LAG: BlockNumber = 10;
+
+/// Where the health endpoint listens and how much lag it tolerates.
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    pub addr: SocketAddr,
+    /// Largest number of blocks the last included block may lag behind.
+    pub max_lag: BlockNumber,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayChainStatus {
+    pub best: BlockNumber,
+    pub finalized: BlockNumber,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub para_id: u32,
+    /// Whether the node collates, or only follows the parachain.
+    pub collating: bool,
+    /// Account of the parachain on the relay chain.
+    pub parachain_account: String,
+    /// `None` until the relay chain node is started, and on full nodes.
+    pub relay_chain: Option<RelayChainStatus>,
+    pub best_block: BlockNumber,
+    /// Last block built by this node since it started.
+    pub last_collated: Option<BlockNumber>,
+    /// Last block included in the relay chain.
+    pub last_included: BlockNumber,
+    /// Blocks between the last collated block, or the best block when not collating, and the
+    /// last included one.
+    pub inclusion_lag: BlockNumber,
+}
+
+#[derive(Default)]
+struct Tracked {
+    best_block: BlockNumber,
+    last_collated: Option<BlockNumber>,
+    last_included: BlockNumber,
+}
+
+/// Status of the collator, kept up to date by [`spawn_status_tasks`].
+pub struct CollatorStatus {
+    para_id: u32,
+    collating: bool,
+    parachain_account: String,
+    relay_chain_client: RwLock<Option<polkadot_service::Client>>,
+    tracked: RwLock<Tracked>,
+}
+
+impl CollatorStatus {
+    pub fn new(para_id: polkadot_primitives::v0::Id, collating: bool) -> Self {
+        let parachain_account =
+            AccountIdConversion::<polkadot_primitives::v0::AccountId>::into_account(&para_id);
+        CollatorStatus {
+            para_id: para_id.into(),
+            collating,
+            parachain_account: parachain_account.to_string(),
+            relay_chain_client: RwLock::new(None),
+            tracked: Default::default(),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        let tracked = self.tracked.read();
+        let head = tracked.last_collated.unwrap_or(tracked.best_block);
+        Status {
+            para_id: self.para_id,
+            collating: self.collating,
+            parachain_account: self.parachain_account.clone(),
+            relay_chain: self
+                .relay_chain_client
+                .read()
+                .as_ref()
+                .map(|client| client.execute_with(RelayChainInfo)),
+            best_block: tracked.best_block,
+            last_collated: tracked.last_collated,
+            last_included: tracked.last_included,
+            inclusion_lag: head.saturating_sub(tracked.last_included),
+        }
+    }
+}
+
+/// Relay chain node builder of collators, keeping the client of the relay chain node for the
+/// collator status.
+pub struct KeepRelayChainClient<B> {
+    builder: B,
+    status: Arc<CollatorStatus>,
+}
+
+impl<B> KeepRelayChainClient<B> {
+    pub fn new(builder: B, status: Arc<CollatorStatus>) -> Self {
+        KeepRelayChainClient { builder, status }
+    }
+}
+
+impl<B: BuildParachainContext> BuildParachainContext for KeepRelayChainClient<B> {
+    type ParachainContext = B::ParachainContext;
+
+    fn build<SP>(
+        self,
+        client: polkadot_service::Client,
+        spawner: SP,
+        network: impl Network + SyncOracle + Clone + 'static,
+    ) -> Result<Self::ParachainContext, ()>
+    where
+        SP: SpawnNamed + Clone + Send + Sync + 'static,
+    {
+        *self.status.relay_chain_client.write() = Some(client.clone());
+        self.builder.build(client, spawner, network)
+    }
+}
+
+/// Reads the best and finalized blocks of the relay chain client, whichever runtime it runs.
+struct RelayChainInfo;
+
+impl ExecuteWithClient for RelayChainInfo {
+    type Output = RelayChainStatus;
+
+    fn execute_with_client<Client, Api, Backend>(self, client: Arc<Client>) -> Self::Output
+    where
+        <Api as sp_api::ApiExt<polkadot_primitives::v0::Block>>::StateBackend:
+            sp_api::StateBackend<BlakeTwo256>,
+        Backend: sc_client_api::Backend<polkadot_primitives::v0::Block>,
+        Backend::State: sp_api::StateBackend<BlakeTwo256>,
+        Api: RuntimeApiCollection<StateBackend = Backend::State>,
+        Client: AbstractClient<polkadot_primitives::v0::Block, Backend, Api = Api> + 'static,
+    {
+        let info = client.info();
+        RelayChainStatus {
+            best: info.best_number,
+            finalized: info.finalized_number,
+        }
+    }
+}
+
+/// Follow the blocks of `client` and serve the health endpoint.
+pub fn spawn_status_tasks<C>(
+    status: Arc<CollatorStatus>,
+    client: Arc<C>,
+    health: Option<HealthConfig>,
+    spawn_handle: SpawnTaskHandle,
+) where
+    C: BlockchainEvents<Block> + HeaderBackend<Block>,
+{
+    {
+        let info = client.info();
+        let mut tracked = status.tracked.write();
+        tracked.best_block = info.best_number;
+        tracked.last_included = info.finalized_number;
+    }
+
+    let mut imports = client.import_notification_stream();
+    let imports_status = status.clone();
+    spawn_handle.spawn("collator-status-imports", async move {
+        while let Some(notification) = imports.next().await {
+            let number = *notification.header.number();
+            let mut tracked = imports_status.tracked.write();
+            if notification.is_new_best {
+                tracked.best_block = number;
+            }
+            if notification.origin == BlockOrigin::Own {
+                tracked.last_collated = Some(number);
+            }
+        }
+    });
+
+    let mut finalized = client.finality_notification_stream();
+    let finality_status = status.clone();
+    spawn_handle.spawn("collator-status-finality", async move {
+        while let Some(notification) = finalized.next().await {
+            finality_status.tracked.write().last_included = *notification.header.number();
+        }
+    });
+
+    if let Some(HealthConfig { addr, max_lag }) = health {
+        spawn_handle.spawn("collator-health", async move {
+            let service = make_service_fn(move |_| {
+                let status = status.clone();
+                future::ok::<_, hyper::Error>(service_fn(move |request| {
+                    future::ok::<_, hyper::Error>(health_response(&status, max_lag, request))
+                }))
+            });
+            // Bound from the task, which runs in the context of the tokio runtime.
+            let result = match Server::try_bind(&addr) {
+                Ok(server) => server.serve(service).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                warn!(
+                    target: COLLATOR_STATUS_TARGET,
+                    "Health endpoint on {} failed: {}", addr, e
+                );
+            }
+        });
+    }
+}
+
+fn health_response(
+    status: &CollatorStatus,
+    max_lag: BlockNumber,
+    request: Request<Body>,
+) -> Response<Body> {
+    let mut response = Response::default();
+    if request.method() != Method::GET || request.uri().path() != "/health" {
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+    let status = status.status();
+    if status.inclusion_lag > max_lag {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+    *response.body_mut() = serde_json::to_string(&status).unwrap_or_default().into();
+    response
+}
+
+#[rpc]
+pub trait CollatorStatusApi {
+    /// Para id, mode, relay chain and collation progress of the collator.
+    #[rpc(name = "subdex_collatorStatus")]
+    fn collator_status(&self) -> RpcResult<Status>;
+}
+
+/// Implementation of [`CollatorStatusApi`].
+pub struct CollatorStatusRpc {
+    status: Arc<CollatorStatus>,
+}
+
+impl CollatorStatusRpc {
+    pub fn new(status: Arc<CollatorStatus>) -> Self {
+        CollatorStatusRpc { status }
+    }
+}
+
+impl CollatorStatusApi for CollatorStatusRpc {
+    fn collator_status(&self) -> RpcResult<Status> {
+        Ok(self.status.status())
+    }
+}
@@ -2,7 +2,10 @@
 
 use crate::{
     chain_spec,
-    cli::{Cli, RelayChainCli, Subcommand},
+    cli::{
+        Cli, ExportDexCommand, ForkStateCommand, LogFormat, RelayChainCli, Subcommand,
+        TryUpgradeCommand,
+    },
 };
 use codec::Encode;
 use cumulus_primitives::ParaId;
@@ -50,16 +53,10 @@ impl SubstrateCli for Cli {
     }
 
     fn load_spec(&self, id: &str) -> std::result::Result<Box<dyn sc_service::ChainSpec>, String> {
-        match id {
-            "staging" => Ok(Box::new(chain_spec::staging_test_net(
-                self.run.parachain_id.unwrap_or(200).into(),
-            ))),
-            "" => Ok(Box::new(chain_spec::get_chain_spec(
-                self.run.parachain_id.unwrap_or(200).into(),
-            ))),
-            path => Ok(Box::new(chain_spec::ChainSpec::from_json_file(
-                path.into(),
-            )?)),
+        let para_id = self.run.parachain_id.unwrap_or(200).into();
+        match chain_spec::preset(id, para_id)? {
+            Some(spec) => Ok(Box::new(spec)),
+            None => Ok(Box::new(chain_spec::ChainSpec::from_json_file(id.into())?)),
         }
     }
 
@@ -192,7 +189,43 @@ pub fn run() -> Result<()> {
 
             Ok(())
         }
+        Some(Subcommand::TryUpgrade(cmd)) => {
+            let runner = cli.create_runner(cmd)?;
+
+            runner.sync_run(|mut config| {
+                let params = crate::service::new_partial(&mut config)?;
+
+                crate::try_upgrade::run(
+                    cmd,
+                    &config,
+                    params.backend,
+                    params.task_manager.spawn_handle(),
+                )
+            })
+        }
+        Some(Subcommand::ForkState(cmd)) => {
+            let runner = cli.create_runner(cmd)?;
+
+            runner.sync_run(|mut config| {
+                let params = crate::service::new_partial(&mut config)?;
+
+                crate::fork_state::run(cmd, &config, params.backend)
+            })
+        }
+        Some(Subcommand::ExportDex(cmd)) => {
+            let runner = cli.create_runner(cmd)?;
+
+            runner.sync_run(|mut config| {
+                let params = crate::service::new_partial(&mut config)?;
+
+                crate::export_dex::run(cmd, params.client, params.backend)
+            })
+        }
         None => {
+            if cli.run.log_format == LogFormat::Json {
+                // Registered first, the Substrate logger is then left unregistered.
+                crate::logging::init_json_logger(&cli.run.base.log_filters()?);
+            }
             let runner = cli.create_runner(&*cli.run)?;
 
             runner.run_node_until_exit(|config| {
@@ -215,6 +248,12 @@ pub fn run() -> Result<()> {
 
                 let parachain_account =
                     AccountIdConversion::<polkadot_primitives::v0::AccountId>::into_account(&id);
+                // `Sibling` derives accounts through the Substrate conversion, not the relay one.
+                let sibling_account = sp_runtime::traits::AccountIdConversion::<
+                    polkadot_primitives::v0::AccountId,
+                >::into_account(
+                    &pallet_subdex_xcmp_router::Sibling(id)
+                );
 
                 let block =
                     generate_genesis_state(&config.chain_spec).map_err(|e| format!("{:?}", e))?;
@@ -227,19 +266,82 @@ pub fn run() -> Result<()> {
 
                 info!("Parachain id: {:?}", id);
                 info!("Parachain Account: {}", parachain_account);
+                info!("Parachain Sibling Account: {}", sibling_account);
                 info!("Parachain genesis state: {}", genesis_state);
                 info!(
                     "Is collating: {}",
                     if cli.run.base.validator { "yes" } else { "no" }
                 );
 
-                crate::service::run_node(config, key, polkadot_config, id, cli.run.base.validator)
-                    .map(|(x, _)| x)
+                let dex_index = cli
+                    .run
+                    .dex_index
+                    .clone()
+                    .map(|path| crate::dex_index::Config {
+                        path,
+                        finalized_only: cli.run.dex_index_finalized,
+                    });
+                let health = cli.run.health_port.map(|port| {
+                    let ip = if cli.run.health_external {
+                        [0, 0, 0, 0]
+                    } else {
+                        [127, 0, 0, 1]
+                    };
+                    crate::collator_status::HealthConfig {
+                        addr: (ip, port).into(),
+                        max_lag: cli
+                            .run
+                            .health_max_lag
+                            .unwrap_or(crate::collator_status::DEFAULT_MAX_LAG),
+                    }
+                });
+
+                crate::service::run_node(
+                    config,
+                    key,
+                    polkadot_config,
+                    id,
+                    cli.run.base.validator,
+                    dex_index,
+                    health,
+                    cli.run.log_format,
+                )
+                .map(|(x, _)| x)
             })
         }
     }
 }
 
+impl CliConfiguration for TryUpgradeCommand {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+
+    fn import_params(&self) -> Option<&ImportParams> {
+        Some(&self.import_params)
+    }
+}
+
+impl CliConfiguration for ForkStateCommand {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+
+    fn import_params(&self) -> Option<&ImportParams> {
+        Some(&self.import_params)
+    }
+}
+
+impl CliConfiguration for ExportDexCommand {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+
+    fn import_params(&self) -> Option<&ImportParams> {
+        Some(&self.import_params)
+    }
+}
+
 impl DefaultConfigurationValues for RelayChainCli {
     fn p2p_listen_port() -> u16 {
         30334
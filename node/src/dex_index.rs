@@ -0,0 +1,1071 @@
+//! Index of the DEX activity in a local SQLite database, served over RPC.
+//!
+//! The indexer follows the best chain, or only finalized blocks, and records for every block:
+//! - the `DexPallet`, `DexXCMP`, `DexRouter`, `DexOrders` and `XcmpRouter` events, which make
+//!   up the activity of accounts, transfers from and to other parachains included,
+//! - the accounts taking part in every indexed event, which account activity is read from,
+//! - the swaps made in `DexPallet`, whichever pallet made them: `DexRouter`, order fills of
+//!   `DexOrders`, cross-chain swaps of `XcmpRouter` and `DexXCMP` all trade through it,
+//! - the liquidity changes made in `DexPallet`, completed with the amounts and the source
+//!   parachain given by the `DexRouter` and `XcmpRouter` events that follow them,
+//! - the transfers from and to the relay chain and other parachains,
+//! - the reserves and spot price of every pool whose reserves changed in the block, which the
+//!   candles are built from whichever pallet moved them.
+//!
+//! Events are decoded with the metadata of the runtime that deposited them, see
+//! [`crate::runtime_events`]. Blocks whose events cannot be decoded are indexed without their
+//! events and recorded in the `gaps` table, instead of holding back the blocks after them.
+//! Gaps are retried when the indexer starts, a new build of the node may know the types it
+//! failed on, and after every runtime upgrade.
+//!
+//! Everything indexed is keyed by block number. When the best chain is reorganized, the blocks
+//! that left it are removed from the index before the blocks that replaced them are indexed.
+//!
+//! Swaps, liquidity changes, transfers and accounts are derived from the indexed events. When
+//! the way they are derived changes, the schema version is bumped and they are derived again
+//! from the events when the index is opened.
+
+use crate::{
+    export_dex::asset,
+    logging::DEX_INDEX_TARGET,
+    runtime_events::{DecodedEvent, EventDecoder},
+};
+use codec::Decode;
+use frame_support::storage::{IterableStorageDoubleMap, StoragePrefixedMap};
+use futures::{future, StreamExt};
+use jsonrpc_core::{Error as RpcError, ErrorCode, Result as RpcResult};
+use jsonrpc_derive::rpc;
+use log::{info, warn};
+use parachain_runtime::{opaque::Block, AccountId, Balance, BlockNumber, Hash, Runtime};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use sc_client_api::{Backend, BlockchainEvents};
+use sc_service::SpawnTaskHandle;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sp_api::{Core, Metadata, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
+use sp_core::{hashing::twox_128, storage::Storage};
+use sp_runtime::{
+    generic::BlockId,
+    traits::{BlakeTwo256, Header as _},
+};
+use sp_state_machine::{Backend as StateBackend, BasicExternalities};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Largest number of rows a single request returns.
+pub const MAX_ROWS: u32 = 1_000;
+
+/// Largest number of candles a single request returns.
+pub const MAX_CANDLES: u64 = 10_000;
+
+/// Pallets whose events are indexed.
+const INDEXED_PALLETS: &[&str] = &[
+    "DexPallet",
+    "DexXCMP",
+    "DexRouter",
+    "DexOrders",
+    "XcmpRouter",
+];
+
+/// Swap of `DexPallet`: (account, asset in, amount in, asset out, amount out).
+const SWAP_EVENT: (&str, &str) = ("DexPallet", "Exchanged");
+
+/// Transfers from and to other chains: (pallet, event, whether the transfer is inbound).
+const TRANSFER_EVENTS: &[(&str, &str, bool)] = &[
+    ("DexXCMP", "TransferredTokensFromRelayChain", true),
+    ("DexXCMP", "TransferredTokensToRelayChain", false),
+    ("DexXCMP", "TransferredTokensViaXCMP", true),
+    ("DexXCMP", "DepositAssetViaXCMP", true),
+    ("DexXCMP", "TransferredTokensToParachain", false),
+    ("XcmpRouter", "ReturnedTransferCredited", true),
+    ("XcmpRouter", "TrappedAssetsClaimed", false),
+    ("XcmpRouter", "TrappedOverflowReturned", false),
+];
+
+/// Version of the schema and of the rows derived from the events, see [`DexIndex::open`].
+const SCHEMA_VERSION: u32 = 1;
+
+/// Where the index is kept and which blocks it follows.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub path: PathBuf,
+    /// Index finalized blocks only, instead of the best chain.
+    pub finalized_only: bool,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS blocks (
+        number INTEGER PRIMARY KEY,
+        hash TEXT NOT NULL,
+        timestamp INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS gaps (
+        block INTEGER PRIMARY KEY,
+        error TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS events (
+        block INTEGER NOT NULL,
+        event_index INTEGER NOT NULL,
+        extrinsic_index INTEGER,
+        pallet TEXT NOT NULL,
+        name TEXT NOT NULL,
+        args TEXT NOT NULL,
+        PRIMARY KEY (block, event_index)
+    );
+    CREATE TABLE IF NOT EXISTS swaps (
+        block INTEGER NOT NULL,
+        event_index INTEGER NOT NULL,
+        account TEXT NOT NULL,
+        asset_in TEXT NOT NULL,
+        amount_in TEXT NOT NULL,
+        asset_out TEXT NOT NULL,
+        amount_out TEXT NOT NULL,
+        PRIMARY KEY (block, event_index)
+    );
+    CREATE INDEX IF NOT EXISTS swaps_by_pair ON swaps (asset_in, asset_out, block);
+    CREATE TABLE IF NOT EXISTS liquidity (
+        block INTEGER NOT NULL,
+        event_index INTEGER NOT NULL,
+        account TEXT NOT NULL,
+        first_asset TEXT NOT NULL,
+        first_amount TEXT,
+        second_asset TEXT NOT NULL,
+        second_amount TEXT,
+        shares TEXT NOT NULL,
+        added INTEGER NOT NULL,
+        para INTEGER,
+        PRIMARY KEY (block, event_index)
+    );
+    CREATE TABLE IF NOT EXISTS transfers (
+        block INTEGER NOT NULL,
+        event_index INTEGER NOT NULL,
+        para INTEGER,
+        account TEXT NOT NULL,
+        asset TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        inbound INTEGER NOT NULL,
+        PRIMARY KEY (block, event_index)
+    );
+    CREATE INDEX IF NOT EXISTS transfers_by_account ON transfers (account, block);
+    CREATE TABLE IF NOT EXISTS account_events (
+        account TEXT NOT NULL,
+        block INTEGER NOT NULL,
+        event_index INTEGER NOT NULL,
+        PRIMARY KEY (account, block, event_index)
+    );
+    CREATE INDEX IF NOT EXISTS account_events_by_block ON account_events (block);
+    CREATE TABLE IF NOT EXISTS prices (
+        block INTEGER NOT NULL,
+        first_asset TEXT NOT NULL,
+        second_asset TEXT NOT NULL,
+        first_reserve TEXT NOT NULL,
+        second_reserve TEXT NOT NULL,
+        price REAL NOT NULL,
+        PRIMARY KEY (first_asset, second_asset, block)
+    );
+";
+
+/// Tables cleared of the blocks removed from the best chain, with their block column.
+const BLOCK_TABLES: &[(&str, &str)] = &[
+    ("blocks", "number"),
+    ("gaps", "block"),
+    ("events", "block"),
+    ("swaps", "block"),
+    ("liquidity", "block"),
+    ("transfers", "block"),
+    ("account_events", "block"),
+    ("prices", "block"),
+];
+
+/// Tables derived from the `events` table.
+const DERIVED_TABLES: &[&str] = &["swaps", "liquidity", "transfers", "account_events"];
+
+/// The index database.
+pub struct DexIndex {
+    db: Mutex<Connection>,
+}
+
+impl DexIndex {
+    /// Open the index at `path`, creating it if needed.
+    ///
+    /// An index of an older schema version has the rows derived from its events derived again.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let mut db = Connection::open(path)?;
+        let version: u32 = db.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+        if version < SCHEMA_VERSION {
+            let tx = db.transaction()?;
+            // Amounts of liquidity changes became optional in version 1.
+            tx.execute_batch("DROP TABLE IF EXISTS liquidity")?;
+            tx.execute_batch(SCHEMA)?;
+            derive_events(&tx)?;
+            tx.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+            tx.commit()?;
+        }
+        Ok(DexIndex { db: Mutex::new(db) })
+    }
+
+    /// Index the blocks of the chain up to `head`, after removing the blocks no longer in it.
+    pub fn sync<B, C>(
+        &self,
+        decoder: &EventDecoder<C>,
+        backend: &B,
+        head: BlockNumber,
+    ) -> Result<(), String>
+    where
+        B: Backend<Block>,
+        C: ProvideRuntimeApi<Block>,
+        C::Api: Core<Block> + Metadata<Block>,
+    {
+        let blockchain = backend.blockchain();
+        let first = self.rollback(
+            |number| {
+                blockchain
+                    .hash(number)
+                    .ok()
+                    .flatten()
+                    .map(|hash| format!("{:?}", hash))
+            },
+            head,
+        )?;
+
+        // One block at a time, so queries are served while catching up.
+        for number in first..=head {
+            let hash = match blockchain.hash(number).ok().flatten() {
+                Some(hash) => hash,
+                None => break,
+            };
+            let mut db = self.db.lock();
+            let tx = db.transaction().map_err(|e| e.to_string())?;
+            index_block(&tx, decoder, backend, number, hash)?;
+            tx.commit().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Index again the events of the blocks recorded as gaps, and return how many are left.
+    pub fn retry_gaps<B, C>(&self, decoder: &EventDecoder<C>, backend: &B) -> Result<u32, String>
+    where
+        B: Backend<Block>,
+        C: ProvideRuntimeApi<Block>,
+        C::Api: Core<Block> + Metadata<Block>,
+    {
+        let mut left = 0;
+        for number in self.gaps().map_err(|e| e.to_string())? {
+            // Indexed blocks are in the chain, see `rollback`.
+            let hash = match backend.blockchain().hash(number).ok().flatten() {
+                Some(hash) => hash,
+                None => continue,
+            };
+            let mut db = self.db.lock();
+            let tx = db.transaction().map_err(|e| e.to_string())?;
+            match decoder.block_events(backend, hash) {
+                Ok(events) => {
+                    index_events(&tx, number, events).map_err(|e| e.to_string())?;
+                    tx.execute("DELETE FROM gaps WHERE block = ?1", params![number])
+                }
+                Err(e) => {
+                    left += 1;
+                    tx.execute(
+                        "UPDATE gaps SET error = ?2 WHERE block = ?1",
+                        params![number, e],
+                    )
+                }
+            }
+            .map_err(|e| e.to_string())?;
+            tx.commit().map_err(|e| e.to_string())?;
+        }
+        Ok(left)
+    }
+
+    /// Blocks indexed without their events.
+    fn gaps(&self) -> rusqlite::Result<Vec<BlockNumber>> {
+        let db = self.db.lock();
+        let mut statement = db.prepare("SELECT block FROM gaps ORDER BY block")?;
+        let gaps = statement
+            .query_map(params![], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(gaps)
+    }
+
+    /// Remove the indexed blocks that are above `head` or no longer have their hash in the
+    /// chain, as told by `canonical`, and return the first block left to index.
+    fn rollback(
+        &self,
+        canonical: impl Fn(BlockNumber) -> Option<String>,
+        head: BlockNumber,
+    ) -> Result<BlockNumber, String> {
+        let mut db = self.db.lock();
+        let mut last: Option<(BlockNumber, String)> = db
+            .query_row(
+                "SELECT number, hash FROM blocks ORDER BY number DESC LIMIT 1",
+                params![],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        // Walk back to the last indexed block still in the chain.
+        while let Some((number, hash)) = last.clone() {
+            if number <= head && canonical(number) == Some(hash) {
+                break;
+            }
+            last = db
+                .query_row(
+                    "SELECT number, hash FROM blocks WHERE number < ?1 ORDER BY number DESC LIMIT 1",
+                    params![number],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .map_err(|e| e.to_string())?;
+        }
+
+        let first = match &last {
+            Some((number, _)) => number + 1,
+            None => 0,
+        };
+        let tx = db.transaction().map_err(|e| e.to_string())?;
+        for (table, column) in BLOCK_TABLES {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE {} >= ?1", table, column),
+                params![first],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(first)
+    }
+
+    /// Swaps between `base` and `quote`, most recent first.
+    fn trades(&self, base: &str, quote: &str, limit: u32) -> rusqlite::Result<Vec<Trade>> {
+        let db = self.db.lock();
+        let mut statement = db.prepare(
+            "SELECT s.block, b.timestamp, s.account, s.asset_in, s.amount_in, s.asset_out,
+                s.amount_out
+            FROM swaps s JOIN blocks b ON b.number = s.block
+            WHERE (s.asset_in = ?1 AND s.asset_out = ?2) OR (s.asset_in = ?2 AND s.asset_out = ?1)
+            ORDER BY s.block DESC, s.event_index DESC LIMIT ?3",
+        )?;
+        let trades = statement
+            .query_map(params![base, quote, limit], |row| {
+                Ok(Trade {
+                    block: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    account: row.get(2)?,
+                    asset_in: row.get(3)?,
+                    amount_in: row.get(4)?,
+                    asset_out: row.get(5)?,
+                    amount_out: row.get(6)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(trades)
+    }
+
+    /// Candles of the price of `base` in `quote`, over `interval` milliseconds each.
+    fn candles(
+        &self,
+        base: &str,
+        quote: &str,
+        interval: u64,
+        from: u64,
+        to: u64,
+    ) -> rusqlite::Result<Vec<Candle>> {
+        let db = self.db.lock();
+        let bucket = |timestamp: u64| from + (timestamp - from) / interval * interval;
+
+        let mut candles: Vec<Candle> = Vec::new();
+        let mut statement = db.prepare(
+            "SELECT b.timestamp, p.first_asset, p.price
+            FROM prices p JOIN blocks b ON b.number = p.block
+            WHERE ((p.first_asset = ?1 AND p.second_asset = ?2)
+                OR (p.first_asset = ?2 AND p.second_asset = ?1))
+                AND b.timestamp >= ?3 AND b.timestamp < ?4
+            ORDER BY p.block",
+        )?;
+        let mut rows = statement.query(params![base, quote, from as i64, to as i64])?;
+        while let Some(row) = rows.next()? {
+            let timestamp = row.get::<_, i64>(0)? as u64;
+            let first_asset: String = row.get(1)?;
+            let price: f64 = row.get(2)?;
+            // Prices are kept as the price of the first asset of the pool in the second one.
+            let price = if first_asset == base {
+                price
+            } else {
+                1.0 / price
+            };
+            let start = bucket(timestamp);
+            match candles.last_mut() {
+                Some(candle) if candle.start == start => {
+                    candle.high = candle.high.max(price);
+                    candle.low = candle.low.min(price);
+                    candle.close = price;
+                }
+                _ => candles.push(Candle {
+                    start,
+                    open: price,
+                    high: price,
+                    low: price,
+                    close: price,
+                    volume: "0".into(),
+                    trades: 0,
+                }),
+            }
+        }
+
+        let mut statement = db.prepare(
+            "SELECT b.timestamp, s.asset_in, s.amount_in, s.amount_out
+            FROM swaps s JOIN blocks b ON b.number = s.block
+            WHERE ((s.asset_in = ?1 AND s.asset_out = ?2) OR (s.asset_in = ?2 AND s.asset_out = ?1))
+                AND b.timestamp >= ?3 AND b.timestamp < ?4",
+        )?;
+        let mut rows = statement.query(params![base, quote, from as i64, to as i64])?;
+        while let Some(row) = rows.next()? {
+            let start = bucket(row.get::<_, i64>(0)? as u64);
+            let asset_in: String = row.get(1)?;
+            // Volume is counted in the base asset.
+            let amount: String = row.get(if asset_in == base { 2 } else { 3 })?;
+            if let Some(candle) = candles.iter_mut().find(|candle| candle.start == start) {
+                let volume = candle.volume.parse::<u128>().unwrap_or_default();
+                candle.volume = volume
+                    .saturating_add(amount.parse().unwrap_or_default())
+                    .to_string();
+                candle.trades += 1;
+            }
+        }
+        Ok(candles)
+    }
+
+    /// Indexed events involving `account`, most recent first.
+    fn account_activity(&self, account: &str, limit: u32) -> rusqlite::Result<Vec<Activity>> {
+        let db = self.db.lock();
+        let mut statement = db.prepare(
+            "SELECT e.block, b.timestamp, e.pallet, e.name, e.args
+            FROM account_events a
+                JOIN events e ON e.block = a.block AND e.event_index = a.event_index
+                JOIN blocks b ON b.number = e.block
+            WHERE a.account = ?1
+            ORDER BY a.block DESC, a.event_index DESC LIMIT ?2",
+        )?;
+        let activity = statement
+            .query_map(params![account, limit], |row| {
+                Ok(Activity {
+                    block: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    pallet: row.get(2)?,
+                    event: row.get(3)?,
+                    args: serde_json::from_str(&row.get::<_, String>(4)?).unwrap_or(Value::Null),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(activity)
+    }
+
+    /// Transfers from and to `account`, most recent first.
+    fn transfers(&self, account: &str, limit: u32) -> rusqlite::Result<Vec<Transfer>> {
+        let db = self.db.lock();
+        let mut statement = db.prepare(
+            "SELECT t.block, b.timestamp, t.para, t.asset, t.amount, t.inbound
+            FROM transfers t JOIN blocks b ON b.number = t.block
+            WHERE t.account = ?1
+            ORDER BY t.block DESC, t.event_index DESC LIMIT ?2",
+        )?;
+        let transfers = statement
+            .query_map(params![account, limit], |row| {
+                Ok(Transfer {
+                    block: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    para: row.get(2)?,
+                    asset: row.get(3)?,
+                    amount: row.get(4)?,
+                    inbound: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(transfers)
+    }
+}
+
+fn index_block<B, C>(
+    db: &Connection,
+    decoder: &EventDecoder<C>,
+    backend: &B,
+    number: BlockNumber,
+    hash: Hash,
+) -> Result<(), String>
+where
+    B: Backend<Block>,
+    C: ProvideRuntimeApi<Block>,
+    C::Api: Core<Block> + Metadata<Block>,
+{
+    let state = backend
+        .state_at(BlockId::Hash(hash))
+        .map_err(|e| format!("State of block {} not available: {:?}", number, e))?;
+    let timestamp = state
+        .storage(&[twox_128(b"Timestamp"), twox_128(b"Now")].concat())
+        .map_err(|e| format!("Unable to read the storage of block {}: {:?}", number, e))?
+        .and_then(|encoded| u64::decode(&mut &encoded[..]).ok())
+        .unwrap_or_default();
+    db.execute(
+        "INSERT INTO blocks (number, hash, timestamp) VALUES (?1, ?2, ?3)",
+        params![number, format!("{:?}", hash), timestamp as i64],
+    )
+    .map_err(|e| e.to_string())?;
+
+    match decoder.block_events(backend, hash) {
+        Ok(events) => index_events(db, number, events).map_err(|e| e.to_string())?,
+        Err(e) => {
+            warn!(
+                target: DEX_INDEX_TARGET,
+                "Indexing block {} without its events: {}", number, e
+            );
+            db.execute(
+                "INSERT INTO gaps (block, error) VALUES (?1, ?2)",
+                params![number, e],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+    }
+
+    let reserves = pool_reserves(&state)
+        .map_err(|e| format!("Unable to read pools of block {}: {}", number, e))?;
+    index_prices(db, number, reserves).map_err(|e| e.to_string())
+}
+
+/// Record the reserves and spot price of the pools whose reserves changed.
+fn index_prices(
+    db: &Connection,
+    number: BlockNumber,
+    reserves: Vec<(String, String, Balance, Balance)>,
+) -> rusqlite::Result<()> {
+    for (first, second, first_reserve, second_reserve) in reserves {
+        let (first_reserve, second_reserve) =
+            (first_reserve.to_string(), second_reserve.to_string());
+        let last: Option<(String, String)> = db
+            .query_row(
+                "SELECT first_reserve, second_reserve FROM prices
+                WHERE first_asset = ?1 AND second_asset = ?2 ORDER BY block DESC LIMIT 1",
+                params![first, second],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if last.as_ref() == Some(&(first_reserve.clone(), second_reserve.clone())) {
+            continue;
+        }
+        let first_amount = first_reserve.parse::<f64>().unwrap_or_default();
+        if first_amount == 0.0 {
+            continue;
+        }
+        let price = second_reserve.parse::<f64>().unwrap_or_default() / first_amount;
+        db.execute(
+            "INSERT INTO prices (block, first_asset, second_asset, first_reserve, second_reserve,
+                price)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![number, first, second, first_reserve, second_reserve, price],
+        )?;
+    }
+    Ok(())
+}
+
+/// Reserves of every pool in `state`: (first asset, second asset, first reserve, second
+/// reserve).
+pub fn pool_reserves<S: StateBackend<BlakeTwo256>>(
+    state: &S,
+) -> Result<Vec<(String, String, Balance, Balance)>, String> {
+    let mut exchanges = Storage::default();
+    let prefix = pallet_subdex::Exchanges::<Runtime>::final_prefix();
+    for key in state.keys(&prefix) {
+        if let Some(value) = state.storage(&key).map_err(|e| format!("{:?}", e))? {
+            exchanges.top.insert(key, value);
+        }
+    }
+    // Read with the typed storage of `pallet_subdex`, on a copy of the pools.
+    Ok(BasicExternalities::new(exchanges).execute_with(|| {
+        pallet_subdex::Exchanges::<Runtime>::iter()
+            .map(|(first, second, exchange)| {
+                (
+                    asset(&first),
+                    asset(&second),
+                    exchange.first_asset_pool,
+                    exchange.second_asset_pool,
+                )
+            })
+            .collect()
+    }))
+}
+
+/// Text of an argument: strings as they are, JSON otherwise.
+fn text(value: &Value) -> String {
+    value
+        .as_str()
+        .map_or_else(|| value.to_string(), str::to_string)
+}
+
+/// Swap recorded by `event`, if it is a swap: (account, asset in, amount in, asset out,
+/// amount out).
+///
+/// Arguments are told apart by type, and assets and amounts of the same type by their order.
+pub fn swap(event: &DecodedEvent) -> Option<(String, String, String, String, String)> {
+    if !event.is(SWAP_EVENT.0, SWAP_EVENT.1) {
+        return None;
+    }
+    match (
+        &event.args_of("AccountId")[..],
+        &event.args_of("Asset")[..],
+        &event.args_of("Balance")[..],
+    ) {
+        ([who], [asset_in, asset_out], [amount_in, amount_out]) => Some((
+            text(who),
+            text(asset_in),
+            text(amount_in),
+            text(asset_out),
+            text(amount_out),
+        )),
+        _ => None,
+    }
+}
+
+fn index_events(
+    db: &Connection,
+    number: BlockNumber,
+    events: Vec<DecodedEvent>,
+) -> rusqlite::Result<()> {
+    for (index, event) in events.into_iter().enumerate() {
+        if INDEXED_PALLETS.contains(&event.pallet.as_str()) {
+            index_event(db, number, index as u32, &event)?;
+        }
+    }
+    Ok(())
+}
+
+fn index_event(
+    db: &Connection,
+    number: BlockNumber,
+    index: u32,
+    event: &DecodedEvent,
+) -> rusqlite::Result<()> {
+    db.execute(
+        "INSERT INTO events (block, event_index, extrinsic_index, pallet, name, args)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            number,
+            index,
+            event.extrinsic_index,
+            event.pallet,
+            event.name,
+            event.args_json().to_string(),
+        ],
+    )?;
+    derive_event(db, number, index, event)
+}
+
+/// Derive the rows of [`DERIVED_TABLES`] again from the indexed events.
+fn derive_events(db: &Connection) -> rusqlite::Result<()> {
+    for table in DERIVED_TABLES {
+        db.execute(&format!("DELETE FROM {}", table), params![])?;
+    }
+    let mut statement = db.prepare(
+        "SELECT block, event_index, extrinsic_index, pallet, name, args FROM events
+        ORDER BY block, event_index",
+    )?;
+    let mut rows = statement.query(params![])?;
+    while let Some(row) = rows.next()? {
+        let args: Value = serde_json::from_str(&row.get::<_, String>(5)?).unwrap_or_default();
+        let event = DecodedEvent {
+            extrinsic_index: row.get(2)?,
+            pallet: row.get(3)?,
+            name: row.get(4)?,
+            args: args
+                .as_array()
+                .map(|args| {
+                    args.iter()
+                        .map(|arg| (text(&arg["type"]), arg["value"].clone()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        derive_event(db, row.get(0)?, row.get(1)?, &event)?;
+    }
+    Ok(())
+}
+
+/// Record the accounts, swap, liquidity change and transfer of an indexed event.
+fn derive_event(
+    db: &Connection,
+    number: BlockNumber,
+    index: u32,
+    event: &DecodedEvent,
+) -> rusqlite::Result<()> {
+    let mut accounts = event.args_of("AccountId");
+    accounts.extend(event.args_of("AccountId32"));
+    for account in accounts {
+        db.execute(
+            "INSERT OR IGNORE INTO account_events (account, block, event_index)
+            VALUES (?1, ?2, ?3)",
+            params![text(account), number, index],
+        )?;
+    }
+
+    if let Some((who, asset_in, amount_in, asset_out, amount_out)) = swap(event) {
+        db.execute(
+            "INSERT INTO swaps (block, event_index, account, asset_in, amount_in, asset_out,
+                amount_out)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![number, index, who, asset_in, amount_in, asset_out, amount_out],
+        )?;
+    }
+
+    if let Some((para, who, asset, amount, inbound)) = transfer(event) {
+        db.execute(
+            "INSERT INTO transfers (block, event_index, para, account, asset, amount, inbound)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![number, index, para, who, asset, amount, inbound],
+        )?;
+    }
+
+    derive_liquidity(db, number, index, event)
+}
+
+/// Record a liquidity change of `DexPallet`, or complete the last one with the amounts and
+/// the source parachain of the `DexRouter` or `XcmpRouter` event that made it.
+fn derive_liquidity(
+    db: &Connection,
+    number: BlockNumber,
+    index: u32,
+    event: &DecodedEvent,
+) -> rusqlite::Result<()> {
+    let accounts = event.args_of("AccountId");
+    let assets = event.args_of("Asset");
+    let balances = event.args_of("Balance");
+    let para = event
+        .args_of("ParaId")
+        .first()
+        .and_then(|para| para.as_u64())
+        .map(|para| para as u32);
+
+    let (added, amounts, para) = match (event.pallet.as_str(), event.name.as_str()) {
+        ("DexPallet", "Invested") | ("DexPallet", "Divested") => {
+            // (account, first asset, second asset, shares)
+            if let ([who], [first_asset, second_asset], [shares]) =
+                (&accounts[..], &assets[..], &balances[..])
+            {
+                db.execute(
+                    "INSERT INTO liquidity (block, event_index, account, first_asset,
+                        second_asset, shares, added)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        number,
+                        index,
+                        text(who),
+                        text(first_asset),
+                        text(second_asset),
+                        text(shares),
+                        event.name == "Invested",
+                    ],
+                )?;
+            }
+            return Ok(());
+        }
+        // (account, first asset, first amount, second asset, second amount, shares)
+        ("DexRouter", "LiquidityAdded") => (true, &balances[..balances.len().min(2)], None),
+        ("DexRouter", "LiquidityRemoved") => (false, &balances[..balances.len().min(2)], None),
+        // (source parachain, sender, account holding the shares, shares)
+        ("XcmpRouter", "RemoteLiquidityAdded") => (true, &[][..], para),
+        // (source parachain, sender, shares, first amount, second amount)
+        ("XcmpRouter", "RemoteLiquidityRemoved") => {
+            (false, &balances[balances.len().min(1)..], para)
+        }
+        _ => return Ok(()),
+    };
+    let (first_amount, second_amount) = match amounts {
+        [first, second] => (Some(text(first)), Some(text(second))),
+        _ => (None, None),
+    };
+    db.execute(
+        "UPDATE liquidity
+        SET first_amount = COALESCE(?4, first_amount),
+            second_amount = COALESCE(?5, second_amount),
+            para = COALESCE(?6, para)
+        WHERE block = ?1 AND event_index = (
+            SELECT MAX(event_index) FROM liquidity
+            WHERE block = ?1 AND event_index < ?2 AND added = ?3
+        )",
+        params![number, index, added, first_amount, second_amount, para],
+    )?;
+    Ok(())
+}
+
+/// Transfer recorded by `event`, if it is a successful transfer: (parachain, relay chain if
+/// `None`, account, asset, amount, whether it is inbound).
+///
+/// The asset is the local id when the event has one, the id on the other chain otherwise.
+pub fn transfer(event: &DecodedEvent) -> Option<(Option<u32>, String, String, String, bool)> {
+    let inbound = TRANSFER_EVENTS
+        .iter()
+        .find(|(pallet, name, _)| event.is(pallet, name))
+        .map(|(_, _, inbound)| *inbound)?;
+    if event
+        .args_of("DispatchResult")
+        .iter()
+        .any(|result| result.as_str() != Some("ok"))
+    {
+        return None;
+    }
+    let para = event
+        .args_of("ParaId")
+        .first()
+        .and_then(|para| para.as_u64())
+        .map(|para| para as u32);
+    let asset = match (
+        event.args_of("AssetId").first(),
+        event.args_of("Option").first(),
+    ) {
+        (Some(asset), _) | (None, Some(asset)) if !asset.is_null() => text(asset),
+        _ => "main".into(),
+    };
+    Some((
+        para,
+        text(event.args_of("AccountId").first()?),
+        asset,
+        text(event.args_of("Balance").first()?),
+        inbound,
+    ))
+}
+
+/// Keep the index up to date with the chain of `client`.
+pub fn spawn_indexer<B, C>(
+    index: Arc<DexIndex>,
+    finalized_only: bool,
+    backend: Arc<B>,
+    client: Arc<C>,
+    spawn_handle: SpawnTaskHandle,
+) where
+    B: Backend<Block> + 'static,
+    C: BlockchainEvents<Block> + ProvideRuntimeApi<Block> + Send + Sync + 'static,
+    C::Api: Core<Block> + Metadata<Block>,
+{
+    let mut heads = if finalized_only {
+        client
+            .finality_notification_stream()
+            .map(|notification| *notification.header.number())
+            .boxed()
+    } else {
+        client
+            .import_notification_stream()
+            .filter(|notification| future::ready(notification.is_new_best))
+            .map(|notification| *notification.header.number())
+            .boxed()
+    };
+
+    let decoder = EventDecoder::new(client.clone());
+    spawn_handle.spawn_blocking("dex-index", async move {
+        let info = backend.blockchain().info();
+        let mut head = Some(if finalized_only {
+            info.finalized_number
+        } else {
+            info.best_number
+        });
+        // Gaps are retried on start, then whenever the runtime of the head changes.
+        let mut spec_version = None;
+        while let Some(number) = head {
+            if let Err(e) = index.sync(&decoder, &*backend, number) {
+                warn!(
+                    target: DEX_INDEX_TARGET,
+                    "Indexing the DEX up to block {} failed: {}", number, e
+                );
+            }
+            let version = backend
+                .blockchain()
+                .hash(number)
+                .ok()
+                .flatten()
+                .and_then(|hash| client.runtime_api().version(&BlockId::Hash(hash)).ok())
+                .map(|version| version.spec_version);
+            if version.is_some() && version != spec_version {
+                spec_version = version;
+                match index.retry_gaps(&decoder, &*backend) {
+                    Ok(0) => {}
+                    Ok(left) => info!(
+                        target: DEX_INDEX_TARGET,
+                        "{} blocks still indexed without their events", left
+                    ),
+                    Err(e) => warn!(
+                        target: DEX_INDEX_TARGET,
+                        "Indexing the events of gaps failed: {}", e
+                    ),
+                }
+            }
+            head = heads.next().await;
+        }
+    });
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trade {
+    pub block: BlockNumber,
+    pub timestamp: i64,
+    pub account: String,
+    pub asset_in: String,
+    pub amount_in: String,
+    pub asset_out: String,
+    pub amount_out: String,
+}
+
+/// Prices of a base asset in a quote asset over an interval.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candle {
+    /// Start of the interval, in milliseconds.
+    pub start: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    /// Amount of the base asset swapped.
+    pub volume: String,
+    pub trades: u32,
+}
+
+/// Transfer from or to another chain.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transfer {
+    pub block: BlockNumber,
+    pub timestamp: i64,
+    /// Other parachain, `None` for the relay chain.
+    pub para: Option<u32>,
+    pub asset: String,
+    pub amount: String,
+    pub inbound: bool,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity {
+    pub block: BlockNumber,
+    pub timestamp: i64,
+    pub pallet: String,
+    pub event: String,
+    /// Arguments, as `{"type", "value"}` objects.
+    pub args: Value,
+}
+
+/// Assets are identified by their id, or `main` for the relay chain currency.
+#[rpc]
+pub trait DexIndexApi {
+    /// Latest swaps between `base` and `quote`.
+    #[rpc(name = "subdex_tradeHistory")]
+    fn trade_history(
+        &self,
+        base: String,
+        quote: String,
+        limit: Option<u32>,
+    ) -> RpcResult<Vec<Trade>>;
+
+    /// Candles of the price of `base` in `quote`, `interval` and timestamps in milliseconds.
+    #[rpc(name = "subdex_candles")]
+    fn candles(
+        &self,
+        base: String,
+        quote: String,
+        interval: u64,
+        from: u64,
+        to: u64,
+    ) -> RpcResult<Vec<Candle>>;
+
+    /// Latest DEX events involving `account`.
+    #[rpc(name = "subdex_accountActivity")]
+    fn account_activity(&self, account: AccountId, limit: Option<u32>) -> RpcResult<Vec<Activity>>;
+
+    /// Latest transfers of `account` from and to other chains.
+    #[rpc(name = "subdex_accountTransfers")]
+    fn account_transfers(&self, account: AccountId, limit: Option<u32>)
+        -> RpcResult<Vec<Transfer>>;
+}
+
+const INVALID_RANGE: i64 = 1;
+const DATABASE_ERROR: i64 = 2;
+
+fn database_error(error: rusqlite::Error) -> RpcError {
+    RpcError {
+        code: ErrorCode::ServerError(DATABASE_ERROR),
+        message: "Unable to query the DEX index.".into(),
+        data: Some(error.to_string().into()),
+    }
+}
+
+/// Implementation of [`DexIndexApi`] querying the index.
+pub struct DexIndexRpc {
+    index: Arc<DexIndex>,
+}
+
+impl DexIndexRpc {
+    pub fn new(index: Arc<DexIndex>) -> Self {
+        DexIndexRpc { index }
+    }
+}
+
+impl DexIndexApi for DexIndexRpc {
+    fn trade_history(
+        &self,
+        base: String,
+        quote: String,
+        limit: Option<u32>,
+    ) -> RpcResult<Vec<Trade>> {
+        self.index
+            .trades(&base, &quote, limit.unwrap_or(MAX_ROWS).min(MAX_ROWS))
+            .map_err(database_error)
+    }
+
+    fn candles(
+        &self,
+        base: String,
+        quote: String,
+        interval: u64,
+        from: u64,
+        to: u64,
+    ) -> RpcResult<Vec<Candle>> {
+        if interval == 0 || to <= from || (to - from) / interval >= MAX_CANDLES {
+            return Err(RpcError {
+                code: ErrorCode::ServerError(INVALID_RANGE),
+                message: format!(
+                    "Time range must not be empty nor span more than {} intervals.",
+                    MAX_CANDLES
+                ),
+                data: None,
+            });
+        }
+        self.index
+            .candles(&base, &quote, interval, from, to)
+            .map_err(database_error)
+    }
+
+    fn account_activity(&self, account: AccountId, limit: Option<u32>) -> RpcResult<Vec<Activity>> {
+        // Accounts are indexed as SS58 addresses.
+        self.index
+            .account_activity(
+                &account.to_string(),
+                limit.unwrap_or(MAX_ROWS).min(MAX_ROWS),
+            )
+            .map_err(database_error)
+    }
+
+    fn account_transfers(
+        &self,
+        account: AccountId,
+        limit: Option<u32>,
+    ) -> RpcResult<Vec<Transfer>> {
+        self.index
+            .transfers(
+                &account.to_string(),
+                limit.unwrap_or(MAX_ROWS).min(MAX_ROWS),
+            )
+            .map_err(database_error)
+    }
+}
@@ -0,0 +1,561 @@
+use super::*;
+use serde_json::json;
+
+fn index() -> DexIndex {
+    DexIndex::open(Path::new(":memory:")).unwrap()
+}
+
+fn hash(number: BlockNumber, fork: &str) -> String {
+    format!("0x{}{:02}", fork, number)
+}
+
+/// Index block `number` of `fork` at `timestamp`, with a row in every other table.
+fn insert_block(index: &DexIndex, number: BlockNumber, fork: &str, timestamp: i64) {
+    let db = index.db.lock();
+    db.execute(
+        "INSERT INTO blocks (number, hash, timestamp) VALUES (?1, ?2, ?3)",
+        params![number, hash(number, fork), timestamp],
+    )
+    .unwrap();
+    db.execute(
+        "INSERT INTO gaps (block, error) VALUES (?1, 'unknown type')",
+        params![number],
+    )
+    .unwrap();
+    db.execute(
+        "INSERT INTO events (block, event_index, pallet, name, args)
+        VALUES (?1, 0, 'DexPallet', 'Exchanged', '[]')",
+        params![number],
+    )
+    .unwrap();
+    db.execute(
+        "INSERT INTO liquidity (block, event_index, account, first_asset, first_amount,
+            second_asset, second_amount, shares, added)
+        VALUES (?1, 0, 'alice', 'main', '1', '1', '1', '1', 1)",
+        params![number],
+    )
+    .unwrap();
+    db.execute(
+        "INSERT INTO transfers (block, event_index, para, account, asset, amount, inbound)
+        VALUES (?1, 0, 100, 'alice', 'main', '1', 1)",
+        params![number],
+    )
+    .unwrap();
+    db.execute(
+        "INSERT INTO account_events (account, block, event_index) VALUES ('alice', ?1, 0)",
+        params![number],
+    )
+    .unwrap();
+}
+
+fn insert_price(index: &DexIndex, number: BlockNumber, price: f64) {
+    index
+        .db
+        .lock()
+        .execute(
+            "INSERT INTO prices (block, first_asset, second_asset, first_reserve,
+                second_reserve, price)
+            VALUES (?1, 'main', '1', '0', '0', ?2)",
+            params![number, price],
+        )
+        .unwrap();
+}
+
+fn insert_swap(index: &DexIndex, number: BlockNumber, asset_in: &str, amounts: (u128, u128)) {
+    let asset_out = if asset_in == "main" { "1" } else { "main" };
+    index
+        .db
+        .lock()
+        .execute(
+            "INSERT INTO swaps (block, event_index, account, asset_in, amount_in, asset_out,
+                amount_out)
+            VALUES (?1, 1, 'alice', ?2, ?3, ?4, ?5)",
+            params![
+                number,
+                asset_in,
+                amounts.0.to_string(),
+                asset_out,
+                amounts.1.to_string()
+            ],
+        )
+        .unwrap();
+}
+
+/// Blocks left in every table indexed by block.
+fn blocks(index: &DexIndex) -> Vec<Vec<BlockNumber>> {
+    let db = index.db.lock();
+    BLOCK_TABLES
+        .iter()
+        .filter(|(table, _)| *table != "swaps" && *table != "prices")
+        .map(|(table, column)| {
+            let mut statement = db
+                .prepare(&format!("SELECT {0} FROM {1} ORDER BY {0}", column, table))
+                .unwrap();
+            let mut rows = statement.query(params![]).unwrap();
+            let mut blocks = Vec::new();
+            while let Some(row) = rows.next().unwrap() {
+                blocks.push(row.get(0).unwrap());
+            }
+            blocks
+        })
+        .collect()
+}
+
+#[test]
+fn rollback_removes_blocks_replaced_by_a_reorg() {
+    let index = index();
+    for number in 0..6 {
+        insert_block(&index, number, "a", 0);
+    }
+
+    // Blocks 3 and above were replaced by fork `b`.
+    let canonical = |number| Some(hash(number, if number < 3 { "a" } else { "b" }));
+    assert_eq!(index.rollback(canonical, 6), Ok(3));
+    assert!(blocks(&index).iter().all(|blocks| *blocks == vec![0, 1, 2]));
+
+    // Blocks of the new fork are kept.
+    for number in 3..5 {
+        insert_block(&index, number, "b", 0);
+    }
+    assert_eq!(index.rollback(canonical, 6), Ok(5));
+    assert!(blocks(&index)
+        .iter()
+        .all(|blocks| *blocks == vec![0, 1, 2, 3, 4]));
+}
+
+#[test]
+fn rollback_removes_blocks_above_the_head() {
+    let index = index();
+    for number in 0..6 {
+        insert_block(&index, number, "a", 0);
+    }
+
+    let canonical = |number| Some(hash(number, "a"));
+    assert_eq!(index.rollback(canonical, 3), Ok(4));
+    assert!(blocks(&index)
+        .iter()
+        .all(|blocks| *blocks == vec![0, 1, 2, 3]));
+}
+
+#[test]
+fn rollback_of_an_empty_index_starts_at_genesis() {
+    assert_eq!(index().rollback(|_| None, 3), Ok(0));
+}
+
+#[test]
+fn candles_are_bucketed_by_interval() {
+    let index = index();
+    for (number, timestamp, price) in &[(1, 1_000, 2.0), (2, 1_999, 4.0), (3, 2_000, 3.0)] {
+        insert_block(&index, *number, "a", *timestamp);
+        insert_price(&index, *number, *price);
+    }
+    // No price change in the last interval, nor out of the requested range.
+    insert_block(&index, 4, "a", 3_500);
+    insert_block(&index, 5, "a", 4_000);
+    insert_price(&index, 5, 8.0);
+    insert_swap(&index, 1, "main", (10, 20));
+    insert_swap(&index, 2, "1", (40, 10));
+    insert_swap(&index, 3, "main", (5, 15));
+
+    let candles = index.candles("main", "1", 1_000, 1_000, 4_000).unwrap();
+    assert_eq!(
+        candles,
+        vec![
+            Candle {
+                start: 1_000,
+                open: 2.0,
+                high: 4.0,
+                low: 2.0,
+                close: 4.0,
+                volume: "20".into(),
+                trades: 2,
+            },
+            Candle {
+                start: 2_000,
+                open: 3.0,
+                high: 3.0,
+                low: 3.0,
+                close: 3.0,
+                volume: "5".into(),
+                trades: 1,
+            },
+        ]
+    );
+}
+
+#[test]
+fn candles_of_the_second_asset_invert_prices() {
+    let index = index();
+    insert_block(&index, 1, "a", 1_000);
+    insert_price(&index, 1, 2.0);
+    insert_swap(&index, 1, "main", (10, 20));
+
+    let candles = index.candles("1", "main", 1_000, 1_000, 2_000).unwrap();
+    assert_eq!(candles.len(), 1);
+    assert_eq!((candles[0].open, candles[0].close), (0.5, 0.5));
+    // Volume is counted in the base asset.
+    assert_eq!(candles[0].volume, "20");
+}
+
+fn event(pallet: &str, name: &str, args: Vec<(&str, Value)>) -> DecodedEvent {
+    DecodedEvent {
+        extrinsic_index: Some(1),
+        pallet: pallet.into(),
+        name: name.into(),
+        args: args
+            .into_iter()
+            .map(|(ty, value)| (ty.to_string(), value))
+            .collect(),
+    }
+}
+
+#[test]
+fn swaps_are_indexed_from_dex_pallet_events() {
+    let index = index();
+    index
+        .db
+        .lock()
+        .execute(
+            "INSERT INTO blocks (number, hash, timestamp) VALUES (1, '0x01', 1000)",
+            params![],
+        )
+        .unwrap();
+    let events = vec![
+        event(
+            "DexPallet",
+            "Exchanged",
+            vec![
+                ("T::AccountId", json!("alice")),
+                ("AssetOf<T>", json!("main")),
+                ("BalanceOf<T>", json!("10")),
+                ("AssetOf<T>", json!("1")),
+                ("BalanceOf<T>", json!("20")),
+            ],
+        ),
+        event(
+            "DexRouter",
+            "Swapped",
+            vec![
+                ("AccountId", json!("alice")),
+                ("Asset", json!("main")),
+                ("Balance", json!("10")),
+                ("Asset", json!("1")),
+                ("Balance", json!("20")),
+            ],
+        ),
+        event(
+            "DexOrders",
+            "OrderFilled",
+            vec![
+                ("OrderId", json!(0)),
+                ("AccountId", json!("bob")),
+                ("Balance", json!("10")),
+                ("Balance", json!("20")),
+                ("Balance", json!("0")),
+            ],
+        ),
+        event("System", "ExtrinsicSuccess", vec![]),
+    ];
+    index_events(&index.db.lock(), 1, events).unwrap();
+
+    // The router swap is the `DexPallet` one, which is not counted twice.
+    assert_eq!(
+        index.trades("1", "main", 10).unwrap(),
+        vec![Trade {
+            block: 1,
+            timestamp: 1_000,
+            account: "alice".into(),
+            asset_in: "main".into(),
+            amount_in: "10".into(),
+            asset_out: "1".into(),
+            amount_out: "20".into(),
+        }]
+    );
+    // Events of the DEX pallets make up the activity of accounts.
+    let activity = index.account_activity("bob", 10).unwrap();
+    assert_eq!(
+        activity
+            .iter()
+            .map(|activity| (activity.pallet.as_str(), activity.event.as_str()))
+            .collect::<Vec<_>>(),
+        vec![("DexOrders", "OrderFilled")]
+    );
+    assert_eq!(index.account_activity("alice", 10).unwrap().len(), 2);
+    let indexed = index
+        .db
+        .lock()
+        .query_row("SELECT COUNT(*) FROM events", params![], |row| {
+            row.get::<_, u32>(0)
+        });
+    assert_eq!(indexed, Ok(3));
+}
+
+fn insert_blocks(index: &DexIndex, numbers: std::ops::RangeInclusive<BlockNumber>) {
+    for number in numbers {
+        index
+            .db
+            .lock()
+            .execute(
+                "INSERT INTO blocks (number, hash, timestamp) VALUES (?1, ?2, 1000)",
+                params![number, hash(number, "a")],
+            )
+            .unwrap();
+    }
+}
+
+/// Liquidity change: (block, account, shares, first amount, second amount, added, para).
+type LiquidityRow = (
+    BlockNumber,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    bool,
+    Option<u32>,
+);
+
+fn liquidity(index: &DexIndex) -> Vec<LiquidityRow> {
+    let db = index.db.lock();
+    let mut statement = db
+        .prepare(
+            "SELECT block, account, shares, first_amount, second_amount, added, para
+            FROM liquidity ORDER BY block, event_index",
+        )
+        .unwrap();
+    let liquidity = statement
+        .query_map(params![], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+                row.get(6)?,
+            ))
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .unwrap();
+    liquidity
+}
+
+fn invested(name: &str, who: &str, shares: &str) -> DecodedEvent {
+    event(
+        "DexPallet",
+        name,
+        vec![
+            ("T::AccountId", json!(who)),
+            ("AssetOf<T>", json!("main")),
+            ("AssetOf<T>", json!("1")),
+            ("BalanceOf<T>", json!(shares)),
+        ],
+    )
+}
+
+#[test]
+fn liquidity_changes_of_every_entry_point_are_indexed_once() {
+    let index = index();
+    insert_blocks(&index, 1..=3);
+    // Through the router.
+    index_events(
+        &index.db.lock(),
+        1,
+        vec![
+            invested("Invested", "alice", "100"),
+            event(
+                "DexRouter",
+                "LiquidityAdded",
+                vec![
+                    ("AccountId", json!("alice")),
+                    ("Asset", json!("main")),
+                    ("Balance", json!("10")),
+                    ("Asset", json!("1")),
+                    ("Balance", json!("20")),
+                    ("Balance", json!("100")),
+                ],
+            ),
+        ],
+    )
+    .unwrap();
+    // From another parachain.
+    index_events(
+        &index.db.lock(),
+        2,
+        vec![
+            invested("Invested", "bob", "50"),
+            event(
+                "XcmpRouter",
+                "RemoteLiquidityAdded",
+                vec![
+                    ("ParaId", json!(100)),
+                    ("AccountId", json!("sender")),
+                    ("AccountId", json!("bob")),
+                    ("Balance", json!("50")),
+                ],
+            ),
+            invested("Divested", "bob", "20"),
+            event(
+                "XcmpRouter",
+                "RemoteLiquidityRemoved",
+                vec![
+                    ("ParaId", json!(100)),
+                    ("AccountId", json!("bob")),
+                    ("Balance", json!("20")),
+                    ("Balance", json!("2")),
+                    ("Balance", json!("4")),
+                ],
+            ),
+        ],
+    )
+    .unwrap();
+    // Directly in `DexPallet`, which does not tell the amounts.
+    index_events(
+        &index.db.lock(),
+        3,
+        vec![invested("Divested", "alice", "30")],
+    )
+    .unwrap();
+
+    assert_eq!(
+        liquidity(&index),
+        vec![
+            (
+                1,
+                "alice".into(),
+                "100".into(),
+                Some("10".into()),
+                Some("20".into()),
+                true,
+                None
+            ),
+            (2, "bob".into(), "50".into(), None, None, true, Some(100)),
+            (
+                2,
+                "bob".into(),
+                "20".into(),
+                Some("2".into()),
+                Some("4".into()),
+                false,
+                Some(100)
+            ),
+            (3, "alice".into(), "30".into(), None, None, false, None),
+        ]
+    );
+}
+
+#[test]
+fn transfers_are_indexed_by_account() {
+    let index = index();
+    insert_blocks(&index, 1..=1);
+    index_events(
+        &index.db.lock(),
+        1,
+        vec![
+            event(
+                "DexXCMP",
+                "DepositAssetViaXCMP",
+                vec![
+                    ("ParaId", json!(100)),
+                    ("Option<AssetId>", json!(7)),
+                    ("AccountId", json!("alice")),
+                    ("AssetId", json!(1)),
+                    ("Balance", json!("10")),
+                ],
+            ),
+            event(
+                "DexXCMP",
+                "TransferredTokensToRelayChain",
+                vec![("AccountId", json!("alice")), ("Balance", json!("5"))],
+            ),
+            event(
+                "DexXCMP",
+                "TransferredTokensViaXCMP",
+                vec![
+                    ("ParaId", json!(100)),
+                    ("Option<AssetId>", Value::Null),
+                    ("AccountId", json!("alice")),
+                    ("Balance", json!("1")),
+                    ("DispatchResult", json!({ "error": "Other" })),
+                ],
+            ),
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        index.transfers("alice", 10).unwrap(),
+        vec![
+            Transfer {
+                block: 1,
+                timestamp: 1_000,
+                para: None,
+                asset: "main".into(),
+                amount: "5".into(),
+                inbound: false,
+            },
+            Transfer {
+                block: 1,
+                timestamp: 1_000,
+                para: Some(100),
+                asset: "1".into(),
+                amount: "10".into(),
+                inbound: true,
+            },
+        ]
+    );
+    assert_eq!(index.transfers("bob", 10).unwrap(), vec![]);
+}
+
+#[test]
+fn rows_of_an_older_schema_are_derived_again() {
+    let path = std::env::temp_dir().join(format!("dex-index-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let db = Connection::open(&path).unwrap();
+        // Version 0, whose liquidity amounts were required.
+        db.execute_batch(
+            "CREATE TABLE liquidity (
+                block INTEGER NOT NULL,
+                event_index INTEGER NOT NULL,
+                account TEXT NOT NULL,
+                first_asset TEXT NOT NULL,
+                first_amount TEXT NOT NULL,
+                second_asset TEXT NOT NULL,
+                second_amount TEXT NOT NULL,
+                shares TEXT NOT NULL,
+                added INTEGER NOT NULL,
+                PRIMARY KEY (block, event_index)
+            );
+            CREATE TABLE events (
+                block INTEGER NOT NULL,
+                event_index INTEGER NOT NULL,
+                extrinsic_index INTEGER,
+                pallet TEXT NOT NULL,
+                name TEXT NOT NULL,
+                args TEXT NOT NULL,
+                PRIMARY KEY (block, event_index)
+            );",
+        )
+        .unwrap();
+        db.execute(
+            "INSERT INTO events (block, event_index, extrinsic_index, pallet, name, args)
+            VALUES (1, 0, NULL, 'DexPallet', 'Invested', ?1)",
+            params![invested("Invested", "bob", "50").args_json().to_string()],
+        )
+        .unwrap();
+    }
+
+    let index = DexIndex::open(&path).unwrap();
+    insert_blocks(&index, 1..=1);
+    assert_eq!(
+        liquidity(&index),
+        vec![(1, "bob".into(), "50".into(), None, None, true, None)]
+    );
+    assert_eq!(index.account_activity("bob", 10).unwrap().len(), 1);
+    // Derived once.
+    drop(index);
+    let index = DexIndex::open(&path).unwrap();
+    assert_eq!(liquidity(&index).len(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,286 @@
+//! Export of the DEX state and of the DEX events from the local database, for analytics.
+//!
+//! Storage is decoded with the types of the runtime linked into the collator. Events are decoded
+//! with the metadata of the runtime that deposited them, see [`crate::runtime_events`], and
+//! blocks whose events cannot be decoded are listed in the `gaps` table instead.
+//!
+//! One file per table is written to the output directory:
+//! - `pools`: reserves and total shares of every pool at the last block of the range,
+//! - `lp_holders`: shares of every liquidity provider at the last block of the range,
+//! - `asset_mappings`: local asset ids of the assets of other parachains,
+//! - `bridged_reserves`: amounts of the assets of other parachains bridged to Subdex,
+//! - `events`: `DexPallet` and `DexXCMP` events of every block of the range, their arguments
+//!   being a list of `{"type", "value"}` objects,
+//! - `gaps`: blocks of the range whose events could not be decoded, and why.
+//!
+//! CSV files always start with a header, even when the table has no rows.
+
+use crate::{
+    cli::{ExportDexCommand, ExportFormat},
+    runtime_events::EventDecoder,
+};
+use frame_support::storage::{IterableStorageDoubleMap, StoragePrefixedMap};
+use parachain_runtime::{opaque::Block, AssetId, BlockNumber, Hash, Runtime};
+use sc_client_api::Backend;
+use serde_json::Value;
+use sp_api::{Core, Metadata, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
+use sp_core::{hashing::twox_128, storage::Storage};
+use sp_runtime::generic::BlockId;
+use sp_state_machine::{Backend as StateBackend, BasicExternalities};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+type Row = Vec<Value>;
+
+const POOL_COLUMNS: &[&str] = &[
+    "first_asset",
+    "second_asset",
+    "first_asset_reserve",
+    "second_asset_reserve",
+    "total_shares",
+];
+const LP_HOLDER_COLUMNS: &[&str] = &["first_asset", "second_asset", "account", "shares"];
+const ASSET_MAPPING_COLUMNS: &[&str] = &["para_id", "para_asset", "asset_id"];
+const BRIDGED_RESERVE_COLUMNS: &[&str] = &["para_id", "para_asset", "supply"];
+const EVENT_COLUMNS: &[&str] = &[
+    "block",
+    "block_hash",
+    "event_index",
+    "extrinsic_index",
+    "pallet",
+    "event",
+    "args",
+];
+const GAP_COLUMNS: &[&str] = &["block", "block_hash", "error"];
+
+/// Export the DEX over the requested block range to `cmd.output`.
+pub fn run<B, C>(cmd: &ExportDexCommand, client: Arc<C>, backend: Arc<B>) -> sc_cli::Result<()>
+where
+    B: Backend<Block>,
+    C: ProvideRuntimeApi<Block>,
+    C::Api: Core<Block> + Metadata<Block>,
+{
+    let info = backend.blockchain().info();
+    let to = cmd.to.unwrap_or(info.best_number).min(info.best_number);
+    if cmd.from > to {
+        return Err(format!("Invalid block range {}..={}", cmd.from, to).into());
+    }
+    std::fs::create_dir_all(&cmd.output)?;
+
+    let to_hash = block_hash(&*backend, to)?;
+    let (pools, holders, mappings, reserves) = dex_state(&*backend, to_hash)?;
+    let table = |name, columns| Table::create(&cmd.output, name, columns, cmd.format);
+    table("pools", POOL_COLUMNS)?.write_all(pools)?;
+    table("lp_holders", LP_HOLDER_COLUMNS)?.write_all(holders)?;
+    table("asset_mappings", ASSET_MAPPING_COLUMNS)?.write_all(mappings)?;
+    table("bridged_reserves", BRIDGED_RESERVE_COLUMNS)?.write_all(reserves)?;
+
+    let decoder = EventDecoder::new(client);
+    let mut events = table("events", EVENT_COLUMNS)?;
+    let mut gaps = table("gaps", GAP_COLUMNS)?;
+    for number in cmd.from..=to {
+        let hash = block_hash(&*backend, number)?;
+        let block_events = match decoder.block_events(&*backend, hash) {
+            Ok(block_events) => block_events,
+            Err(e) => {
+                log::warn!("Skipping the events of block {}: {}", number, e);
+                gaps.write(vec![number.into(), format!("{:?}", hash).into(), e.into()])?;
+                continue;
+            }
+        };
+        for (index, event) in block_events.into_iter().enumerate() {
+            if event.pallet != "DexPallet" && event.pallet != "DexXCMP" {
+                continue;
+            }
+            let args = event.args_json();
+            events.write(vec![
+                number.into(),
+                format!("{:?}", hash).into(),
+                index.into(),
+                event.extrinsic_index.map_or(Value::Null, Value::from),
+                event.pallet.into(),
+                event.name.into(),
+                args,
+            ])?;
+        }
+    }
+    events.flush()?;
+    gaps.flush()?;
+
+    eprintln!(
+        "Exported the DEX at block {} and its events of blocks {}..={} to {:?}",
+        to, cmd.from, to, cmd.output
+    );
+    Ok(())
+}
+
+fn block_hash<B: Backend<Block>>(backend: &B, number: BlockNumber) -> sc_cli::Result<Hash> {
+    backend
+        .blockchain()
+        .hash(number)
+        .ok()
+        .flatten()
+        .ok_or_else(|| format!("Block {} not found", number).into())
+}
+
+/// Rows of the pools, LP holders, asset mappings and bridged reserves at block `at`.
+fn dex_state<B: Backend<Block>>(
+    backend: &B,
+    at: Hash,
+) -> sc_cli::Result<(Vec<Row>, Vec<Row>, Vec<Row>, Vec<Row>)> {
+    let state = backend
+        .state_at(BlockId::Hash(at))
+        .map_err(|e| format!("State of block {} not available: {:?}", at, e))?;
+    let mut storage = Storage::default();
+    for module in &[
+        pallet_subdex::Exchanges::<Runtime>::module_prefix(),
+        pallet_subdex_xcmp::AssetIdByParaAssetId::<Runtime>::module_prefix(),
+        pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::module_prefix(),
+    ] {
+        for key in state.keys(&twox_128(module)) {
+            if let Some(value) = state
+                .storage(&key)
+                .map_err(|e| format!("Unable to read the DEX storage: {:?}", e))?
+            {
+                storage.top.insert(key, value);
+            }
+        }
+    }
+
+    // Read with the typed storage of the pallets, on a copy of their storage.
+    Ok(BasicExternalities::new(storage).execute_with(|| {
+        let mut pools = Vec::new();
+        let mut holders = Vec::new();
+        for (first, second, exchange) in pallet_subdex::Exchanges::<Runtime>::iter() {
+            let (first, second) = (asset(&first), asset(&second));
+            pools.push(vec![
+                first.clone().into(),
+                second.clone().into(),
+                exchange.first_asset_pool.to_string().into(),
+                exchange.second_asset_pool.to_string().into(),
+                exchange.total_shares.to_string().into(),
+            ]);
+            for (holder, shares) in exchange.shares.iter() {
+                holders.push(vec![
+                    first.clone().into(),
+                    second.clone().into(),
+                    holder.to_string().into(),
+                    shares.to_string().into(),
+                ]);
+            }
+        }
+        let mappings = pallet_subdex_xcmp::AssetIdByParaAssetId::<Runtime>::iter()
+            .map(|(para_id, para_asset, asset_id)| {
+                vec![
+                    u32::from(para_id).to_string().into(),
+                    para_asset_id(para_asset).into(),
+                    asset_id.to_string().into(),
+                ]
+            })
+            .collect();
+        let reserves = pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::iter()
+            .map(|(para_id, para_asset, supply)| {
+                vec![
+                    u32::from(para_id).to_string().into(),
+                    para_asset_id(para_asset).into(),
+                    supply.to_string().into(),
+                ]
+            })
+            .collect();
+        (pools, holders, mappings, reserves)
+    }))
+}
+
+/// Asset id as written to exported tables, `main` for the relay chain currency.
+pub fn asset(asset: &pallet_subdex::Asset<AssetId>) -> String {
+    match asset {
+        pallet_subdex::Asset::MainNetworkCurrency => "main".to_string(),
+        pallet_subdex::Asset::ParachainAsset(asset_id) => asset_id.to_string(),
+    }
+}
+
+/// Id of an asset on its parachain, `main` for the currency of the parachain.
+fn para_asset_id(asset_id: Option<AssetId>) -> String {
+    asset_id.map_or_else(|| "main".to_string(), |asset_id| asset_id.to_string())
+}
+
+/// Output file of a table.
+struct Table {
+    format: ExportFormat,
+    columns: &'static [&'static str],
+    file: BufWriter<File>,
+}
+
+impl Table {
+    /// Create the file of table `name`, starting with its header in CSV.
+    fn create(
+        dir: &Path,
+        name: &str,
+        columns: &'static [&'static str],
+        format: ExportFormat,
+    ) -> io::Result<Self> {
+        let extension = match format {
+            ExportFormat::Json => "jsonl",
+            ExportFormat::Csv => "csv",
+        };
+        let mut file = BufWriter::new(File::create(dir.join(format!("{}.{}", name, extension)))?);
+        if let ExportFormat::Csv = format {
+            writeln!(file, "{}", columns.join(","))?;
+        }
+        Ok(Self {
+            format,
+            columns,
+            file,
+        })
+    }
+
+    /// Write `row`, holding a value per column.
+    fn write(&mut self, row: Row) -> io::Result<()> {
+        match self.format {
+            ExportFormat::Json => {
+                let object = self
+                    .columns
+                    .iter()
+                    .map(|column| column.to_string())
+                    .zip(row)
+                    .collect::<serde_json::Map<_, _>>();
+                writeln!(self.file, "{}", Value::Object(object))
+            }
+            ExportFormat::Csv => {
+                let fields = row.iter().map(csv_field).collect::<Vec<_>>();
+                writeln!(self.file, "{}", fields.join(","))
+            }
+        }
+    }
+
+    fn write_all(mut self, rows: Vec<Row>) -> io::Result<()> {
+        for row in rows {
+            self.write(row)?;
+        }
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// `value` as a CSV field: strings as they are, empty for null and JSON otherwise, quoted if
+/// it contains a separator or a quote.
+fn csv_field(value: &Value) -> String {
+    let field = match value {
+        Value::String(value) => value.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    };
+    if field.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
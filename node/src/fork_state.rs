@@ -0,0 +1,203 @@
+//! Export of the state of a block into a raw chain spec, to fork the chain locally.
+//!
+//! The fork starts from a copy of the storage of the block, so the DEX pools, balances and
+//! queues are as on the forked chain. Storage describing the position of the chain, like the
+//! block number, and the relay chain state of `ParachainUpgrade` is reset to genesis values.
+//! The keys governing the chain, the sudo key, the technical committee and the price signer,
+//! are replaced, the proposals of the technical committee dropped, and the runtime code
+//! optionally swapped for another wasm.
+
+use crate::cli::ForkStateCommand;
+use codec::Encode;
+use frame_support::storage::{StoragePrefixedMap, StorageValue};
+use pallet_collective::Instance1;
+use parachain_runtime::{opaque::Block, AccountId, BlockNumber, Runtime};
+use sc_client_api::Backend;
+use sc_service::Configuration;
+use sp_core::{
+    crypto::{Pair, Ss58Codec},
+    hashing::{twox_128, twox_64},
+    hexdisplay::HexDisplay,
+    sr25519,
+    storage::{well_known_keys, ChildInfo},
+};
+use sp_runtime::{generic::BlockId, traits::IdentifyAccount, MultiSigner};
+use sp_state_machine::Backend as StateBackend;
+use std::{collections::BTreeMap, io::Write, sync::Arc};
+
+#[cfg(test)]
+mod tests;
+
+type StorageMap = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Storage reset to its genesis value: (pallet, item), the whole pallet if no item is given.
+const RESET_STORAGE: &[(&str, Option<&str>)] = &[
+    ("System", Some("Number")),
+    ("System", Some("ParentHash")),
+    ("System", Some("BlockHash")),
+    ("System", Some("ExtrinsicsRoot")),
+    ("System", Some("Digest")),
+    ("System", Some("Events")),
+    ("System", Some("EventCount")),
+    ("System", Some("EventTopics")),
+    // Gone, the runtime upgrade hooks run in the first block of the fork.
+    ("System", Some("LastRuntimeUpgrade")),
+    // Relay chain state, the fork learns it again from its relay chain.
+    ("ParachainUpgrade", None),
+];
+
+/// Hash `frame_system` uses as the parent of the genesis block.
+const HASH69: [u8; 32] = [69; 32];
+
+/// Write the chain spec of a fork of the chain at the requested block.
+pub fn run<B: Backend<Block>>(
+    cmd: &ForkStateCommand,
+    config: &Configuration,
+    backend: Arc<B>,
+) -> sc_cli::Result<()> {
+    let at = crate::try_upgrade::block_hash(cmd.at.as_deref(), &*backend)?;
+    let state = backend
+        .state_at(BlockId::Hash(at))
+        .map_err(|e| format!("State of block {} not available: {:?}", at, e))?;
+
+    let mut top: StorageMap = state.pairs().into_iter().collect();
+    let child_roots = top
+        .keys()
+        .filter(|key| key.starts_with(well_known_keys::DEFAULT_CHILD_STORAGE_KEY_PREFIX))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut children = BTreeMap::new();
+    for prefixed_key in child_roots {
+        // Child roots are computed again from the content of the child tries.
+        top.remove(&prefixed_key);
+        let storage_key =
+            prefixed_key[well_known_keys::DEFAULT_CHILD_STORAGE_KEY_PREFIX.len()..].to_vec();
+        let child_info = ChildInfo::new_default(&storage_key);
+        let mut child = StorageMap::new();
+        for key in state.child_keys(&child_info, &[]) {
+            if let Some(value) = state
+                .child_storage(&child_info, &key)
+                .map_err(|e| format!("Unable to read child storage: {:?}", e))?
+            {
+                child.insert(key, value);
+            }
+        }
+        children.insert(storage_key, child);
+    }
+
+    let top = fork_storage(cmd, top)?;
+
+    let mut spec: serde_json::Value = serde_json::from_str(&config.chain_spec.as_json(true)?)
+        .map_err(|e| format!("Invalid chain spec: {}", e))?;
+    spec["name"] = format!("{} Fork", config.chain_spec.name()).into();
+    spec["id"] = format!("{}_fork", config.chain_spec.id()).into();
+    spec["chainType"] = "Local".into();
+    spec["bootNodes"] = serde_json::json!([]);
+    spec["telemetryEndpoints"] = serde_json::Value::Null;
+    if let Some(relay_chain) = &cmd.relay_chain {
+        spec["relay_chain"] = relay_chain.as_str().into();
+    }
+    spec["genesis"] = serde_json::json!({
+        "raw": {
+            "top": to_json(&top),
+            "childrenDefault": children
+                .iter()
+                .map(|(key, child)| (hex(key), to_json(child)))
+                .collect::<serde_json::Map<_, _>>(),
+        }
+    });
+    let spec = serde_json::to_string_pretty(&spec)
+        .map_err(|e| format!("Unable to encode chain spec: {}", e))?;
+
+    if let Some(output) = &cmd.output {
+        std::fs::write(output, spec)?;
+    } else {
+        std::io::stdout().write_all(spec.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Top storage of the fork, from the top storage `top` of the forked block.
+fn fork_storage(cmd: &ForkStateCommand, mut top: StorageMap) -> sc_cli::Result<StorageMap> {
+    let technical_committee =
+        twox_128(pallet_collective::ProposalOf::<Runtime, Instance1>::module_prefix());
+    for (pallet, item) in RESET_STORAGE {
+        let prefix = match item {
+            Some(item) => storage_key(pallet, item),
+            None => twox_128(pallet.as_bytes()).to_vec(),
+        };
+        top.retain(|key, _| !key.starts_with(&prefix));
+    }
+    // Proposals and votes of the replaced members.
+    top.retain(|key, _| !key.starts_with(&technical_committee));
+
+    top.insert(storage_key("System", "ParentHash"), HASH69.encode());
+    let genesis_number: BlockNumber = 0;
+    top.insert(
+        [
+            storage_key("System", "BlockHash"),
+            twox_64(&genesis_number.encode()).to_vec(),
+            genesis_number.encode(),
+        ]
+        .concat(),
+        HASH69.encode(),
+    );
+    top.insert(storage_key("Sudo", "Key"), account(&cmd.sudo)?.encode());
+    let mut members = cmd
+        .technical_committee
+        .iter()
+        .map(|member| account(member))
+        .collect::<sc_cli::Result<Vec<_>>>()?;
+    if members.is_empty() {
+        return Err("The technical committee needs at least one member".into());
+    }
+    // Kept sorted by the collective pallet.
+    members.sort();
+    members.dedup();
+    top.insert(
+        pallet_collective::Members::<Runtime, Instance1>::hashed_key().to_vec(),
+        members.encode(),
+    );
+    top.insert(
+        pallet_subdex_price_feed::PriceSigner::hashed_key().to_vec(),
+        public(&cmd.price_signer)?.encode(),
+    );
+    if let Some(wasm) = &cmd.wasm {
+        top.insert(well_known_keys::CODE.to_vec(), std::fs::read(wasm)?);
+    }
+    Ok(top)
+}
+
+/// Hashed key of the storage value `item` of `pallet`, or prefix of the storage map `item`.
+fn storage_key(pallet: &str, item: &str) -> Vec<u8> {
+    [twox_128(pallet.as_bytes()), twox_128(item.as_bytes())].concat()
+}
+
+/// Account of an SS58 address or of the sr25519 key of a secret URI.
+fn account(address: &str) -> sc_cli::Result<AccountId> {
+    if let Ok(account) = AccountId::from_ss58check(address) {
+        return Ok(account);
+    }
+    Ok(MultiSigner::from(public(address)?).into_account())
+}
+
+/// sr25519 key of an SS58 address or of a secret URI.
+fn public(address: &str) -> sc_cli::Result<sr25519::Public> {
+    if let Ok(public) = sr25519::Public::from_ss58check(address) {
+        return Ok(public);
+    }
+    Ok(sr25519::Pair::from_string(address, None)
+        .map_err(|e| format!("Invalid key {}: {:?}", address, e))?
+        .public())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", HexDisplay::from(&bytes))
+}
+
+fn to_json(storage: &StorageMap) -> serde_json::Map<String, serde_json::Value> {
+    storage
+        .iter()
+        .map(|(key, value)| (hex(key), hex(value).into()))
+        .collect()
+}
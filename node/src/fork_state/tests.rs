@@ -0,0 +1,110 @@
+use super::*;
+use sp_core::crypto::AccountId32;
+use structopt::StructOpt;
+
+fn command(args: &[&str]) -> ForkStateCommand {
+    ForkStateCommand::from_iter(std::iter::once("fork-state").chain(args.iter().cloned()))
+}
+
+fn alice() -> AccountId {
+    account("//Alice").unwrap()
+}
+
+fn bob() -> AccountId {
+    account("//Bob").unwrap()
+}
+
+/// Storage of a forked block, with a proposal and a vote of the technical committee.
+fn forked_block() -> StorageMap {
+    let committee = twox_128(b"Instance1Collective");
+    let mut top = StorageMap::new();
+    top.insert(storage_key("System", "Number"), 42u32.encode());
+    top.insert(storage_key("System", "ParentHash"), [1u8; 32].encode());
+    top.insert(
+        [storage_key("ParachainUpgrade", "ValidationData"), vec![1]].concat(),
+        vec![1],
+    );
+    top.insert(
+        storage_key("Sudo", "Key"),
+        AccountId32::new([1; 32]).encode(),
+    );
+    top.insert(
+        storage_key("Instance1Collective", "Members"),
+        vec![AccountId32::new([1; 32])].encode(),
+    );
+    top.insert([&committee[..], &b"proposal"[..]].concat(), vec![1]);
+    top.insert([&committee[..], &b"vote"[..]].concat(), vec![1]);
+    top.insert(storage_key("DexPriceFeed", "UpdateFee"), vec![1]);
+    top
+}
+
+#[test]
+fn governing_keys_are_replaced() {
+    let top = fork_storage(&command(&[]), forked_block()).unwrap();
+
+    assert_eq!(
+        top.get(&storage_key("Sudo", "Key")),
+        Some(&alice().encode())
+    );
+    let mut members = vec![alice(), bob(), account("//Charlie").unwrap()];
+    members.sort();
+    assert_eq!(
+        top.get(&storage_key("Instance1Collective", "Members")),
+        Some(&members.encode())
+    );
+    assert_eq!(
+        top.get(&storage_key("DexPriceFeed", "PriceSigner")),
+        Some(&public("//Alice").unwrap().encode())
+    );
+}
+
+#[test]
+fn keys_are_read_from_the_options() {
+    let cmd = command(&[
+        "--sudo",
+        &bob().to_ss58check(),
+        "--technical-committee",
+        "//Bob,//Bob",
+        "--price-signer",
+        "//Bob",
+    ]);
+    let top = fork_storage(&cmd, forked_block()).unwrap();
+
+    assert_eq!(top.get(&storage_key("Sudo", "Key")), Some(&bob().encode()));
+    assert_eq!(
+        top.get(&storage_key("Instance1Collective", "Members")),
+        Some(&vec![bob()].encode())
+    );
+    assert_eq!(
+        top.get(&storage_key("DexPriceFeed", "PriceSigner")),
+        Some(&public("//Bob").unwrap().encode())
+    );
+    assert!(fork_storage(&command(&["--price-signer", "not a key"]), forked_block()).is_err());
+}
+
+#[test]
+fn position_and_proposals_are_reset() {
+    let top = fork_storage(&command(&[]), forked_block()).unwrap();
+    let committee = twox_128(b"Instance1Collective");
+
+    assert_eq!(top.get(&storage_key("System", "Number")), None);
+    assert_eq!(
+        top.get(&storage_key("System", "ParentHash")),
+        Some(&HASH69.encode())
+    );
+    assert!(!top
+        .keys()
+        .any(|key| key.starts_with(&twox_128(b"ParachainUpgrade"))));
+    // Only the new members are left of the technical committee.
+    assert_eq!(
+        top.keys()
+            .filter(|key| key.starts_with(&committee))
+            .collect::<Vec<_>>(),
+        vec![&storage_key("Instance1Collective", "Members")]
+    );
+    // The rest of the state is kept.
+    assert_eq!(
+        top.get(&storage_key("DexPriceFeed", "UpdateFee")),
+        Some(&vec![1])
+    );
+}
@@ -0,0 +1,144 @@
+//! Structured JSON log output, for log pipelines.
+//!
+//! The parachain and the embedded relay chain node log through the same logger. Every task of
+//! a chain runs with the chain set in a thread local while it is polled, see [`tag_tasks`], so
+//! each line is attributed to the chain whose task logged it. Lines logged outside of the
+//! tasks, while starting, have a `null` chain unless they come from the informant, whose prefix
+//! tells the chain, see [`informant_format`].
+
+use crate::cli::LogFormat;
+use ansi_term::Color;
+use futures::Future;
+use sc_informant::OutputFormat;
+use sc_service::{config::TaskType, TaskExecutor};
+use std::{
+    cell::Cell,
+    io::Write,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Target of the DEX index logs.
+pub const DEX_INDEX_TARGET: &str = "subdex-index";
+/// Target of the DEX metrics logs.
+pub const DEX_METRICS_TARGET: &str = "subdex-metrics";
+/// Target of the collator status logs.
+pub const COLLATOR_STATUS_TARGET: &str = "subdex-status";
+
+const PARACHAIN: &str = "parachain";
+const RELAY_CHAIN: &str = "relaychain";
+
+thread_local! {
+    /// Chain of the task being polled on this thread.
+    static CHAIN: Cell<Option<&'static str>> = Cell::new(None);
+}
+
+/// Run the tasks of `executor` as tasks of the relay chain if `relay_chain`, or of the
+/// parachain, so that the lines they log are attributed to it.
+pub fn tag_tasks(executor: TaskExecutor, relay_chain: bool) -> TaskExecutor {
+    let chain = if relay_chain { RELAY_CHAIN } else { PARACHAIN };
+    (move |future: Pin<Box<dyn Future<Output = ()> + Send>>, task_type: TaskType| {
+        executor.spawn(Box::pin(Tagged { chain, future }), task_type)
+    })
+    .into()
+}
+
+/// Future polled with its chain set in [`CHAIN`].
+struct Tagged {
+    chain: &'static str,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl Future for Tagged {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let previous = CHAIN.with(|chain| chain.replace(Some(self.chain)));
+        let poll = self.future.as_mut().poll(cx);
+        CHAIN.with(|chain| chain.set(previous));
+        poll
+    }
+}
+
+/// Informant output of the parachain, or of the relay chain if `relay_chain`.
+pub fn informant_format(format: LogFormat, relay_chain: bool) -> OutputFormat {
+    let (name, color) = if relay_chain {
+        ("Relaychain", Color::Blue)
+    } else {
+        ("Parachain", Color::Yellow)
+    };
+    match format {
+        LogFormat::Text => OutputFormat {
+            enable_color: true,
+            prefix: format!("[{}] ", color.bold().paint(name)),
+        },
+        LogFormat::Json => OutputFormat {
+            enable_color: false,
+            prefix: format!("[{}] ", name),
+        },
+    }
+}
+
+/// Register a logger writing one JSON object per line to stderr, with the `time`, `level`,
+/// `target`, `chain` and `message` of the record.
+///
+/// `pattern` is parsed as the `--log` filters, after the ones of `RUST_LOG`.
+pub fn init_json_logger(pattern: &str) {
+    let mut builder = env_logger::Builder::new();
+    // Same defaults as the Substrate logger.
+    builder.filter(Some("ws"), log::LevelFilter::Off);
+    builder.filter(Some("yamux"), log::LevelFilter::Off);
+    builder.filter(Some("hyper"), log::LevelFilter::Warn);
+    builder.filter(Some("cranelift_wasm"), log::LevelFilter::Warn);
+    builder.filter(None, log::LevelFilter::Info);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    builder.parse_filters(pattern);
+
+    builder.format(|buf, record| {
+        let message = strip_ansi(&record.args().to_string());
+        let (prefix_chain, message) = match split_chain(&message) {
+            Some((chain, message)) => (Some(chain), message),
+            None => (None, message.as_str()),
+        };
+        let chain = CHAIN.with(Cell::get).or(prefix_chain);
+        let line = serde_json::json!({
+            "time": buf.timestamp_millis().to_string(),
+            "level": record.level().to_string(),
+            "target": record.target(),
+            "chain": chain,
+            "message": message,
+        });
+        writeln!(buf, "{}", line)
+    });
+
+    // Fails when a logger is registered already, which then keeps logging.
+    let _ = builder.try_init();
+}
+
+/// Chain of an informant line, and the line without its prefix.
+fn split_chain(message: &str) -> Option<(&'static str, &str)> {
+    if let Some(message) = message.strip_prefix("[Parachain] ") {
+        Some((PARACHAIN, message))
+    } else if let Some(message) = message.strip_prefix("[Relaychain] ") {
+        Some((RELAY_CHAIN, message))
+    } else {
+        None
+    }
+}
+
+/// `message` without its ANSI escape sequences.
+fn strip_ansi(message: &str) -> String {
+    let mut stripped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip up to the final byte of the sequence.
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
@@ -9,7 +9,16 @@ mod chain_spec;
 #[macro_use]
 mod service;
 mod cli;
+mod collator_status;
 mod command;
+mod dex_index;
+mod export_dex;
+mod fork_state;
+mod logging;
+mod metrics;
+mod rpc;
+mod runtime_events;
+mod try_upgrade;
 
 fn main() -> sc_cli::Result<()> {
     command::run()
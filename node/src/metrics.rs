@@ -0,0 +1,272 @@
+//! Prometheus metrics of the DEX activity and of the messages exchanged with other chains.
+//!
+//! Metrics are derived from every finalized block, once, so blocks of forks that are later
+//! abandoned are never counted:
+//! - swaps from the `DexPallet` events, which every entry point of the DEX trades through,
+//! - liquidity changes from the `DexRouter` events,
+//! - pool reserves from the `pallet_subdex` storage,
+//! - failed cross-chain operations from the `XcmpRouter` events,
+//! - XCMP, downward and upward messages from the message traces, which nodes only keep with
+//!   offchain indexing enabled. The traces of a block are written when its child is imported,
+//!   so they are counted with the child, as those of its parent.
+//!
+//! Events are decoded with the metadata of the runtime of their block. Blocks whose events
+//! cannot be decoded are skipped with a warning.
+
+use crate::{
+    dex_index::{pool_reserves, swap},
+    logging::DEX_METRICS_TARGET,
+    runtime_events::{DecodedEvent, EventDecoder},
+};
+use codec::Decode;
+use futures::StreamExt;
+use log::warn;
+use pallet_subdex_message_trace::{trace_key, Channel, Direction, MessageTrace, Outcome};
+use parachain_runtime::{opaque::Block, AccountId, BlockNumber, Hash};
+use sc_client_api::{Backend, BlockchainEvents};
+use sc_service::SpawnTaskHandle;
+use sp_api::{Core, Metadata, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
+use sp_core::offchain::{OffchainStorage, STORAGE_PREFIX};
+use sp_runtime::{generic::BlockId, traits::Header as _};
+use std::sync::Arc;
+use substrate_prometheus_endpoint::{
+    register, CounterVec, GaugeVec, Opts, PrometheusError, Registry, F64, U64,
+};
+
+/// DEX and message metrics of the collator.
+#[derive(Clone)]
+pub struct DexMetrics {
+    swaps: CounterVec<U64>,
+    swap_volume: CounterVec<F64>,
+    pool_reserves: GaugeVec<F64>,
+    liquidity_events: CounterVec<U64>,
+    messages: CounterVec<U64>,
+    xcmp_failures: CounterVec<U64>,
+}
+
+impl DexMetrics {
+    pub fn register(registry: &Registry) -> Result<Self, PrometheusError> {
+        Ok(DexMetrics {
+            swaps: register(
+                CounterVec::new(
+                    Opts::new("subdex_swaps_total", "Swaps executed in DexPallet"),
+                    &["asset_in", "asset_out"],
+                )?,
+                registry,
+            )?,
+            swap_volume: register(
+                CounterVec::new(
+                    Opts::new(
+                        "subdex_swap_volume_total",
+                        "Amount of asset_in swapped in DexPallet",
+                    ),
+                    &["asset_in", "asset_out"],
+                )?,
+                registry,
+            )?,
+            pool_reserves: register(
+                GaugeVec::new(
+                    Opts::new("subdex_pool_reserve", "Reserve of an asset in a DEX pool"),
+                    &["first_asset", "second_asset", "asset"],
+                )?,
+                registry,
+            )?,
+            liquidity_events: register(
+                CounterVec::new(
+                    Opts::new(
+                        "subdex_liquidity_events_total",
+                        "Liquidity added to or removed from DEX pools through DexRouter",
+                    ),
+                    &["first_asset", "second_asset", "kind"],
+                )?,
+                registry,
+            )?,
+            messages: register(
+                CounterVec::new(
+                    Opts::new(
+                        "subdex_messages_total",
+                        "XCMP, downward and upward messages handled, by outcome",
+                    ),
+                    &["direction", "channel", "outcome"],
+                )?,
+                registry,
+            )?,
+            xcmp_failures: register(
+                CounterVec::new(
+                    Opts::new(
+                        "subdex_xcmp_failures_total",
+                        "Cross-chain operations that failed, by XcmpRouter event",
+                    ),
+                    &["event"],
+                )?,
+                registry,
+            )?,
+        })
+    }
+
+    fn observe_events(&self, events: Vec<DecodedEvent>) {
+        /// `XcmpRouter` events of failed cross-chain operations.
+        const XCMP_FAILURES: &[&str] = &[
+            "UnexpectedMessage",
+            "MessageRejected",
+            "SwapAndReturnRefunded",
+            "SwapAndReturnFailed",
+            "RemoteLiquidityFailed",
+            "TransferReturned",
+            "TransferTrapped",
+        ];
+
+        for event in events {
+            if let Some((_, asset_in, amount_in, asset_out, _)) = swap(&event) {
+                let labels = [asset_in.as_str(), asset_out.as_str()];
+                self.swaps.with_label_values(&labels).inc();
+                self.swap_volume
+                    .with_label_values(&labels)
+                    .inc_by(amount_in.parse().unwrap_or_default());
+                continue;
+            }
+            let kind = if event.is("DexRouter", "LiquidityAdded") {
+                "added"
+            } else if event.is("DexRouter", "LiquidityRemoved") {
+                "removed"
+            } else {
+                if event.pallet == "XcmpRouter" && XCMP_FAILURES.contains(&event.name.as_str()) {
+                    self.xcmp_failures
+                        .with_label_values(&[event.name.as_str()])
+                        .inc();
+                }
+                continue;
+            };
+            if let [first, second] = &event.args_of("Asset")[..] {
+                let (first, second) = (
+                    first.as_str().unwrap_or_default(),
+                    second.as_str().unwrap_or_default(),
+                );
+                self.liquidity_events
+                    .with_label_values(&[first, second, kind])
+                    .inc();
+            }
+        }
+    }
+
+    fn observe_traces(&self, traces: Vec<MessageTrace<AccountId>>) {
+        for trace in traces {
+            let direction = match trace.direction {
+                Direction::Inbound => "inbound",
+                Direction::Outbound => "outbound",
+            };
+            let channel = match trace.channel {
+                Channel::Xcmp(_) => "xcmp",
+                Channel::Downward => "downward",
+                Channel::Upward => "upward",
+            };
+            let outcome = match trace.outcome {
+                Outcome::Queued => "queued",
+                Outcome::Processed => "processed",
+                Outcome::Rejected => "rejected",
+                Outcome::Sent => "sent",
+                Outcome::NotSent => "not_sent",
+            };
+            self.messages
+                .with_label_values(&[direction, channel, outcome])
+                .inc();
+        }
+    }
+}
+
+/// Update `metrics` with every block finalized in `client` from now on.
+pub fn spawn_metrics<B, C>(
+    metrics: DexMetrics,
+    backend: Arc<B>,
+    client: Arc<C>,
+    spawn_handle: SpawnTaskHandle,
+) where
+    B: Backend<Block> + 'static,
+    C: BlockchainEvents<Block> + ProvideRuntimeApi<Block> + Send + Sync + 'static,
+    C::Api: Core<Block> + Metadata<Block>,
+{
+    let mut finalized = client.finality_notification_stream();
+    let decoder = EventDecoder::new(client);
+
+    spawn_handle.spawn("dex-metrics", async move {
+        let mut last = backend.blockchain().info().finalized_number;
+        while let Some(notification) = finalized.next().await {
+            // Finality may skip blocks, all of which are in the chain once finalized.
+            let head = *notification.header.number();
+            for number in last + 1..=head {
+                match backend.blockchain().hash(number).ok().flatten() {
+                    Some(hash) => observe_block(&metrics, &decoder, &*backend, number, hash),
+                    None => warn!(
+                        target: DEX_METRICS_TARGET,
+                        "Hash of finalized block {} not available", number
+                    ),
+                }
+            }
+            last = last.max(head);
+        }
+    });
+}
+
+fn observe_block<B, C>(
+    metrics: &DexMetrics,
+    decoder: &EventDecoder<C>,
+    backend: &B,
+    number: BlockNumber,
+    hash: Hash,
+) where
+    B: Backend<Block>,
+    C: ProvideRuntimeApi<Block>,
+    C::Api: Core<Block> + Metadata<Block>,
+{
+    match decoder.block_events(backend, hash) {
+        Ok(events) => metrics.observe_events(events),
+        Err(e) => warn!(
+            target: DEX_METRICS_TARGET,
+            "Skipping the events of block {}: {}", number, e
+        ),
+    }
+
+    match backend.state_at(BlockId::Hash(hash)) {
+        Ok(state) => match pool_reserves(&state) {
+            Ok(reserves) => {
+                for (first, second, first_reserve, second_reserve) in reserves {
+                    metrics
+                        .pool_reserves
+                        .with_label_values(&[first.as_str(), second.as_str(), first.as_str()])
+                        .set(first_reserve as f64);
+                    metrics
+                        .pool_reserves
+                        .with_label_values(&[first.as_str(), second.as_str(), second.as_str()])
+                        .set(second_reserve as f64);
+                }
+            }
+            Err(e) => warn!(
+                target: DEX_METRICS_TARGET,
+                "Unable to read pools of block {}: {}", number, e
+            ),
+        },
+        Err(e) => warn!(
+            target: DEX_METRICS_TARGET,
+            "State of block {} not available: {:?}", number, e
+        ),
+    }
+
+    let parent_hash = match backend.blockchain().header(BlockId::Hash(hash)) {
+        Ok(Some(header)) => *header.parent_hash(),
+        _ => {
+            warn!(
+                target: DEX_METRICS_TARGET,
+                "Header of block {} not available", number
+            );
+            return;
+        }
+    };
+    let traces = backend
+        .offchain_storage()
+        .and_then(|storage| storage.get(STORAGE_PREFIX, &trace_key(number - 1, parent_hash)))
+        .and_then(|encoded| Vec::<MessageTrace<AccountId>>::decode(&mut &encoded[..]).ok());
+    if let Some(traces) = traces {
+        metrics.observe_traces(traces);
+    }
+}
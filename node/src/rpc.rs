@@ -0,0 +1,67 @@
+//! RPC extensions of the Subdex collator.
+
+use crate::{
+    collator_status::{CollatorStatus, CollatorStatusApi, CollatorStatusRpc},
+    dex_index::{DexIndex, DexIndexApi, DexIndexRpc},
+};
+use codec::Decode;
+use cumulus_primitives::DownwardMessage;
+use cumulus_upward_message::RococoUpwardMessage;
+use pallet_subdex_message_trace::Channel;
+use pallet_subdex_message_trace_rpc::{MessageTraceApi, MessageTraces, PayloadDecoder};
+use pallet_subdex_xcmp_router::VersionedXcmpMessageOf;
+use parachain_runtime::{opaque::Block, AccountId, Runtime};
+use sp_blockchain::HeaderBackend;
+use sp_core::offchain::OffchainStorage;
+use std::sync::Arc;
+
+/// RPC extensions served next to the Substrate ones.
+pub type RpcExtension = jsonrpc_core::IoHandler<sc_rpc::Metadata>;
+
+/// Decodes message payloads with the types of the Subdex runtime.
+pub struct RuntimePayloadDecoder;
+
+impl PayloadDecoder for RuntimePayloadDecoder {
+    fn decode(channel: &Channel, mut payload: &[u8]) -> Option<String> {
+        match channel {
+            Channel::Xcmp(_) => VersionedXcmpMessageOf::<Runtime>::decode(&mut payload)
+                .ok()
+                .map(|message| format!("{:?}", message)),
+            Channel::Downward => DownwardMessage::decode(&mut payload)
+                .ok()
+                .map(|message| format!("{:?}", message)),
+            Channel::Upward => RococoUpwardMessage::decode(&mut payload)
+                .ok()
+                .map(|message| format!("{:?}", message)),
+        }
+    }
+}
+
+/// Build the RPC extensions of a full node.
+///
+/// Message traces are only served when the node has an offchain database, the DEX index when
+/// it is enabled.
+pub fn create_full<C, S>(
+    client: Arc<C>,
+    offchain_storage: Option<S>,
+    dex_index: Option<Arc<DexIndex>>,
+    collator_status: Arc<CollatorStatus>,
+) -> RpcExtension
+where
+    C: HeaderBackend<Block> + Send + Sync + 'static,
+    S: OffchainStorage + 'static,
+{
+    let mut io = jsonrpc_core::IoHandler::default();
+    io.extend_with(CollatorStatusApi::to_delegate(CollatorStatusRpc::new(
+        collator_status,
+    )));
+    if let Some(storage) = offchain_storage {
+        io.extend_with(MessageTraceApi::<AccountId, _>::to_delegate(
+            MessageTraces::<_, _, RuntimePayloadDecoder, Block>::new(client, storage),
+        ));
+    }
+    if let Some(index) = dex_index {
+        io.extend_with(DexIndexApi::to_delegate(DexIndexRpc::new(index)));
+    }
+    io
+}
@@ -0,0 +1,445 @@
+//! Decoding of block events with the metadata of the runtime that deposited them.
+//!
+//! The events of a block are decoded with the metadata of the runtime of its parent, which
+//! executed it, so blocks of older runtimes decode as well as the latest ones. Metadata is
+//! fetched once per runtime version.
+//!
+//! Metadata only names the types of the event arguments, so arguments are decoded by type
+//! name, see [`decode_named`]. An argument of a type this build does not know makes the events
+//! of its block undecodable, which callers report instead of guessing.
+
+use codec::{Compact, Decode};
+use frame_metadata::{DecodeDifferent, RuntimeMetadata, RuntimeMetadataPrefixed};
+use parachain_runtime::{opaque::Block, AccountId, AssetId, Hash};
+use parking_lot::Mutex;
+use sc_client_api::Backend;
+use serde_json::{json, Value};
+use sp_api::{Core, Metadata, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
+use sp_core::{hashing::twox_128, hexdisplay::HexDisplay};
+use sp_runtime::{generic::BlockId, traits::Header as _};
+use sp_state_machine::Backend as StateBackend;
+use std::{collections::HashMap, sync::Arc};
+
+/// Variants of the enums without fields found in events, by type name.
+const FIELDLESS_ENUMS: &[(&str, &[&str])] = &[
+    ("DispatchClass", &["Normal", "Operational", "Mandatory"]),
+    ("Pays", &["Yes", "No"]),
+    ("BalanceStatus", &["Free", "Reserved"]),
+    ("Status", &["Free", "Reserved"]),
+    ("Fee", &["RelayWithdraw", "PriceFeedUpdate", "TradeRate"]),
+    ("TransferFailurePolicy", &["Return", "Trap"]),
+    ("ParaPermission", &["Denied", "MappedAssetsOnly", "Allowed"]),
+    (
+        "TransferError",
+        &[
+            "BelowMinimum",
+            "UnknownAsset",
+            "NotCredited",
+            "AssetNotAllowed",
+            "SupplyCapExceeded",
+            "NotRefunded",
+        ],
+    ),
+];
+
+/// An event deposited in a block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedEvent {
+    /// Index of the extrinsic that deposited the event, if any.
+    pub extrinsic_index: Option<u32>,
+    /// Pallet name, as in the metadata.
+    pub pallet: String,
+    pub name: String,
+    /// Type name, as in the metadata, and value of every argument.
+    pub args: Vec<(String, Value)>,
+}
+
+impl DecodedEvent {
+    /// Whether this is the event `name` of `pallet`.
+    pub fn is(&self, pallet: &str, name: &str) -> bool {
+        self.pallet == pallet && self.name == name
+    }
+
+    /// Values of the arguments whose type is named `ty`, e.g. `Balance` for `BalanceOf<T>`.
+    pub fn args_of(&self, ty: &str) -> Vec<&Value> {
+        self.args
+            .iter()
+            .filter(|(arg_ty, _)| type_name(arg_ty) == ty)
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    /// Arguments as a JSON array of `{"type", "value"}` objects.
+    pub fn args_json(&self) -> Value {
+        self.args
+            .iter()
+            .map(|(ty, value)| json!({ "type": ty, "value": value }))
+            .collect()
+    }
+}
+
+/// Events of a pallet, by index: (name, argument types).
+#[derive(Clone, Debug, Default)]
+struct PalletEvents {
+    name: String,
+    events: Vec<(String, Vec<String>)>,
+}
+
+/// Decodes the events of blocks, whichever runtime deposited them.
+pub struct EventDecoder<C> {
+    client: Arc<C>,
+    /// Events of every pallet, by spec version, in the order of the outer event enum.
+    runtimes: Mutex<HashMap<u32, Arc<Vec<PalletEvents>>>>,
+}
+
+impl<C> EventDecoder<C>
+where
+    C: ProvideRuntimeApi<Block>,
+    C::Api: Core<Block> + Metadata<Block>,
+{
+    pub fn new(client: Arc<C>) -> Self {
+        EventDecoder {
+            client,
+            runtimes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Events deposited in block `hash`.
+    pub fn block_events<B: Backend<Block>>(
+        &self,
+        backend: &B,
+        hash: Hash,
+    ) -> Result<Vec<DecodedEvent>, String> {
+        let header = backend
+            .blockchain()
+            .header(BlockId::Hash(hash))
+            .ok()
+            .flatten()
+            .ok_or_else(|| format!("Header of block {} not available", hash))?;
+        let state = backend
+            .state_at(BlockId::Hash(hash))
+            .map_err(|e| format!("State of block {} not available: {:?}", hash, e))?;
+        let encoded = match state
+            .storage(&[twox_128(b"System"), twox_128(b"Events")].concat())
+            .map_err(|e| format!("Unable to read events of block {}: {:?}", hash, e))?
+        {
+            Some(encoded) => encoded,
+            None => return Ok(Vec::new()),
+        };
+        // The genesis block was not executed by any runtime, and has no events anyway.
+        let runtime_at = if header.number() == &0 {
+            hash
+        } else {
+            *header.parent_hash()
+        };
+        let pallets = self.runtime_events(runtime_at)?;
+        decode_events(&pallets, &encoded)
+            .map_err(|e| format!("Unable to decode events of block {}: {}", hash, e))
+    }
+
+    fn runtime_events(&self, at: Hash) -> Result<Arc<Vec<PalletEvents>>, String> {
+        let at = BlockId::Hash(at);
+        let api = self.client.runtime_api();
+        let version = api
+            .version(&at)
+            .map_err(|e| format!("Unable to read the runtime version at {}: {:?}", at, e))?
+            .spec_version;
+        if let Some(pallets) = self.runtimes.lock().get(&version) {
+            return Ok(pallets.clone());
+        }
+        let metadata = api
+            .metadata(&at)
+            .map_err(|e| format!("Unable to read the runtime metadata at {}: {:?}", at, e))?;
+        let pallets = Arc::new(pallet_events(&metadata)?);
+        self.runtimes.lock().insert(version, pallets.clone());
+        Ok(pallets)
+    }
+}
+
+fn decoded<B, O>(value: DecodeDifferent<B, O>) -> Result<O, String> {
+    match value {
+        DecodeDifferent::Decoded(value) => Ok(value),
+        DecodeDifferent::Encode(_) => Err("Metadata was not decoded".into()),
+    }
+}
+
+/// Events of the pallets that have some, in the order of the outer event enum.
+fn pallet_events(metadata: &[u8]) -> Result<Vec<PalletEvents>, String> {
+    let metadata = RuntimeMetadataPrefixed::decode(&mut &metadata[..])
+        .map_err(|e| format!("Invalid metadata: {:?}", e))?;
+    let modules = match metadata.1 {
+        RuntimeMetadata::V11(metadata) => decoded(metadata.modules)?,
+        _ => return Err("Unsupported metadata version".into()),
+    };
+    let mut pallets = Vec::new();
+    for module in modules {
+        let events = match module.event {
+            Some(events) => decoded(events)?,
+            None => continue,
+        };
+        let events = events
+            .into_iter()
+            .map(|event| Ok((decoded(event.name)?, decoded(event.arguments)?)))
+            .collect::<Result<_, String>>()?;
+        pallets.push(PalletEvents {
+            name: decoded(module.name)?,
+            events,
+        });
+    }
+    Ok(pallets)
+}
+
+/// Decode the `System::Events` storage value `encoded`.
+fn decode_events(pallets: &[PalletEvents], encoded: &[u8]) -> Result<Vec<DecodedEvent>, String> {
+    let input = &mut &encoded[..];
+    let count = Compact::<u32>::decode(input).map_err(|e| e.to_string())?.0;
+    let mut events = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let extrinsic_index = match u8::decode(input).map_err(|e| e.to_string())? {
+            0 => Some(u32::decode(input).map_err(|e| e.to_string())?),
+            _ => None,
+        };
+        let (pallet, event) = (
+            u8::decode(input).map_err(|e| e.to_string())?,
+            u8::decode(input).map_err(|e| e.to_string())?,
+        );
+        let pallet = pallets
+            .get(pallet as usize)
+            .ok_or_else(|| format!("Unknown pallet {}", pallet))?;
+        let (name, types) = pallet
+            .events
+            .get(event as usize)
+            .ok_or_else(|| format!("Unknown event {} of {}", event, pallet.name))?;
+        let args = types
+            .iter()
+            .map(|ty| Ok((ty.clone(), decode_arg(ty, input)?)))
+            .collect::<Result<_, String>>()
+            .map_err(|e| format!("{}::{}: {}", pallet.name, name, e))?;
+        Vec::<Hash>::decode(input).map_err(|e| e.to_string())?;
+        events.push(DecodedEvent {
+            extrinsic_index,
+            pallet: pallet.name.clone(),
+            name: name.clone(),
+            args,
+        });
+    }
+    Ok(events)
+}
+
+/// Type named in the metadata, with its generic arguments.
+#[derive(Clone, Debug, PartialEq)]
+enum Type {
+    /// Type name without path, e.g. `AccountId` for `<T as Trait>::AccountId`.
+    Named(String, Vec<Type>),
+    Tuple(Vec<Type>),
+    Array(Box<Type>, usize),
+}
+
+/// Name of `ty` without its path nor generic arguments, aliases resolved.
+fn type_name(ty: &str) -> String {
+    match parse_type(ty) {
+        Some(Type::Named(name, _)) => match name.as_str() {
+            "BalanceOf" => "Balance".into(),
+            "AssetOf" => "Asset".into(),
+            _ => name,
+        },
+        _ => ty.to_string(),
+    }
+}
+
+fn parse_type(ty: &str) -> Option<Type> {
+    let ty: String = ty.chars().filter(|c| !c.is_whitespace()).collect();
+    let (parsed, rest) = parse(&ty)?;
+    if rest.is_empty() {
+        Some(parsed)
+    } else {
+        None
+    }
+}
+
+/// Parse the type at the start of `s`, and return it with what follows it.
+fn parse(s: &str) -> Option<(Type, &str)> {
+    if let Some(rest) = s.strip_prefix('(') {
+        let (items, rest) = parse_list(rest, ')')?;
+        return Some((Type::Tuple(items), rest));
+    }
+    if let Some(rest) = s.strip_prefix('[') {
+        let (item, rest) = parse(rest)?;
+        let rest = rest.strip_prefix(';')?;
+        let end = rest.find(']')?;
+        let len = rest[..end].parse().ok()?;
+        return Some((Type::Array(Box::new(item), len), &rest[end + 1..]));
+    }
+    // Qualified paths, e.g. `<T as Trait>::AccountId`, are named after their last segment.
+    let mut s = s;
+    if s.starts_with('<') {
+        let mut depth = 0;
+        let end = s.char_indices().find_map(|(i, c)| {
+            match c {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                Some(i)
+            } else {
+                None
+            }
+        })?;
+        s = s[end + 1..].strip_prefix("::")?;
+    }
+    let end = s
+        .find(|c| c == '<' || c == ',' || c == '>' || c == ')' || c == ';' || c == ']')
+        .unwrap_or_else(|| s.len());
+    let name = s[..end].rsplit("::").next()?.to_string();
+    let rest = &s[end..];
+    match rest.strip_prefix('<') {
+        Some(rest) => {
+            let (params, rest) = parse_list(rest, '>')?;
+            Some((Type::Named(name, params), rest))
+        }
+        None => Some((Type::Named(name, Vec::new()), rest)),
+    }
+}
+
+/// Parse the comma separated types up to `close`, and return them with what follows `close`.
+fn parse_list(mut s: &str, close: char) -> Option<(Vec<Type>, &str)> {
+    let mut items = Vec::new();
+    loop {
+        if let Some(rest) = s.strip_prefix(close) {
+            return Some((items, rest));
+        }
+        let (item, rest) = parse(s)?;
+        items.push(item);
+        s = rest.strip_prefix(',').unwrap_or(rest);
+    }
+}
+
+/// Decode an argument of the type named `ty` in the metadata.
+fn decode_arg(ty: &str, input: &mut &[u8]) -> Result<Value, String> {
+    let parsed = parse_type(ty).ok_or_else(|| format!("Invalid type {}", ty))?;
+    decode_type(&parsed, input).map_err(|e| format!("{} ({})", e, ty))
+}
+
+fn decode_type(ty: &Type, input: &mut &[u8]) -> Result<Value, String> {
+    match ty {
+        Type::Tuple(items) if items.is_empty() => Ok(Value::Null),
+        Type::Tuple(items) => items.iter().map(|item| decode_type(item, input)).collect(),
+        Type::Array(item, len) if **item == Type::Named("u8".into(), Vec::new()) => {
+            Ok(hex(&take(input, *len)?))
+        }
+        Type::Array(item, len) => (0..*len).map(|_| decode_type(item, input)).collect(),
+        Type::Named(name, params) => match (name.as_str(), &params[..]) {
+            ("Vec", [Type::Named(item, _)]) if item == "u8" => {
+                let len = Compact::<u32>::decode(input).map_err(|e| e.to_string())?.0;
+                Ok(hex(&take(input, len as usize)?))
+            }
+            ("Vec", [item]) => {
+                let len = Compact::<u32>::decode(input).map_err(|e| e.to_string())?.0;
+                (0..len).map(|_| decode_type(item, input)).collect()
+            }
+            ("Option", [item]) => match u8::decode(input).map_err(|e| e.to_string())? {
+                0 => Ok(Value::Null),
+                1 => decode_type(item, input),
+                tag => Err(format!("Invalid option {}", tag)),
+            },
+            ("Compact", [_]) => Ok(Value::from(
+                Compact::<u128>::decode(input)
+                    .map_err(|e| e.to_string())?
+                    .0
+                    .to_string(),
+            )),
+            ("Box", [item]) => decode_type(item, input),
+            _ => decode_named(name, input),
+        },
+    }
+}
+
+fn take(input: &mut &[u8], len: usize) -> Result<Vec<u8>, String> {
+    if input.len() < len {
+        return Err("Not enough data".into());
+    }
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+    Ok(taken.to_vec())
+}
+
+fn hex(bytes: &[u8]) -> Value {
+    Value::from(format!("0x{}", HexDisplay::from(&bytes)))
+}
+
+fn decode_as<T: Decode>(input: &mut &[u8]) -> Result<T, String> {
+    T::decode(input).map_err(|e| e.to_string())
+}
+
+/// Decode a value of the type `name`, among the types found in the events of Subdex.
+///
+/// Amounts are decoded to strings, as they do not fit in JSON numbers, and assets to their id,
+/// or `main` for the relay chain currency, as in the exported tables.
+fn decode_named(name: &str, input: &mut &[u8]) -> Result<Value, String> {
+    let value = match name {
+        "bool" => Value::from(decode_as::<bool>(input)?),
+        "u8" | "XcmpVersion" => Value::from(decode_as::<u8>(input)?),
+        "u16" => Value::from(decode_as::<u16>(input)?),
+        "u32"
+        | "BlockNumber"
+        | "RelayChainBlockNumber"
+        | "ParaId"
+        | "ProposalIndex"
+        | "MemberCount" => Value::from(decode_as::<u32>(input)?),
+        "u64" | "Moment" | "Weight" | "AssetId" | "OrderId" | "SubscriptionId" | "WithdrawalId"
+        | "MessageIndex" => Value::from(decode_as::<u64>(input)?),
+        "u128" | "Balance" | "BalanceOf" => Value::from(decode_as::<u128>(input)?.to_string()),
+        "AccountId" | "AccountId32" => Value::from(decode_as::<AccountId>(input)?.to_string()),
+        "Hash" | "H256" | "Public" => hex(&take(input, 32)?),
+        "Asset" | "AssetOf" => Value::from(match decode_as::<u8>(input)? {
+            0 => "main".to_string(),
+            1 => decode_as::<AssetId>(input)?.to_string(),
+            tag => return Err(format!("Invalid asset {}", tag)),
+        }),
+        "MessageOrigin" => match decode_as::<u8>(input)? {
+            0 => Value::from("relay"),
+            1 => json!({ "parachain": decode_as::<u32>(input)? }),
+            tag => return Err(format!("Invalid message origin {}", tag)),
+        },
+        "DispatchError" => decode_dispatch_error(input)?,
+        "DispatchResult" => match decode_as::<u8>(input)? {
+            0 => Value::from("ok"),
+            1 => json!({ "error": decode_dispatch_error(input)? }),
+            tag => return Err(format!("Invalid dispatch result {}", tag)),
+        },
+        "DispatchInfo" => json!({
+            "weight": decode_as::<u64>(input)?,
+            "class": decode_named("DispatchClass", input)?,
+            "paysFee": decode_named("Pays", input)?,
+        }),
+        _ => {
+            let variants = FIELDLESS_ENUMS
+                .iter()
+                .find(|(enum_name, _)| *enum_name == name)
+                .map(|(_, variants)| *variants)
+                .ok_or_else(|| format!("Unknown type {}", name))?;
+            let tag = decode_as::<u8>(input)?;
+            Value::from(
+                *variants
+                    .get(tag as usize)
+                    .ok_or_else(|| format!("Invalid {} {}", name, tag))?,
+            )
+        }
+    };
+    Ok(value)
+}
+
+fn decode_dispatch_error(input: &mut &[u8]) -> Result<Value, String> {
+    Ok(match decode_as::<u8>(input)? {
+        0 => Value::from("Other"),
+        1 => Value::from("CannotLookup"),
+        2 => Value::from("BadOrigin"),
+        3 => json!({
+            "module": decode_as::<u8>(input)?,
+            "error": decode_as::<u8>(input)?,
+        }),
+        tag => return Err(format!("Invalid dispatch error {}", tag)),
+    })
+}
@@ -1,14 +1,12 @@
 // Copyright 2020 Parity Technologies (UK) Ltd.
 
-use ansi_term::Color;
+use cumulus_collator::CollatorBuilder;
 use cumulus_network::DelayedBlockAnnounceValidator;
-use cumulus_service::{
-    prepare_node_config, start_collator, start_full_node, StartCollatorParams, StartFullNodeParams,
-};
+use cumulus_service::{prepare_node_config, start_full_node, StartFullNodeParams};
 use polkadot_primitives::v0::CollatorPair;
+use sc_client_api::Backend;
 use sc_executor::native_executor_instance;
 pub use sc_executor::NativeExecutor;
-use sc_informant::OutputFormat;
 use sc_service::{Configuration, PartialComponents, Role, TFullBackend, TFullClient, TaskManager};
 use sp_runtime::traits::BlakeTwo256;
 use sp_trie::PrefixedMemoryDB;
@@ -104,6 +102,9 @@ pub fn run_node(
     mut polkadot_config: polkadot_collator::Configuration,
     id: polkadot_primitives::v0::Id,
     validator: bool,
+    dex_index: Option<crate::dex_index::Config>,
+    health: Option<crate::collator_status::HealthConfig>,
+    log_format: crate::cli::LogFormat,
 ) -> sc_service::error::Result<(
     TaskManager,
     Arc<
@@ -120,14 +121,14 @@ pub fn run_node(
 
     let mut parachain_config = prepare_node_config(parachain_config);
 
-    parachain_config.informant_output_format = OutputFormat {
-        enable_color: true,
-        prefix: format!("[{}] ", Color::Yellow.bold().paint("Parachain")),
-    };
-    polkadot_config.informant_output_format = OutputFormat {
-        enable_color: true,
-        prefix: format!("[{}] ", Color::Blue.bold().paint("Relaychain")),
-    };
+    parachain_config.informant_output_format = crate::logging::informant_format(log_format, false);
+    polkadot_config.informant_output_format = crate::logging::informant_format(log_format, true);
+    if log_format == crate::cli::LogFormat::Json {
+        parachain_config.task_executor =
+            crate::logging::tag_tasks(parachain_config.task_executor.clone(), false);
+        polkadot_config.task_executor =
+            crate::logging::tag_tasks(polkadot_config.task_executor.clone(), true);
+    }
 
     let params = new_partial(&mut parachain_config)?;
     params
@@ -137,6 +138,17 @@ pub fn run_node(
 
     let client = params.client.clone();
     let backend = params.backend.clone();
+    let offchain_storage = backend.offchain_storage();
+    let dex_index = match dex_index {
+        Some(config) => {
+            let index = crate::dex_index::DexIndex::open(&config.path).map_err(|e| {
+                sc_service::Error::Other(format!("Unable to open the DEX index: {}", e))
+            })?;
+            Some((Arc::new(index), config.finalized_only))
+        }
+        None => None,
+    };
+    let collator_status = Arc::new(crate::collator_status::CollatorStatus::new(id, validator));
     let block_announce_validator = DelayedBlockAnnounceValidator::new();
     let block_announce_validator_builder = {
         let block_announce_validator = block_announce_validator.clone();
@@ -160,10 +172,31 @@ pub fn run_node(
             finality_proof_provider: None,
         })?;
 
+    // Signs the DEX price feed updates when the price signer key is in the keystore.
+    sc_service::build_offchain_workers(
+        &parachain_config,
+        backend.clone(),
+        task_manager.spawn_handle(),
+        client.clone(),
+        network.clone(),
+    );
+
     sc_service::spawn_tasks(sc_service::SpawnTasksParams {
         on_demand: None,
         remote_blockchain: None,
-        rpc_extensions_builder: Box::new(|_| ()),
+        rpc_extensions_builder: {
+            let dex_index = dex_index.as_ref().map(|(index, _)| index.clone());
+            let collator_status = collator_status.clone();
+            let client = client.clone();
+            Box::new(move |_| {
+                crate::rpc::create_full(
+                    client.clone(),
+                    offchain_storage.clone(),
+                    dex_index.clone(),
+                    collator_status.clone(),
+                )
+            })
+        },
         client: client.clone(),
         transaction_pool: transaction_pool.clone(),
         task_manager: &mut task_manager,
@@ -176,6 +209,35 @@ pub fn run_node(
         system_rpc_tx,
     })?;
 
+    if let Some(registry) = prometheus_registry.as_ref() {
+        let metrics = crate::metrics::DexMetrics::register(registry).map_err(|e| {
+            sc_service::Error::Other(format!("Unable to register the DEX metrics: {}", e))
+        })?;
+        crate::metrics::spawn_metrics(
+            metrics,
+            params.backend.clone(),
+            client.clone(),
+            task_manager.spawn_handle(),
+        );
+    }
+
+    if let Some((index, finalized_only)) = dex_index {
+        crate::dex_index::spawn_indexer(
+            index,
+            finalized_only,
+            params.backend.clone(),
+            client.clone(),
+            task_manager.spawn_handle(),
+        );
+    }
+
+    crate::collator_status::spawn_status_tasks(
+        collator_status.clone(),
+        client.clone(),
+        health,
+        task_manager.spawn_handle(),
+    );
+
     let announce_block = Arc::new(move |hash, data| network.announce_block(hash, data));
 
     if validator {
@@ -185,21 +247,25 @@ pub fn run_node(
             prometheus_registry.as_ref(),
         );
 
-        let params = StartCollatorParams {
-            para_id: id,
-            block_import: client.clone(),
+        // Started as `cumulus_service::start_collator` does, keeping the relay chain client.
+        let builder = CollatorBuilder::new(
             proposer_factory,
-            inherent_data_providers: params.inherent_data_providers,
-            block_status: client.clone(),
+            params.inherent_data_providers,
+            client.clone(),
+            client.clone(),
+            id,
+            client.clone(),
             announce_block,
-            client: client.clone(),
             block_announce_validator,
-            task_manager: &mut task_manager,
-            polkadot_config,
-            collator_key,
-        };
-
-        start_collator(params)?;
+        );
+        let builder = crate::collator_status::KeepRelayChainClient::new(builder, collator_status);
+        let (polkadot_future, polkadot_task_manager) =
+            polkadot_collator::start_collator(builder, id, collator_key, polkadot_config)
+                .map_err(|e| sc_service::Error::Other(e.to_string()))?;
+        task_manager
+            .spawn_essential_handle()
+            .spawn("polkadot", polkadot_future);
+        task_manager.add_child(polkadot_task_manager);
     } else {
         let params = StartFullNodeParams {
             client: client.clone(),
@@ -0,0 +1,198 @@
+//! Dry run of a runtime upgrade against the state in the local database.
+//!
+//! The candidate wasm is executed on top of the state of a block, with `:code` replaced, and
+//! runs the upgrade hooks of `Executive` through `TryUpgradeApi`, then the storage migrations
+//! they started to the end. Nothing is written to the database. When the wasm was built with
+//! the `try-runtime` feature, the storage migrations check the storage before and after they
+//! run, and a failed check fails the upgrade. Otherwise a warning says the checks were skipped.
+
+use crate::{cli::TryUpgradeCommand, service::Executor};
+use codec::Decode;
+use frame_metadata::{DecodeDifferent, RuntimeMetadata, RuntimeMetadataPrefixed};
+use parachain_runtime::{opaque::Block, Hash, Weight};
+use sc_client_api::Backend;
+use sc_executor::NativeExecutor;
+use sc_service::{Configuration, SpawnTaskHandle};
+use sp_blockchain::HeaderBackend;
+use sp_core::{
+    hashing::{blake2_256, twox_128},
+    hexdisplay::HexDisplay,
+    storage::well_known_keys,
+    traits::{RuntimeCode, WrappedRuntimeCode},
+};
+use sp_runtime::{
+    generic::BlockId,
+    traits::{BlakeTwo256, NumberFor},
+};
+use sp_state_machine::{ExecutionStrategy, Extensions, OverlayedChanges, StateMachine};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// Length of the hashed storage prefix of a storage item.
+const ITEM_PREFIX_LEN: usize = 32;
+
+/// Upgrade the state of the requested block to `cmd.wasm` and report what the upgrade did.
+pub fn run<B: Backend<Block>>(
+    cmd: &TryUpgradeCommand,
+    config: &Configuration,
+    backend: Arc<B>,
+    spawn_handle: SpawnTaskHandle,
+) -> sc_cli::Result<()> {
+    let code = std::fs::read(&cmd.wasm)?;
+    let at = block_hash(cmd.at.as_deref(), &*backend)?;
+    let state = backend
+        .state_at(BlockId::Hash(at))
+        .map_err(|e| format!("State of block {} not available: {:?}", at, e))?;
+
+    let executor = NativeExecutor::<Executor>::new(
+        config.wasm_method,
+        config.default_heap_pages,
+        config.max_runtime_instances,
+    );
+    let code_fetcher = WrappedRuntimeCode(code.as_slice().into());
+    let runtime_code = RuntimeCode {
+        code_fetcher: &code_fetcher,
+        hash: blake2_256(&code).to_vec(),
+        heap_pages: config.default_heap_pages,
+    };
+    let call = |overlay: &mut OverlayedChanges, method: &str| {
+        StateMachine::<_, BlakeTwo256, NumberFor<Block>, _>::new(
+            &state,
+            None,
+            overlay,
+            &executor,
+            method,
+            &[],
+            Extensions::default(),
+            &runtime_code,
+            Box::new(spawn_handle.clone()),
+        )
+        .execute(ExecutionStrategy::AlwaysWasm)
+        .map_err(|e| format!("{:?}", e))
+    };
+
+    let names = call(&mut OverlayedChanges::default(), "Metadata_metadata")
+        .map(|metadata| storage_item_names(&metadata))
+        .unwrap_or_default();
+    let checked = call(
+        &mut OverlayedChanges::default(),
+        "TryUpgradeApi_checks_migrations",
+    )
+    .ok()
+    .and_then(|checked| bool::decode(&mut &checked[..]).ok())
+    .unwrap_or(false);
+    if !checked {
+        println!(
+            "Warning: {:?} was not built with the `try-runtime` feature, the storage is not \
+             checked before and after the migrations",
+            cmd.wasm
+        );
+    }
+
+    let mut overlay = OverlayedChanges::default();
+    overlay.set_storage(well_known_keys::CODE.to_vec(), Some(code.clone()));
+    println!("Upgrading the state of block {:?} to {:?}", at, cmd.wasm);
+    let weight = match call(&mut overlay, "TryUpgradeApi_on_runtime_upgrade") {
+        Ok(weight) => Weight::decode(&mut &weight[..])
+            .map_err(|e| format!("Invalid weight returned: {:?}", e))?,
+        Err(error) => {
+            println!("Upgrade failed: {}", error);
+            return Err("Runtime upgrade failed".into());
+        }
+    };
+    println!("Weight used: {}", weight);
+    let steps = match call(&mut overlay, "TryUpgradeApi_finish_migrations") {
+        Ok(steps) => u32::decode(&mut &steps[..])
+            .map_err(|e| format!("Invalid number of steps returned: {:?}", e))?,
+        Err(error) => {
+            println!("Migrations failed: {}", error);
+            return Err("Storage migrations failed".into());
+        }
+    };
+    println!("Blocks taken by the migrations: {}", steps);
+
+    let changes = overlay
+        .into_storage_changes::<_, BlakeTwo256, NumberFor<Block>>(
+            &state,
+            None,
+            at,
+            Default::default(),
+        )
+        .map_err(|e| format!("Unable to collect storage changes: {}", e))?;
+    let mut changed = BTreeMap::<String, usize>::new();
+    for (key, _) in changes.main_storage_changes {
+        *changed.entry(describe_key(&key, &names)).or_default() += 1;
+    }
+    println!("Changed storage:");
+    for (item, keys) in changed {
+        println!("  {}: {} key(s)", item, keys);
+    }
+    Ok(())
+}
+
+/// Hash of the block `at`, the best block if unspecified.
+pub fn block_hash<B: Backend<Block>>(at: Option<&str>, backend: &B) -> sc_cli::Result<Hash> {
+    match at {
+        Some(hash) => Ok(hash
+            .trim_start_matches("0x")
+            .parse::<Hash>()
+            .map_err(|e| format!("Invalid block hash {}: {:?}", hash, e))?),
+        None => Ok(backend.blockchain().info().best_hash),
+    }
+}
+
+/// Names of the storage items of the runtime, by their hashed prefix.
+///
+/// Includes the `StorageVersion` item the runtime keeps for pallets not versioning their
+/// storage themselves.
+fn storage_item_names(metadata: &[u8]) -> BTreeMap<Vec<u8>, String> {
+    let mut names = BTreeMap::new();
+    let metadata = Vec::<u8>::decode(&mut &metadata[..])
+        .ok()
+        .and_then(|metadata| RuntimeMetadataPrefixed::decode(&mut &metadata[..]).ok());
+    let modules = match metadata {
+        Some(RuntimeMetadataPrefixed(_, RuntimeMetadata::V11(metadata))) => {
+            match metadata.modules {
+                DecodeDifferent::Decoded(modules) => modules,
+                _ => return names,
+            }
+        }
+        _ => return names,
+    };
+
+    for module in modules {
+        let storage = match module.storage {
+            Some(DecodeDifferent::Decoded(storage)) => storage,
+            _ => continue,
+        };
+        let (prefix, entries) = match (storage.prefix, storage.entries) {
+            (DecodeDifferent::Decoded(prefix), DecodeDifferent::Decoded(entries)) => {
+                (prefix, entries)
+            }
+            _ => continue,
+        };
+        let items = entries
+            .into_iter()
+            .filter_map(|entry| match entry.name {
+                DecodeDifferent::Decoded(name) => Some(name),
+                _ => None,
+            })
+            .chain(Some("StorageVersion".to_string()));
+        for item in items {
+            let key = [twox_128(prefix.as_bytes()), twox_128(item.as_bytes())].concat();
+            names.insert(key, format!("{}::{}", prefix, item));
+        }
+    }
+    names
+}
+
+/// Human readable name of the storage item `key` belongs to.
+fn describe_key(key: &[u8], names: &BTreeMap<Vec<u8>, String>) -> String {
+    if key.starts_with(b":") {
+        return String::from_utf8_lossy(key).into_owned();
+    }
+    let prefix = &key[..key.len().min(ITEM_PREFIX_LEN)];
+    names
+        .get(prefix)
+        .cloned()
+        .unwrap_or_else(|| format!("0x{}", HexDisplay::from(&prefix)))
+}
@@ -0,0 +1,16 @@
+//! Runtime API definition for the Subdex message queue pallet.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+
+sp_api::decl_runtime_apis! {
+    pub trait DexMessageQueueApi<BlockNumber> where
+        BlockNumber: Codec,
+    {
+        /// Number of inbound messages waiting to be processed.
+        fn queue_depth() -> u64;
+        /// Block the oldest waiting message was received in.
+        fn oldest_received_at() -> Option<BlockNumber>;
+    }
+}
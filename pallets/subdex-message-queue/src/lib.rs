@@ -0,0 +1,319 @@
+//! # Subdex message queue pallet
+//!
+//! Queue between the message broker and the pallets handling inbound messages.
+//!
+//! The message broker hands over inbound XCMP and downward messages as soon as they are
+//! included, however many there are. This pallet only stores them and processes them in
+//! order in `on_initialize` of the following blocks, within [`Trait::MaxProcessingWeight`].
+//! Messages that do not fit stay queued for later blocks.
+//!
+//! The weight of handling a message is not known in advance, so every message is accounted
+//! for with the fixed weight configured for its kind. The oldest message is always processed,
+//! even if it alone exceeds the budget, so the queue cannot get stuck.
+//!
+//! Messages refused by [`Trait::MessageFilter`], e.g. because their handling is paused, are not
+//! processed but moved to the back of the queue, so they wait without holding up the others.
+//!
+//! A sibling parachain can have at most [`Trait::MaxQueuedPerOrigin`] XCMP messages queued, so
+//! that a single sibling cannot fill the queue and delay the messages of all the others. Its
+//! further messages are dropped, with a trace and [`Event::MessageDropped`], until some of its
+//! queued messages are processed.
+//!
+//! Downward messages accepted by [`Trait::ImmediateDownwardMessages`], such as the commands
+//! the relay chain governs the chain with, are not queued but handled as soon as they are
+//! received, so they neither wait behind the queued messages nor can be held by the filter.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Codec, Decode, Encode, EncodeLike};
+use cumulus_primitives::{
+    xcmp::XCMPMessageHandler, DownwardMessage, DownwardMessageHandler, ParaId,
+};
+use frame_support::{
+    decl_event, decl_module, decl_storage,
+    traits::{Filter, Get},
+    weights::Weight,
+};
+use pallet_subdex_message_trace::{
+    Channel, Direction, MessageTrace, MessageTracer, Outcome, TracedMessage,
+};
+use sp_runtime::RuntimeDebug;
+use sp_std::{fmt::Debug, prelude::*};
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+pub type MessageIndex = u64;
+
+/// Where an inbound message comes from.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum MessageOrigin {
+    Relay,
+    Parachain(ParaId),
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub enum InboundMessage<XcmpMessage> {
+    Downward(DownwardMessage),
+    Xcmp(ParaId, XcmpMessage),
+}
+
+impl<XcmpMessage: Encode> InboundMessage<XcmpMessage> {
+    pub fn origin(&self) -> MessageOrigin {
+        match self {
+            InboundMessage::Downward(_) => MessageOrigin::Relay,
+            InboundMessage::Xcmp(src, _) => MessageOrigin::Parachain(*src),
+        }
+    }
+
+    /// Trace of the message with the given outcome.
+    pub fn trace<AccountId: Decode>(&self, outcome: Outcome) -> MessageTrace<AccountId>
+    where
+        XcmpMessage: TracedMessage<AccountId>,
+    {
+        match self {
+            InboundMessage::Downward(message) => {
+                let accounts = match message {
+                    // Relay chain accounts are decoded as Subdex accounts, like deposits are.
+                    DownwardMessage::TransferInto(dest, ..) => {
+                        AccountId::decode(&mut &dest.encode()[..])
+                            .into_iter()
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                };
+                MessageTrace::new(
+                    Direction::Inbound,
+                    Channel::Downward,
+                    message,
+                    accounts,
+                    outcome,
+                )
+            }
+            InboundMessage::Xcmp(src, message) => MessageTrace::new(
+                Direction::Inbound,
+                Channel::Xcmp(*src),
+                message,
+                message.accounts(),
+                outcome,
+            ),
+        }
+    }
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct QueuedMessage<BlockNumber, XcmpMessage> {
+    /// Block the message was received in.
+    pub received_at: BlockNumber,
+    pub message: InboundMessage<XcmpMessage>,
+}
+
+pub type QueuedMessageOf<T> =
+    QueuedMessage<<T as frame_system::Trait>::BlockNumber, <T as Trait>::XcmpMessage>;
+
+pub trait Trait: frame_system::Trait {
+    type Event: From<Event> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// Inbound XCMP message type.
+    type XcmpMessage: Codec
+        + EncodeLike
+        + Clone
+        + PartialEq
+        + Debug
+        + TracedMessage<Self::AccountId>;
+
+    /// Handler of queued XCMP messages.
+    type XcmpMessageHandler: XCMPMessageHandler<Self::XcmpMessage>;
+
+    /// Handler of queued downward messages.
+    type DownwardMessageHandler: DownwardMessageHandler;
+
+    /// Weight available for processing messages in a block.
+    type MaxProcessingWeight: Get<Weight>;
+
+    /// Weight accounted for handling an XCMP message.
+    type XcmpMessageWeight: Get<Weight>;
+
+    /// Weight accounted for handling a downward message.
+    type DownwardMessageWeight: Get<Weight>;
+
+    /// Receives the traces of queued and processed messages.
+    type MessageTracer: MessageTracer<Self::AccountId>;
+
+    /// Messages that can be processed now, the others are kept queued.
+    type MessageFilter: Filter<InboundMessage<Self::XcmpMessage>>;
+
+    /// Maximum number of XCMP messages queued per sending parachain.
+    type MaxQueuedPerOrigin: Get<u32>;
+
+    /// Downward messages handled when received instead of being queued.
+    type ImmediateDownwardMessages: Filter<DownwardMessage>;
+}
+
+decl_storage! {
+    trait Store for Module<T: Trait> as DexMessageQueue {
+        /// Index of the oldest queued message.
+        pub Head get(fn head): MessageIndex;
+
+        /// Index the next received message is queued at.
+        pub Tail get(fn tail): MessageIndex;
+
+        pub Messages get(fn messages):
+            map hasher(twox_64_concat) MessageIndex => Option<QueuedMessageOf<T>>;
+
+        /// Number of queued XCMP messages per sending parachain.
+        pub QueuedFrom get(fn queued_from): map hasher(twox_64_concat) ParaId => u32;
+    }
+}
+
+decl_event!(
+    pub enum Event {
+        /// Inbound message queued: (index, origin).
+        MessageQueued(MessageIndex, MessageOrigin),
+        /// Queued messages processed: (number processed, number moved to the back of the queue
+        /// by the message filter, number still queued).
+        MessagesProcessed(u32, u32, u64),
+        /// Inbound message dropped, its origin having `MaxQueuedPerOrigin` messages queued.
+        MessageDropped(MessageOrigin),
+    }
+);
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        const MaxProcessingWeight: Weight = T::MaxProcessingWeight::get();
+        const XcmpMessageWeight: Weight = T::XcmpMessageWeight::get();
+        const DownwardMessageWeight: Weight = T::DownwardMessageWeight::get();
+        const MaxQueuedPerOrigin: u32 = T::MaxQueuedPerOrigin::get();
+
+        fn deposit_event() = default;
+
+        fn on_initialize() -> Weight {
+            Self::process()
+        }
+    }
+}
+
+impl<T: Trait> Module<T> {
+    /// Number of messages waiting to be processed.
+    pub fn queue_depth() -> u64 {
+        Self::tail().saturating_sub(Self::head())
+    }
+
+    /// Block the oldest waiting message was received in.
+    pub fn oldest_received_at() -> Option<T::BlockNumber> {
+        Self::messages(Self::head()).map(|queued| queued.received_at)
+    }
+
+    fn enqueue(message: InboundMessage<T::XcmpMessage>) {
+        let index = Self::tail();
+        let origin = message.origin();
+        if let MessageOrigin::Parachain(src) = origin {
+            if Self::queued_from(src) >= T::MaxQueuedPerOrigin::get() {
+                T::MessageTracer::trace(message.trace(Outcome::Rejected));
+                return Self::deposit_event(Event::MessageDropped(origin));
+            }
+            QueuedFrom::mutate(src, |count| *count += 1);
+        }
+        T::MessageTracer::trace(message.trace(Outcome::Queued));
+        Messages::<T>::insert(
+            index,
+            QueuedMessage {
+                received_at: <frame_system::Module<T>>::block_number(),
+                message,
+            },
+        );
+        Tail::put(index + 1);
+        Self::deposit_event(Event::MessageQueued(index, origin));
+    }
+
+    fn message_weight(message: &InboundMessage<T::XcmpMessage>) -> Weight {
+        let (handling, accesses) = match message {
+            InboundMessage::Downward(_) => (T::DownwardMessageWeight::get(), 1),
+            // The count of messages queued from the sending parachain is updated as well.
+            InboundMessage::Xcmp(..) => (T::XcmpMessageWeight::get(), 2),
+        };
+        handling.saturating_add(T::DbWeight::get().reads_writes(accesses, accesses))
+    }
+
+    /// Process queued messages, oldest first, until the weight budget is used up.
+    ///
+    /// Filtered messages are moved behind the last queued one, and only visited once per block.
+    fn process() -> Weight {
+        let (mut head, end) = (Self::head(), Self::tail());
+        let mut tail = end;
+        let mut used = T::DbWeight::get().reads(2);
+        if head == end {
+            return used;
+        }
+
+        let budget = T::MaxProcessingWeight::get();
+        let (mut processed, mut deferred) = (0u32, 0u32);
+        while head < end {
+            let queued = match Self::messages(head) {
+                Some(queued) => queued,
+                None => {
+                    head += 1;
+                    continue;
+                }
+            };
+            let allowed = T::MessageFilter::filter(&queued.message);
+            let weight = if allowed {
+                Self::message_weight(&queued.message)
+            } else {
+                T::DbWeight::get().reads_writes(1, 2)
+            };
+            if processed + deferred > 0 && used.saturating_add(weight) > budget {
+                break;
+            }
+
+            Messages::<T>::remove(head);
+            head += 1;
+            used = used.saturating_add(weight);
+            if !allowed {
+                Messages::<T>::insert(tail, queued);
+                tail += 1;
+                deferred += 1;
+                continue;
+            }
+            T::MessageTracer::trace(queued.message.trace(Outcome::Processed));
+            match queued.message {
+                InboundMessage::Downward(message) => {
+                    T::DownwardMessageHandler::handle_downward_message(&message)
+                }
+                InboundMessage::Xcmp(src, message) => {
+                    QueuedFrom::mutate_exists(src, |count| {
+                        *count = count
+                            .and_then(|count| count.checked_sub(1))
+                            .filter(|c| *c > 0)
+                    });
+                    T::XcmpMessageHandler::handle_xcmp_message(src, &message)
+                }
+            }
+            processed += 1;
+        }
+
+        Head::put(head);
+        Tail::put(tail);
+        Self::deposit_event(Event::MessagesProcessed(processed, deferred, tail - head));
+        used.saturating_add(T::DbWeight::get().writes(2))
+    }
+}
+
+impl<T: Trait> XCMPMessageHandler<T::XcmpMessage> for Module<T> {
+    fn handle_xcmp_message(src: ParaId, message: &T::XcmpMessage) {
+        Self::enqueue(InboundMessage::Xcmp(src, message.clone()))
+    }
+}
+
+impl<T: Trait> DownwardMessageHandler for Module<T> {
+    fn handle_downward_message(message: &DownwardMessage) {
+        let inbound = InboundMessage::<T::XcmpMessage>::Downward(message.clone());
+        if !T::ImmediateDownwardMessages::filter(message) {
+            return Self::enqueue(inbound);
+        }
+        T::MessageTracer::trace(inbound.trace(Outcome::Processed));
+        T::DownwardMessageHandler::handle_downward_message(message)
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{InboundMessage, Module, Trait};
+use codec::{Decode, Encode};
+use cumulus_primitives::{
+    xcmp::XCMPMessageHandler, DownwardMessage, DownwardMessageHandler, ParaId,
+};
+use frame_support::{
+    impl_outer_event, impl_outer_origin, parameter_types, traits::Filter, weights::Weight,
+};
+use pallet_subdex_message_trace::TracedMessage;
+use sp_core::H256;
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+    Perbill,
+};
+use std::cell::RefCell;
+
+pub type AccountId = u64;
+
+pub const SIBLING: u32 = 200;
+
+impl_outer_origin! {
+    pub enum Origin for Test {}
+}
+
+mod message_queue {
+    pub use crate::Event;
+}
+
+impl_outer_event! {
+    pub enum TestEvent for Test {
+        frame_system<T>,
+        message_queue,
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Test;
+
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::one();
+}
+
+impl frame_system::Trait for Test {
+    type BaseCallFilter = ();
+    type Origin = Origin;
+    type Call = ();
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId;
+    type Lookup = IdentityLookup<AccountId>;
+    type Header = Header;
+    type Event = TestEvent;
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type ModuleToIndex = ();
+    type AccountData = ();
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+}
+
+/// XCMP message carrying a number, to tell messages apart.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct TestMessage(pub u32);
+
+impl TracedMessage<AccountId> for TestMessage {
+    fn accounts(&self) -> Vec<AccountId> {
+        Vec::new()
+    }
+}
+
+thread_local! {
+    static HANDLED: RefCell<Vec<InboundMessage<TestMessage>>> = RefCell::new(Vec::new());
+    static DOWNWARD_PAUSED: RefCell<bool> = RefCell::new(false);
+}
+
+pub struct MockMessageHandler;
+
+impl XCMPMessageHandler<TestMessage> for MockMessageHandler {
+    fn handle_xcmp_message(src: ParaId, message: &TestMessage) {
+        HANDLED.with(|handled| {
+            handled
+                .borrow_mut()
+                .push(InboundMessage::Xcmp(src, message.clone()))
+        });
+    }
+}
+
+impl DownwardMessageHandler for MockMessageHandler {
+    fn handle_downward_message(message: &DownwardMessage) {
+        HANDLED.with(|handled| {
+            handled
+                .borrow_mut()
+                .push(InboundMessage::Downward(message.clone()))
+        });
+    }
+}
+
+/// Messages handed to the handlers so far, oldest first.
+pub fn handled() -> Vec<InboundMessage<TestMessage>> {
+    HANDLED.with(|handled| handled.borrow().clone())
+}
+
+/// Refuses downward messages while they are paused.
+pub struct MockMessageFilter;
+
+impl Filter<InboundMessage<TestMessage>> for MockMessageFilter {
+    fn filter(message: &InboundMessage<TestMessage>) -> bool {
+        match message {
+            InboundMessage::Xcmp(..) => true,
+            InboundMessage::Downward(_) => !DOWNWARD_PAUSED.with(|paused| *paused.borrow()),
+        }
+    }
+}
+
+pub fn pause_downward(paused: bool) {
+    DOWNWARD_PAUSED.with(|downward_paused| *downward_paused.borrow_mut() = paused);
+}
+
+/// Payload of the downward messages handled when received.
+pub const IMMEDIATE: u8 = u8::max_value();
+
+pub struct MockImmediateMessages;
+
+impl Filter<DownwardMessage> for MockImmediateMessages {
+    fn filter(message: &DownwardMessage) -> bool {
+        *message == DownwardMessage::Opaque(vec![IMMEDIATE])
+    }
+}
+
+parameter_types! {
+    pub const MaxProcessingWeight: Weight = 250;
+    pub const XcmpMessageWeight: Weight = 100;
+    pub const DownwardMessageWeight: Weight = 300;
+    pub const MaxQueuedPerOrigin: u32 = 3;
+}
+
+impl Trait for Test {
+    type Event = TestEvent;
+    type XcmpMessage = TestMessage;
+    type XcmpMessageHandler = MockMessageHandler;
+    type DownwardMessageHandler = MockMessageHandler;
+    type MaxProcessingWeight = MaxProcessingWeight;
+    type XcmpMessageWeight = XcmpMessageWeight;
+    type DownwardMessageWeight = DownwardMessageWeight;
+    type MessageTracer = ();
+    type MessageFilter = MockMessageFilter;
+    type MaxQueuedPerOrigin = MaxQueuedPerOrigin;
+    type ImmediateDownwardMessages = MockImmediateMessages;
+}
+
+pub type System = frame_system::Module<Test>;
+pub type DexMessageQueue = Module<Test>;
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let storage = frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    let mut ext = sp_io::TestExternalities::new(storage);
+    ext.execute_with(|| System::set_block_number(1));
+    ext
+}
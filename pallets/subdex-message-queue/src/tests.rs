@@ -0,0 +1,160 @@
+use crate::{mock::*, Event, InboundMessage, MessageOrigin};
+use cumulus_primitives::{
+    xcmp::XCMPMessageHandler, DownwardMessage, DownwardMessageHandler, ParaId,
+};
+use frame_support::traits::OnInitialize;
+
+fn xcmp(n: u32) -> InboundMessage<TestMessage> {
+    InboundMessage::Xcmp(ParaId::from(SIBLING), TestMessage(n))
+}
+
+fn downward(n: u8) -> InboundMessage<TestMessage> {
+    InboundMessage::Downward(DownwardMessage::Opaque(vec![n]))
+}
+
+fn receive(message: InboundMessage<TestMessage>) {
+    match message {
+        InboundMessage::Xcmp(src, message) => DexMessageQueue::handle_xcmp_message(src, &message),
+        InboundMessage::Downward(message) => DexMessageQueue::handle_downward_message(&message),
+    }
+}
+
+fn next_block() {
+    let block = System::block_number() + 1;
+    System::set_block_number(block);
+    DexMessageQueue::on_initialize(block);
+}
+
+fn last_processed() -> Option<Event> {
+    System::events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            TestEvent::message_queue(event @ Event::MessagesProcessed(..)) => Some(event),
+            _ => None,
+        })
+        .last()
+}
+
+#[test]
+fn messages_wait_for_the_next_block() {
+    new_test_ext().execute_with(|| {
+        receive(xcmp(1));
+        assert_eq!(DexMessageQueue::queue_depth(), 1);
+        assert_eq!(DexMessageQueue::oldest_received_at(), Some(1));
+        assert!(handled().is_empty());
+
+        next_block();
+        assert_eq!(handled(), vec![xcmp(1)]);
+        assert_eq!(DexMessageQueue::queue_depth(), 0);
+        assert_eq!(last_processed(), Some(Event::MessagesProcessed(1, 0, 0)));
+    });
+}
+
+#[test]
+fn messages_beyond_the_budget_stay_queued() {
+    new_test_ext().execute_with(|| {
+        (1..=3).for_each(|n| receive(xcmp(n)));
+
+        next_block();
+        assert_eq!(handled(), vec![xcmp(1), xcmp(2)]);
+        assert_eq!(last_processed(), Some(Event::MessagesProcessed(2, 0, 1)));
+
+        next_block();
+        assert_eq!(handled(), vec![xcmp(1), xcmp(2), xcmp(3)]);
+        assert_eq!(DexMessageQueue::queue_depth(), 0);
+    });
+}
+
+#[test]
+fn the_oldest_message_is_processed_above_the_budget() {
+    new_test_ext().execute_with(|| {
+        receive(downward(1));
+        receive(xcmp(2));
+
+        next_block();
+        assert_eq!(handled(), vec![downward(1)]);
+        assert_eq!(DexMessageQueue::queue_depth(), 1);
+
+        next_block();
+        assert_eq!(handled(), vec![downward(1), xcmp(2)]);
+    });
+}
+
+#[test]
+fn filtered_messages_move_to_the_back_of_the_queue() {
+    new_test_ext().execute_with(|| {
+        pause_downward(true);
+        receive(downward(1));
+        receive(xcmp(2));
+        receive(downward(3));
+
+        next_block();
+        assert_eq!(handled(), vec![xcmp(2)]);
+        assert_eq!(last_processed(), Some(Event::MessagesProcessed(1, 2, 2)));
+        assert_eq!(DexMessageQueue::head(), 3);
+        assert_eq!(DexMessageQueue::tail(), 5);
+        assert_eq!(
+            DexMessageQueue::messages(3).map(|queued| queued.message),
+            Some(downward(1))
+        );
+        assert_eq!(
+            DexMessageQueue::messages(4).map(|queued| queued.message),
+            Some(downward(3))
+        );
+        // Deferred messages keep the block they were received in.
+        assert_eq!(DexMessageQueue::oldest_received_at(), Some(1));
+
+        next_block();
+        assert_eq!(handled(), vec![xcmp(2)]);
+        assert_eq!(last_processed(), Some(Event::MessagesProcessed(0, 2, 2)));
+
+        pause_downward(false);
+        next_block();
+        assert_eq!(handled(), vec![xcmp(2), downward(1)]);
+        next_block();
+        assert_eq!(handled(), vec![xcmp(2), downward(1), downward(3)]);
+        assert_eq!(DexMessageQueue::queue_depth(), 0);
+    });
+}
+
+#[test]
+fn xcmp_messages_are_bounded_per_sibling() {
+    new_test_ext().execute_with(|| {
+        let other = InboundMessage::Xcmp(ParaId::from(SIBLING + 1), TestMessage(5));
+        (1..=4).for_each(|n| receive(xcmp(n)));
+        receive(other.clone());
+
+        assert_eq!(DexMessageQueue::queue_depth(), 4);
+        assert_eq!(DexMessageQueue::queued_from(ParaId::from(SIBLING)), 3);
+        assert!(System::events().iter().any(|record| record.event
+            == TestEvent::message_queue(Event::MessageDropped(MessageOrigin::Parachain(
+                SIBLING.into()
+            )))));
+
+        // Processed messages make room for new ones.
+        next_block();
+        assert_eq!(handled(), vec![xcmp(1), xcmp(2)]);
+        assert_eq!(DexMessageQueue::queued_from(ParaId::from(SIBLING)), 1);
+        receive(xcmp(6));
+        assert_eq!(DexMessageQueue::queued_from(ParaId::from(SIBLING)), 2);
+
+        next_block();
+        next_block();
+        assert_eq!(handled(), vec![xcmp(1), xcmp(2), xcmp(3), other, xcmp(6)]);
+        assert!(!crate::QueuedFrom::contains_key(ParaId::from(SIBLING)));
+    });
+}
+
+#[test]
+fn immediate_downward_messages_are_not_queued() {
+    new_test_ext().execute_with(|| {
+        pause_downward(true);
+        receive(xcmp(1));
+        receive(downward(1));
+        receive(downward(IMMEDIATE));
+
+        // Handled at once, even with downward messages paused and others queued before.
+        assert_eq!(handled(), vec![downward(IMMEDIATE)]);
+        assert_eq!(DexMessageQueue::queue_depth(), 2);
+    });
+}
@@ -0,0 +1,178 @@
+//! RPC serving the message traces kept in the offchain database.
+//!
+//! Traces are read for the blocks of the chain the node follows, by their hash. The traces of
+//! a block are written when its child is imported, so those of the best block are not served
+//! yet.
+
+use codec::Decode;
+use cumulus_primitives::ParaId;
+use jsonrpc_core::{Error as RpcError, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+use pallet_subdex_message_trace::{trace_key, Channel, Direction, MessageTrace, Outcome};
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_core::{
+    offchain::{OffchainStorage, STORAGE_PREFIX},
+    Bytes, H256,
+};
+use sp_runtime::traits::{Block as BlockT, NumberFor, One};
+use std::{marker::PhantomData, sync::Arc};
+
+#[cfg(test)]
+mod tests;
+
+/// Largest number of blocks a single request can cover.
+pub const MAX_BLOCK_RANGE: u32 = 10_000;
+
+/// Which messages to return.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TraceFilter<AccountId> {
+    /// Messages about the account.
+    Account(AccountId),
+    /// Messages from or to the parachain.
+    ParaId(ParaId),
+}
+
+impl<AccountId: PartialEq> TraceFilter<AccountId> {
+    fn matches(&self, trace: &MessageTrace<AccountId>) -> bool {
+        match self {
+            TraceFilter::Account(account) => trace.accounts.contains(account),
+            TraceFilter::ParaId(para_id) => trace.channel == Channel::Xcmp(*para_id),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TracedMessage<AccountId, BlockNumber> {
+    /// Block the message was handled in.
+    pub block: BlockNumber,
+    pub direction: Direction,
+    pub channel: Channel,
+    pub hash: H256,
+    pub payload: Bytes,
+    /// The payload decoded with the runtime types, if it could be.
+    pub decoded: Option<String>,
+    pub accounts: Vec<AccountId>,
+    pub outcome: Outcome,
+}
+
+/// Turns message payloads into a readable form.
+///
+/// Implemented by the node, which knows the message types of its runtime.
+pub trait PayloadDecoder: Send + Sync + 'static {
+    fn decode(channel: &Channel, payload: &[u8]) -> Option<String>;
+}
+
+impl PayloadDecoder for () {
+    fn decode(_: &Channel, _: &[u8]) -> Option<String> {
+        None
+    }
+}
+
+#[rpc]
+pub trait MessageTraceApi<AccountId, BlockNumber> {
+    /// Traced messages matching `filter` handled in blocks `from` to `to`, both included.
+    #[rpc(name = "subdex_xcmpMessages")]
+    fn xcmp_messages(
+        &self,
+        filter: TraceFilter<AccountId>,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<Vec<TracedMessage<AccountId, BlockNumber>>>;
+}
+
+/// Implementation of [`MessageTraceApi`] reading the offchain database.
+pub struct MessageTraces<C, S, D, Block> {
+    client: Arc<C>,
+    storage: S,
+    _marker: PhantomData<(D, Block)>,
+}
+
+impl<C, S, D, Block> MessageTraces<C, S, D, Block> {
+    pub fn new(client: Arc<C>, storage: S) -> Self {
+        MessageTraces {
+            client,
+            storage,
+            _marker: PhantomData,
+        }
+    }
+}
+
+const INVALID_RANGE: i64 = 1;
+const DECODE_ERROR: i64 = 2;
+const CLIENT_ERROR: i64 = 3;
+
+impl<C, S, D, Block, AccountId> MessageTraceApi<AccountId, NumberFor<Block>>
+    for MessageTraces<C, S, D, Block>
+where
+    C: HeaderBackend<Block> + Send + Sync + 'static,
+    S: OffchainStorage + 'static,
+    D: PayloadDecoder,
+    Block: BlockT,
+    AccountId: Decode + Clone + PartialEq + Send + Sync + 'static,
+{
+    fn xcmp_messages(
+        &self,
+        filter: TraceFilter<AccountId>,
+        from: NumberFor<Block>,
+        to: NumberFor<Block>,
+    ) -> Result<Vec<TracedMessage<AccountId, NumberFor<Block>>>> {
+        if to < from || to - from >= MAX_BLOCK_RANGE.into() {
+            return Err(RpcError {
+                code: ErrorCode::ServerError(INVALID_RANGE),
+                message: format!(
+                    "Block range must not be empty nor span more than {} blocks.",
+                    MAX_BLOCK_RANGE
+                ),
+                data: None,
+            });
+        }
+
+        let mut messages = Vec::new();
+        let mut block = from;
+        loop {
+            let hash = self.client.hash(block).map_err(|e| RpcError {
+                code: ErrorCode::ServerError(CLIENT_ERROR),
+                message: "Unable to read the block hash.".into(),
+                data: Some(format!("{:?}", e).into()),
+            })?;
+            // Blocks above the best one have no hash, nor traces.
+            let hash = match hash {
+                Some(hash) => hash,
+                None => break,
+            };
+            if let Some(encoded) = self.storage.get(STORAGE_PREFIX, &trace_key(block, hash)) {
+                let traces =
+                    Vec::<MessageTrace<AccountId>>::decode(&mut &encoded[..]).map_err(|e| {
+                        RpcError {
+                            code: ErrorCode::ServerError(DECODE_ERROR),
+                            message: "Unable to decode message traces.".into(),
+                            data: Some(format!("{:?}", e).into()),
+                        }
+                    })?;
+                messages.extend(
+                    traces
+                        .into_iter()
+                        .filter(|t| filter.matches(t))
+                        .map(|trace| TracedMessage {
+                            block,
+                            direction: trace.direction,
+                            channel: trace.channel,
+                            hash: trace.hash.into(),
+                            decoded: D::decode(&trace.channel, &trace.payload),
+                            payload: trace.payload.into(),
+                            accounts: trace.accounts,
+                            outcome: trace.outcome,
+                        }),
+                );
+            }
+            if block == to {
+                break;
+            }
+            block += One::one();
+        }
+        Ok(messages)
+    }
+}
@@ -0,0 +1,111 @@
+use super::*;
+use codec::Encode;
+use sp_blockchain::{BlockStatus, Info};
+use sp_core::offchain::storage::InMemOffchainStorage;
+use sp_runtime::{generic::BlockId, testing::Header, OpaqueExtrinsic};
+
+type Block = sp_runtime::generic::Block<Header, OpaqueExtrinsic>;
+type AccountId = u64;
+
+/// Chain whose block `n` has hash `n` repeated, up to block `BEST`.
+struct TestChain;
+
+const BEST: u64 = 3;
+
+fn canonical(number: u64) -> H256 {
+    H256::repeat_byte(number as u8)
+}
+
+impl HeaderBackend<Block> for TestChain {
+    fn header(&self, _: BlockId<Block>) -> sp_blockchain::Result<Option<Header>> {
+        unimplemented!()
+    }
+
+    fn info(&self) -> Info<Block> {
+        unimplemented!()
+    }
+
+    fn status(&self, _: BlockId<Block>) -> sp_blockchain::Result<BlockStatus> {
+        unimplemented!()
+    }
+
+    fn number(&self, _: H256) -> sp_blockchain::Result<Option<u64>> {
+        unimplemented!()
+    }
+
+    fn hash(&self, number: u64) -> sp_blockchain::Result<Option<H256>> {
+        Ok(Some(canonical(number)).filter(|_| number <= BEST))
+    }
+}
+
+fn trace(account: AccountId, para_id: u32) -> MessageTrace<AccountId> {
+    MessageTrace::new(
+        Direction::Inbound,
+        Channel::Xcmp(para_id.into()),
+        &account,
+        vec![account],
+        Outcome::Processed,
+    )
+}
+
+fn traces() -> MessageTraces<TestChain, InMemOffchainStorage, (), Block> {
+    let mut storage = InMemOffchainStorage::default();
+    let mut index = |number: u64, hash: H256, traces: Vec<MessageTrace<AccountId>>| {
+        storage.set(STORAGE_PREFIX, &trace_key(number, hash), &traces.encode())
+    };
+    index(1, canonical(1), vec![trace(1, 200), trace(2, 300)]);
+    // Block 2 of an abandoned fork.
+    index(2, H256::repeat_byte(0xff), vec![trace(1, 200)]);
+    index(2, canonical(2), vec![trace(2, 200)]);
+    index(3, canonical(3), vec![trace(1, 300)]);
+    MessageTraces::new(Arc::new(TestChain), storage)
+}
+
+fn blocks(messages: Vec<TracedMessage<AccountId, u64>>) -> Vec<(u64, Vec<AccountId>)> {
+    messages
+        .into_iter()
+        .map(|message| (message.block, message.accounts))
+        .collect()
+}
+
+#[test]
+fn traces_of_the_canonical_blocks_are_served() {
+    let traces = traces();
+    let messages = traces
+        .xcmp_messages(TraceFilter::Account(1), 1, 10)
+        .unwrap();
+    assert_eq!(blocks(messages), vec![(1, vec![1]), (3, vec![1])]);
+
+    let messages = traces
+        .xcmp_messages(TraceFilter::ParaId(200.into()), 2, 3)
+        .unwrap();
+    assert_eq!(blocks(messages), vec![(2, vec![2])]);
+}
+
+#[test]
+fn served_traces_are_decoded() {
+    let messages = traces()
+        .xcmp_messages(TraceFilter::<AccountId>::ParaId(300.into()), 1, 1)
+        .unwrap();
+    let expected = trace(2, 300);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].hash, H256::from(expected.hash));
+    assert_eq!(messages[0].payload, Bytes(expected.payload));
+    assert_eq!(messages[0].outcome, Outcome::Processed);
+    assert_eq!(messages[0].decoded, None);
+}
+
+#[test]
+fn block_ranges_are_bounded() {
+    let traces = traces();
+    assert!(traces
+        .xcmp_messages(TraceFilter::<AccountId>::Account(1), 2, 1)
+        .is_err());
+    assert!(traces
+        .xcmp_messages(
+            TraceFilter::<AccountId>::Account(1),
+            1,
+            MAX_BLOCK_RANGE as u64
+        )
+        .is_err());
+}
@@ -0,0 +1,186 @@
+//! # Subdex message trace pallet
+//!
+//! Keeps a trace of the XCMP, downward and upward messages Subdex receives and sends, so
+//! missing cross-chain transfers can be followed end to end.
+//!
+//! Pallets handling messages report them through [`MessageTracer`]. Pallets that send upward
+//! messages without knowing about traces get a [`TracedUpwardMessageSender`] instead of the
+//! message broker. The traces of a block are collected in storage while the block executes and
+//! written to the offchain database with offchain indexing in `on_initialize` of its child,
+//! under [`trace_key`] of the block number and hash. Nodes only keep them with offchain
+//! indexing enabled (`--enable-offchain-indexing true`), and serve them through the
+//! `subdex_xcmpMessages` RPC.
+//!
+//! A block does not know its own hash, hence the traces are written by the child block, which
+//! knows its parent's. Offchain indexing writes the traces of every imported block, including
+//! the blocks of forks; keyed by hash, those of different forks are kept apart, and readers
+//! look up the traces of the blocks of the chain they follow by their hash.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Decode, Encode};
+use cumulus_primitives::{ParaId, UpwardMessageOrigin, UpwardMessageSender};
+use frame_support::{decl_module, decl_storage, traits::Get, weights::Weight};
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+use sp_runtime::{traits::One, RuntimeDebug};
+use sp_std::{marker::PhantomData, prelude::*};
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+/// Prefix of the offchain database keys traces are stored under.
+pub const TRACE_KEY_PREFIX: &[u8] = b"subdex/message-trace";
+
+/// Offchain database key of the traces of block `number` with hash `hash`.
+pub fn trace_key<BlockNumber: Encode, Hash: Encode>(number: BlockNumber, hash: Hash) -> Vec<u8> {
+    (TRACE_KEY_PREFIX, number, hash).encode()
+}
+
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// Channel a message travels on.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub enum Channel {
+    /// XCMP message from or to a sibling parachain.
+    Xcmp(ParaId),
+    /// Message from the relay chain.
+    Downward,
+    /// Message to the relay chain.
+    Upward,
+}
+
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub enum Outcome {
+    /// Inbound message stored until it can be processed.
+    Queued,
+    /// Inbound message handed to the pallet handling it. What came of it is reported in the
+    /// events of the same block.
+    Processed,
+    /// Inbound message dropped without being processed.
+    Rejected,
+    /// Outbound message handed to the relay chain.
+    Sent,
+    /// Outbound message that could not be sent.
+    NotSent,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct MessageTrace<AccountId> {
+    pub direction: Direction,
+    pub channel: Channel,
+    /// Blake2-256 hash of `payload`, the same on the sending and the receiving chain.
+    pub hash: [u8; 32],
+    /// The encoded message.
+    pub payload: Vec<u8>,
+    /// Accounts the message is about.
+    pub accounts: Vec<AccountId>,
+    pub outcome: Outcome,
+}
+
+impl<AccountId> MessageTrace<AccountId> {
+    pub fn new<Message: Encode>(
+        direction: Direction,
+        channel: Channel,
+        message: &Message,
+        accounts: Vec<AccountId>,
+        outcome: Outcome,
+    ) -> Self {
+        let payload = message.encode();
+        MessageTrace {
+            direction,
+            channel,
+            hash: sp_io::hashing::blake2_256(&payload),
+            payload,
+            accounts,
+            outcome,
+        }
+    }
+}
+
+/// Receives the traces of handled messages.
+pub trait MessageTracer<AccountId> {
+    fn trace(trace: MessageTrace<AccountId>);
+}
+
+impl<AccountId> MessageTracer<AccountId> for () {
+    fn trace(_: MessageTrace<AccountId>) {}
+}
+
+/// Sends upward messages through `Sender` and reports them to `Tracer`.
+///
+/// The accounts an upward message is about are not known here, so its trace lists none.
+pub struct TracedUpwardMessageSender<Sender, Tracer, AccountId>(
+    PhantomData<(Sender, Tracer, AccountId)>,
+);
+
+impl<Message, Sender, Tracer, AccountId> UpwardMessageSender<Message>
+    for TracedUpwardMessageSender<Sender, Tracer, AccountId>
+where
+    Message: Encode,
+    Sender: UpwardMessageSender<Message>,
+    Tracer: MessageTracer<AccountId>,
+{
+    fn send_upward_message(message: &Message, origin: UpwardMessageOrigin) -> Result<(), ()> {
+        let result = Sender::send_upward_message(message, origin);
+        Tracer::trace(MessageTrace::new(
+            Direction::Outbound,
+            Channel::Upward,
+            message,
+            Vec::new(),
+            if result.is_ok() {
+                Outcome::Sent
+            } else {
+                Outcome::NotSent
+            },
+        ));
+        result
+    }
+}
+
+/// Messages that can tell which accounts they are about.
+pub trait TracedMessage<AccountId> {
+    fn accounts(&self) -> Vec<AccountId>;
+}
+
+pub trait Trait: frame_system::Trait {}
+
+decl_storage! {
+    trait Store for Module<T: Trait> as DexMessageTrace {
+        /// Traces per block, moved to the offchain database by the child of the block.
+        pub BlockTraces get(fn block_traces):
+            map hasher(twox_64_concat) T::BlockNumber => Vec<MessageTrace<T::AccountId>>;
+    }
+}
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        fn on_initialize(now: T::BlockNumber) -> Weight {
+            let parent = now.saturating_sub(One::one());
+            let traces = BlockTraces::<T>::take(parent);
+            if !traces.is_empty() {
+                let parent_hash = <frame_system::Module<T>>::parent_hash();
+                sp_io::offchain_index::set(&trace_key(parent, parent_hash), &traces.encode());
+            }
+            T::DbWeight::get().reads_writes(1, 1)
+        }
+    }
+}
+
+impl<T: Trait> MessageTracer<T::AccountId> for Module<T> {
+    fn trace(trace: MessageTrace<T::AccountId>) {
+        BlockTraces::<T>::append(<frame_system::Module<T>>::block_number(), trace);
+    }
+}
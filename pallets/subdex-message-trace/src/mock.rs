@@ -0,0 +1,98 @@
+use crate::{Module, Trait};
+use codec::{Decode, Encode};
+use cumulus_primitives::{UpwardMessageOrigin, UpwardMessageSender};
+use frame_support::{impl_outer_origin, parameter_types, weights::Weight};
+use sp_core::H256;
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+    Perbill,
+};
+use std::cell::RefCell;
+
+pub type AccountId = u64;
+
+impl_outer_origin! {
+    pub enum Origin for Test {}
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Test;
+
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::one();
+}
+
+impl frame_system::Trait for Test {
+    type BaseCallFilter = ();
+    type Origin = Origin;
+    type Call = ();
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId;
+    type Lookup = IdentityLookup<AccountId>;
+    type Header = Header;
+    type Event = ();
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type ModuleToIndex = ();
+    type AccountData = ();
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+}
+
+impl Trait for Test {}
+
+/// Upward message carrying a number, to tell messages apart.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct TestMessage(pub u32);
+
+thread_local! {
+    static FAIL_SEND: RefCell<bool> = RefCell::new(false);
+}
+
+pub struct MockUpwardMessageSender;
+
+impl UpwardMessageSender<TestMessage> for MockUpwardMessageSender {
+    fn send_upward_message(_: &TestMessage, _: UpwardMessageOrigin) -> Result<(), ()> {
+        if FAIL_SEND.with(|fail| *fail.borrow()) {
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Make the relay chain refuse upward messages, or accept them again.
+pub fn fail_sending(fail: bool) {
+    FAIL_SEND.with(|current| *current.borrow_mut() = fail);
+}
+
+pub type System = frame_system::Module<Test>;
+pub type DexMessageTrace = Module<Test>;
+
+/// Upward message sender tracing to the pallet.
+pub type TracedSender =
+    crate::TracedUpwardMessageSender<MockUpwardMessageSender, DexMessageTrace, AccountId>;
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let storage = frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    let mut ext = sp_io::TestExternalities::new(storage);
+    ext.execute_with(|| System::set_block_number(1));
+    ext
+}
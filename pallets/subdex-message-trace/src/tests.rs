@@ -0,0 +1,90 @@
+use crate::{
+    mock::*, trace_key, Channel, Direction, MessageTrace, MessageTracer, Outcome, TRACE_KEY_PREFIX,
+};
+use codec::Encode;
+use cumulus_primitives::{ParaId, UpwardMessageOrigin, UpwardMessageSender};
+use frame_support::traits::OnInitialize;
+use sp_core::H256;
+
+fn trace(n: u32) -> MessageTrace<AccountId> {
+    MessageTrace::new(
+        Direction::Inbound,
+        Channel::Xcmp(ParaId::from(200)),
+        &TestMessage(n),
+        vec![1],
+        Outcome::Processed,
+    )
+}
+
+#[test]
+fn traces_are_kept_per_block() {
+    new_test_ext().execute_with(|| {
+        DexMessageTrace::trace(trace(1));
+        DexMessageTrace::trace(trace(2));
+        System::set_block_number(2);
+        DexMessageTrace::trace(trace(3));
+
+        assert_eq!(DexMessageTrace::block_traces(1), vec![trace(1), trace(2)]);
+        assert_eq!(DexMessageTrace::block_traces(2), vec![trace(3)]);
+    });
+}
+
+#[test]
+fn traces_are_moved_out_of_the_state_by_the_child_block() {
+    new_test_ext().execute_with(|| {
+        DexMessageTrace::trace(trace(1));
+        System::set_block_number(2);
+        System::set_parent_hash(H256::repeat_byte(1));
+        // Traced by pallets initialized before this one.
+        DexMessageTrace::trace(trace(2));
+        DexMessageTrace::on_initialize(2);
+
+        assert!(DexMessageTrace::block_traces(1).is_empty());
+        assert_eq!(DexMessageTrace::block_traces(2), vec![trace(2)]);
+    });
+}
+
+#[test]
+fn traces_of_blocks_of_different_forks_have_different_keys() {
+    let (first, second) = (H256::repeat_byte(1), H256::repeat_byte(2));
+    assert_ne!(trace_key(1u64, first), trace_key(1u64, second));
+    assert_ne!(trace_key(1u64, first), trace_key(2u64, first));
+    assert_eq!(
+        trace_key(1u64, first),
+        (TRACE_KEY_PREFIX, 1u64, first).encode()
+    );
+}
+
+#[test]
+fn traced_upward_messages_report_whether_they_were_sent() {
+    new_test_ext().execute_with(|| {
+        assert_eq!(
+            TracedSender::send_upward_message(&TestMessage(1), UpwardMessageOrigin::Signed),
+            Ok(())
+        );
+        fail_sending(true);
+        assert_eq!(
+            TracedSender::send_upward_message(&TestMessage(2), UpwardMessageOrigin::Signed),
+            Err(())
+        );
+
+        let upward = |n, outcome| {
+            MessageTrace::new(
+                Direction::Outbound,
+                Channel::Upward,
+                &TestMessage(n),
+                Vec::new(),
+                outcome,
+            )
+        };
+        let traces = DexMessageTrace::block_traces(1);
+        assert_eq!(
+            traces,
+            vec![upward(1, Outcome::Sent), upward(2, Outcome::NotSent)]
+        );
+        assert_eq!(
+            traces[0].hash,
+            sp_io::hashing::blake2_256(&TestMessage(1).encode())
+        );
+    });
+}
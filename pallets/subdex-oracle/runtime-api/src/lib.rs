@@ -0,0 +1,18 @@
+//! Runtime API definition for the Subdex price oracle pallet.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+
+sp_api::decl_runtime_apis! {
+    pub trait DexOracleApi<Asset, Moment, Price> where
+        Asset: Codec,
+        Moment: Codec,
+        Price: Codec,
+    {
+        /// Time-weighted average price of `base` in `quote` over at least the last `window`.
+        fn twap(base: Asset, quote: Asset, window: Moment) -> Option<Price>;
+        /// Current pool price of `base` in `quote`.
+        fn spot_price(base: Asset, quote: Asset) -> Option<Price>;
+    }
+}
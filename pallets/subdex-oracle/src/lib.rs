@@ -0,0 +1,282 @@
+//! # Subdex price oracle pallet
+//!
+//! Time-weighted average prices derived from the `pallet_subdex` pools.
+//!
+//! For every pool that has been traded through the router, the pallet keeps two cumulative
+//! price accumulators, one per direction, which grow by `price * elapsed milliseconds` each
+//! time the pool is about to change. Accumulators are updated *before* the trade, so they
+//! only ever see prices that survived until the end of a previous block and cannot be moved
+//! within a single transaction. A ring buffer of past accumulator snapshots, keyed by
+//! timestamp, allows averaging over arbitrary windows.
+//!
+//! Updates happen through [`OnPoolUpdate`], which the router calls before every swap and
+//! liquidity change. A trade bypassing the router would change the reserves without an update,
+//! and its price would be accounted for over the whole period since the previous one, so
+//! runtimes must refuse direct `pallet_subdex` trades.
+//!
+//! Accumulators wrap on overflow; differences between two snapshots are still correct as long
+//! as a window does not accumulate more than `u128::MAX`.
+//!
+//! Other pallets read prices through the [`Twap`] trait, clients through the runtime API.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Decode, Encode};
+use frame_support::{decl_error, decl_event, decl_module, decl_storage, ensure, traits::Get};
+use frame_system::ensure_signed;
+use pallet_subdex_router::{AssetOf, MomentOf, OnPoolUpdate};
+use sp_runtime::{
+    traits::{Saturating, UniqueSaturatedInto, Zero},
+    FixedPointNumber, FixedU128, RuntimeDebug,
+};
+use sp_std::prelude::*;
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+/// Snapshot of the price accumulators of a pool.
+#[derive(Encode, Decode, Clone, Copy, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct Observation<Moment> {
+    pub timestamp: Moment,
+    /// Sum of `price of first asset in second asset * elapsed ms`, as raw `FixedU128`.
+    pub first_price_cumulative: u128,
+    /// Sum of `price of second asset in first asset * elapsed ms`, as raw `FixedU128`.
+    pub second_price_cumulative: u128,
+}
+
+/// Latest accumulators of a pool and the position in its observation ring buffer.
+#[derive(Encode, Decode, Clone, Copy, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct PoolAccumulator<Moment> {
+    pub last: Observation<Moment>,
+    /// Ring buffer slot the next observation is written to.
+    pub next_index: u32,
+    /// Number of observations in the ring buffer.
+    pub observations: u32,
+}
+
+/// Price queries for other pallets.
+pub trait Twap<Asset, Moment> {
+    /// Price of `base` in `quote`, averaged over at least the last `window` milliseconds.
+    ///
+    /// Returns `None` if the pool is unknown or has not been observed for long enough.
+    fn twap(base: Asset, quote: Asset, window: Moment) -> Option<FixedU128>;
+
+    /// Price of `base` in `quote` at the current pool reserves.
+    fn spot_price(base: Asset, quote: Asset) -> Option<FixedU128>;
+}
+
+pub trait Trait: pallet_subdex_router::Trait {
+    type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// Number of past observations kept per pool.
+    type MaxObservations: Get<u32>;
+}
+
+decl_storage! {
+    trait Store for Module<T: Trait> as DexOracle {
+        /// Accumulators per pool, keyed by the assets in canonical order.
+        pub Accumulators get(fn accumulators):
+            map hasher(blake2_128_concat) (AssetOf<T>, AssetOf<T>) => Option<PoolAccumulator<MomentOf<T>>>;
+
+        /// Past observations per pool, ring buffer ordered by timestamp.
+        pub Observations get(fn observations):
+            double_map hasher(blake2_128_concat) (AssetOf<T>, AssetOf<T>), hasher(twox_64_concat) u32
+            => Observation<MomentOf<T>>;
+    }
+}
+
+decl_event!(
+    pub enum Event<T>
+    where
+        Asset = AssetOf<T>,
+    {
+        /// A pool is now tracked by the oracle.
+        PoolTracked(Asset, Asset),
+    }
+);
+
+decl_error! {
+    pub enum Error for Module<T: Trait> {
+        /// There is no pool for the given pair of assets.
+        PoolNotFound,
+    }
+}
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        type Error = Error<T>;
+
+        const MaxObservations: u32 = T::MaxObservations::get();
+
+        fn deposit_event() = default;
+
+        /// Record an observation for a pool without trading, e.g. to start tracking it or to
+        /// keep a rarely traded pool fresh.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(4, 2)]
+        pub fn poke(origin, first: AssetOf<T>, second: AssetOf<T>) {
+            ensure_signed(origin)?;
+            ensure!(
+                pallet_subdex_router::Module::<T>::reserves(first, second).is_some(),
+                Error::<T>::PoolNotFound
+            );
+            Self::update(first, second);
+        }
+    }
+}
+
+impl<T: Trait> Module<T> {
+    /// Order the pair so each pool has a single storage entry.
+    fn canonical(first: AssetOf<T>, second: AssetOf<T>) -> ((AssetOf<T>, AssetOf<T>), bool) {
+        if first.encode() <= second.encode() {
+            ((first, second), false)
+        } else {
+            ((second, first), true)
+        }
+    }
+
+    fn prices(first: AssetOf<T>, second: AssetOf<T>) -> Option<(FixedU128, FixedU128)> {
+        let (first_reserve, second_reserve) =
+            pallet_subdex_router::Module::<T>::reserves(first, second)?;
+        if first_reserve.is_zero() || second_reserve.is_zero() {
+            return None;
+        }
+        let first_reserve: u128 = first_reserve.unique_saturated_into();
+        let second_reserve: u128 = second_reserve.unique_saturated_into();
+        Some((
+            FixedU128::saturating_from_rational(second_reserve, first_reserve),
+            FixedU128::saturating_from_rational(first_reserve, second_reserve),
+        ))
+    }
+
+    fn elapsed(from: MomentOf<T>, to: MomentOf<T>) -> u128 {
+        let elapsed: u64 = to.saturating_sub(from).unique_saturated_into();
+        elapsed as u128
+    }
+
+    /// Accumulators advanced to `now`, at the prices that held since the last observation.
+    fn extrapolate(
+        acc: &PoolAccumulator<MomentOf<T>>,
+        (first_price, second_price): (FixedU128, FixedU128),
+        now: MomentOf<T>,
+    ) -> Observation<MomentOf<T>> {
+        let elapsed = Self::elapsed(acc.last.timestamp, now);
+        Observation {
+            timestamp: now,
+            first_price_cumulative: acc
+                .last
+                .first_price_cumulative
+                .wrapping_add(first_price.into_inner().wrapping_mul(elapsed)),
+            second_price_cumulative: acc
+                .last
+                .second_price_cumulative
+                .wrapping_add(second_price.into_inner().wrapping_mul(elapsed)),
+        }
+    }
+
+    /// Advance the accumulators of a pool to the current time and snapshot them.
+    ///
+    /// Must be called before the reserves of the pool change.
+    pub fn update(first: AssetOf<T>, second: AssetOf<T>) {
+        let (pair, _) = Self::canonical(first, second);
+        let now = pallet_timestamp::Module::<T>::get();
+        // The reserves have not changed since the last update yet, so the current prices are
+        // the ones that held over the whole elapsed period.
+        let prices = match Self::prices(pair.0, pair.1) {
+            Some(prices) => prices,
+            None => return,
+        };
+
+        let mut acc = match Self::accumulators(pair) {
+            Some(acc) => acc,
+            None => {
+                Self::deposit_event(RawEvent::PoolTracked(pair.0, pair.1));
+                PoolAccumulator {
+                    last: Observation {
+                        timestamp: now,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }
+            }
+        };
+
+        if acc.observations == 0 || acc.last.timestamp < now {
+            acc.last = Self::extrapolate(&acc, prices, now);
+            Observations::<T>::insert(pair, acc.next_index, acc.last);
+            acc.next_index = (acc.next_index + 1) % T::MaxObservations::get().max(1);
+            acc.observations = acc
+                .observations
+                .saturating_add(1)
+                .min(T::MaxObservations::get().max(1));
+            Accumulators::<T>::insert(pair, acc);
+        }
+    }
+
+    /// Latest observation not newer than `target`.
+    fn observation_before(
+        pair: (AssetOf<T>, AssetOf<T>),
+        acc: &PoolAccumulator<MomentOf<T>>,
+        target: MomentOf<T>,
+    ) -> Option<Observation<MomentOf<T>>> {
+        let len = acc.observations;
+        if len == 0 {
+            return None;
+        }
+        // Oldest entry sits at `next_index` once the ring buffer is full, at 0 before.
+        let oldest = if len < T::MaxObservations::get().max(1) {
+            0
+        } else {
+            acc.next_index
+        };
+        let at = |i: u32| Self::observations(pair, (oldest + i) % len);
+
+        if at(0).timestamp > target {
+            return None;
+        }
+        let (mut low, mut high) = (0, len - 1);
+        while low < high {
+            let mid = low + (high - low + 1) / 2;
+            if at(mid).timestamp <= target {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        Some(at(low))
+    }
+}
+
+impl<T: Trait> Twap<AssetOf<T>, MomentOf<T>> for Module<T> {
+    fn twap(base: AssetOf<T>, quote: AssetOf<T>, window: MomentOf<T>) -> Option<FixedU128> {
+        let (pair, swapped) = Self::canonical(base, quote);
+        let acc = Self::accumulators(pair)?;
+        let now = pallet_timestamp::Module::<T>::get();
+        let start = Self::observation_before(pair, &acc, now.saturating_sub(window))?;
+        let end = Self::extrapolate(&acc, Self::prices(pair.0, pair.1)?, now);
+
+        let elapsed = Self::elapsed(start.timestamp, end.timestamp);
+        if elapsed == 0 {
+            return None;
+        }
+        let cumulative = if swapped {
+            end.second_price_cumulative
+                .wrapping_sub(start.second_price_cumulative)
+        } else {
+            end.first_price_cumulative
+                .wrapping_sub(start.first_price_cumulative)
+        };
+        Some(FixedU128::from_inner(cumulative / elapsed))
+    }
+
+    fn spot_price(base: AssetOf<T>, quote: AssetOf<T>) -> Option<FixedU128> {
+        Self::prices(base, quote).map(|(price, _)| price)
+    }
+}
+
+impl<T: Trait> OnPoolUpdate<AssetOf<T>> for Module<T> {
+    fn on_pool_update(first: AssetOf<T>, second: AssetOf<T>) {
+        Self::update(first, second)
+    }
+}
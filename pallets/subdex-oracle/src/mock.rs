@@ -0,0 +1,167 @@
+use crate::{Module, Trait};
+use frame_support::{impl_outer_event, impl_outer_origin, parameter_types, weights::Weight};
+use pallet_subdex::Asset;
+use sp_core::H256;
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+    Perbill,
+};
+
+pub type AccountId = u64;
+pub type Balance = u128;
+pub type AssetId = u64;
+
+pub const ALICE: AccountId = 1;
+pub const BOB: AccountId = 2;
+
+/// No deadline for router calls.
+pub const NO_DEADLINE: u64 = u64::MAX;
+
+pub const MAIN: Asset<AssetId> = Asset::MainNetworkCurrency;
+pub const BTC: Asset<AssetId> = Asset::ParachainAsset(1);
+
+impl_outer_origin! {
+    pub enum Origin for Test {}
+}
+
+mod oracle {
+    pub use crate::Event;
+}
+
+impl_outer_event! {
+    pub enum TestEvent for Test {
+        frame_system<T>,
+        pallet_balances<T>,
+        pallet_subdex<T>,
+        pallet_subdex_router<T>,
+        oracle<T>,
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Test;
+
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::one();
+}
+
+impl frame_system::Trait for Test {
+    type BaseCallFilter = ();
+    type Origin = Origin;
+    type Call = ();
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId;
+    type Lookup = IdentityLookup<AccountId>;
+    type Header = Header;
+    type Event = TestEvent;
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type ModuleToIndex = ();
+    type AccountData = pallet_balances::AccountData<Balance>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+}
+
+parameter_types! {
+    pub const ExistentialDeposit: Balance = 1;
+}
+
+impl pallet_balances::Trait for Test {
+    type Balance = Balance;
+    type Event = TestEvent;
+    type DustRemoval = ();
+    type ExistentialDeposit = ExistentialDeposit;
+    type AccountStore = System;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const MinimumPeriod: u64 = 1;
+}
+
+impl pallet_timestamp::Trait for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const FeeRateNominator: Balance = 3;
+    pub const FeeRateDenominator: Balance = 1000;
+    pub const MinMainNetworkAssetAmount: Balance = 1000;
+    pub const MinParachainAssetAmount: Balance = 1000;
+}
+
+impl pallet_subdex::Trait for Test {
+    type Event = TestEvent;
+    type Currency = Balances;
+    type IMoment = u64;
+    type AssetId = AssetId;
+    type FeeRateNominator = FeeRateNominator;
+    type FeeRateDenominator = FeeRateDenominator;
+    type MinMainNetworkAssetAmount = MinMainNetworkAssetAmount;
+    type MinParachainAssetAmount = MinParachainAssetAmount;
+}
+
+impl pallet_subdex_router::Trait for Test {
+    type Event = TestEvent;
+    type OnPoolUpdate = DexOracle;
+    type DexPaused = ();
+}
+
+parameter_types! {
+    pub const MaxObservations: u32 = 3;
+}
+
+impl Trait for Test {
+    type Event = TestEvent;
+    type MaxObservations = MaxObservations;
+}
+
+pub type System = frame_system::Module<Test>;
+pub type Balances = pallet_balances::Module<Test>;
+pub type Timestamp = pallet_timestamp::Module<Test>;
+pub type Dex = pallet_subdex::Module<Test>;
+pub type DexRouter = pallet_subdex_router::Module<Test>;
+pub type DexOracle = Module<Test>;
+
+/// Alice and Bob hold 1_000_000 of `MAIN` and `BTC`, Alice created a `MAIN`/`BTC` pool with
+/// 100_000 of each and it is 1_000 milliseconds past the epoch.
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let mut storage = frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    pallet_balances::GenesisConfig::<Test> {
+        balances: vec![(ALICE, 1_000_000), (BOB, 1_000_000)],
+    }
+    .assimilate_storage(&mut storage)
+    .unwrap();
+
+    let mut ext = sp_io::TestExternalities::new(storage);
+    ext.execute_with(|| {
+        System::set_block_number(1);
+        Timestamp::set_timestamp(1_000);
+        for who in &[ALICE, BOB] {
+            pallet_subdex::AssetBalances::<Test>::insert(who, 1, 1_000_000);
+        }
+        Dex::initialize_exchange(Origin::signed(ALICE), MAIN, 100_000, BTC, 100_000)
+            .expect("the pool is created");
+    });
+    ext
+}
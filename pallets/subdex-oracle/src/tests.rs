@@ -0,0 +1,122 @@
+use crate::{mock::*, Error, RawEvent, Twap};
+use frame_support::{assert_noop, assert_ok};
+use pallet_subdex::Asset;
+use sp_runtime::{FixedPointNumber, FixedU128};
+
+fn poke_at(now: u64) {
+    Timestamp::set_timestamp(now);
+    assert_ok!(DexOracle::poke(Origin::signed(ALICE), MAIN, BTC));
+}
+
+fn sell_main(who: AccountId, amount: Balance) {
+    assert_ok!(DexRouter::swap_exact_in(
+        Origin::signed(who),
+        MAIN,
+        amount,
+        BTC,
+        0,
+        NO_DEADLINE
+    ));
+}
+
+#[test]
+fn poke_starts_tracking_a_pool() {
+    new_test_ext().execute_with(|| {
+        assert_eq!(DexOracle::twap(MAIN, BTC, 0), None);
+        assert_noop!(
+            DexOracle::poke(Origin::signed(ALICE), MAIN, Asset::ParachainAsset(2)),
+            Error::<Test>::PoolNotFound
+        );
+
+        poke_at(1_000);
+
+        assert_eq!(
+            System::events().pop().unwrap().event,
+            TestEvent::oracle(RawEvent::PoolTracked(MAIN, BTC))
+        );
+        assert_eq!(
+            DexOracle::accumulators((MAIN, BTC)).unwrap().observations,
+            1
+        );
+        assert_eq!(DexOracle::spot_price(MAIN, BTC), Some(FixedU128::one()));
+    });
+}
+
+#[test]
+fn twap_averages_prices_over_window() {
+    new_test_ext().execute_with(|| {
+        poke_at(1_000);
+        Timestamp::set_timestamp(2_000);
+        sell_main(BOB, 50_000);
+        let price = DexOracle::spot_price(MAIN, BTC).unwrap();
+        assert!(price < FixedU128::one());
+
+        Timestamp::set_timestamp(4_000);
+
+        // Only the new price held since 2_000.
+        assert_eq!(DexOracle::twap(MAIN, BTC, 2_000), Some(price));
+        assert_eq!(
+            DexOracle::twap(BTC, MAIN, 2_000),
+            DexOracle::spot_price(BTC, MAIN)
+        );
+        // A price of one from 1_000 to 2_000, then the new price until 4_000.
+        assert_eq!(
+            DexOracle::twap(MAIN, BTC, 3_000),
+            Some(FixedU128::from_inner(
+                (FixedU128::one().into_inner() * 1_000 + price.into_inner() * 2_000) / 3_000
+            ))
+        );
+        // Longer than the pool has been tracked.
+        assert_eq!(DexOracle::twap(MAIN, BTC, 3_001), None);
+    });
+}
+
+#[test]
+fn trades_within_a_block_do_not_move_twap() {
+    new_test_ext().execute_with(|| {
+        poke_at(1_000);
+        Timestamp::set_timestamp(2_000);
+
+        // Push the price down and take it back up in the same block.
+        let btc_before = pallet_subdex::Module::<Test>::asset_balances(BOB, 1);
+        sell_main(BOB, 500_000);
+        assert!(
+            DexOracle::spot_price(MAIN, BTC).unwrap() < FixedU128::saturating_from_rational(1, 10)
+        );
+        assert_eq!(DexOracle::twap(MAIN, BTC, 1_000), Some(FixedU128::one()));
+        let bought = pallet_subdex::Module::<Test>::asset_balances(BOB, 1) - btc_before;
+        assert_ok!(DexRouter::swap_exact_in(
+            Origin::signed(BOB),
+            BTC,
+            bought,
+            MAIN,
+            0,
+            NO_DEADLINE
+        ));
+        assert_eq!(DexOracle::twap(MAIN, BTC, 1_000), Some(FixedU128::one()));
+
+        Timestamp::set_timestamp(3_000);
+
+        let twap = DexOracle::twap(MAIN, BTC, 2_000).unwrap();
+        assert!(twap > FixedU128::saturating_from_rational(99, 100));
+        assert!(twap < FixedU128::saturating_from_rational(101, 100));
+    });
+}
+
+#[test]
+fn old_observations_are_overwritten() {
+    new_test_ext().execute_with(|| {
+        for now in &[1_000, 2_000, 3_000, 4_000] {
+            poke_at(*now);
+        }
+        // Same timestamp, nothing to record.
+        poke_at(4_000);
+
+        let acc = DexOracle::accumulators((MAIN, BTC)).unwrap();
+        assert_eq!((acc.observations, acc.next_index), (3, 1));
+        assert_eq!(DexOracle::observations((MAIN, BTC), 0).timestamp, 4_000);
+        assert_eq!(DexOracle::twap(MAIN, BTC, 2_000), Some(FixedU128::one()));
+        // The observation at 1_000 is gone.
+        assert_eq!(DexOracle::twap(MAIN, BTC, 2_500), None);
+    });
+}
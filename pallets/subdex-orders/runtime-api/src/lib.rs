@@ -0,0 +1,19 @@
+//! Runtime API definition for the Subdex limit order pallet.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use sp_std::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+    pub trait DexOrdersApi<AccountId, Asset, Order> where
+        AccountId: Codec,
+        Asset: Codec,
+        Order: Codec,
+    {
+        /// Open orders selling `asset_sell` for `asset_buy`, best price first.
+        fn open_orders(asset_sell: Asset, asset_buy: Asset) -> Vec<Order>;
+        /// Open orders placed by `who`.
+        fn orders_of(who: AccountId) -> Vec<Order>;
+    }
+}
@@ -0,0 +1,523 @@
+//! # Subdex limit order pallet
+//!
+//! On-chain "sell X for Y when the price is at least P" orders.
+//!
+//! Placing an order moves the sold amount into the pallet account, where it stays escrowed
+//! until the order is filled, cancelled or expires. At the start of every block open orders
+//! are matched against the `pallet_subdex` pools: an order is filled up to the largest amount
+//! the pool can absorb without the average price of the fill falling below the order limit,
+//! so large orders are filled partially over several blocks as arbitrage brings the pool
+//! price back. The number of orders looked at per block is bounded by
+//! [`Trait::MaxOrderChecksPerBlock`]; pairs are visited round robin so a busy pair cannot
+//! starve the others.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Decode, Encode};
+use frame_support::{
+    decl_error, decl_event, decl_module, decl_storage, dispatch::DispatchResult, ensure,
+    traits::Get, transactional, weights::Weight,
+};
+use frame_system::ensure_signed;
+use pallet_subdex_router::{AssetOf, BalanceOf};
+use sp_core::U256;
+use sp_runtime::{
+    traits::{AccountIdConversion, One, Saturating, UniqueSaturatedInto, Zero},
+    DispatchError, ModuleId, RuntimeDebug,
+};
+use sp_std::{cmp::Ordering, prelude::*};
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+pub type OrderId = u64;
+
+/// Limit price of an order: it executes while `received * sell >= sold * buy`.
+#[derive(Encode, Decode, Clone, Copy, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct Price<Balance> {
+    pub buy: Balance,
+    pub sell: Balance,
+}
+
+impl<Balance: Copy + UniqueSaturatedInto<u128>> Price<Balance> {
+    /// Compare two prices without dividing.
+    pub fn cmp_price(&self, other: &Self) -> Ordering {
+        mul_wide(self.buy, other.sell).cmp(&mul_wide(other.buy, self.sell))
+    }
+
+    /// Whether receiving `received` for `sold` is at or above the price.
+    pub fn is_reached(&self, sold: Balance, received: Balance) -> bool {
+        mul_wide(received, self.sell) >= mul_wide(sold, self.buy)
+    }
+}
+
+/// `a * b`, computed in 256 bits so that it cannot overflow for balances of up to 128 bits.
+fn mul_wide<Balance: UniqueSaturatedInto<u128>>(a: Balance, b: Balance) -> U256 {
+    wide(a) * wide(b)
+}
+
+/// `a` in 256 bits.
+fn wide<Balance: UniqueSaturatedInto<u128>>(a: Balance) -> U256 {
+    U256::from(a.unique_saturated_into())
+}
+
+/// Number of times a fill amount is lowered until the pool quote reaches the order limit.
+const FILL_BACKOFF_STEPS: u32 = 8;
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct LimitOrder<AccountId, Asset, Balance, BlockNumber> {
+    pub id: OrderId,
+    pub owner: AccountId,
+    pub asset_sell: Asset,
+    pub asset_buy: Asset,
+    /// Amount of `asset_sell` still escrowed and waiting to be sold.
+    pub amount_remaining: Balance,
+    /// Amount of `asset_sell` sold so far.
+    pub amount_sold: Balance,
+    /// Amount of `asset_buy` received so far.
+    pub amount_received: Balance,
+    pub limit: Price<Balance>,
+    pub placed_at: BlockNumber,
+    pub expires_at: Option<BlockNumber>,
+}
+
+pub type LimitOrderOf<T> = LimitOrder<
+    <T as frame_system::Trait>::AccountId,
+    AssetOf<T>,
+    BalanceOf<T>,
+    <T as frame_system::Trait>::BlockNumber,
+>;
+
+/// An order in the queue of its pair, with what is needed to sort and expire it without
+/// reading the order.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct QueuedOrder<Balance, BlockNumber> {
+    pub id: OrderId,
+    pub limit: Price<Balance>,
+    pub expires_at: Option<BlockNumber>,
+}
+
+pub type QueuedOrderOf<T> = QueuedOrder<BalanceOf<T>, <T as frame_system::Trait>::BlockNumber>;
+
+pub trait Trait: pallet_subdex_router::Trait {
+    type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// Id of the account escrowing the funds of open orders.
+    type ModuleId: Get<ModuleId>;
+
+    /// Maximum number of orders looked at while matching in a single block.
+    type MaxOrderChecksPerBlock: Get<u32>;
+
+    /// Maximum number of open orders per direction of a pair.
+    type MaxOrdersPerPair: Get<u32>;
+
+    /// Maximum number of open orders per account.
+    type MaxOrdersPerAccount: Get<u32>;
+
+    /// Smallest amount sold by a single fill, so orders are not ground down into dust.
+    type MinimumFill: Get<BalanceOf<Self>>;
+}
+
+decl_storage! {
+    trait Store for Module<T: Trait> as DexOrders {
+        pub NextOrderId get(fn next_order_id): OrderId;
+
+        pub Orders get(fn orders): map hasher(twox_64_concat) OrderId => Option<LimitOrderOf<T>>;
+
+        /// Open orders per (sold asset, bought asset), lowest limit price first.
+        pub PairOrders get(fn pair_orders):
+            map hasher(blake2_128_concat) (AssetOf<T>, AssetOf<T>) => Vec<QueuedOrderOf<T>>;
+
+        /// Pairs that currently have open orders, in matching order.
+        pub ActivePairs get(fn active_pairs): Vec<(AssetOf<T>, AssetOf<T>)>;
+
+        /// Index into `ActivePairs` where matching starts in the next block.
+        pub PairCursor: u32;
+
+        pub AccountOrders get(fn account_orders):
+            map hasher(blake2_128_concat) T::AccountId => Vec<OrderId>;
+    }
+}
+
+decl_event!(
+    pub enum Event<T>
+    where
+        AccountId = <T as frame_system::Trait>::AccountId,
+        Asset = AssetOf<T>,
+        Balance = BalanceOf<T>,
+    {
+        /// Order placed: (order id, owner, asset sold, amount, asset bought).
+        OrderPlaced(OrderId, AccountId, Asset, Balance, Asset),
+        /// Order (partially) filled: (order id, owner, amount sold, amount received, amount remaining).
+        OrderFilled(OrderId, AccountId, Balance, Balance, Balance),
+        /// Order completely filled and closed.
+        OrderCompleted(OrderId, AccountId),
+        /// Order cancelled by its owner, remaining amount refunded.
+        OrderCancelled(OrderId, AccountId, Balance),
+        /// Order expired, remaining amount refunded.
+        OrderExpired(OrderId, AccountId, Balance),
+    }
+);
+
+decl_error! {
+    pub enum Error for Module<T: Trait> {
+        /// No open order with the given id.
+        UnknownOrder,
+        /// Only the owner of an order can cancel it.
+        NotOrderOwner,
+        /// Sold and bought asset are the same.
+        SameAsset,
+        /// The order amount or price is zero.
+        ZeroAmount,
+        /// The order is smaller than the minimum fill.
+        OrderTooSmall,
+        /// There is no pool for the given pair of assets.
+        PoolNotFound,
+        /// The expiry block is in the past.
+        AlreadyExpired,
+        /// Too many open orders for this pair.
+        TooManyPairOrders,
+        /// Too many open orders for this account.
+        TooManyAccountOrders,
+        /// The fill did not reach the limit price.
+        LimitNotReached,
+        /// An amount calculation overflowed.
+        Overflow,
+    }
+}
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        type Error = Error<T>;
+
+        const MaxOrderChecksPerBlock: u32 = T::MaxOrderChecksPerBlock::get();
+        const MaxOrdersPerPair: u32 = T::MaxOrdersPerPair::get();
+        const MaxOrdersPerAccount: u32 = T::MaxOrdersPerAccount::get();
+        const MinimumFill: BalanceOf<T> = T::MinimumFill::get();
+
+        fn deposit_event() = default;
+
+        fn on_initialize(now: T::BlockNumber) -> Weight {
+            Self::match_orders(now)
+        }
+
+        /// Escrow `amount` of `asset_sell` and sell it for `asset_buy` whenever at least
+        /// `limit.buy` is received per `limit.sell` sold.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(6, 6)]
+        #[transactional]
+        pub fn place_order(
+            origin,
+            asset_sell: AssetOf<T>,
+            amount: BalanceOf<T>,
+            asset_buy: AssetOf<T>,
+            limit: Price<BalanceOf<T>>,
+            expires_at: Option<T::BlockNumber>,
+        ) -> DispatchResult {
+            let owner = ensure_signed(origin)?;
+            ensure!(asset_sell != asset_buy, Error::<T>::SameAsset);
+            ensure!(
+                !amount.is_zero() && !limit.buy.is_zero() && !limit.sell.is_zero(),
+                Error::<T>::ZeroAmount
+            );
+            ensure!(amount >= T::MinimumFill::get(), Error::<T>::OrderTooSmall);
+            ensure!(
+                pallet_subdex_router::Module::<T>::reserves(asset_sell, asset_buy).is_some(),
+                Error::<T>::PoolNotFound
+            );
+            let now = <frame_system::Module<T>>::block_number();
+            if let Some(expiry) = expires_at {
+                ensure!(expiry > now, Error::<T>::AlreadyExpired);
+            }
+
+            let mut account_orders = Self::account_orders(&owner);
+            ensure!(
+                (account_orders.len() as u32) < T::MaxOrdersPerAccount::get(),
+                Error::<T>::TooManyAccountOrders
+            );
+            let pair = (asset_sell, asset_buy);
+            let mut pair_orders = Self::pair_orders(pair);
+            ensure!(
+                (pair_orders.len() as u32) < T::MaxOrdersPerPair::get(),
+                Error::<T>::TooManyPairOrders
+            );
+
+            pallet_subdex_router::Module::<T>::transfer(
+                &owner,
+                &Self::account_id(),
+                asset_sell,
+                amount,
+            )?;
+
+            let id = Self::next_order_id();
+            NextOrderId::put(id + 1);
+
+            let position = pair_orders
+                .iter()
+                .position(|other| limit.cmp_price(&other.limit) == Ordering::Less)
+                .unwrap_or_else(|| pair_orders.len());
+            pair_orders.insert(position, QueuedOrder { id, limit, expires_at });
+            if pair_orders.len() == 1 {
+                ActivePairs::<T>::append(pair);
+            }
+            PairOrders::<T>::insert(pair, pair_orders);
+            account_orders.push(id);
+            AccountOrders::<T>::insert(&owner, account_orders);
+
+            Orders::<T>::insert(id, LimitOrder {
+                id,
+                owner: owner.clone(),
+                asset_sell,
+                asset_buy,
+                amount_remaining: amount,
+                amount_sold: Zero::zero(),
+                amount_received: Zero::zero(),
+                limit,
+                placed_at: now,
+                expires_at,
+            });
+
+            Self::deposit_event(RawEvent::OrderPlaced(id, owner, asset_sell, amount, asset_buy));
+            Ok(())
+        }
+
+        /// Cancel an open order and refund what has not been sold yet.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(5, 5)]
+        #[transactional]
+        pub fn cancel_order(origin, id: OrderId) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            let order = Self::orders(id).ok_or(Error::<T>::UnknownOrder)?;
+            ensure!(order.owner == who, Error::<T>::NotOrderOwner);
+
+            let refunded = order.amount_remaining;
+            Self::close_order(order)?;
+
+            Self::deposit_event(RawEvent::OrderCancelled(id, who, refunded));
+            Ok(())
+        }
+    }
+}
+
+impl<T: Trait> Module<T> {
+    /// The account escrowing the funds of open orders.
+    pub fn account_id() -> T::AccountId {
+        T::ModuleId::get().into_account()
+    }
+
+    /// Open orders selling `asset_sell` for `asset_buy`, lowest limit price first.
+    pub fn open_orders(asset_sell: AssetOf<T>, asset_buy: AssetOf<T>) -> Vec<LimitOrderOf<T>> {
+        Self::pair_orders((asset_sell, asset_buy))
+            .into_iter()
+            .filter_map(|queued| Self::orders(queued.id))
+            .collect()
+    }
+
+    /// Open orders placed by `who`.
+    pub fn orders_of(who: &T::AccountId) -> Vec<LimitOrderOf<T>> {
+        Self::account_orders(who)
+            .into_iter()
+            .filter_map(Self::orders)
+            .collect()
+    }
+
+    /// Refund the unsold part of an order and remove it from all indices.
+    fn close_order(order: LimitOrderOf<T>) -> DispatchResult {
+        if !order.amount_remaining.is_zero() {
+            pallet_subdex_router::Module::<T>::transfer(
+                &Self::account_id(),
+                &order.owner,
+                order.asset_sell,
+                order.amount_remaining,
+            )?;
+        }
+
+        let pair = (order.asset_sell, order.asset_buy);
+        let mut pair_orders = Self::pair_orders(pair);
+        pair_orders.retain(|queued| queued.id != order.id);
+        if pair_orders.is_empty() {
+            PairOrders::<T>::remove(pair);
+            ActivePairs::<T>::mutate(|pairs| pairs.retain(|p| *p != pair));
+        } else {
+            PairOrders::<T>::insert(pair, pair_orders);
+        }
+        AccountOrders::<T>::mutate(&order.owner, |ids| ids.retain(|id| *id != order.id));
+        Orders::<T>::remove(order.id);
+        Ok(())
+    }
+
+    /// Largest amount of `order.asset_sell` that can be sold right now at or above the order limit.
+    ///
+    /// For a constant product pool with reserves `r_in`/`r_out` and fee factor `f`, selling `x`
+    /// yields `x * f * r_out / (r_in + x * f)`, so the average price stays above `buy / sell`
+    /// as long as `x <= r_out * sell / buy - r_in / f`. The pool rounds what it pays out down,
+    /// which can leave an amount at that bound just below the limit, so the amount is lowered
+    /// until its quote reaches the limit.
+    fn fillable_amount(order: &LimitOrderOf<T>) -> Result<BalanceOf<T>, DispatchError> {
+        let (reserve_in, reserve_out) =
+            pallet_subdex_router::Module::<T>::reserves(order.asset_sell, order.asset_buy)
+                .ok_or(Error::<T>::PoolNotFound)?;
+        let denominator = <T as pallet_subdex::Trait>::FeeRateDenominator::get();
+        let after_fee =
+            denominator.saturating_sub(<T as pallet_subdex::Trait>::FeeRateNominator::get());
+
+        // In 256 bits, as the limit can be far from the pool price.
+        let out_bound = mul_wide(reserve_out, order.limit.sell)
+            .checked_div(wide(order.limit.buy))
+            .ok_or(Error::<T>::Overflow)?;
+        // Rounded up, so that the bound on `x` is rounded down.
+        let in_bound = mul_wide(reserve_in, denominator)
+            .saturating_add(wide(after_fee).saturating_sub(U256::one()))
+            .checked_div(wide(after_fee))
+            .ok_or(Error::<T>::Overflow)?;
+
+        let mut amount: BalanceOf<T> = out_bound
+            .saturating_sub(in_bound)
+            .min(wide(order.amount_remaining))
+            .low_u128()
+            .unique_saturated_into();
+        for _ in 0..FILL_BACKOFF_STEPS {
+            if amount.is_zero() {
+                break;
+            }
+            let quote =
+                pallet_subdex_router::Module::<T>::amount_out(reserve_in, reserve_out, amount)?;
+            if order.limit.is_reached(amount, quote) {
+                return Ok(amount);
+            }
+            amount = amount
+                .saturating_sub(amount / 64u32.into())
+                .saturating_sub(One::one());
+        }
+        Ok(Zero::zero())
+    }
+
+    /// Sell `amount` of an escrowed order through the pool and forward the proceeds to its owner.
+    #[transactional]
+    fn fill(
+        order: &mut LimitOrderOf<T>,
+        amount: BalanceOf<T>,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        let escrow = Self::account_id();
+        let received = pallet_subdex_router::Module::<T>::do_swap(
+            &escrow,
+            order.asset_sell,
+            amount,
+            order.asset_buy,
+        )?;
+        ensure!(
+            order.limit.is_reached(amount, received),
+            Error::<T>::LimitNotReached
+        );
+        pallet_subdex_router::Module::<T>::transfer(
+            &escrow,
+            &order.owner,
+            order.asset_buy,
+            received,
+        )?;
+
+        order.amount_remaining -= amount;
+        order.amount_sold = order.amount_sold.saturating_add(amount);
+        order.amount_received = order.amount_received.saturating_add(received);
+        Ok(received)
+    }
+
+    /// Match open orders against the pools, looking at no more than
+    /// `MaxOrderChecksPerBlock` orders.
+    fn match_orders(now: T::BlockNumber) -> Weight {
+        let pairs = Self::active_pairs();
+        if pairs.is_empty() {
+            return T::DbWeight::get().reads(1);
+        }
+
+        let max_checks = T::MaxOrderChecksPerBlock::get();
+        let start = PairCursor::get() as usize % pairs.len();
+        let mut checks = 0u32;
+        let mut fills = 0u32;
+        let mut visited = 0usize;
+
+        while visited < pairs.len() && checks < max_checks {
+            let pair = pairs[(start + visited) % pairs.len()];
+            visited += 1;
+
+            // Orders are sorted by limit price, so once one cannot be filled none of the
+            // following can either, and only the expired ones are left to close.
+            let mut fillable = true;
+            for queued in Self::pair_orders(pair) {
+                let expired = queued.expires_at.map_or(false, |expiry| expiry <= now);
+                if !fillable && !expired {
+                    continue;
+                }
+                if checks >= max_checks {
+                    break;
+                }
+                checks += 1;
+
+                let id = queued.id;
+                let mut order = match Self::orders(id) {
+                    Some(order) => order,
+                    None => continue,
+                };
+
+                if expired {
+                    let refunded = order.amount_remaining;
+                    let owner = order.owner.clone();
+                    if Self::close_order(order).is_ok() {
+                        Self::deposit_event(RawEvent::OrderExpired(id, owner, refunded));
+                    }
+                    continue;
+                }
+
+                let amount = match Self::fillable_amount(&order) {
+                    Ok(amount)
+                        if !amount.is_zero()
+                            && (amount >= T::MinimumFill::get()
+                                || amount == order.amount_remaining) =>
+                    {
+                        amount
+                    }
+                    Ok(_) => {
+                        fillable = false;
+                        continue;
+                    }
+                    // An order that cannot be quoted is skipped, not left to hold back the pair.
+                    Err(_) => continue,
+                };
+
+                match Self::fill(&mut order, amount) {
+                    Ok(received) => {
+                        fills += 1;
+                        Self::deposit_event(RawEvent::OrderFilled(
+                            id,
+                            order.owner.clone(),
+                            amount,
+                            received,
+                            order.amount_remaining,
+                        ));
+                        if order.amount_remaining.is_zero() {
+                            let owner = order.owner.clone();
+                            if Self::close_order(order).is_ok() {
+                                Self::deposit_event(RawEvent::OrderCompleted(id, owner));
+                            }
+                        } else {
+                            Orders::<T>::insert(id, order);
+                        }
+                    }
+                    Err(_) => continue,
+                }
+            }
+        }
+
+        PairCursor::put(((start + visited) % pairs.len()) as u32);
+
+        T::DbWeight::get()
+            .reads_writes(2, 1)
+            .saturating_add(T::DbWeight::get().reads(3).saturating_mul(checks as Weight))
+            .saturating_add(
+                T::DbWeight::get()
+                    .reads_writes(8, 8)
+                    .saturating_add(50_000_000)
+                    .saturating_mul(fills as Weight),
+            )
+    }
+}
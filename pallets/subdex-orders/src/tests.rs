@@ -0,0 +1,283 @@
+use crate::{mock::*, Error, Price, RawEvent};
+use frame_support::{assert_noop, assert_ok, traits::OnInitialize};
+use pallet_subdex::Asset;
+use std::cmp::Ordering;
+
+/// Sell `amount` BTC for MAIN, receiving at least `buy` MAIN per `sell` BTC.
+fn sell_btc(
+    who: AccountId,
+    amount: Balance,
+    buy: Balance,
+    sell: Balance,
+    expires_at: Option<u64>,
+) -> frame_support::dispatch::DispatchResult {
+    DexOrders::place_order(
+        Origin::signed(who),
+        BTC,
+        amount,
+        MAIN,
+        Price { buy, sell },
+        expires_at,
+    )
+}
+
+fn order_events() -> Vec<RawEvent<AccountId, Asset<AssetId>, Balance>> {
+    System::events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            TestEvent::orders(event) => Some(event),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn prices_are_compared_without_overflow() {
+    let high = Price::<u128> {
+        buy: u128::MAX,
+        sell: u128::MAX - 1,
+    };
+    assert_eq!(
+        high.cmp_price(&Price { buy: 2, sell: 2 }),
+        Ordering::Greater
+    );
+    assert_eq!(Price { buy: 2, sell: 2 }.cmp_price(&high), Ordering::Less);
+
+    let limit = Price::<u128> {
+        buy: u128::MAX,
+        sell: 1,
+    };
+    assert!(!limit.is_reached(2, u128::MAX));
+    assert!(limit.is_reached(1, u128::MAX));
+}
+
+#[test]
+fn place_order_escrows_amount() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(sell_btc(ALICE, 1_000, 2, 1, None));
+
+        assert_eq!(asset_balance(ALICE, 1), 899_000);
+        assert_eq!(asset_balance(DexOrders::account_id(), 1), 1_000);
+        let order = DexOrders::orders(0).expect("the order is open");
+        assert_eq!(order.amount_remaining, 1_000);
+        assert_eq!(DexOrders::orders_of(&ALICE), vec![order]);
+        assert_eq!(DexOrders::active_pairs(), vec![(BTC, MAIN)]);
+        assert_eq!(
+            order_events(),
+            vec![RawEvent::OrderPlaced(0, ALICE, BTC, 1_000, MAIN)]
+        );
+    });
+}
+
+#[test]
+fn place_order_checks_order() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexOrders::place_order(
+                Origin::signed(ALICE),
+                BTC,
+                1_000,
+                BTC,
+                Price { buy: 1, sell: 1 },
+                None
+            ),
+            Error::<Test>::SameAsset
+        );
+        assert_noop!(
+            sell_btc(ALICE, 1_000, 0, 1, None),
+            Error::<Test>::ZeroAmount
+        );
+        assert_noop!(
+            sell_btc(ALICE, 99, 1, 1, None),
+            Error::<Test>::OrderTooSmall
+        );
+        assert_noop!(
+            DexOrders::place_order(
+                Origin::signed(ALICE),
+                Asset::ParachainAsset(2),
+                1_000,
+                MAIN,
+                Price { buy: 1, sell: 1 },
+                None
+            ),
+            Error::<Test>::PoolNotFound
+        );
+        assert_noop!(
+            sell_btc(ALICE, 1_000, 1, 1, Some(1)),
+            Error::<Test>::AlreadyExpired
+        );
+    });
+}
+
+#[test]
+fn pair_orders_are_sorted_by_limit_price() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(sell_btc(ALICE, 1_000, 3, 1, None));
+        assert_ok!(sell_btc(BOB, 1_000, 2, 1, None));
+        assert_ok!(sell_btc(ALICE, 1_000, 5, 2, None));
+
+        let ids: Vec<_> = DexOrders::pair_orders((BTC, MAIN))
+            .into_iter()
+            .map(|queued| queued.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2, 0]);
+    });
+}
+
+#[test]
+fn cancel_order_refunds_remaining_amount() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(sell_btc(ALICE, 1_000, 2, 1, None));
+
+        assert_noop!(
+            DexOrders::cancel_order(Origin::signed(BOB), 0),
+            Error::<Test>::NotOrderOwner
+        );
+        assert_ok!(DexOrders::cancel_order(Origin::signed(ALICE), 0));
+
+        assert_eq!(asset_balance(ALICE, 1), 900_000);
+        assert_eq!(asset_balance(DexOrders::account_id(), 1), 0);
+        assert_eq!(DexOrders::orders(0), None);
+        assert!(DexOrders::pair_orders((BTC, MAIN)).is_empty());
+        assert!(DexOrders::active_pairs().is_empty());
+        assert!(DexOrders::account_orders(ALICE).is_empty());
+        assert_eq!(
+            order_events().pop(),
+            Some(RawEvent::OrderCancelled(0, ALICE, 1_000))
+        );
+        assert_noop!(
+            DexOrders::cancel_order(Origin::signed(ALICE), 0),
+            Error::<Test>::UnknownOrder
+        );
+    });
+}
+
+#[test]
+fn order_below_pool_price_is_filled() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(sell_btc(ALICE, 1_000, 1, 2, None));
+        let main_before = Balances::free_balance(ALICE);
+
+        DexOrders::on_initialize(2);
+
+        let received = match order_events()[1] {
+            RawEvent::OrderFilled(0, ALICE, 1_000, received, 0) => received,
+            ref event => panic!("unexpected event {:?}", event),
+        };
+        assert!(received * 2 >= 1_000);
+        assert_eq!(Balances::free_balance(ALICE), main_before + received);
+        assert_eq!(
+            order_events().pop(),
+            Some(RawEvent::OrderCompleted(0, ALICE))
+        );
+        assert_eq!(DexOrders::orders(0), None);
+        assert!(DexOrders::active_pairs().is_empty());
+    });
+}
+
+#[test]
+fn order_above_pool_price_stays_open() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(sell_btc(ALICE, 1_000, 2, 1, None));
+
+        DexOrders::on_initialize(2);
+
+        assert_eq!(DexOrders::orders(0).unwrap().amount_remaining, 1_000);
+        assert_eq!(order_events().len(), 1);
+    });
+}
+
+#[test]
+fn large_order_is_filled_partially_at_its_limit() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(sell_btc(ALICE, 200_000, 1, 2, None));
+
+        DexOrders::on_initialize(2);
+
+        let order = DexOrders::orders(0).expect("the order stays open");
+        assert!(order.amount_sold > 0);
+        assert!(order.amount_remaining > 0);
+        assert_eq!(order.amount_sold + order.amount_remaining, 200_000);
+        assert!(order
+            .limit
+            .is_reached(order.amount_sold, order.amount_received));
+        assert_eq!(
+            asset_balance(DexOrders::account_id(), 1),
+            order.amount_remaining
+        );
+        assert_eq!(
+            order_events().pop(),
+            Some(RawEvent::OrderFilled(
+                0,
+                ALICE,
+                order.amount_sold,
+                order.amount_received,
+                order.amount_remaining
+            ))
+        );
+    });
+}
+
+#[test]
+fn expired_order_is_refunded() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(sell_btc(ALICE, 1_000, 2, 1, Some(3)));
+
+        DexOrders::on_initialize(2);
+        assert!(DexOrders::orders(0).is_some());
+
+        DexOrders::on_initialize(3);
+
+        assert_eq!(DexOrders::orders(0), None);
+        assert_eq!(asset_balance(ALICE, 1), 900_000);
+        assert_eq!(
+            order_events().pop(),
+            Some(RawEvent::OrderExpired(0, ALICE, 1_000))
+        );
+    });
+}
+
+#[test]
+fn order_far_below_pool_price_is_filled_with_the_orders_behind_it() {
+    new_test_ext().execute_with(|| {
+        // The fill bound of this limit overflows 128 bits.
+        assert_ok!(sell_btc(
+            ALICE,
+            1_000,
+            1,
+            1_000_000_000_000_000_000_000_000_000_000,
+            None
+        ));
+        assert_ok!(sell_btc(BOB, 1_000, 1, 2, None));
+
+        DexOrders::on_initialize(2);
+
+        assert_eq!(DexOrders::orders(0), None);
+        assert_eq!(DexOrders::orders(1), None);
+        assert_eq!(
+            order_events()
+                .into_iter()
+                .filter(|event| matches!(event, RawEvent::OrderCompleted(..)))
+                .count(),
+            2
+        );
+    });
+}
+
+#[test]
+fn orders_behind_an_unfillable_order_expire() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(sell_btc(ALICE, 1_000, 2, 1, None));
+        assert_ok!(sell_btc(BOB, 1_000, 3, 1, Some(3)));
+
+        DexOrders::on_initialize(3);
+
+        assert_eq!(DexOrders::orders(0).unwrap().amount_remaining, 1_000);
+        assert_eq!(DexOrders::orders(1), None);
+        assert_eq!(asset_balance(BOB, 1), 1_000_000);
+        assert_eq!(
+            order_events().pop(),
+            Some(RawEvent::OrderExpired(1, BOB, 1_000))
+        );
+    });
+}
@@ -0,0 +1,536 @@
+//! # Subdex price feed pallet
+//!
+//! Publishes DEX prices to sibling parachains over XCMP.
+//!
+//! Anyone can subscribe a sibling parachain to a pair, choosing how often it should receive an
+//! update and over which window the time-weighted average price is computed. Updates are paid
+//! for in advance: the subscriber reserves `UpdateFee` per prepaid update, and every delivered
+//! update slashes one fee from that reserve and is handed to `OnUpdateFee`. Once the prepaid
+//! updates run out the subscription ends, unless it has been topped up, even while the fee is
+//! zero. On top of the fees, every subscription reserves [`Trait::SubscriptionDeposit`] from its
+//! owner until it ends, so that subscriptions are never free to open.
+//!
+//! Updates arrive as [`SubdexXcmpMessage::PriceFeed`], signed by the `PriceSigner` key set by
+//! root. The prices due in a block are kept in `PendingFeeds` until the offchain worker of a
+//! collator holding that key in its keystore, under [`KEY_TYPE`], signs them and submits the
+//! signatures with `publish_signed`. Only then are the updates sent and their fees paid, so
+//! subscribers can check every price against the published signer key, wherever the message
+//! was relayed from. Updates left unsigned for `SigningPeriod` blocks are dropped.
+//!
+//! The number of updates prepared per block is bounded by [`Trait::MaxUpdatesPerBlock`];
+//! updates that do not fit are postponed to the next block.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Decode, Encode};
+use cumulus_primitives::ParaId;
+use frame_support::{
+    debug, decl_error, decl_event, decl_module, decl_storage, ensure,
+    traits::{Currency, Get, OnUnbalanced, ReservableCurrency},
+    weights::Weight,
+};
+use frame_system::{
+    ensure_none, ensure_root, ensure_signed,
+    offchain::{SendTransactionTypes, SubmitTransaction},
+};
+use pallet_subdex_oracle::Twap;
+use pallet_subdex_router::{AssetOf, MomentOf};
+use pallet_subdex_xcmp_router::{
+    PriceFeedMessage, SignedPriceFeedMessage, SubdexXcmpMessage, SubscriptionId,
+};
+use sp_core::sr25519;
+use sp_runtime::{
+    traits::{Saturating, UniqueSaturatedInto, Zero},
+    transaction_validity::{
+        InvalidTransaction, TransactionPriority, TransactionSource, TransactionValidity,
+        ValidTransaction,
+    },
+    KeyTypeId, RuntimeDebug,
+};
+use sp_std::prelude::*;
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+/// Key type of the price signer key in the keystore of collators.
+pub const KEY_TYPE: KeyTypeId = KeyTypeId(*b"sdxp");
+
+type BalanceOf<T> =
+    <<T as Trait>::Currency as Currency<<T as frame_system::Trait>::AccountId>>::Balance;
+
+type NegativeImbalanceOf<T> =
+    <<T as Trait>::Currency as Currency<<T as frame_system::Trait>::AccountId>>::NegativeImbalance;
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct Subscription<AccountId, Asset, Balance, BlockNumber, Moment> {
+    /// Account paying for the updates.
+    pub owner: AccountId,
+    /// Parachain the updates are sent to.
+    pub para_id: ParaId,
+    pub base: Asset,
+    pub quote: Asset,
+    /// Number of blocks between two updates.
+    pub interval: BlockNumber,
+    /// Window of the time-weighted average price, in milliseconds.
+    pub twap_window: Moment,
+    /// Amount reserved from `owner` that is left to pay for updates.
+    pub prepaid: Balance,
+    /// Number of updates left to send.
+    pub updates: u32,
+    /// Amount reserved from `owner` until the subscription ends.
+    pub deposit: Balance,
+}
+
+pub type SubscriptionOf<T> = Subscription<
+    <T as frame_system::Trait>::AccountId,
+    AssetOf<T>,
+    BalanceOf<T>,
+    <T as frame_system::Trait>::BlockNumber,
+    MomentOf<T>,
+>;
+
+pub type PriceFeedMessageOf<T> =
+    PriceFeedMessage<<T as pallet_subdex::Trait>::AssetId, MomentOf<T>>;
+
+pub trait Trait: pallet_subdex_xcmp_router::Trait + SendTransactionTypes<Call<Self>> {
+    type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// Currency the update fees are paid in.
+    type Currency: ReservableCurrency<Self::AccountId>;
+
+    /// Handler for the paid update fees.
+    type OnUpdateFee: OnUnbalanced<NegativeImbalanceOf<Self>>;
+
+    /// Source of the published prices.
+    type Oracle: Twap<AssetOf<Self>, MomentOf<Self>>;
+
+    /// Amount reserved from the owner of a subscription until it ends, whatever the update fee.
+    type SubscriptionDeposit: Get<BalanceOf<Self>>;
+
+    /// Smallest allowed number of blocks between two updates of a subscription.
+    type MinInterval: Get<Self::BlockNumber>;
+
+    /// Maximum number of updates prepared in a single block.
+    type MaxUpdatesPerBlock: Get<u32>;
+
+    /// Number of blocks the updates of a block can be signed in before they are dropped.
+    type SigningPeriod: Get<Self::BlockNumber>;
+
+    /// Priority of the transactions publishing signed updates.
+    type UnsignedPriority: Get<TransactionPriority>;
+}
+
+decl_storage! {
+    trait Store for Module<T: Trait> as DexPriceFeed {
+        /// Fee charged for every delivered update.
+        pub UpdateFee get(fn update_fee) config(): BalanceOf<T>;
+
+        pub NextSubscriptionId get(fn next_subscription_id): SubscriptionId;
+
+        pub Subscriptions get(fn subscriptions):
+            map hasher(twox_64_concat) SubscriptionId => Option<SubscriptionOf<T>>;
+
+        /// Subscriptions due for an update, per block.
+        pub Schedule get(fn schedule):
+            map hasher(twox_64_concat) T::BlockNumber => Vec<SubscriptionId>;
+
+        /// Key price updates are signed with.
+        pub PriceSigner get(fn price_signer): Option<sr25519::Public>;
+
+        /// Updates waiting to be signed, per block they were prepared in.
+        pub PendingFeeds get(fn pending_feeds):
+            map hasher(twox_64_concat) T::BlockNumber => Vec<PriceFeedMessageOf<T>>;
+    }
+    add_extra_genesis {
+        config(price_signer): Option<sr25519::Public>;
+        build(|config: &GenesisConfig<T>| {
+            if let Some(signer) = config.price_signer {
+                PriceSigner::put(signer);
+            }
+        });
+    }
+}
+
+decl_event!(
+    pub enum Event<T>
+    where
+        AccountId = <T as frame_system::Trait>::AccountId,
+        Asset = AssetOf<T>,
+        Balance = BalanceOf<T>,
+    {
+        /// Subscription created: (id, owner, parachain, base, quote).
+        Subscribed(SubscriptionId, AccountId, ParaId, Asset, Asset),
+        /// More updates paid for: (id, amount added).
+        ToppedUp(SubscriptionId, Balance),
+        /// Subscription cancelled by its owner: (id, released prepaid fees and deposit).
+        Unsubscribed(SubscriptionId, Balance),
+        /// Prepaid updates of a subscription ran out.
+        SubscriptionExhausted(SubscriptionId),
+        /// Price update sent: (id, parachain, fee paid).
+        PriceSent(SubscriptionId, ParaId, Balance),
+        /// Price update could not be sent or was not signed in time, it will be retried at the
+        /// next interval.
+        PriceNotSent(SubscriptionId, ParaId),
+        /// Update fee changed.
+        UpdateFeeSet(Balance),
+        /// Price signer key changed.
+        PriceSignerSet(Option<sr25519::Public>),
+    }
+);
+
+decl_error! {
+    pub enum Error for Module<T: Trait> {
+        /// No subscription with the given id.
+        UnknownSubscription,
+        /// Only the owner of a subscription can change it.
+        NotSubscriptionOwner,
+        /// Base and quote are the same asset.
+        SameAsset,
+        /// There is no pool for the given pair of assets.
+        PoolNotFound,
+        /// The update interval is below `MinInterval`.
+        IntervalTooShort,
+        /// At least one update has to be paid for.
+        NoUpdatesPaid,
+        /// No price signer key is set.
+        NoPriceSigner,
+        /// No updates of the given block wait to be signed.
+        NoPendingFeeds,
+        /// The signatures do not match the updates waiting to be signed.
+        BadSignature,
+    }
+}
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        type Error = Error<T>;
+
+        const SubscriptionDeposit: BalanceOf<T> = T::SubscriptionDeposit::get();
+        const MinInterval: T::BlockNumber = T::MinInterval::get();
+        const MaxUpdatesPerBlock: u32 = T::MaxUpdatesPerBlock::get();
+        const SigningPeriod: T::BlockNumber = T::SigningPeriod::get();
+
+        fn deposit_event() = default;
+
+        fn on_initialize(now: T::BlockNumber) -> Weight {
+            Self::expire(now).saturating_add(Self::prepare(now))
+        }
+
+        fn offchain_worker(now: T::BlockNumber) {
+            Self::sign(now);
+        }
+
+        /// Send `base`/`quote` prices to `para_id` every `interval` blocks, prepaying `updates`
+        /// updates.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(3, 4)]
+        pub fn subscribe(
+            origin,
+            para_id: ParaId,
+            base: AssetOf<T>,
+            quote: AssetOf<T>,
+            interval: T::BlockNumber,
+            twap_window: MomentOf<T>,
+            updates: u32,
+        ) {
+            let owner = ensure_signed(origin)?;
+            ensure!(base != quote, Error::<T>::SameAsset);
+            ensure!(
+                pallet_subdex_router::Module::<T>::reserves(base, quote).is_some(),
+                Error::<T>::PoolNotFound
+            );
+            ensure!(interval >= T::MinInterval::get(), Error::<T>::IntervalTooShort);
+            ensure!(updates > 0, Error::<T>::NoUpdatesPaid);
+
+            let prepaid = Self::update_fee().saturating_mul(updates.into());
+            let deposit = T::SubscriptionDeposit::get();
+            T::Currency::reserve(&owner, prepaid.saturating_add(deposit))?;
+
+            let id = Self::next_subscription_id();
+            NextSubscriptionId::put(id + 1);
+            Subscriptions::<T>::insert(id, Subscription {
+                owner: owner.clone(),
+                para_id,
+                base,
+                quote,
+                interval,
+                twap_window,
+                prepaid,
+                updates,
+                deposit,
+            });
+            let next = <frame_system::Module<T>>::block_number() + 1u32.into();
+            Schedule::<T>::append(next, id);
+
+            Self::deposit_event(RawEvent::Subscribed(id, owner, para_id, base, quote));
+        }
+
+        /// Prepay `updates` more updates of a subscription.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(2, 2)]
+        pub fn top_up(origin, id: SubscriptionId, updates: u32) {
+            let who = ensure_signed(origin)?;
+            ensure!(updates > 0, Error::<T>::NoUpdatesPaid);
+            let mut subscription = Self::subscriptions(id).ok_or(Error::<T>::UnknownSubscription)?;
+            ensure!(subscription.owner == who, Error::<T>::NotSubscriptionOwner);
+
+            let amount = Self::update_fee().saturating_mul(updates.into());
+            T::Currency::reserve(&who, amount)?;
+            subscription.prepaid = subscription.prepaid.saturating_add(amount);
+            subscription.updates = subscription.updates.saturating_add(updates);
+            Subscriptions::<T>::insert(id, subscription);
+
+            Self::deposit_event(RawEvent::ToppedUp(id, amount));
+        }
+
+        /// Cancel a subscription and release its deposit and what is left of its prepaid fees.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(2, 2)]
+        pub fn unsubscribe(origin, id: SubscriptionId) {
+            let who = ensure_signed(origin)?;
+            let subscription = Self::subscriptions(id).ok_or(Error::<T>::UnknownSubscription)?;
+            ensure!(subscription.owner == who, Error::<T>::NotSubscriptionOwner);
+
+            let released = Self::release(&subscription);
+            Subscriptions::<T>::remove(id);
+
+            Self::deposit_event(RawEvent::Unsubscribed(id, released));
+        }
+
+        /// Set the fee charged for every delivered update.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn set_update_fee(origin, fee: BalanceOf<T>) {
+            ensure_root(origin)?;
+            UpdateFee::<T>::put(fee);
+            Self::deposit_event(RawEvent::UpdateFeeSet(fee));
+        }
+
+        /// Set the key price updates are signed with, `None` stopping updates.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn set_price_signer(origin, signer: Option<sr25519::Public>) {
+            ensure_root(origin)?;
+            match signer {
+                Some(signer) => PriceSigner::put(signer),
+                None => PriceSigner::kill(),
+            }
+            Self::deposit_event(RawEvent::PriceSignerSet(signer));
+        }
+
+        /// Send the updates prepared in `block` with their `signatures`, in the order of
+        /// `PendingFeeds`.
+        #[weight = 10_000
+            + T::DbWeight::get().reads_writes(3, 1)
+            + (25_000_000 + T::DbWeight::get().reads_writes(6, 4)) * signatures.len() as Weight]
+        pub fn publish_signed(
+            origin,
+            block: T::BlockNumber,
+            signatures: Vec<sr25519::Signature>,
+        ) {
+            ensure_none(origin)?;
+            let (signer, feeds) = Self::check_signatures(block, &signatures)?;
+            PendingFeeds::<T>::remove(block);
+
+            let fee = Self::update_fee();
+            for (feed, signature) in feeds.into_iter().zip(signatures) {
+                Self::deliver(SignedPriceFeedMessage { feed, signer, signature }, fee);
+            }
+        }
+    }
+}
+
+impl<T: Trait> Module<T> {
+    /// Drop the updates that were not signed within `SigningPeriod`.
+    fn expire(now: T::BlockNumber) -> Weight {
+        let period = T::SigningPeriod::get();
+        if now < period {
+            return 0;
+        }
+        let expired = PendingFeeds::<T>::take(now - period);
+        for feed in &expired {
+            if let Some(subscription) = Self::subscriptions(feed.subscription) {
+                Self::deposit_event(RawEvent::PriceNotSent(
+                    feed.subscription,
+                    subscription.para_id,
+                ));
+            }
+        }
+        T::DbWeight::get().reads_writes(1 + expired.len() as Weight, 1)
+    }
+
+    /// Prepare the updates due in block `now` for signing.
+    fn prepare(now: T::BlockNumber) -> Weight {
+        let mut due = Schedule::<T>::take(now);
+        if due.is_empty() {
+            return T::DbWeight::get().reads_writes(1, 1);
+        }
+
+        let max_updates = T::MaxUpdatesPerBlock::get() as usize;
+        if due.len() > max_updates {
+            let postponed = due.split_off(max_updates);
+            Schedule::<T>::mutate(now + 1u32.into(), |next| next.extend(postponed));
+        }
+
+        let fee = Self::update_fee();
+        let processed = due.len() as Weight;
+        let feeds: Vec<_> = due
+            .into_iter()
+            .filter_map(|id| {
+                let subscription = Self::subscriptions(id)?;
+                Self::prepare_feed(id, subscription, fee, now)
+            })
+            .collect();
+        if !feeds.is_empty() {
+            PendingFeeds::<T>::insert(now, feeds);
+        }
+
+        T::DbWeight::get().reads_writes(3, 3).saturating_add(
+            T::DbWeight::get()
+                .reads_writes(6, 3)
+                .saturating_add(25_000_000)
+                .saturating_mul(processed),
+        )
+    }
+
+    fn prepare_feed(
+        id: SubscriptionId,
+        subscription: SubscriptionOf<T>,
+        fee: BalanceOf<T>,
+        now: T::BlockNumber,
+    ) -> Option<PriceFeedMessageOf<T>> {
+        if subscription.updates == 0 || subscription.prepaid < fee {
+            Self::release(&subscription);
+            Subscriptions::<T>::remove(id);
+            Self::deposit_event(RawEvent::SubscriptionExhausted(id));
+            return None;
+        }
+
+        Schedule::<T>::append(now + subscription.interval, id);
+
+        let spot_price = match T::Oracle::spot_price(subscription.base, subscription.quote) {
+            Some(price) => price,
+            None => {
+                Self::deposit_event(RawEvent::PriceNotSent(id, subscription.para_id));
+                return None;
+            }
+        };
+        Some(PriceFeedMessage {
+            subscription: id,
+            base: subscription.base,
+            quote: subscription.quote,
+            spot_price,
+            twap: T::Oracle::twap(
+                subscription.base,
+                subscription.quote,
+                subscription.twap_window,
+            ),
+            twap_window: subscription.twap_window,
+            timestamp: pallet_timestamp::Module::<T>::get(),
+        })
+    }
+
+    /// Release the deposit and the prepaid fees left of a subscription, returning their sum.
+    fn release(subscription: &SubscriptionOf<T>) -> BalanceOf<T> {
+        let released = subscription.prepaid.saturating_add(subscription.deposit);
+        T::Currency::unreserve(&subscription.owner, released);
+        released
+    }
+
+    /// Sign the updates prepared in block `now` if the price signer key is in the keystore.
+    fn sign(now: T::BlockNumber) {
+        let feeds = Self::pending_feeds(now);
+        let signer = match Self::price_signer() {
+            Some(signer) if !feeds.is_empty() => signer,
+            _ => return,
+        };
+        if !sp_io::crypto::sr25519_public_keys(KEY_TYPE).contains(&signer) {
+            return;
+        }
+
+        let signatures = feeds
+            .iter()
+            .map(|feed| sp_io::crypto::sr25519_sign(KEY_TYPE, &signer, &feed.encode()))
+            .collect::<Option<Vec<_>>>();
+        let signatures = match signatures {
+            Some(signatures) => signatures,
+            None => {
+                debug::warn!("Unable to sign the price updates of block {:?}", now);
+                return;
+            }
+        };
+        let call = Call::publish_signed(now, signatures);
+        if SubmitTransaction::<T, Call<T>>::submit_unsigned_transaction(call.into()).is_err() {
+            debug::warn!("Unable to submit the price updates of block {:?}", now);
+        }
+    }
+
+    /// Price signer and updates prepared in `block`, if `signatures` sign them all.
+    fn check_signatures(
+        block: T::BlockNumber,
+        signatures: &[sr25519::Signature],
+    ) -> Result<(sr25519::Public, Vec<PriceFeedMessageOf<T>>), Error<T>> {
+        let signer = Self::price_signer().ok_or(Error::<T>::NoPriceSigner)?;
+        let feeds = Self::pending_feeds(block);
+        ensure!(!feeds.is_empty(), Error::<T>::NoPendingFeeds);
+        ensure!(feeds.len() == signatures.len(), Error::<T>::BadSignature);
+        let signed = feeds.iter().zip(signatures).all(|(feed, signature)| {
+            sp_io::crypto::sr25519_verify(signature, &feed.encode(), &signer)
+        });
+        ensure!(signed, Error::<T>::BadSignature);
+        Ok((signer, feeds))
+    }
+
+    fn deliver(
+        message: SignedPriceFeedMessage<<T as pallet_subdex::Trait>::AssetId, MomentOf<T>>,
+        fee: BalanceOf<T>,
+    ) {
+        let id = message.feed.subscription;
+        let mut subscription = match Self::subscriptions(id) {
+            Some(subscription) => subscription,
+            None => return,
+        };
+        let para_id = subscription.para_id;
+        // The fee may have been raised since the update was prepared.
+        if subscription.prepaid < fee {
+            Self::deposit_event(RawEvent::PriceNotSent(id, para_id));
+            return;
+        }
+
+        let message = SubdexXcmpMessage::PriceFeed(message);
+        if pallet_subdex_xcmp_router::Module::<T>::send(para_id, message).is_err() {
+            Self::deposit_event(RawEvent::PriceNotSent(id, para_id));
+            return;
+        }
+
+        if !fee.is_zero() {
+            let (paid, _) = T::Currency::slash_reserved(&subscription.owner, fee);
+            T::OnUpdateFee::on_unbalanced(paid);
+        }
+        subscription.prepaid -= fee;
+        subscription.updates = subscription.updates.saturating_sub(1);
+        Subscriptions::<T>::insert(id, subscription);
+
+        Self::deposit_event(RawEvent::PriceSent(id, para_id, fee));
+    }
+}
+
+impl<T: Trait> frame_support::unsigned::ValidateUnsigned for Module<T> {
+    type Call = Call<T>;
+
+    fn validate_unsigned(_source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+        let (block, signatures) = match call {
+            Call::publish_signed(block, signatures) => (block, signatures),
+            _ => return InvalidTransaction::Call.into(),
+        };
+        match Self::check_signatures(*block, signatures) {
+            Ok(_) => {}
+            Err(Error::<T>::NoPendingFeeds) => return InvalidTransaction::Stale.into(),
+            Err(_) => return InvalidTransaction::BadProof.into(),
+        }
+
+        ValidTransaction::with_tag_prefix("DexPriceFeed")
+            .priority(T::UnsignedPriority::get())
+            .and_provides(block)
+            .longevity(T::SigningPeriod::get().unique_saturated_into())
+            .propagate(true)
+            .build()
+    }
+}
@@ -0,0 +1,291 @@
+use crate::{Module, Trait};
+use codec::{Decode, Encode};
+use cumulus_primitives::{
+    xcmp::XCMPMessageSender, ParaId, UpwardMessageOrigin, UpwardMessageSender,
+};
+use cumulus_upward_message::BalancesMessage;
+use frame_support::{
+    impl_outer_dispatch, impl_outer_event, impl_outer_origin, parameter_types, weights::Weight,
+};
+use frame_system::offchain::SendTransactionTypes;
+use pallet_subdex::Asset;
+use pallet_subdex_oracle::Twap;
+use pallet_subdex_router::AssetOf;
+use pallet_subdex_xcmp_router::VersionedXcmpMessageOf;
+use sp_core::H256;
+use sp_runtime::{
+    testing::{Header, TestXt},
+    traits::{BlakeTwo256, IdentityLookup},
+    transaction_validity::TransactionPriority,
+    FixedU128, ModuleId, Perbill,
+};
+use std::cell::RefCell;
+
+pub type AccountId = u64;
+pub type Balance = u128;
+pub type AssetId = u64;
+
+pub const ALICE: AccountId = 1;
+pub const BOB: AccountId = 2;
+
+pub const SIBLING: u32 = 200;
+
+pub const MAIN: Asset<AssetId> = Asset::MainNetworkCurrency;
+pub const BTC: Asset<AssetId> = Asset::ParachainAsset(1);
+
+impl_outer_origin! {
+    pub enum Origin for Test {}
+}
+
+impl_outer_dispatch! {
+    pub enum Call for Test where origin: Origin {
+        frame_system::System,
+        price_feed::DexPriceFeed,
+    }
+}
+
+mod price_feed {
+    pub use crate::Event;
+}
+
+impl_outer_event! {
+    pub enum TestEvent for Test {
+        frame_system<T>,
+        pallet_balances<T>,
+        pallet_subdex<T>,
+        pallet_subdex_xcmp<T>,
+        pallet_subdex_router<T>,
+        pallet_subdex_xcmp_router<T>,
+        price_feed<T>,
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Test;
+
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::one();
+}
+
+impl frame_system::Trait for Test {
+    type BaseCallFilter = ();
+    type Origin = Origin;
+    type Call = Call;
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId;
+    type Lookup = IdentityLookup<AccountId>;
+    type Header = Header;
+    type Event = TestEvent;
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type ModuleToIndex = ();
+    type AccountData = pallet_balances::AccountData<Balance>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+}
+
+impl<C> SendTransactionTypes<C> for Test
+where
+    Call: From<C>,
+{
+    type OverarchingCall = Call;
+    type Extrinsic = TestXt<Call, ()>;
+}
+
+parameter_types! {
+    pub const ExistentialDeposit: Balance = 1;
+}
+
+impl pallet_balances::Trait for Test {
+    type Balance = Balance;
+    type Event = TestEvent;
+    type DustRemoval = ();
+    type ExistentialDeposit = ExistentialDeposit;
+    type AccountStore = System;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const MinimumPeriod: u64 = 1;
+}
+
+impl pallet_timestamp::Trait for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const FeeRateNominator: Balance = 3;
+    pub const FeeRateDenominator: Balance = 1000;
+    pub const MinMainNetworkAssetAmount: Balance = 1000;
+    pub const MinParachainAssetAmount: Balance = 1000;
+}
+
+impl pallet_subdex::Trait for Test {
+    type Event = TestEvent;
+    type Currency = Balances;
+    type IMoment = u64;
+    type AssetId = AssetId;
+    type FeeRateNominator = FeeRateNominator;
+    type FeeRateDenominator = FeeRateDenominator;
+    type MinMainNetworkAssetAmount = MinMainNetworkAssetAmount;
+    type MinParachainAssetAmount = MinParachainAssetAmount;
+}
+
+impl pallet_subdex_router::Trait for Test {
+    type Event = TestEvent;
+    type OnPoolUpdate = ();
+    type DexPaused = ();
+}
+
+/// Upward transfer as the relay chain would receive it.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct Transfer(pub AccountId, pub Balance);
+
+impl BalancesMessage<AccountId, Balance> for Transfer {
+    fn transfer(dest: AccountId, amount: Balance) -> Self {
+        Transfer(dest, amount)
+    }
+}
+
+pub struct MockUpwardMessageSender;
+
+impl UpwardMessageSender<Transfer> for MockUpwardMessageSender {
+    fn send_upward_message(_: &Transfer, _: UpwardMessageOrigin) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+impl pallet_subdex_xcmp::Trait for Test {
+    type Event = TestEvent;
+    type UpwardMessageSender = MockUpwardMessageSender;
+    type UpwardMessage = Transfer;
+    type XCMPMessageSender = XcmpRouter;
+}
+
+thread_local! {
+    static SENT: RefCell<Vec<(ParaId, VersionedXcmpMessageOf<Test>)>> = RefCell::new(Vec::new());
+    static FAIL_SEND: RefCell<bool> = RefCell::new(false);
+}
+
+pub struct MockXcmpMessageSender;
+
+impl XCMPMessageSender<VersionedXcmpMessageOf<Test>> for MockXcmpMessageSender {
+    fn send_xcmp_message(dest: ParaId, message: &VersionedXcmpMessageOf<Test>) -> Result<(), ()> {
+        if FAIL_SEND.with(|fail| *fail.borrow()) {
+            return Err(());
+        }
+        SENT.with(|sent| sent.borrow_mut().push((dest, message.clone())));
+        Ok(())
+    }
+}
+
+/// XCMP messages sent so far.
+pub fn sent_messages() -> Vec<(ParaId, VersionedXcmpMessageOf<Test>)> {
+    SENT.with(|sent| sent.borrow().clone())
+}
+
+/// Make sibling parachains refuse XCMP messages.
+pub fn fail_sending() {
+    FAIL_SEND.with(|fail| *fail.borrow_mut() = true);
+}
+
+parameter_types! {
+    pub const XcmpRouterModuleId: ModuleId = ModuleId(*b"sdx/xcmp");
+    pub const SelfParaId: ParaId = ParaId::from(100);
+    pub const MaxTrappedAccounts: u32 = 100;
+}
+
+impl pallet_subdex_xcmp_router::Trait for Test {
+    type Event = TestEvent;
+    type XCMPMessageSender = MockXcmpMessageSender;
+    type ModuleId = XcmpRouterModuleId;
+    type MessageTracer = ();
+    type SelfParaId = SelfParaId;
+    type MaxTrappedAccounts = MaxTrappedAccounts;
+}
+
+/// Prices of one for every pair with a pool, without history.
+pub struct MockOracle;
+
+impl Twap<AssetOf<Test>, u64> for MockOracle {
+    fn twap(_: AssetOf<Test>, _: AssetOf<Test>, _: u64) -> Option<FixedU128> {
+        None
+    }
+
+    fn spot_price(base: AssetOf<Test>, quote: AssetOf<Test>) -> Option<FixedU128> {
+        pallet_subdex_router::Module::<Test>::reserves(base, quote).map(|_| FixedU128::from(1))
+    }
+}
+
+parameter_types! {
+    pub const SubscriptionDeposit: Balance = 1_000;
+    pub const MinInterval: u64 = 10;
+    pub const MaxUpdatesPerBlock: u32 = 2;
+    pub const SigningPeriod: u64 = 3;
+    pub const UnsignedPriority: TransactionPriority = 100;
+}
+
+impl Trait for Test {
+    type Event = TestEvent;
+    type Currency = Balances;
+    type OnUpdateFee = ();
+    type Oracle = MockOracle;
+    type SubscriptionDeposit = SubscriptionDeposit;
+    type MinInterval = MinInterval;
+    type MaxUpdatesPerBlock = MaxUpdatesPerBlock;
+    type SigningPeriod = SigningPeriod;
+    type UnsignedPriority = UnsignedPriority;
+}
+
+pub type System = frame_system::Module<Test>;
+pub type Balances = pallet_balances::Module<Test>;
+pub type Timestamp = pallet_timestamp::Module<Test>;
+pub type Dex = pallet_subdex::Module<Test>;
+pub type XcmpRouter = pallet_subdex_xcmp_router::Module<Test>;
+pub type DexPriceFeed = Module<Test>;
+
+/// Alice and Bob hold 1_000_000, Alice created a `MAIN`/`BTC` pool, updates cost 100 and
+/// subscriptions reserve a deposit of 1_000.
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let mut storage = frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    pallet_balances::GenesisConfig::<Test> {
+        balances: vec![(ALICE, 1_000_000), (BOB, 1_000_000)],
+    }
+    .assimilate_storage(&mut storage)
+    .unwrap();
+    crate::GenesisConfig::<Test> {
+        update_fee: 100,
+        price_signer: None,
+    }
+    .assimilate_storage(&mut storage)
+    .unwrap();
+
+    let mut ext = sp_io::TestExternalities::new(storage);
+    ext.execute_with(|| {
+        System::set_block_number(1);
+        Timestamp::set_timestamp(1_000);
+        pallet_subdex::AssetBalances::<Test>::insert(ALICE, 1, 1_000_000);
+        Dex::initialize_exchange(Origin::signed(ALICE), MAIN, 100_000, BTC, 100_000)
+            .expect("the pool is created");
+    });
+    ext
+}
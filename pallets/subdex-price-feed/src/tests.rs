@@ -0,0 +1,324 @@
+use crate::{mock::*, Error, RawEvent};
+use codec::Encode;
+use frame_support::{
+    assert_noop, assert_ok, dispatch::DispatchResult, traits::OnInitialize,
+    unsigned::ValidateUnsigned,
+};
+use pallet_subdex_router::AssetOf;
+use pallet_subdex_xcmp_router::SubdexXcmpMessage;
+use sp_core::{sr25519, Pair};
+use sp_runtime::transaction_validity::{
+    InvalidTransaction, TransactionSource, TransactionValidityError,
+};
+
+fn signer() -> sr25519::Pair {
+    sr25519::Pair::from_string("//Alice", None).expect("static values are valid; qed")
+}
+
+fn set_signer() {
+    assert_ok!(DexPriceFeed::set_price_signer(
+        Origin::root(),
+        Some(signer().public())
+    ));
+}
+
+/// Subscribe `SIBLING` to `MAIN`/`BTC` every 10 blocks, prepaying `updates` updates.
+fn subscribe(who: AccountId, updates: u32) -> DispatchResult {
+    DexPriceFeed::subscribe(
+        Origin::signed(who),
+        SIBLING.into(),
+        MAIN,
+        BTC,
+        10,
+        1_000,
+        updates,
+    )
+}
+
+/// Signatures of the updates prepared in `block` by `pair`.
+fn sign(pair: &sr25519::Pair, block: u64) -> Vec<sr25519::Signature> {
+    DexPriceFeed::pending_feeds(block)
+        .iter()
+        .map(|feed| pair.sign(&feed.encode()))
+        .collect()
+}
+
+fn validate(
+    block: u64,
+    signatures: Vec<sr25519::Signature>,
+) -> Result<(), TransactionValidityError> {
+    DexPriceFeed::validate_unsigned(
+        TransactionSource::External,
+        &crate::Call::publish_signed(block, signatures),
+    )
+    .map(drop)
+}
+
+fn feed_events() -> Vec<RawEvent<AccountId, AssetOf<Test>, Balance>> {
+    System::events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            TestEvent::price_feed(event) => Some(event),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn subscribe_reserves_prepaid_updates() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(subscribe(BOB, 3));
+
+        assert_eq!(Balances::reserved_balance(BOB), 1_300);
+        let subscription = DexPriceFeed::subscriptions(0).expect("the subscription exists");
+        assert_eq!(subscription.prepaid, 300);
+        assert_eq!(subscription.updates, 3);
+        assert_eq!(subscription.deposit, 1_000);
+        assert_eq!(DexPriceFeed::schedule(2), vec![0]);
+        assert_eq!(
+            feed_events(),
+            vec![RawEvent::Subscribed(0, BOB, SIBLING.into(), MAIN, BTC)]
+        );
+
+        assert_ok!(DexPriceFeed::top_up(Origin::signed(BOB), 0, 2));
+        assert_eq!(DexPriceFeed::subscriptions(0).unwrap().prepaid, 500);
+        assert_eq!(DexPriceFeed::subscriptions(0).unwrap().updates, 5);
+        assert_noop!(
+            DexPriceFeed::top_up(Origin::signed(ALICE), 0, 2),
+            Error::<Test>::NotSubscriptionOwner
+        );
+
+        assert_ok!(DexPriceFeed::unsubscribe(Origin::signed(BOB), 0));
+        assert_eq!(Balances::reserved_balance(BOB), 0);
+        assert_eq!(DexPriceFeed::subscriptions(0), None);
+        assert_eq!(feed_events().pop(), Some(RawEvent::Unsubscribed(0, 1_500)));
+    });
+}
+
+#[test]
+fn subscribe_checks_subscription() {
+    new_test_ext().execute_with(|| {
+        let subscribe = |base, quote, interval, updates| {
+            DexPriceFeed::subscribe(
+                Origin::signed(BOB),
+                SIBLING.into(),
+                base,
+                quote,
+                interval,
+                1_000,
+                updates,
+            )
+        };
+
+        assert_noop!(subscribe(MAIN, MAIN, 10, 1), Error::<Test>::SameAsset);
+        assert_noop!(
+            subscribe(MAIN, pallet_subdex::Asset::ParachainAsset(2), 10, 1),
+            Error::<Test>::PoolNotFound
+        );
+        assert_noop!(subscribe(MAIN, BTC, 9, 1), Error::<Test>::IntervalTooShort);
+        assert_noop!(subscribe(MAIN, BTC, 10, 0), Error::<Test>::NoUpdatesPaid);
+    });
+}
+
+#[test]
+fn signed_update_is_sent_and_paid() {
+    new_test_ext().execute_with(|| {
+        set_signer();
+        assert_ok!(subscribe(BOB, 2));
+        let issuance = Balances::total_issuance();
+
+        DexPriceFeed::on_initialize(2);
+
+        // Nothing is sent or paid before the update is signed.
+        assert!(sent_messages().is_empty());
+        assert_eq!(DexPriceFeed::pending_feeds(2).len(), 1);
+        assert_eq!(DexPriceFeed::schedule(12), vec![0]);
+
+        let signatures = sign(&signer(), 2);
+        assert_ok!(validate(2, signatures.clone()));
+        assert_ok!(DexPriceFeed::publish_signed(Origin::none(), 2, signatures));
+
+        let (dest, message) = sent_messages().pop().expect("the update is sent");
+        assert_eq!(dest, SIBLING.into());
+        match message.message() {
+            SubdexXcmpMessage::PriceFeed(signed) => {
+                assert!(signed.verify());
+                assert_eq!(signed.signer, signer().public());
+                assert_eq!(signed.feed.subscription, 0);
+                assert_eq!(signed.feed.timestamp, 1_000);
+            }
+            message => panic!("unexpected message {:?}", message),
+        }
+        assert!(DexPriceFeed::pending_feeds(2).is_empty());
+        assert_eq!(Balances::reserved_balance(BOB), 1_100);
+        assert_eq!(Balances::total_issuance(), issuance - 100);
+        assert_eq!(DexPriceFeed::subscriptions(0).unwrap().prepaid, 100);
+        assert_eq!(
+            feed_events().pop(),
+            Some(RawEvent::PriceSent(0, SIBLING.into(), 100))
+        );
+
+        // Signatures cannot be replayed.
+        assert_eq!(
+            validate(2, sign(&signer(), 2)),
+            Err(InvalidTransaction::Stale.into())
+        );
+    });
+}
+
+#[test]
+fn updates_need_signatures_of_price_signer() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(subscribe(BOB, 2));
+        DexPriceFeed::on_initialize(2);
+
+        assert_noop!(
+            DexPriceFeed::publish_signed(Origin::none(), 2, sign(&signer(), 2)),
+            Error::<Test>::NoPriceSigner
+        );
+
+        set_signer();
+        let other = sr25519::Pair::from_string("//Bob", None).unwrap();
+        assert_eq!(
+            validate(2, sign(&other, 2)),
+            Err(InvalidTransaction::BadProof.into())
+        );
+        assert_noop!(
+            DexPriceFeed::publish_signed(Origin::none(), 2, sign(&other, 2)),
+            Error::<Test>::BadSignature
+        );
+        assert_noop!(
+            DexPriceFeed::publish_signed(Origin::none(), 2, Vec::new()),
+            Error::<Test>::BadSignature
+        );
+        assert_noop!(
+            DexPriceFeed::publish_signed(Origin::signed(ALICE), 2, sign(&signer(), 2)),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert!(sent_messages().is_empty());
+    });
+}
+
+#[test]
+fn unsigned_updates_expire_unpaid() {
+    new_test_ext().execute_with(|| {
+        set_signer();
+        assert_ok!(subscribe(BOB, 2));
+        DexPriceFeed::on_initialize(2);
+
+        DexPriceFeed::on_initialize(5);
+
+        assert!(DexPriceFeed::pending_feeds(2).is_empty());
+        assert_eq!(
+            feed_events().pop(),
+            Some(RawEvent::PriceNotSent(0, SIBLING.into()))
+        );
+        assert_eq!(Balances::reserved_balance(BOB), 1_200);
+        assert!(sent_messages().is_empty());
+    });
+}
+
+#[test]
+fn failed_send_is_not_paid() {
+    new_test_ext().execute_with(|| {
+        set_signer();
+        assert_ok!(subscribe(BOB, 2));
+        DexPriceFeed::on_initialize(2);
+        fail_sending();
+
+        assert_ok!(DexPriceFeed::publish_signed(
+            Origin::none(),
+            2,
+            sign(&signer(), 2)
+        ));
+
+        assert_eq!(Balances::reserved_balance(BOB), 1_200);
+        assert_eq!(
+            feed_events().pop(),
+            Some(RawEvent::PriceNotSent(0, SIBLING.into()))
+        );
+    });
+}
+
+#[test]
+fn subscription_ends_when_prepaid_updates_run_out() {
+    new_test_ext().execute_with(|| {
+        set_signer();
+        assert_ok!(subscribe(BOB, 1));
+        DexPriceFeed::on_initialize(2);
+        assert_ok!(DexPriceFeed::publish_signed(
+            Origin::none(),
+            2,
+            sign(&signer(), 2)
+        ));
+        assert_eq!(Balances::reserved_balance(BOB), 1_000);
+
+        DexPriceFeed::on_initialize(12);
+
+        assert_eq!(Balances::reserved_balance(BOB), 0);
+        assert_eq!(DexPriceFeed::subscriptions(0), None);
+        assert!(DexPriceFeed::pending_feeds(12).is_empty());
+        assert_eq!(
+            feed_events().pop(),
+            Some(RawEvent::SubscriptionExhausted(0))
+        );
+    });
+}
+
+#[test]
+fn updates_above_max_per_block_are_postponed() {
+    new_test_ext().execute_with(|| {
+        for _ in 0..3 {
+            assert_ok!(subscribe(BOB, 2));
+        }
+
+        DexPriceFeed::on_initialize(2);
+
+        let prepared: Vec<_> = DexPriceFeed::pending_feeds(2)
+            .iter()
+            .map(|feed| feed.subscription)
+            .collect();
+        assert_eq!(prepared, vec![0, 1]);
+        assert_eq!(DexPriceFeed::schedule(3), vec![2]);
+        assert_eq!(DexPriceFeed::schedule(12), vec![0, 1]);
+
+        DexPriceFeed::on_initialize(3);
+
+        assert_eq!(DexPriceFeed::pending_feeds(3).len(), 1);
+        assert_eq!(DexPriceFeed::schedule(13), vec![2]);
+    });
+}
+
+#[test]
+fn free_subscriptions_reserve_a_deposit_and_end() {
+    new_test_ext().execute_with(|| {
+        set_signer();
+        assert_ok!(DexPriceFeed::set_update_fee(Origin::root(), 0));
+        assert_ok!(subscribe(BOB, 1));
+        assert_eq!(Balances::reserved_balance(BOB), 1_000);
+
+        DexPriceFeed::on_initialize(2);
+        assert_ok!(DexPriceFeed::publish_signed(
+            Origin::none(),
+            2,
+            sign(&signer(), 2)
+        ));
+        assert_eq!(DexPriceFeed::subscriptions(0).unwrap().updates, 0);
+
+        DexPriceFeed::on_initialize(12);
+
+        assert_eq!(DexPriceFeed::subscriptions(0), None);
+        assert!(DexPriceFeed::pending_feeds(12).is_empty());
+        assert_eq!(Balances::reserved_balance(BOB), 0);
+    });
+}
+
+#[test]
+fn subscribe_needs_the_deposit() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexPriceFeed::set_update_fee(Origin::root(), 0));
+        let _ = Balances::make_free_balance_be(&BOB, 999);
+        assert!(subscribe(BOB, 1).is_err());
+        assert_eq!(DexPriceFeed::subscriptions(0), None);
+    });
+}
@@ -0,0 +1,199 @@
+//! # Subdex relay commands pallet
+//!
+//! Lets the relay chain govern Subdex in an emergency, through downward messages.
+//!
+//! Downward messages can only be sent by the relay chain, so a [`RelayCommand`] received in
+//! the payload of a `DownwardMessage::Opaque` is executed with the authority of the relay
+//! chain. Only payloads starting with [`RELAY_COMMAND_PREFIX`] are commands, so that other
+//! opaque messages cannot be mistaken for one, see [`encode_command`]. The relay chain can
+//! pause and resume the DEX, schedule a runtime upgrade and change the fees Subdex charges.
+//! Runtime upgrades and fees are applied by the [`Trait::CommandHandler`] of the runtime, the
+//! pause is kept in [`DexPaused`] for the runtime to enforce, see [`IsDexPaused`].
+//!
+//! Commands are meant to be executed as soon as they are received, [`IsRelayCommand`] tells
+//! them apart for the runtime to keep them out of its inbound message queue.
+//!
+//! All other downward messages, as well as opaque messages without the prefix, are handed to
+//! [`Trait::DownwardMessageHandler`] unchanged. Prefixed payloads that do not decode as a
+//! command are dropped with [`RawEvent::InvalidCommand`].
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Decode, DecodeAll, Encode};
+use cumulus_primitives::{DownwardMessage, DownwardMessageHandler};
+use frame_support::{
+    decl_event, decl_module, decl_storage,
+    dispatch::DispatchResult,
+    traits::{Filter, Get},
+    Parameter,
+};
+use frame_system::ensure_root;
+use sp_runtime::{traits::Hash, DispatchError, RuntimeDebug};
+use sp_std::{marker::PhantomData, prelude::*};
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+/// Prefix of the opaque downward messages carrying a [`RelayCommand`].
+pub const RELAY_COMMAND_PREFIX: [u8; 8] = *b"subdexrc";
+
+/// Fees the relay chain can change.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum Fee {
+    /// Fee charged for withdrawing to the relay chain.
+    RelayWithdraw,
+    /// Fee charged for every delivered price feed update.
+    PriceFeedUpdate,
+    /// Nominator of the fee rate of the DEX pools, over `FeeRateDenominator`.
+    TradeRate,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub enum RelayCommand<Balance> {
+    /// Stop all trading and liquidity changes on the DEX.
+    PauseDex,
+    /// Resume trading and liquidity changes on the DEX.
+    ResumeDex,
+    /// Schedule an upgrade of the runtime to the given code.
+    UpgradeRuntime(Vec<u8>),
+    /// Change a fee.
+    SetFee(Fee, Balance),
+}
+
+/// Payload of the `DownwardMessage::Opaque` executing `command`.
+pub fn encode_command<Balance: Encode>(command: &RelayCommand<Balance>) -> Vec<u8> {
+    let mut payload = RELAY_COMMAND_PREFIX.to_vec();
+    command.encode_to(&mut payload);
+    payload
+}
+
+/// Applies the relay commands that change other parts of the runtime.
+pub trait RelayCommandHandler<Balance> {
+    fn upgrade_runtime(code: Vec<u8>) -> DispatchResult;
+    fn set_fee(fee: Fee, amount: Balance) -> DispatchResult;
+}
+
+pub trait Trait: frame_system::Trait {
+    type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// Type of the fee amounts set by the relay chain.
+    type Balance: Parameter;
+
+    /// Applies runtime upgrades and fee changes.
+    type CommandHandler: RelayCommandHandler<Self::Balance>;
+
+    /// Handler of downward messages that are not relay commands.
+    type DownwardMessageHandler: DownwardMessageHandler;
+}
+
+decl_storage! {
+    trait Store for Module<T: Trait> as DexRelayCommands {
+        /// Whether the DEX is paused.
+        pub DexPaused get(fn dex_paused): bool;
+    }
+}
+
+decl_event!(
+    pub enum Event<T>
+    where
+        Balance = <T as Trait>::Balance,
+        Hash = <T as frame_system::Trait>::Hash,
+    {
+        /// The DEX was paused.
+        DexPaused,
+        /// The DEX was resumed.
+        DexResumed,
+        /// Runtime upgrade scheduled by the relay chain: (hash of the code).
+        RuntimeUpgradeScheduled(Hash),
+        /// Fee changed by the relay chain.
+        FeeSet(Fee, Balance),
+        /// Relay command could not be executed.
+        CommandFailed(DispatchError),
+        /// Prefixed downward message that is not a relay command was dropped.
+        InvalidCommand,
+    }
+);
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        fn deposit_event() = default;
+
+        /// Pause or resume the DEX, e.g. to resume it without waiting for the relay chain.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn set_dex_paused(origin, paused: bool) {
+            ensure_root(origin)?;
+            Self::do_set_dex_paused(paused);
+        }
+    }
+}
+
+impl<T: Trait> Module<T> {
+    fn do_set_dex_paused(paused: bool) {
+        DexPaused::put(paused);
+        Self::deposit_event(if paused {
+            RawEvent::DexPaused
+        } else {
+            RawEvent::DexResumed
+        });
+    }
+
+    fn execute(command: RelayCommand<T::Balance>) -> DispatchResult {
+        match command {
+            RelayCommand::PauseDex => Self::do_set_dex_paused(true),
+            RelayCommand::ResumeDex => Self::do_set_dex_paused(false),
+            RelayCommand::UpgradeRuntime(code) => {
+                let hash = T::Hashing::hash(&code);
+                T::CommandHandler::upgrade_runtime(code)?;
+                Self::deposit_event(RawEvent::RuntimeUpgradeScheduled(hash));
+            }
+            RelayCommand::SetFee(fee, amount) => {
+                T::CommandHandler::set_fee(fee, amount.clone())?;
+                Self::deposit_event(RawEvent::FeeSet(fee, amount));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Trait> DownwardMessageHandler for Module<T> {
+    fn handle_downward_message(message: &DownwardMessage) {
+        if let DownwardMessage::Opaque(payload) = message {
+            if payload.starts_with(&RELAY_COMMAND_PREFIX) {
+                let encoded = &payload[RELAY_COMMAND_PREFIX.len()..];
+                match RelayCommand::<T::Balance>::decode_all(encoded) {
+                    Ok(command) => {
+                        if let Err(error) = Self::execute(command) {
+                            Self::deposit_event(RawEvent::CommandFailed(error));
+                        }
+                    }
+                    Err(_) => Self::deposit_event(RawEvent::InvalidCommand),
+                }
+                return;
+            }
+        }
+        T::DownwardMessageHandler::handle_downward_message(message)
+    }
+}
+
+/// Downward messages carrying a relay command, whether or not it decodes.
+pub struct IsRelayCommand;
+
+impl Filter<DownwardMessage> for IsRelayCommand {
+    fn filter(message: &DownwardMessage) -> bool {
+        match message {
+            DownwardMessage::Opaque(payload) => payload.starts_with(&RELAY_COMMAND_PREFIX),
+            _ => false,
+        }
+    }
+}
+
+/// Whether the relay chain or root paused the DEX.
+pub struct IsDexPaused<T>(PhantomData<T>);
+
+impl<T: Trait> Get<bool> for IsDexPaused<T> {
+    fn get() -> bool {
+        Module::<T>::dex_paused()
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{Fee, Module, RelayCommandHandler, Trait};
+use cumulus_primitives::{DownwardMessage, DownwardMessageHandler};
+use frame_support::{
+    dispatch::DispatchResult, impl_outer_event, impl_outer_origin, parameter_types, weights::Weight,
+};
+use sp_core::H256;
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+    DispatchError, Perbill,
+};
+use std::cell::RefCell;
+
+pub type AccountId = u64;
+pub type Balance = u128;
+
+impl_outer_origin! {
+    pub enum Origin for Test {}
+}
+
+mod relay_commands {
+    pub use crate::Event;
+}
+
+impl_outer_event! {
+    pub enum TestEvent for Test {
+        frame_system<T>,
+        relay_commands<T>,
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Test;
+
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::one();
+}
+
+impl frame_system::Trait for Test {
+    type BaseCallFilter = ();
+    type Origin = Origin;
+    type Call = ();
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId;
+    type Lookup = IdentityLookup<AccountId>;
+    type Header = Header;
+    type Event = TestEvent;
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type ModuleToIndex = ();
+    type AccountData = ();
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+}
+
+thread_local! {
+    static UPGRADES: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new());
+    static FEES: RefCell<Vec<(Fee, Balance)>> = RefCell::new(Vec::new());
+    static FORWARDED: RefCell<Vec<DownwardMessage>> = RefCell::new(Vec::new());
+}
+
+/// Records the upgrades and fees instead of applying them, rejecting empty code.
+pub struct MockCommandHandler;
+
+impl RelayCommandHandler<Balance> for MockCommandHandler {
+    fn upgrade_runtime(code: Vec<u8>) -> DispatchResult {
+        if code.is_empty() {
+            return Err(DispatchError::Other("empty code"));
+        }
+        UPGRADES.with(|upgrades| upgrades.borrow_mut().push(code));
+        Ok(())
+    }
+
+    fn set_fee(fee: Fee, amount: Balance) -> DispatchResult {
+        FEES.with(|fees| fees.borrow_mut().push((fee, amount)));
+        Ok(())
+    }
+}
+
+pub fn upgrades() -> Vec<Vec<u8>> {
+    UPGRADES.with(|upgrades| upgrades.borrow().clone())
+}
+
+pub fn fees() -> Vec<(Fee, Balance)> {
+    FEES.with(|fees| fees.borrow().clone())
+}
+
+/// Records the downward messages that are not relay commands.
+pub struct MockDownwardMessageHandler;
+
+impl DownwardMessageHandler for MockDownwardMessageHandler {
+    fn handle_downward_message(message: &DownwardMessage) {
+        FORWARDED.with(|forwarded| forwarded.borrow_mut().push(message.clone()));
+    }
+}
+
+pub fn forwarded() -> Vec<DownwardMessage> {
+    FORWARDED.with(|forwarded| forwarded.borrow().clone())
+}
+
+impl Trait for Test {
+    type Event = TestEvent;
+    type Balance = Balance;
+    type CommandHandler = MockCommandHandler;
+    type DownwardMessageHandler = MockDownwardMessageHandler;
+}
+
+pub type System = frame_system::Module<Test>;
+pub type DexRelayCommands = Module<Test>;
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let storage = frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    let mut ext = sp_io::TestExternalities::new(storage);
+    ext.execute_with(|| System::set_block_number(1));
+    ext
+}
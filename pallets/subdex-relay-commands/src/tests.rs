@@ -0,0 +1,144 @@
+use crate::{
+    encode_command, mock::*, Fee, IsRelayCommand, RawEvent, RelayCommand, RELAY_COMMAND_PREFIX,
+};
+use codec::Encode;
+use cumulus_primitives::{DownwardMessage, DownwardMessageHandler};
+use frame_support::{assert_noop, assert_ok, traits::Filter};
+use sp_core::H256;
+use sp_runtime::{
+    traits::{BlakeTwo256, Hash},
+    DispatchError,
+};
+
+fn command_events() -> Vec<RawEvent<Balance, H256>> {
+    System::events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            TestEvent::relay_commands(event) => Some(event),
+            _ => None,
+        })
+        .collect()
+}
+
+fn send(command: RelayCommand<Balance>) {
+    DexRelayCommands::handle_downward_message(&DownwardMessage::Opaque(encode_command(&command)));
+}
+
+#[test]
+fn encoded_commands_start_with_the_prefix() {
+    let payload = encode_command(&RelayCommand::<Balance>::PauseDex);
+    assert_eq!(payload[..RELAY_COMMAND_PREFIX.len()], RELAY_COMMAND_PREFIX);
+    assert_eq!(
+        payload[RELAY_COMMAND_PREFIX.len()..],
+        RelayCommand::<Balance>::PauseDex.encode()[..]
+    );
+}
+
+#[test]
+fn pause_and_resume_the_dex() {
+    new_test_ext().execute_with(|| {
+        send(RelayCommand::PauseDex);
+        assert!(DexRelayCommands::dex_paused());
+        send(RelayCommand::ResumeDex);
+        assert!(!DexRelayCommands::dex_paused());
+        assert_eq!(
+            command_events(),
+            vec![RawEvent::DexPaused, RawEvent::DexResumed]
+        );
+        assert!(forwarded().is_empty());
+    });
+}
+
+#[test]
+fn unprefixed_payloads_are_not_commands() {
+    new_test_ext().execute_with(|| {
+        let message = DownwardMessage::Opaque(RelayCommand::<Balance>::PauseDex.encode());
+        DexRelayCommands::handle_downward_message(&message);
+        assert!(!DexRelayCommands::dex_paused());
+        assert!(command_events().is_empty());
+        assert_eq!(forwarded(), vec![message]);
+    });
+}
+
+#[test]
+fn prefixed_payloads_that_do_not_decode_are_dropped() {
+    new_test_ext().execute_with(|| {
+        let mut payload = encode_command(&RelayCommand::<Balance>::PauseDex);
+        payload.push(0);
+        DexRelayCommands::handle_downward_message(&DownwardMessage::Opaque(payload));
+        DexRelayCommands::handle_downward_message(&DownwardMessage::Opaque(
+            RELAY_COMMAND_PREFIX.to_vec(),
+        ));
+        assert!(!DexRelayCommands::dex_paused());
+        assert_eq!(
+            command_events(),
+            vec![RawEvent::InvalidCommand, RawEvent::InvalidCommand]
+        );
+        assert!(forwarded().is_empty());
+    });
+}
+
+#[test]
+fn upgrade_the_runtime() {
+    new_test_ext().execute_with(|| {
+        let code = vec![1, 2, 3];
+        send(RelayCommand::UpgradeRuntime(code.clone()));
+        assert_eq!(upgrades(), vec![code.clone()]);
+        assert_eq!(
+            command_events(),
+            vec![RawEvent::RuntimeUpgradeScheduled(BlakeTwo256::hash(&code))]
+        );
+    });
+}
+
+#[test]
+fn failed_commands_are_reported() {
+    new_test_ext().execute_with(|| {
+        send(RelayCommand::UpgradeRuntime(Vec::new()));
+        assert!(upgrades().is_empty());
+        assert_eq!(
+            command_events(),
+            vec![RawEvent::CommandFailed(DispatchError::Other("empty code"))]
+        );
+    });
+}
+
+#[test]
+fn set_fees() {
+    new_test_ext().execute_with(|| {
+        send(RelayCommand::SetFee(Fee::RelayWithdraw, 10));
+        send(RelayCommand::SetFee(Fee::TradeRate, 5));
+        assert_eq!(fees(), vec![(Fee::RelayWithdraw, 10), (Fee::TradeRate, 5)]);
+        assert_eq!(
+            command_events(),
+            vec![
+                RawEvent::FeeSet(Fee::RelayWithdraw, 10),
+                RawEvent::FeeSet(Fee::TradeRate, 5)
+            ]
+        );
+    });
+}
+
+#[test]
+fn root_can_pause_the_dex() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexRelayCommands::set_dex_paused(Origin::signed(1), true),
+            DispatchError::BadOrigin
+        );
+        assert_ok!(DexRelayCommands::set_dex_paused(Origin::root(), true));
+        assert!(DexRelayCommands::dex_paused());
+    });
+}
+
+#[test]
+fn prefixed_payloads_are_relay_commands() {
+    let command = DownwardMessage::Opaque(encode_command(&RelayCommand::<Balance>::ResumeDex));
+    assert!(IsRelayCommand::filter(&command));
+    assert!(IsRelayCommand::filter(&DownwardMessage::Opaque(
+        RELAY_COMMAND_PREFIX.to_vec()
+    )));
+    assert!(!IsRelayCommand::filter(&DownwardMessage::Opaque(
+        RelayCommand::<Balance>::ResumeDex.encode()
+    )));
+}
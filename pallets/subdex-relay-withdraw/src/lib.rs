@@ -0,0 +1,152 @@
+//! # Subdex relay withdraw pallet
+//!
+//! Sends the main network currency back to the relay chain.
+//!
+//! The main network currency of Subdex is the relay chain currency, deposited by the relay
+//! chain through downward messages. Withdrawing burns the amount on Subdex and sends an
+//! upward transfer of the same amount to an account on the relay chain, which releases it
+//! from the Subdex parachain account there.
+//!
+//! Every withdrawal gets a [`WithdrawalId`] and is reported together with the hash of the
+//! upward message carrying it, so it can be followed on the relay chain. A fee is charged on
+//! top of the withdrawn amount and withdrawals below [`Trait::MinWithdrawAmount`] are refused,
+//! as the relay chain would not credit them.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use cumulus_primitives::{UpwardMessageOrigin, UpwardMessageSender};
+use cumulus_upward_message::BalancesMessage;
+use frame_support::{
+    decl_error, decl_event, decl_module, decl_storage, ensure,
+    traits::{Currency, ExistenceRequirement, Get, Imbalance, OnUnbalanced, WithdrawReason},
+    transactional,
+};
+use frame_system::ensure_signed;
+use pallet_subdex_message_trace::{Channel, Direction, MessageTrace, MessageTracer, Outcome};
+use sp_runtime::traits::{CheckedAdd, Hash};
+use sp_std::prelude::*;
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+pub type WithdrawalId = u64;
+
+type BalanceOf<T> =
+    <<T as Trait>::Currency as Currency<<T as frame_system::Trait>::AccountId>>::Balance;
+
+type NegativeImbalanceOf<T> =
+    <<T as Trait>::Currency as Currency<<T as frame_system::Trait>::AccountId>>::NegativeImbalance;
+
+pub trait Trait: frame_system::Trait {
+    type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// The main network currency, deposited by the relay chain.
+    type Currency: Currency<Self::AccountId>;
+
+    /// Sends messages to the relay chain.
+    type UpwardMessageSender: UpwardMessageSender<Self::UpwardMessage>;
+
+    /// Upward message type of the relay chain.
+    type UpwardMessage: Codec + BalancesMessage<Self::AccountId, BalanceOf<Self>>;
+
+    /// Fee charged for every withdrawal, on top of the withdrawn amount.
+    type WithdrawFee: Get<BalanceOf<Self>>;
+
+    /// Handler for the paid withdrawal fees.
+    type OnWithdrawFee: OnUnbalanced<NegativeImbalanceOf<Self>>;
+
+    /// Smallest amount that can be withdrawn.
+    type MinWithdrawAmount: Get<BalanceOf<Self>>;
+
+    /// Receives the traces of sent upward messages.
+    type MessageTracer: MessageTracer<Self::AccountId>;
+}
+
+decl_storage! {
+    trait Store for Module<T: Trait> as DexRelayWithdraw {
+        pub NextWithdrawalId get(fn next_withdrawal_id): WithdrawalId;
+    }
+}
+
+decl_event!(
+    pub enum Event<T>
+    where
+        AccountId = <T as frame_system::Trait>::AccountId,
+        Balance = BalanceOf<T>,
+        Hash = <T as frame_system::Trait>::Hash,
+    {
+        /// Main network currency sent to the relay chain: (id, sender, receiver on the relay
+        /// chain, amount, fee paid, hash of the upward message).
+        WithdrawnToRelayChain(WithdrawalId, AccountId, AccountId, Balance, Balance, Hash),
+    }
+);
+
+decl_error! {
+    pub enum Error for Module<T: Trait> {
+        /// The amount is below `MinWithdrawAmount`.
+        AmountTooLow,
+        /// Amount and fee overflow.
+        Overflow,
+        /// The upward message could not be sent.
+        SendFailed,
+    }
+}
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        type Error = Error<T>;
+
+        const WithdrawFee: BalanceOf<T> = T::WithdrawFee::get();
+        const MinWithdrawAmount: BalanceOf<T> = T::MinWithdrawAmount::get();
+
+        fn deposit_event() = default;
+
+        /// Burn `amount` of the main network currency and send it to `dest` on the relay chain.
+        #[weight = 50_000_000 + T::DbWeight::get().reads_writes(3, 3)]
+        #[transactional]
+        pub fn withdraw_to_relay_chain(origin, dest: T::AccountId, amount: BalanceOf<T>) {
+            let who = ensure_signed(origin)?;
+            ensure!(amount >= T::MinWithdrawAmount::get(), Error::<T>::AmountTooLow);
+            let fee = T::WithdrawFee::get();
+            let total = amount.checked_add(&fee).ok_or(Error::<T>::Overflow)?;
+
+            let withdrawn = T::Currency::withdraw(
+                &who,
+                total,
+                WithdrawReason::Transfer.into(),
+                ExistenceRequirement::KeepAlive,
+            )?;
+
+            let message = T::UpwardMessage::transfer(dest.clone(), amount);
+            T::UpwardMessageSender::send_upward_message(&message, UpwardMessageOrigin::Signed)
+                .map_err(|_| Error::<T>::SendFailed)?;
+
+            // The withdrawn amount is released on the relay chain, so it leaves the issuance
+            // here; only the fee is handed over.
+            let (paid, _burnt) = withdrawn.split(fee);
+            T::OnWithdrawFee::on_unbalanced(paid);
+
+            T::MessageTracer::trace(MessageTrace::new(
+                Direction::Outbound,
+                Channel::Upward,
+                &message,
+                vec![who.clone(), dest.clone()],
+                Outcome::Sent,
+            ));
+
+            let id = Self::next_withdrawal_id();
+            NextWithdrawalId::put(id + 1);
+            Self::deposit_event(RawEvent::WithdrawnToRelayChain(
+                id,
+                who,
+                dest,
+                amount,
+                fee,
+                T::Hashing::hash_of(&message),
+            ));
+        }
+    }
+}
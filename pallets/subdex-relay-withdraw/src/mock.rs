@@ -0,0 +1,157 @@
+use crate::{Module, Trait};
+use codec::{Decode, Encode};
+use cumulus_primitives::{UpwardMessageOrigin, UpwardMessageSender};
+use cumulus_upward_message::BalancesMessage;
+use frame_support::{impl_outer_event, impl_outer_origin, parameter_types, weights::Weight};
+use sp_core::H256;
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+    Perbill,
+};
+use std::cell::RefCell;
+
+pub type AccountId = u64;
+pub type Balance = u64;
+
+pub const ALICE: AccountId = 1;
+pub const BOB: AccountId = 2;
+
+impl_outer_origin! {
+    pub enum Origin for Test {}
+}
+
+mod relay_withdraw {
+    pub use crate::Event;
+}
+
+impl_outer_event! {
+    pub enum TestEvent for Test {
+        frame_system<T>,
+        pallet_balances<T>,
+        relay_withdraw<T>,
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Test;
+
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::one();
+}
+
+impl frame_system::Trait for Test {
+    type BaseCallFilter = ();
+    type Origin = Origin;
+    type Call = ();
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId;
+    type Lookup = IdentityLookup<AccountId>;
+    type Header = Header;
+    type Event = TestEvent;
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type ModuleToIndex = ();
+    type AccountData = pallet_balances::AccountData<Balance>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+}
+
+parameter_types! {
+    pub const ExistentialDeposit: Balance = 10;
+}
+
+impl pallet_balances::Trait for Test {
+    type Balance = Balance;
+    type Event = TestEvent;
+    type DustRemoval = ();
+    type ExistentialDeposit = ExistentialDeposit;
+    type AccountStore = System;
+    type WeightInfo = ();
+}
+
+/// Upward transfer as the relay chain would receive it.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct Transfer(pub AccountId, pub Balance);
+
+impl BalancesMessage<AccountId, Balance> for Transfer {
+    fn transfer(dest: AccountId, amount: Balance) -> Self {
+        Transfer(dest, amount)
+    }
+}
+
+thread_local! {
+    static SENT: RefCell<Vec<Transfer>> = RefCell::new(Vec::new());
+    static FAIL_SEND: RefCell<bool> = RefCell::new(false);
+}
+
+pub struct MockUpwardMessageSender;
+
+impl UpwardMessageSender<Transfer> for MockUpwardMessageSender {
+    fn send_upward_message(message: &Transfer, _: UpwardMessageOrigin) -> Result<(), ()> {
+        if FAIL_SEND.with(|fail| *fail.borrow()) {
+            return Err(());
+        }
+        SENT.with(|sent| sent.borrow_mut().push(message.clone()));
+        Ok(())
+    }
+}
+
+/// Upward messages sent so far.
+pub fn sent_messages() -> Vec<Transfer> {
+    SENT.with(|sent| sent.borrow().clone())
+}
+
+/// Make the relay chain refuse upward messages.
+pub fn fail_sending() {
+    FAIL_SEND.with(|fail| *fail.borrow_mut() = true);
+}
+
+parameter_types! {
+    pub const WithdrawFee: Balance = 5;
+    pub const MinWithdrawAmount: Balance = 100;
+}
+
+impl Trait for Test {
+    type Event = TestEvent;
+    type Currency = Balances;
+    type UpwardMessageSender = MockUpwardMessageSender;
+    type UpwardMessage = Transfer;
+    type WithdrawFee = WithdrawFee;
+    type OnWithdrawFee = ();
+    type MinWithdrawAmount = MinWithdrawAmount;
+    type MessageTracer = ();
+}
+
+pub type System = frame_system::Module<Test>;
+pub type Balances = pallet_balances::Module<Test>;
+pub type RelayWithdraw = Module<Test>;
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let mut storage = frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    pallet_balances::GenesisConfig::<Test> {
+        balances: vec![(ALICE, 1_000)],
+    }
+    .assimilate_storage(&mut storage)
+    .unwrap();
+
+    let mut ext = sp_io::TestExternalities::new(storage);
+    ext.execute_with(|| System::set_block_number(1));
+    ext
+}
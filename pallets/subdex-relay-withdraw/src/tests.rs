@@ -0,0 +1,108 @@
+use crate::{mock::*, Error, RawEvent};
+use frame_support::{assert_noop, assert_ok};
+use sp_runtime::traits::{BlakeTwo256, Hash};
+
+fn last_event() -> TestEvent {
+    System::events()
+        .pop()
+        .expect("an event was deposited")
+        .event
+}
+
+#[test]
+fn withdraw_burns_amount_and_fee_and_sends_upward_transfer() {
+    new_test_ext().execute_with(|| {
+        let issuance = Balances::total_issuance();
+
+        assert_ok!(RelayWithdraw::withdraw_to_relay_chain(
+            Origin::signed(ALICE),
+            BOB,
+            300
+        ));
+
+        assert_eq!(Balances::free_balance(ALICE), 695);
+        assert_eq!(Balances::total_issuance(), issuance - 305);
+        assert_eq!(sent_messages(), vec![Transfer(BOB, 300)]);
+        assert_eq!(
+            last_event(),
+            TestEvent::relay_withdraw(RawEvent::WithdrawnToRelayChain(
+                0,
+                ALICE,
+                BOB,
+                300,
+                5,
+                BlakeTwo256::hash_of(&Transfer(BOB, 300)),
+            ))
+        );
+    });
+}
+
+#[test]
+fn withdrawals_get_increasing_ids() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(RelayWithdraw::withdraw_to_relay_chain(
+            Origin::signed(ALICE),
+            BOB,
+            100
+        ));
+        assert_ok!(RelayWithdraw::withdraw_to_relay_chain(
+            Origin::signed(ALICE),
+            ALICE,
+            200
+        ));
+
+        assert_eq!(RelayWithdraw::next_withdrawal_id(), 2);
+        match last_event() {
+            TestEvent::relay_withdraw(RawEvent::WithdrawnToRelayChain(id, _, dest, amount, ..)) => {
+                assert_eq!((id, dest, amount), (1, ALICE, 200))
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    });
+}
+
+#[test]
+fn withdraw_below_minimum_fails() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            RelayWithdraw::withdraw_to_relay_chain(Origin::signed(ALICE), BOB, 99),
+            Error::<Test>::AmountTooLow
+        );
+        assert!(sent_messages().is_empty());
+    });
+}
+
+#[test]
+fn withdraw_requires_amount_and_fee() {
+    new_test_ext().execute_with(|| {
+        // 996 + 5 is more than the balance.
+        assert_noop!(
+            RelayWithdraw::withdraw_to_relay_chain(Origin::signed(ALICE), BOB, 996),
+            pallet_balances::Error::<Test, pallet_balances::DefaultInstance>::InsufficientBalance
+        );
+        assert!(sent_messages().is_empty());
+    });
+}
+
+#[test]
+fn withdraw_keeps_sender_alive() {
+    new_test_ext().execute_with(|| {
+        // Would leave 5, below the existential deposit.
+        assert_noop!(
+            RelayWithdraw::withdraw_to_relay_chain(Origin::signed(ALICE), BOB, 990),
+            pallet_balances::Error::<Test, pallet_balances::DefaultInstance>::KeepAlive
+        );
+    });
+}
+
+#[test]
+fn failed_upward_message_keeps_funds() {
+    new_test_ext().execute_with(|| {
+        fail_sending();
+        assert_noop!(
+            RelayWithdraw::withdraw_to_relay_chain(Origin::signed(ALICE), BOB, 300),
+            Error::<Test>::SendFailed
+        );
+        assert_eq!(Balances::free_balance(ALICE), 1_000);
+    });
+}
@@ -0,0 +1,563 @@
+//! # Subdex router pallet
+//!
+//! Thin layer over `pallet_subdex` that lets traders bound what a DEX call may cost them.
+//!
+//! Every call takes a `deadline`, checked against `pallet_timestamp`, and a slippage limit:
+//! `min_*` for amounts received and `max_*` for amounts paid. The underlying `pallet_subdex`
+//! call is executed inside a storage transaction and the actual balance changes of the caller
+//! are compared against the limits afterwards, so a call that ends up outside of them leaves
+//! no trace but its fee.
+//!
+//! The [`CheckDeadline`] signed extension rejects router calls whose deadline has already
+//! passed while they are still in the transaction pool, so they never reach a block.
+//!
+//! Other in-tree pallets use [`Module`] as their entry point into `pallet_subdex`, which keeps
+//! the knowledge about its storage layout in one place. Swaps and liquidity changes are refused
+//! while [`Trait::DexPaused`] pauses them, whichever pallet they come from. Runtimes are expected to
+//! refuse direct `pallet_subdex` swaps and liquidity changes in their `BaseCallFilter`, so that
+//! all of them go through the router.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Decode, Encode};
+use frame_support::{
+    decl_error, decl_event, decl_module,
+    dispatch::DispatchResult,
+    ensure,
+    storage::StorageDoubleMap,
+    traits::{Currency, ExistenceRequirement, Get, IsSubType},
+    transactional,
+};
+use frame_system::{ensure_signed, RawOrigin};
+use pallet_subdex::Asset;
+use sp_runtime::{
+    traits::{CheckedAdd, CheckedMul, DispatchInfoOf, Saturating, SignedExtension, Zero},
+    transaction_validity::{
+        InvalidTransaction, TransactionValidity, TransactionValidityError, ValidTransaction,
+    },
+    DispatchError, RuntimeDebug,
+};
+use sp_std::{fmt::Debug, marker::PhantomData, prelude::*};
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+pub type BalanceOf<T> = <<T as pallet_subdex::Trait>::Currency as Currency<
+    <T as frame_system::Trait>::AccountId,
+>>::Balance;
+
+pub type AssetOf<T> = Asset<<T as pallet_subdex::Trait>::AssetId>;
+
+pub type MomentOf<T> = <T as pallet_timestamp::Trait>::Moment;
+
+/// Hook called right before the reserves of a pool change through the router.
+pub trait OnPoolUpdate<Asset> {
+    fn on_pool_update(first: Asset, second: Asset);
+}
+
+impl<Asset> OnPoolUpdate<Asset> for () {
+    fn on_pool_update(_: Asset, _: Asset) {}
+}
+
+/// DEX operations that can be paused.
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum DexOperation {
+    Swap,
+    InvestLiquidity,
+    DivestLiquidity,
+}
+
+/// Tells which DEX operations are currently refused.
+pub trait DexPause {
+    fn is_paused(operation: DexOperation) -> bool;
+}
+
+impl DexPause for () {
+    fn is_paused(_: DexOperation) -> bool {
+        false
+    }
+}
+
+pub trait Trait: pallet_subdex::Trait + pallet_timestamp::Trait {
+    type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// Notified before every swap or liquidity change, e.g. to keep a price oracle up to date.
+    type OnPoolUpdate: OnPoolUpdate<AssetOf<Self>>;
+
+    /// Which swaps and liquidity changes are currently refused.
+    type DexPaused: DexPause;
+}
+
+decl_event!(
+    pub enum Event<T>
+    where
+        AccountId = <T as frame_system::Trait>::AccountId,
+        Asset = AssetOf<T>,
+        Balance = BalanceOf<T>,
+    {
+        /// Swap executed through the router: (trader, asset in, amount in, asset out, amount out).
+        Swapped(AccountId, Asset, Balance, Asset, Balance),
+        /// Liquidity added through the router: (provider, first asset, first amount,
+        /// second asset, second amount, shares).
+        LiquidityAdded(AccountId, Asset, Balance, Asset, Balance, Balance),
+        /// Liquidity removed through the router: (provider, first asset, first amount,
+        /// second asset, second amount, shares).
+        LiquidityRemoved(AccountId, Asset, Balance, Asset, Balance, Balance),
+    }
+);
+
+decl_error! {
+    pub enum Error for Module<T: Trait> {
+        /// The deadline of the call has passed.
+        DeadlineExpired,
+        /// Less than the requested minimum would be received.
+        InsufficientOutputAmount,
+        /// More than the requested maximum would have to be paid.
+        ExcessiveInputAmount,
+        /// There is no pool for the given pair of assets.
+        PoolNotFound,
+        /// The pool does not hold enough of the requested asset.
+        InsufficientLiquidity,
+        /// An amount calculation overflowed.
+        Overflow,
+        /// Both sides of the call refer to the same asset.
+        SameAsset,
+        /// The DEX is paused.
+        DexPaused,
+    }
+}
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        type Error = Error<T>;
+
+        fn deposit_event() = default;
+
+        // Weights cover the storage accesses of the router, of the `pallet_subdex` call and of
+        // `OnPoolUpdate` keeping an oracle up to date, plus the amount calculations.
+
+        /// Swap exactly `amount_in` of `asset_in`, receiving at least `min_amount_out` of `asset_out`.
+        #[weight = 60_000_000 + T::DbWeight::get().reads_writes(12, 7)]
+        #[transactional]
+        pub fn swap_exact_in(
+            origin,
+            asset_in: AssetOf<T>,
+            amount_in: BalanceOf<T>,
+            asset_out: AssetOf<T>,
+            min_amount_out: BalanceOf<T>,
+            deadline: MomentOf<T>,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            Self::ensure_deadline(deadline)?;
+
+            let amount_out = Self::do_swap(&who, asset_in, amount_in, asset_out)?;
+            ensure!(amount_out >= min_amount_out, Error::<T>::InsufficientOutputAmount);
+
+            Self::deposit_event(RawEvent::Swapped(who, asset_in, amount_in, asset_out, amount_out));
+            Ok(())
+        }
+
+        /// Receive at least `amount_out` of `asset_out`, paying at most `max_amount_in` of `asset_in`.
+        #[weight = 70_000_000 + T::DbWeight::get().reads_writes(14, 7)]
+        #[transactional]
+        pub fn swap_exact_out(
+            origin,
+            asset_in: AssetOf<T>,
+            max_amount_in: BalanceOf<T>,
+            asset_out: AssetOf<T>,
+            amount_out: BalanceOf<T>,
+            deadline: MomentOf<T>,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            Self::ensure_deadline(deadline)?;
+
+            let amount_in = Self::amount_in_for(asset_in, asset_out, amount_out)?;
+            ensure!(amount_in <= max_amount_in, Error::<T>::ExcessiveInputAmount);
+
+            let received = Self::do_swap(&who, asset_in, amount_in, asset_out)?;
+            ensure!(received >= amount_out, Error::<T>::InsufficientOutputAmount);
+
+            Self::deposit_event(RawEvent::Swapped(who, asset_in, amount_in, asset_out, received));
+            Ok(())
+        }
+
+        /// Buy `shares` of the `first_asset`/`second_asset` pool, paying at most the given amounts.
+        #[weight = 60_000_000 + T::DbWeight::get().reads_writes(13, 7)]
+        #[transactional]
+        pub fn invest_liquidity(
+            origin,
+            first_asset: AssetOf<T>,
+            second_asset: AssetOf<T>,
+            shares: BalanceOf<T>,
+            max_first_asset_amount: BalanceOf<T>,
+            max_second_asset_amount: BalanceOf<T>,
+            deadline: MomentOf<T>,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            Self::ensure_deadline(deadline)?;
+
+            let (first_paid, second_paid) =
+                Self::do_invest(&who, first_asset, second_asset, shares)?;
+            ensure!(
+                first_paid <= max_first_asset_amount && second_paid <= max_second_asset_amount,
+                Error::<T>::ExcessiveInputAmount
+            );
+
+            Self::deposit_event(RawEvent::LiquidityAdded(
+                who, first_asset, first_paid, second_asset, second_paid, shares,
+            ));
+            Ok(())
+        }
+
+        /// Sell `shares` of the `first_asset`/`second_asset` pool, receiving at least the given amounts.
+        #[weight = 60_000_000 + T::DbWeight::get().reads_writes(13, 7)]
+        #[transactional]
+        pub fn divest_liquidity(
+            origin,
+            first_asset: AssetOf<T>,
+            second_asset: AssetOf<T>,
+            shares: BalanceOf<T>,
+            min_first_asset_amount: BalanceOf<T>,
+            min_second_asset_amount: BalanceOf<T>,
+            deadline: MomentOf<T>,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            Self::ensure_deadline(deadline)?;
+
+            let (first_received, second_received) = Self::do_divest(
+                &who,
+                first_asset,
+                second_asset,
+                shares,
+                min_first_asset_amount,
+                min_second_asset_amount,
+            )?;
+            ensure!(
+                first_received >= min_first_asset_amount
+                    && second_received >= min_second_asset_amount,
+                Error::<T>::InsufficientOutputAmount
+            );
+
+            Self::deposit_event(RawEvent::LiquidityRemoved(
+                who, first_asset, first_received, second_asset, second_received, shares,
+            ));
+            Ok(())
+        }
+    }
+}
+
+impl<T: Trait> Module<T> {
+    fn ensure_deadline(deadline: MomentOf<T>) -> DispatchResult {
+        ensure!(
+            pallet_timestamp::Module::<T>::get() <= deadline,
+            Error::<T>::DeadlineExpired
+        );
+        Ok(())
+    }
+
+    /// Balance of `who` in `asset`, as seen by `pallet_subdex`.
+    pub fn balance_of(who: &T::AccountId, asset: AssetOf<T>) -> BalanceOf<T> {
+        match asset {
+            Asset::MainNetworkCurrency => T::Currency::free_balance(who),
+            Asset::ParachainAsset(asset_id) => {
+                pallet_subdex::Module::<T>::asset_balances(who, asset_id)
+            }
+        }
+    }
+
+    /// Move `amount` of `asset` between two accounts.
+    pub fn transfer(
+        from: &T::AccountId,
+        to: &T::AccountId,
+        asset: AssetOf<T>,
+        amount: BalanceOf<T>,
+    ) -> DispatchResult {
+        match asset {
+            Asset::MainNetworkCurrency => {
+                T::Currency::transfer(from, to, amount, ExistenceRequirement::AllowDeath)
+            }
+            Asset::ParachainAsset(asset_id) => {
+                let from_balance = pallet_subdex::Module::<T>::asset_balances(from, asset_id);
+                ensure!(from_balance >= amount, Error::<T>::InsufficientLiquidity);
+                let to_balance = pallet_subdex::Module::<T>::asset_balances(to, asset_id)
+                    .checked_add(&amount)
+                    .ok_or(Error::<T>::Overflow)?;
+                if from_balance == amount {
+                    pallet_subdex::AssetBalances::<T>::remove(from, asset_id);
+                } else {
+                    pallet_subdex::AssetBalances::<T>::insert(
+                        from,
+                        asset_id,
+                        from_balance - amount,
+                    );
+                }
+                pallet_subdex::AssetBalances::<T>::insert(to, asset_id, to_balance);
+                Ok(())
+            }
+        }
+    }
+
+    /// The `first`/`second` pool, and whether `pallet_subdex` stores it the other way round.
+    fn exchange(
+        first: AssetOf<T>,
+        second: AssetOf<T>,
+    ) -> Option<(pallet_subdex::Exchange<T>, bool)> {
+        if pallet_subdex::Exchanges::<T>::contains_key(first, second) {
+            Some((pallet_subdex::Module::<T>::exchanges(first, second), false))
+        } else if pallet_subdex::Exchanges::<T>::contains_key(second, first) {
+            Some((pallet_subdex::Module::<T>::exchanges(second, first), true))
+        } else {
+            None
+        }
+    }
+
+    /// Current reserves of the `first`/`second` pool, in the order the assets were given.
+    pub fn reserves(first: AssetOf<T>, second: AssetOf<T>) -> Option<(BalanceOf<T>, BalanceOf<T>)> {
+        Self::exchange(first, second).map(|(exchange, swapped)| {
+            if swapped {
+                (exchange.second_asset_pool, exchange.first_asset_pool)
+            } else {
+                (exchange.first_asset_pool, exchange.second_asset_pool)
+            }
+        })
+    }
+
+    /// Total number of shares of the `first`/`second` pool.
+    pub fn total_shares(first: AssetOf<T>, second: AssetOf<T>) -> Option<BalanceOf<T>> {
+        Self::exchange(first, second).map(|(exchange, _)| exchange.total_shares)
+    }
+
+    /// Shares of the `first`/`second` pool owned by `who`.
+    pub fn shares_of(who: &T::AccountId, first: AssetOf<T>, second: AssetOf<T>) -> BalanceOf<T> {
+        Self::exchange(first, second)
+            .and_then(|(exchange, _)| exchange.shares.get(who).copied())
+            .unwrap_or_else(Zero::zero)
+    }
+
+    /// Number of shares `first_amount` and `second_amount` currently buy in the
+    /// `first`/`second` pool, limited by the scarcer of the two.
+    pub fn shares_for(
+        first: AssetOf<T>,
+        first_amount: BalanceOf<T>,
+        second: AssetOf<T>,
+        second_amount: BalanceOf<T>,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        let (first_reserve, second_reserve) =
+            Self::reserves(first, second).ok_or(Error::<T>::PoolNotFound)?;
+        let total_shares = Self::total_shares(first, second).ok_or(Error::<T>::PoolNotFound)?;
+        ensure!(
+            !first_reserve.is_zero() && !second_reserve.is_zero(),
+            Error::<T>::InsufficientLiquidity
+        );
+        let by_first = first_amount
+            .checked_mul(&total_shares)
+            .ok_or(Error::<T>::Overflow)?
+            / first_reserve;
+        let by_second = second_amount
+            .checked_mul(&total_shares)
+            .ok_or(Error::<T>::Overflow)?
+            / second_reserve;
+        Ok(by_first.min(by_second))
+    }
+
+    /// Amount of `asset_out` a swap of `amount_in` would currently yield, fees included.
+    pub fn amount_out_for(
+        asset_in: AssetOf<T>,
+        asset_out: AssetOf<T>,
+        amount_in: BalanceOf<T>,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        let (reserve_in, reserve_out) =
+            Self::reserves(asset_in, asset_out).ok_or(Error::<T>::PoolNotFound)?;
+        Self::amount_out(reserve_in, reserve_out, amount_in)
+    }
+
+    /// Amount a swap of `amount_in` yields from a pool with the given reserves, fees included.
+    pub fn amount_out(
+        reserve_in: BalanceOf<T>,
+        reserve_out: BalanceOf<T>,
+        amount_in: BalanceOf<T>,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        let denominator = T::FeeRateDenominator::get();
+        let amount_in_after_fee = amount_in
+            .checked_mul(&(denominator - T::FeeRateNominator::get()))
+            .ok_or(Error::<T>::Overflow)?;
+        let numerator = amount_in_after_fee
+            .checked_mul(&reserve_out)
+            .ok_or(Error::<T>::Overflow)?;
+        let divisor = reserve_in
+            .checked_mul(&denominator)
+            .and_then(|r| r.checked_add(&amount_in_after_fee))
+            .ok_or(Error::<T>::Overflow)?;
+        ensure!(!divisor.is_zero(), Error::<T>::InsufficientLiquidity);
+        Ok(numerator / divisor)
+    }
+
+    /// Amount of `asset_in` needed to currently receive `amount_out` of `asset_out`, fees included.
+    pub fn amount_in_for(
+        asset_in: AssetOf<T>,
+        asset_out: AssetOf<T>,
+        amount_out: BalanceOf<T>,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        let (reserve_in, reserve_out) =
+            Self::reserves(asset_in, asset_out).ok_or(Error::<T>::PoolNotFound)?;
+        ensure!(amount_out < reserve_out, Error::<T>::InsufficientLiquidity);
+        let denominator = T::FeeRateDenominator::get();
+        let numerator = reserve_in
+            .checked_mul(&amount_out)
+            .and_then(|n| n.checked_mul(&denominator))
+            .ok_or(Error::<T>::Overflow)?;
+        let divisor = (reserve_out - amount_out)
+            .checked_mul(&(denominator - T::FeeRateNominator::get()))
+            .ok_or(Error::<T>::Overflow)?;
+        ensure!(!divisor.is_zero(), Error::<T>::InsufficientLiquidity);
+        Ok(numerator / divisor + 1u32.into())
+    }
+
+    /// Execute a `pallet_subdex` swap on behalf of `who` and return the amount received.
+    pub fn do_swap(
+        who: &T::AccountId,
+        asset_in: AssetOf<T>,
+        amount_in: BalanceOf<T>,
+        asset_out: AssetOf<T>,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        ensure!(asset_in != asset_out, Error::<T>::SameAsset);
+        ensure!(
+            !T::DexPaused::is_paused(DexOperation::Swap),
+            Error::<T>::DexPaused
+        );
+        T::OnPoolUpdate::on_pool_update(asset_in, asset_out);
+        let before = Self::balance_of(who, asset_out);
+        pallet_subdex::Module::<T>::swap(
+            RawOrigin::Signed(who.clone()).into(),
+            asset_in,
+            amount_in,
+            asset_out,
+        )?;
+        Ok(Self::balance_of(who, asset_out).saturating_sub(before))
+    }
+
+    /// Buy `shares` of a pool on behalf of `who` and return the amounts paid.
+    pub fn do_invest(
+        who: &T::AccountId,
+        first_asset: AssetOf<T>,
+        second_asset: AssetOf<T>,
+        shares: BalanceOf<T>,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>), DispatchError> {
+        ensure!(first_asset != second_asset, Error::<T>::SameAsset);
+        ensure!(
+            !T::DexPaused::is_paused(DexOperation::InvestLiquidity),
+            Error::<T>::DexPaused
+        );
+        let first_before = Self::balance_of(who, first_asset);
+        let second_before = Self::balance_of(who, second_asset);
+        T::OnPoolUpdate::on_pool_update(first_asset, second_asset);
+        pallet_subdex::Module::<T>::invest_liquidity(
+            RawOrigin::Signed(who.clone()).into(),
+            first_asset,
+            second_asset,
+            shares,
+        )?;
+        Ok((
+            first_before.saturating_sub(Self::balance_of(who, first_asset)),
+            second_before.saturating_sub(Self::balance_of(who, second_asset)),
+        ))
+    }
+
+    /// Sell `shares` of a pool on behalf of `who` and return the amounts received.
+    pub fn do_divest(
+        who: &T::AccountId,
+        first_asset: AssetOf<T>,
+        second_asset: AssetOf<T>,
+        shares: BalanceOf<T>,
+        min_first_asset_amount: BalanceOf<T>,
+        min_second_asset_amount: BalanceOf<T>,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>), DispatchError> {
+        ensure!(first_asset != second_asset, Error::<T>::SameAsset);
+        ensure!(
+            !T::DexPaused::is_paused(DexOperation::DivestLiquidity),
+            Error::<T>::DexPaused
+        );
+        let first_before = Self::balance_of(who, first_asset);
+        let second_before = Self::balance_of(who, second_asset);
+        T::OnPoolUpdate::on_pool_update(first_asset, second_asset);
+        pallet_subdex::Module::<T>::divest_liquidity(
+            RawOrigin::Signed(who.clone()).into(),
+            first_asset,
+            second_asset,
+            shares,
+            min_first_asset_amount,
+            min_second_asset_amount,
+        )?;
+        Ok((
+            Self::balance_of(who, first_asset).saturating_sub(first_before),
+            Self::balance_of(who, second_asset).saturating_sub(second_before),
+        ))
+    }
+}
+
+impl<T: Trait> Call<T> {
+    /// Deadline of the call, if it has one.
+    pub fn deadline(&self) -> Option<MomentOf<T>> {
+        match self {
+            Call::swap_exact_in(.., deadline)
+            | Call::swap_exact_out(.., deadline)
+            | Call::invest_liquidity(.., deadline)
+            | Call::divest_liquidity(.., deadline) => Some(*deadline),
+            _ => None,
+        }
+    }
+}
+
+/// Rejects router calls whose deadline has passed before they are included in a block.
+#[derive(Encode, Decode, Clone, Eq, PartialEq)]
+pub struct CheckDeadline<T: Trait + Send + Sync>(PhantomData<T>);
+
+impl<T: Trait + Send + Sync> CheckDeadline<T> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: Trait + Send + Sync> Debug for CheckDeadline<T> {
+    #[cfg(feature = "std")]
+    fn fmt(&self, f: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+        write!(f, "CheckDeadline")
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn fmt(&self, _: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+        Ok(())
+    }
+}
+
+impl<T: Trait + Send + Sync> SignedExtension for CheckDeadline<T>
+where
+    <T as frame_system::Trait>::Call: IsSubType<Call<T>>,
+{
+    const IDENTIFIER: &'static str = "CheckDeadline";
+    type AccountId = T::AccountId;
+    type Call = <T as frame_system::Trait>::Call;
+    type AdditionalSigned = ();
+    type Pre = ();
+
+    fn additional_signed(&self) -> Result<Self::AdditionalSigned, TransactionValidityError> {
+        Ok(())
+    }
+
+    fn validate(
+        &self,
+        _who: &Self::AccountId,
+        call: &Self::Call,
+        _info: &DispatchInfoOf<Self::Call>,
+        _len: usize,
+    ) -> TransactionValidity {
+        if let Some(deadline) = call.is_sub_type().and_then(Call::deadline) {
+            if pallet_timestamp::Module::<T>::get() > deadline {
+                return Err(InvalidTransaction::Stale.into());
+            }
+        }
+        Ok(ValidTransaction::default())
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{mock::*, CheckDeadline, DexOperation, Error, RawEvent};
+use frame_support::{assert_noop, assert_ok};
+use sp_runtime::{
+    traits::SignedExtension,
+    transaction_validity::{InvalidTransaction, TransactionValidityError},
+};
+
+fn last_event() -> TestEvent {
+    System::events()
+        .pop()
+        .expect("an event was deposited")
+        .event
+}
+
+#[test]
+fn swap_exact_in_pays_amount_in_and_receives_amount_out() {
+    new_test_ext().execute_with(|| {
+        let expected_out = DexRouter::amount_out_for(MAIN, BTC, 1_000).unwrap();
+        let main_before = Balances::free_balance(ALICE);
+        let btc_before = asset_balance(ALICE, 1);
+
+        assert_ok!(DexRouter::swap_exact_in(
+            Origin::signed(ALICE),
+            MAIN,
+            1_000,
+            BTC,
+            expected_out,
+            1_000
+        ));
+
+        let received = asset_balance(ALICE, 1) - btc_before;
+        assert!(received >= expected_out);
+        assert_eq!(Balances::free_balance(ALICE), main_before - 1_000);
+        assert_eq!(pool_updates(), vec![(MAIN, BTC)]);
+        assert_eq!(
+            last_event(),
+            TestEvent::router(RawEvent::Swapped(ALICE, MAIN, 1_000, BTC, received))
+        );
+    });
+}
+
+#[test]
+fn swap_exact_in_fails_below_min_amount_out() {
+    new_test_ext().execute_with(|| {
+        let expected_out = DexRouter::amount_out_for(MAIN, BTC, 1_000).unwrap();
+
+        assert_noop!(
+            DexRouter::swap_exact_in(
+                Origin::signed(ALICE),
+                MAIN,
+                1_000,
+                BTC,
+                expected_out + 1,
+                1_000
+            ),
+            Error::<Test>::InsufficientOutputAmount
+        );
+    });
+}
+
+#[test]
+fn swap_exact_out_fails_above_max_amount_in() {
+    new_test_ext().execute_with(|| {
+        let needed_in = DexRouter::amount_in_for(MAIN, BTC, 1_000).unwrap();
+
+        assert_noop!(
+            DexRouter::swap_exact_out(
+                Origin::signed(ALICE),
+                MAIN,
+                needed_in - 1,
+                BTC,
+                1_000,
+                1_000
+            ),
+            Error::<Test>::ExcessiveInputAmount
+        );
+    });
+}
+
+#[test]
+fn swap_exact_out_receives_at_least_amount_out() {
+    new_test_ext().execute_with(|| {
+        let needed_in = DexRouter::amount_in_for(MAIN, BTC, 1_000).unwrap();
+        let btc_before = asset_balance(ALICE, 1);
+
+        assert_ok!(DexRouter::swap_exact_out(
+            Origin::signed(ALICE),
+            MAIN,
+            needed_in,
+            BTC,
+            1_000,
+            1_000
+        ));
+
+        assert!(asset_balance(ALICE, 1) - btc_before >= 1_000);
+    });
+}
+
+#[test]
+fn invest_liquidity_fails_above_max_amounts() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexRouter::invest_liquidity(Origin::signed(BOB), MAIN, BTC, 1_000, 0, 0, 1_000),
+            Error::<Test>::ExcessiveInputAmount
+        );
+    });
+}
+
+#[test]
+fn calls_fail_after_deadline() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexRouter::swap_exact_in(Origin::signed(ALICE), MAIN, 1_000, BTC, 0, 999),
+            Error::<Test>::DeadlineExpired
+        );
+        assert_noop!(
+            DexRouter::swap_exact_out(Origin::signed(ALICE), MAIN, 10_000, BTC, 1_000, 999),
+            Error::<Test>::DeadlineExpired
+        );
+        assert_noop!(
+            DexRouter::invest_liquidity(
+                Origin::signed(BOB),
+                MAIN,
+                BTC,
+                1_000,
+                100_000,
+                100_000,
+                999
+            ),
+            Error::<Test>::DeadlineExpired
+        );
+        assert_noop!(
+            DexRouter::divest_liquidity(Origin::signed(ALICE), MAIN, BTC, 1_000, 0, 0, 999),
+            Error::<Test>::DeadlineExpired
+        );
+        assert!(pool_updates().is_empty());
+    });
+}
+
+#[test]
+fn calls_fail_while_dex_paused() {
+    new_test_ext().execute_with(|| {
+        pause_dex();
+
+        assert_noop!(
+            DexRouter::swap_exact_in(Origin::signed(ALICE), MAIN, 1_000, BTC, 0, 1_000),
+            Error::<Test>::DexPaused
+        );
+        assert_noop!(
+            DexRouter::divest_liquidity(Origin::signed(ALICE), MAIN, BTC, 1_000, 0, 0, 1_000),
+            Error::<Test>::DexPaused
+        );
+        assert!(pool_updates().is_empty());
+    });
+}
+
+#[test]
+fn paused_operations_fail_alone() {
+    new_test_ext().execute_with(|| {
+        pause(DexOperation::InvestLiquidity);
+
+        assert_noop!(
+            DexRouter::invest_liquidity(Origin::signed(BOB), MAIN, BTC, 1_000, 0, 0, 1_000),
+            Error::<Test>::DexPaused
+        );
+        assert_noop!(
+            DexRouter::do_invest(&BOB, MAIN, BTC, 1_000),
+            Error::<Test>::DexPaused
+        );
+        assert_ok!(DexRouter::swap_exact_in(
+            Origin::signed(ALICE),
+            MAIN,
+            1_000,
+            BTC,
+            0,
+            1_000
+        ));
+    });
+}
+
+#[test]
+fn swaps_need_a_pool_of_two_assets() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexRouter::swap_exact_in(Origin::signed(ALICE), BTC, 1_000, BTC, 0, 1_000),
+            Error::<Test>::SameAsset
+        );
+        assert_noop!(
+            DexRouter::swap_exact_out(Origin::signed(ALICE), MAIN, 10_000, ETH, 1_000, 1_000),
+            Error::<Test>::PoolNotFound
+        );
+    });
+}
+
+#[test]
+fn check_deadline_rejects_stale_router_calls() {
+    new_test_ext().execute_with(|| {
+        let swap =
+            |deadline| Call::DexRouter(crate::Call::swap_exact_in(MAIN, 1_000, BTC, 0, deadline));
+        let validate = |call: &Call| {
+            CheckDeadline::<Test>::new().validate(&ALICE, call, &Default::default(), 0)
+        };
+
+        assert_eq!(
+            validate(&swap(999)),
+            Err(TransactionValidityError::Invalid(InvalidTransaction::Stale))
+        );
+        assert!(validate(&swap(1_000)).is_ok());
+
+        Timestamp::set_timestamp(1_001);
+        assert_eq!(
+            validate(&swap(1_000)),
+            Err(TransactionValidityError::Invalid(InvalidTransaction::Stale))
+        );
+        assert!(validate(&Call::System(frame_system::Call::remark(vec![]))).is_ok());
+    });
+}
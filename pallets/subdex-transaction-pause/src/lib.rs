@@ -0,0 +1,137 @@
+//! # Subdex transaction pause pallet
+//!
+//! Lets governance switch off whole pallets or single calls at runtime.
+//!
+//! Pallets and calls are named as in the runtime metadata, e.g. `DexPallet` and `swap`.
+//! [`PausedCallFilter`] refuses paused calls and is meant to be used as the
+//! `BaseCallFilter` of the runtime. Work that is not done through calls, like handling inbound
+//! messages, can be paused under a name chosen by the runtime and checked with
+//! [`Module::is_paused`].
+//!
+//! Pallets in [`Trait::ProtectedPallets`] cannot be paused, so governance and unpausing always
+//! stay reachable.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use frame_support::{
+    decl_error, decl_event, decl_module, decl_storage, ensure,
+    traits::{EnsureOrigin, Filter, Get, GetCallMetadata},
+};
+use sp_std::{marker::PhantomData, prelude::*};
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+pub trait Trait: frame_system::Trait {
+    type Event: From<Event> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// Origin allowed to pause and unpause.
+    type PauseOrigin: EnsureOrigin<Self::Origin>;
+
+    /// Pallets that can never be paused.
+    type ProtectedPallets: Get<Vec<Vec<u8>>>;
+}
+
+decl_storage! {
+    trait Store for Module<T: Trait> as TransactionPause {
+        /// Paused pallets.
+        pub PausedPallets get(fn is_pallet_paused): map hasher(blake2_128_concat) Vec<u8> => bool;
+
+        /// Paused calls, by pallet and call name.
+        pub PausedCalls get(fn is_call_paused):
+            double_map hasher(blake2_128_concat) Vec<u8>, hasher(blake2_128_concat) Vec<u8>
+            => bool;
+    }
+}
+
+decl_event!(
+    pub enum Event {
+        /// All calls of a pallet paused.
+        PalletPaused(Vec<u8>),
+        /// Pallet unpaused, calls paused one by one stay paused.
+        PalletUnpaused(Vec<u8>),
+        /// Call paused: (pallet, call).
+        CallPaused(Vec<u8>, Vec<u8>),
+        /// Call unpaused: (pallet, call).
+        CallUnpaused(Vec<u8>, Vec<u8>),
+    }
+);
+
+decl_error! {
+    pub enum Error for Module<T: Trait> {
+        /// The pallet cannot be paused.
+        ProtectedPallet,
+    }
+}
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        type Error = Error<T>;
+
+        fn deposit_event() = default;
+
+        /// Pause all calls of `pallet`.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn pause_pallet(origin, pallet: Vec<u8>) {
+            T::PauseOrigin::ensure_origin(origin)?;
+            ensure!(!Self::is_protected(&pallet), Error::<T>::ProtectedPallet);
+            PausedPallets::insert(&pallet, true);
+            Self::deposit_event(Event::PalletPaused(pallet));
+        }
+
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn unpause_pallet(origin, pallet: Vec<u8>) {
+            T::PauseOrigin::ensure_origin(origin)?;
+            PausedPallets::remove(&pallet);
+            Self::deposit_event(Event::PalletUnpaused(pallet));
+        }
+
+        /// Pause `call` of `pallet`.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn pause_call(origin, pallet: Vec<u8>, call: Vec<u8>) {
+            T::PauseOrigin::ensure_origin(origin)?;
+            ensure!(!Self::is_protected(&pallet), Error::<T>::ProtectedPallet);
+            PausedCalls::insert(&pallet, &call, true);
+            Self::deposit_event(Event::CallPaused(pallet, call));
+        }
+
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn unpause_call(origin, pallet: Vec<u8>, call: Vec<u8>) {
+            T::PauseOrigin::ensure_origin(origin)?;
+            PausedCalls::remove(&pallet, &call);
+            Self::deposit_event(Event::CallUnpaused(pallet, call));
+        }
+    }
+}
+
+impl<T: Trait> Module<T> {
+    fn is_protected(pallet: &[u8]) -> bool {
+        T::ProtectedPallets::get()
+            .iter()
+            .any(|protected| &protected[..] == pallet)
+    }
+
+    /// Whether `call` of `pallet` is paused, on its own or with the whole pallet.
+    pub fn is_paused(pallet: &[u8], call: &[u8]) -> bool {
+        !Self::is_protected(pallet)
+            && (Self::is_pallet_paused(pallet) || Self::is_call_paused(pallet, call))
+    }
+}
+
+/// Refuses paused calls.
+pub struct PausedCallFilter<T>(PhantomData<T>);
+
+impl<T: Trait> Filter<T::Call> for PausedCallFilter<T>
+where
+    T::Call: GetCallMetadata,
+{
+    fn filter(call: &T::Call) -> bool {
+        let metadata = call.get_call_metadata();
+        !Module::<T>::is_paused(
+            metadata.pallet_name.as_bytes(),
+            metadata.function_name.as_bytes(),
+        )
+    }
+}
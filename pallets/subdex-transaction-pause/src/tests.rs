@@ -0,0 +1,137 @@
+use crate::{mock::*, Error, Event, PausedCallFilter};
+use frame_support::{assert_noop, assert_ok, traits::Filter};
+use sp_runtime::DispatchError;
+
+fn remark() -> Call {
+    Call::System(frame_system::Call::remark(Vec::new()))
+}
+
+fn set_heap_pages() -> Call {
+    Call::System(frame_system::Call::set_heap_pages(1))
+}
+
+fn pause_events() -> Vec<Event> {
+    System::events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            TestEvent::transaction_pause(event) => Some(event),
+            _ => None,
+        })
+        .collect()
+}
+
+fn allowed(call: &Call) -> bool {
+    PausedCallFilter::<Test>::filter(call)
+}
+
+#[test]
+fn pause_and_unpause_a_call() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(TransactionPause::pause_call(
+            Origin::root(),
+            b"System".to_vec(),
+            b"remark".to_vec()
+        ));
+        assert!(TransactionPause::is_paused(b"System", b"remark"));
+        assert!(!allowed(&remark()));
+        assert!(allowed(&set_heap_pages()));
+
+        assert_ok!(TransactionPause::unpause_call(
+            Origin::root(),
+            b"System".to_vec(),
+            b"remark".to_vec()
+        ));
+        assert!(allowed(&remark()));
+        assert_eq!(
+            pause_events(),
+            vec![
+                Event::CallPaused(b"System".to_vec(), b"remark".to_vec()),
+                Event::CallUnpaused(b"System".to_vec(), b"remark".to_vec()),
+            ]
+        );
+    });
+}
+
+#[test]
+fn pause_and_unpause_a_pallet() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(TransactionPause::pause_pallet(
+            Origin::root(),
+            b"System".to_vec()
+        ));
+        assert!(!allowed(&remark()));
+        assert!(!allowed(&set_heap_pages()));
+        assert!(TransactionPause::is_paused(b"System", b"any_call"));
+
+        assert_ok!(TransactionPause::unpause_pallet(
+            Origin::root(),
+            b"System".to_vec()
+        ));
+        assert!(allowed(&remark()));
+        assert!(allowed(&set_heap_pages()));
+    });
+}
+
+#[test]
+fn calls_paused_alone_stay_paused_with_their_pallet_unpaused() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(TransactionPause::pause_call(
+            Origin::root(),
+            b"System".to_vec(),
+            b"remark".to_vec()
+        ));
+        assert_ok!(TransactionPause::pause_pallet(
+            Origin::root(),
+            b"System".to_vec()
+        ));
+        assert_ok!(TransactionPause::unpause_pallet(
+            Origin::root(),
+            b"System".to_vec()
+        ));
+        assert!(!allowed(&remark()));
+        assert!(allowed(&set_heap_pages()));
+    });
+}
+
+#[test]
+fn protected_pallets_cannot_be_paused() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            TransactionPause::pause_pallet(Origin::root(), b"TransactionPause".to_vec()),
+            Error::<Test>::ProtectedPallet
+        );
+        assert_noop!(
+            TransactionPause::pause_call(
+                Origin::root(),
+                b"TransactionPause".to_vec(),
+                b"unpause_pallet".to_vec()
+            ),
+            Error::<Test>::ProtectedPallet
+        );
+        assert!(allowed(&Call::TransactionPause(
+            crate::Call::unpause_pallet(b"System".to_vec())
+        )));
+    });
+}
+
+#[test]
+fn only_the_pause_origin_can_pause() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            TransactionPause::pause_pallet(Origin::signed(ALICE), b"System".to_vec()),
+            DispatchError::BadOrigin
+        );
+        assert_noop!(
+            TransactionPause::pause_call(
+                Origin::signed(ALICE),
+                b"System".to_vec(),
+                b"remark".to_vec()
+            ),
+            DispatchError::BadOrigin
+        );
+        assert_noop!(
+            TransactionPause::unpause_pallet(Origin::signed(ALICE), b"System".to_vec()),
+            DispatchError::BadOrigin
+        );
+    });
+}
@@ -0,0 +1,22 @@
+//! Runtime API definition for the Subdex XCMP router pallet.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use cumulus_primitives::ParaId;
+use sp_std::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+    pub trait DexSiblingApi<AccountId, AssetId, Balance> where
+        AccountId: Codec,
+        AssetId: Codec,
+        Balance: Codec,
+    {
+        /// Sovereign account of Subdex on siblings deriving accounts like Subdex does, holding
+        /// the reserves of the assets bridged to Subdex.
+        fn reserve_account() -> AccountId;
+        /// Amount of every asset of `para_id` bridged to Subdex, which `para_id` should hold in
+        /// reserve in the account of Subdex.
+        fn bridged_supplies(para_id: ParaId) -> Vec<(Option<AssetId>, Balance)>;
+    }
+}
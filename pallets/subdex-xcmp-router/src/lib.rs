@@ -0,0 +1,1121 @@
+//! # Subdex XCMP router pallet
+//!
+//! Single entry and exit point for XCMP messages of the Subdex parachain.
+//!
+//! Inbound messages are decoded as [`SubdexXcmpMessage`]: asset transfers are handed over to
+//! `pallet_subdex_xcmp` unchanged, the other kinds are dispatched to the pallets handling
+//! them. Outbound messages, including the transfers sent by `pallet_subdex_xcmp`, are wrapped
+//! into [`SubdexXcmpMessage`] before they reach the message broker.
+//!
+//! Messages travel in a [`VersionedXcmpMessage`]. Outbound messages use the version
+//! negotiated with their destination, see [`Module::query_xcmp_version`], and are sent
+//! unversioned to chains that never announced one.
+//!
+//! ## Cross-chain swaps
+//!
+//! [`SubdexXcmpMessage::SwapAndReturn`] lets an account of a sibling parachain swap in a
+//! single message: the deposited asset is credited to the pallet account, swapped through
+//! the DEX and the result is sent on to the requested account and parachain. If the swap or
+//! the transfer out fails, everything but the deposit is rolled back and the deposit is sent
+//! back to the sender on the chain it came from.
+//!
+//! Only assets native to the destination parachain can be sent there. The relay chain currency
+//! is bought by naming Subdex itself as destination and its native currency as target asset:
+//! it is then credited to the receiver on Subdex, from where it can be withdrawn to the relay
+//! chain.
+//!
+//! ## Cross-chain liquidity
+//!
+//! [`SubdexXcmpMessage::AddLiquidity`] and [`SubdexXcmpMessage::RemoveLiquidity`] let an
+//! account of a sibling parachain provide liquidity to a pool of two assets of its chain. The
+//! shares are held by an account derived from the parachain and the remote account, see
+//! [`Module::liquidity_account`], and withdrawn assets are sent back to the remote account.
+//! Nobody holds the key of that account, so deposits of a failed operation that cannot be sent
+//! back are taken out of it again and trapped for the remote account instead.
+//!
+//! ## Failed transfers
+//!
+//! Assets that arrive with a message but cannot be credited on Subdex, e.g. because the amount
+//! is below `MinParachainAssetAmount`, are handled according to the [`TransferFailurePolicy`]
+//! governance chose for the sending parachain. They are either sent back with a
+//! [`SubdexXcmpMessage::TransferReturned`] carrying the [`TransferError`], or trapped in
+//! [`Trapped`] until the account they were meant for claims them with
+//! [`Module::claim_trapped`]. Chains have to understand returned transfers to use the first
+//! policy, so trapping is the default. Returned transfers that cannot be credited either are
+//! always trapped, so a transfer never bounces between two chains. Deposits of a failed
+//! cross-chain swap or liquidity operation that cannot be sent back are trapped as well, with
+//! [`TransferError::NotRefunded`].
+//!
+//! Assets of an asset of a parachain are trapped for at most [`Trait::MaxTrappedAccounts`]
+//! accounts at a time. Once that many accounts have some trapped, what fails to be credited to
+//! other accounts is added to a single [`TrappedOverflow`] amount of the asset, which root sends
+//! back with [`Module::return_trapped_overflow`].
+//!
+//! ## Governance
+//!
+//! Root decides which parachains Subdex accepts messages from with a [`ParaPermission`] per
+//! parachain, falling back to [`DefaultParaPermission`]. Messages of denied parachains are
+//! dropped with a [`RawEvent::MessageRejected`], together with the assets they carry, and
+//! parachains limited to mapped assets cannot have new local assets created for them. Assets
+//! can be mapped to a chosen local id before they are first bridged with
+//! [`Module::map_asset`] and the mapping made permanent with [`Module::freeze_asset_mapping`].
+//!
+//! The amount of every asset bridged in from a parachain is tracked in [`BridgedSupply`] and
+//! can be capped with [`Module::set_supply_cap`]; transfers that would exceed the cap are not
+//! credited. The supply of assets bridged in before it was tracked is set by the storage
+//! migration of the runtime, and root can correct a supply with [`Module::set_bridged_supply`].
+//!
+//! ## Reserves
+//!
+//! Subdex holds no reserves for its siblings: it only ever sends assets back to the chain they
+//! came from, so no sibling issues assets backed by funds on Subdex and there is nothing to
+//! account for on Subdex. Reserves only exist the other way round: the bridged supply of the
+//! assets of a sibling is what the sibling should hold in reserve for Subdex, in the sovereign
+//! account of Subdex on its side, [`Module::reserve_account`] for siblings deriving accounts
+//! from [`Sibling`]. Both the account and the bridged supply are exposed through the
+//! `DexSiblingApi` runtime API, so the reserves can be audited against the balance of that
+//! account on the sibling.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Decode, Encode};
+use cumulus_primitives::{
+    xcmp::{XCMPMessageHandler, XCMPMessageSender},
+    ParaId,
+};
+use frame_support::{
+    decl_error, decl_event, decl_module, decl_storage,
+    dispatch::DispatchResult,
+    ensure,
+    traits::{Currency, ExistenceRequirement, Get},
+    transactional, StorageDoubleMap, StorageValue,
+};
+use frame_system::{ensure_root, ensure_signed, RawOrigin};
+use pallet_subdex::Asset;
+use pallet_subdex_message_trace::{
+    Channel, Direction, MessageTrace, MessageTracer, Outcome, TracedMessage,
+};
+use pallet_subdex_router::{BalanceOf, MomentOf};
+use pallet_subdex_xcmp::XCMPMessage;
+use sp_io::hashing::blake2_256;
+use sp_runtime::{
+    traits::{AccountIdConversion, CheckedAdd, One, Saturating, Zero},
+    DispatchError, ModuleId, RuntimeDebug, TypeId,
+};
+use sp_std::{mem, prelude::*};
+
+pub mod message;
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+pub use message::*;
+
+pub type XcmpMessageOf<T> = SubdexXcmpMessage<
+    <T as frame_system::Trait>::AccountId,
+    BalanceOf<T>,
+    <T as pallet_subdex::Trait>::AssetId,
+    MomentOf<T>,
+>;
+
+pub type VersionedXcmpMessageOf<T> = VersionedXcmpMessage<
+    <T as frame_system::Trait>::AccountId,
+    BalanceOf<T>,
+    <T as pallet_subdex::Trait>::AssetId,
+    MomentOf<T>,
+>;
+
+pub type TransferMessageOf<T> = XCMPMessage<
+    <T as frame_system::Trait>::AccountId,
+    BalanceOf<T>,
+    <T as pallet_subdex::Trait>::AssetId,
+>;
+
+pub type SwapAndReturnMessageOf<T> = SwapAndReturnMessage<
+    <T as frame_system::Trait>::AccountId,
+    BalanceOf<T>,
+    <T as pallet_subdex::Trait>::AssetId,
+>;
+
+pub type AddLiquidityMessageOf<T> = AddLiquidityMessage<
+    <T as frame_system::Trait>::AccountId,
+    BalanceOf<T>,
+    <T as pallet_subdex::Trait>::AssetId,
+>;
+
+pub type RemoveLiquidityMessageOf<T> = RemoveLiquidityMessage<
+    <T as frame_system::Trait>::AccountId,
+    BalanceOf<T>,
+    <T as pallet_subdex::Trait>::AssetId,
+>;
+
+pub type ReturnedTransferMessageOf<T> = ReturnedTransferMessage<
+    <T as frame_system::Trait>::AccountId,
+    BalanceOf<T>,
+    <T as pallet_subdex::Trait>::AssetId,
+>;
+
+/// What happens to assets of an inbound transfer that cannot be credited.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum TransferFailurePolicy {
+    /// Send the assets back to the sending chain in a returned transfer.
+    Return,
+    /// Keep the assets claimable by the account they were meant for.
+    Trap,
+}
+
+impl Default for TransferFailurePolicy {
+    fn default() -> Self {
+        TransferFailurePolicy::Trap
+    }
+}
+
+/// Which messages are accepted from a parachain.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum ParaPermission {
+    /// All messages are dropped.
+    Denied,
+    /// Messages are accepted, but only assets with a local id can be credited.
+    MappedAssetsOnly,
+    /// Messages are accepted and new assets get a local id when they are first bridged.
+    Allowed,
+}
+
+impl Default for ParaPermission {
+    fn default() -> Self {
+        ParaPermission::Allowed
+    }
+}
+
+/// A sibling parachain, from which its sovereign account on other parachains is derived.
+///
+/// Its type id differs from the one of `ParaId`, so sibling accounts never collide with the
+/// relay chain accounts of parachains.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub struct Sibling(pub ParaId);
+
+impl TypeId for Sibling {
+    const TYPE_ID: [u8; 4] = *b"sibl";
+}
+
+pub trait Trait: pallet_subdex_xcmp::Trait + pallet_subdex_router::Trait {
+    type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// Sends messages to sibling parachains.
+    type XCMPMessageSender: XCMPMessageSender<VersionedXcmpMessageOf<Self>>;
+
+    /// Id of the account holding assets while a cross-chain operation is executed.
+    type ModuleId: Get<ModuleId>;
+
+    /// Receives the traces of sent and rejected messages.
+    type MessageTracer: MessageTracer<Self::AccountId>;
+
+    /// Id of the Subdex parachain.
+    type SelfParaId: Get<ParaId>;
+
+    /// Maximum number of accounts assets of a single asset of a parachain are trapped for.
+    type MaxTrappedAccounts: Get<u32>;
+}
+
+decl_storage! {
+    trait Store for Module<T: Trait> as XcmpRouter {
+        /// Message format version negotiated with each parachain.
+        pub ParaVersions get(fn para_version): map hasher(twox_64_concat) ParaId => XcmpVersion;
+
+        /// Handling of failed inbound transfers, per sending parachain.
+        pub FailurePolicies get(fn failure_policy):
+            map hasher(twox_64_concat) ParaId => TransferFailurePolicy;
+
+        /// Amounts of failed inbound transfers waiting to be claimed, per account they were
+        /// meant for and per asset, as identified on the parachain it came from.
+        pub Trapped get(fn trapped):
+            double_map hasher(blake2_128_concat) T::AccountId,
+            hasher(blake2_128_concat) (ParaId, Option<T::AssetId>) => BalanceOf<T>;
+
+        /// Number of accounts with assets trapped, per parachain and asset.
+        pub TrappedAccounts get(fn trapped_accounts):
+            double_map hasher(twox_64_concat) ParaId, hasher(blake2_128_concat) Option<T::AssetId>
+            => u32;
+
+        /// Amounts of failed inbound transfers that could not be trapped for their account
+        /// because `MaxTrappedAccounts` accounts already have assets of the same asset trapped,
+        /// per parachain and asset.
+        pub TrappedOverflow get(fn trapped_overflow):
+            double_map hasher(twox_64_concat) ParaId, hasher(blake2_128_concat) Option<T::AssetId>
+            => BalanceOf<T>;
+
+        /// Permission of parachains without an entry in `ParaPermissions`.
+        pub DefaultParaPermission get(fn default_para_permission): ParaPermission;
+
+        pub ParaPermissions get(fn para_permissions):
+            map hasher(twox_64_concat) ParaId => Option<ParaPermission>;
+
+        /// Mappings of assets of a parachain that can no longer be changed.
+        pub FrozenMappings get(fn is_mapping_frozen):
+            double_map hasher(twox_64_concat) ParaId, hasher(blake2_128_concat) Option<T::AssetId>
+            => bool;
+
+        /// Amount of an asset of a parachain currently bridged to Subdex, which the parachain
+        /// holds in reserve for Subdex.
+        pub BridgedSupply get(fn bridged_supply):
+            double_map hasher(twox_64_concat) ParaId, hasher(blake2_128_concat) Option<T::AssetId>
+            => BalanceOf<T>;
+
+        /// Largest amount of an asset of a parachain that can be bridged to Subdex.
+        pub SupplyCaps get(fn supply_cap):
+            double_map hasher(twox_64_concat) ParaId, hasher(blake2_128_concat) Option<T::AssetId>
+            => Option<BalanceOf<T>>;
+    }
+}
+
+decl_event!(
+    pub enum Event<T>
+    where
+        AccountId = <T as frame_system::Trait>::AccountId,
+        AssetId = <T as pallet_subdex::Trait>::AssetId,
+        Balance = BalanceOf<T>,
+    {
+        /// A message of a kind Subdex only sends was received and ignored.
+        UnexpectedMessage(ParaId),
+        /// Cross-chain swap executed: (source parachain, destination parachain, receiver,
+        /// amount in, amount out).
+        SwapAndReturnExecuted(ParaId, ParaId, AccountId, Balance, Balance),
+        /// Cross-chain swap failed, the deposit was sent back: (source parachain, sender,
+        /// amount, reason).
+        SwapAndReturnRefunded(ParaId, AccountId, Balance, DispatchError),
+        /// Cross-chain swap failed and the deposit could not be sent back, it was trapped for
+        /// the sender: (source parachain, sender, amount, reason).
+        SwapAndReturnFailed(ParaId, AccountId, Balance, DispatchError),
+        /// Liquidity provided from another parachain: (source parachain, sender, account
+        /// holding the shares, shares bought).
+        RemoteLiquidityAdded(ParaId, AccountId, AccountId, Balance),
+        /// Liquidity withdrawn to another parachain: (source parachain, sender, shares sold,
+        /// first amount, second amount).
+        RemoteLiquidityRemoved(ParaId, AccountId, Balance, Balance, Balance),
+        /// Cross-chain liquidity operation failed, deposits were sent back or trapped:
+        /// (source parachain, sender, reason).
+        RemoteLiquidityFailed(ParaId, AccountId, DispatchError),
+        /// Inbound transfer could not be credited and was sent back: (source parachain,
+        /// beneficiary, asset, amount, reason).
+        TransferReturned(ParaId, AccountId, Option<AssetId>, Balance, TransferError),
+        /// Inbound transfer could not be credited and was trapped: (source parachain,
+        /// beneficiary, asset, amount, reason).
+        TransferTrapped(ParaId, AccountId, Option<AssetId>, Balance, TransferError),
+        /// Transfer sent by Subdex was returned and credited again: (parachain returning it,
+        /// beneficiary, asset, amount, reason given by that parachain).
+        ReturnedTransferCredited(ParaId, AccountId, Option<AssetId>, Balance, TransferError),
+        /// Inbound transfer could not be credited and too many accounts have assets of the same
+        /// asset trapped, it was added to the trapped overflow of the asset: (source parachain,
+        /// beneficiary, asset, amount, reason).
+        TransferTrappedInOverflow(ParaId, AccountId, Option<AssetId>, Balance, TransferError),
+        /// Trapped assets sent back: (parachain, claimer, receiver on that parachain, asset,
+        /// amount).
+        TrappedAssetsClaimed(ParaId, AccountId, AccountId, Option<AssetId>, Balance),
+        /// Trapped overflow of an asset sent back: (parachain, receiver on that parachain,
+        /// asset, amount).
+        TrappedOverflowReturned(ParaId, AccountId, Option<AssetId>, Balance),
+        /// Handling of failed transfers from a parachain changed.
+        FailurePolicySet(ParaId, TransferFailurePolicy),
+        /// Message of a denied parachain dropped, with the assets it carried.
+        MessageRejected(ParaId),
+        /// Permission of a parachain changed, `None` meaning the default applies.
+        ParaPermissionSet(ParaId, Option<ParaPermission>),
+        /// Permission of parachains without their own changed.
+        DefaultParaPermissionSet(ParaPermission),
+        /// Asset of a parachain mapped: (parachain, asset on that parachain, local id).
+        AssetMapped(ParaId, Option<AssetId>, AssetId),
+        /// Mapping of an asset of a parachain frozen.
+        AssetMappingFrozen(ParaId, Option<AssetId>),
+        /// Bridged supply cap of an asset of a parachain changed, `None` meaning no cap.
+        SupplyCapSet(ParaId, Option<AssetId>, Option<Balance>),
+        /// Bridged supply of an asset of a parachain recorded: (parachain, asset, previous
+        /// supply, new supply).
+        BridgedSupplySet(ParaId, Option<AssetId>, Balance, Balance),
+        /// Message format version of a parachain requested.
+        XcmpVersionQueried(ParaId),
+        /// Message format version used with a parachain changed.
+        XcmpVersionSet(ParaId, XcmpVersion),
+    }
+);
+
+decl_error! {
+    pub enum Error for Module<T: Trait> {
+        /// The asset has never been bridged from the given parachain.
+        UnknownAsset,
+        /// The deposited amount was not credited.
+        DepositFailed,
+        /// Less than the requested minimum would be received.
+        InsufficientOutputAmount,
+        /// No assets are trapped for the caller.
+        NothingTrapped,
+        /// The message could not be sent.
+        SendFailed,
+        /// The mapping of the asset is frozen.
+        MappingFrozen,
+        /// The local asset id may already be assigned to another asset.
+        AssetIdInUse,
+        /// The asset is still bridged under its current mapping.
+        BridgedSupplyOutstanding,
+        /// The parachain may not bridge this asset.
+        AssetNotAllowed,
+        /// The transfer would exceed the bridged supply cap of the asset.
+        SupplyCapExceeded,
+        /// Subdex does not understand this message format version.
+        UnsupportedVersion,
+        /// The bought asset is not native to the destination parachain, or is not known to
+        /// Subdex. Only the relay chain currency can be bought for an account on Subdex.
+        TargetAssetNotNative,
+    }
+}
+
+decl_module! {
+    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        type Error = Error<T>;
+
+        fn deposit_event() = default;
+
+        /// Set how failed inbound transfers from `para_id` are handled.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn set_failure_policy(origin, para_id: ParaId, policy: TransferFailurePolicy) {
+            ensure_root(origin)?;
+            FailurePolicies::insert(para_id, policy);
+            Self::deposit_event(RawEvent::FailurePolicySet(para_id, policy));
+        }
+
+        /// Send assets trapped for the caller back to `dest` on the parachain they came from.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(2, 2)]
+        pub fn claim_trapped(
+            origin,
+            para_id: ParaId,
+            asset: Option<T::AssetId>,
+            dest: T::AccountId,
+        ) {
+            let who = ensure_signed(origin)?;
+            let amount = Self::trapped(&who, (para_id, asset));
+            ensure!(!amount.is_zero(), Error::<T>::NothingTrapped);
+
+            let transfer = XCMPMessage::TransferToken(dest.clone(), amount, asset);
+            Self::send(para_id, SubdexXcmpMessage::Transfer(transfer))
+                .map_err(|_| Error::<T>::SendFailed)?;
+            Trapped::<T>::remove(&who, (para_id, asset));
+            TrappedAccounts::<T>::mutate(para_id, asset, |count| *count = count.saturating_sub(1));
+
+            Self::deposit_event(RawEvent::TrappedAssetsClaimed(para_id, who, dest, asset, amount));
+        }
+
+        /// Send the trapped overflow of `asset` of `para_id` back to `dest` on that parachain.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(2, 1)]
+        pub fn return_trapped_overflow(
+            origin,
+            para_id: ParaId,
+            asset: Option<T::AssetId>,
+            dest: T::AccountId,
+        ) {
+            ensure_root(origin)?;
+            let amount = Self::trapped_overflow(para_id, asset);
+            ensure!(!amount.is_zero(), Error::<T>::NothingTrapped);
+
+            let transfer = XCMPMessage::TransferToken(dest.clone(), amount, asset);
+            Self::send(para_id, SubdexXcmpMessage::Transfer(transfer))
+                .map_err(|_| Error::<T>::SendFailed)?;
+            TrappedOverflow::<T>::remove(para_id, asset);
+
+            Self::deposit_event(RawEvent::TrappedOverflowReturned(para_id, dest, asset, amount));
+        }
+
+        /// Set which messages are accepted from `para_id`, `None` to use the default.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn set_para_permission(
+            origin,
+            para_id: ParaId,
+            permission: Option<ParaPermission>,
+        ) {
+            ensure_root(origin)?;
+            ParaPermissions::mutate(para_id, |current| *current = permission);
+            Self::deposit_event(RawEvent::ParaPermissionSet(para_id, permission));
+        }
+
+        /// Set which messages are accepted from parachains without their own permission.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn set_default_para_permission(origin, permission: ParaPermission) {
+            ensure_root(origin)?;
+            DefaultParaPermission::put(permission);
+            Self::deposit_event(RawEvent::DefaultParaPermissionSet(permission));
+        }
+
+        /// Credit `asset` of `para_id` as the unused local asset `local_asset_id`.
+        ///
+        /// An asset that has already been bridged can only be mapped again once none of it is
+        /// left on Subdex.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(4, 2)]
+        pub fn map_asset(
+            origin,
+            para_id: ParaId,
+            asset: Option<T::AssetId>,
+            local_asset_id: T::AssetId,
+        ) {
+            ensure_root(origin)?;
+            ensure!(!Self::is_mapping_frozen(para_id, asset), Error::<T>::MappingFrozen);
+            ensure!(
+                Self::bridged_supply(para_id, asset).is_zero(),
+                Error::<T>::BridgedSupplyOutstanding
+            );
+            // Ids below the next one `pallet_subdex_xcmp` assigns may be in use already.
+            let next_asset_id = pallet_subdex_xcmp::NextAssetId::<T>::get();
+            ensure!(local_asset_id >= next_asset_id, Error::<T>::AssetIdInUse);
+
+            pallet_subdex_xcmp::AssetIdByParaAssetId::<T>::insert(para_id, asset, local_asset_id);
+            pallet_subdex_xcmp::NextAssetId::<T>::put(local_asset_id + One::one());
+
+            Self::deposit_event(RawEvent::AssetMapped(para_id, asset, local_asset_id));
+        }
+
+        /// Prevent the mapping of `asset` of `para_id` from ever changing.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(1, 1)]
+        pub fn freeze_asset_mapping(origin, para_id: ParaId, asset: Option<T::AssetId>) {
+            ensure_root(origin)?;
+            ensure!(Self::local_asset_id(para_id, asset).is_some(), Error::<T>::UnknownAsset);
+            FrozenMappings::<T>::insert(para_id, asset, true);
+            Self::deposit_event(RawEvent::AssetMappingFrozen(para_id, asset));
+        }
+
+        /// Ask `para_id` for the latest message format version it understands.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(1, 1)]
+        pub fn query_xcmp_version(origin, para_id: ParaId) {
+            ensure_root(origin)?;
+            Self::send(para_id, SubdexXcmpMessage::VersionQuery)
+                .map_err(|_| Error::<T>::SendFailed)?;
+            Self::deposit_event(RawEvent::XcmpVersionQueried(para_id));
+        }
+
+        /// Use message format `version` with `para_id`, regardless of what it announced.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn set_xcmp_version(origin, para_id: ParaId, version: XcmpVersion) {
+            ensure_root(origin)?;
+            ensure!(version <= LATEST_XCMP_VERSION, Error::<T>::UnsupportedVersion);
+            ParaVersions::insert(para_id, version);
+            Self::deposit_event(RawEvent::XcmpVersionSet(para_id, version));
+        }
+
+        /// Set the largest amount of `asset` of `para_id` that can be bridged, `None` for no
+        /// limit.
+        #[weight = 10_000 + T::DbWeight::get().writes(1)]
+        pub fn set_supply_cap(
+            origin,
+            para_id: ParaId,
+            asset: Option<T::AssetId>,
+            cap: Option<BalanceOf<T>>,
+        ) {
+            ensure_root(origin)?;
+            SupplyCaps::<T>::mutate(para_id, asset, |current| *current = cap);
+            Self::deposit_event(RawEvent::SupplyCapSet(para_id, asset, cap));
+        }
+
+        /// Record `supply` as the bridged supply of `asset` of `para_id`, e.g. to account for
+        /// amounts bridged in before the supply was tracked.
+        #[weight = 10_000 + T::DbWeight::get().reads_writes(1, 1)]
+        pub fn set_bridged_supply(
+            origin,
+            para_id: ParaId,
+            asset: Option<T::AssetId>,
+            supply: BalanceOf<T>,
+        ) {
+            ensure_root(origin)?;
+            let previous = BridgedSupply::<T>::mutate(para_id, asset, |current| {
+                mem::replace(current, supply)
+            });
+            Self::deposit_event(RawEvent::BridgedSupplySet(para_id, asset, previous, supply));
+        }
+    }
+}
+
+impl<T: Trait> Module<T> {
+    /// The account holding assets while a cross-chain operation is executed.
+    pub fn account_id() -> T::AccountId {
+        T::ModuleId::get().into_account()
+    }
+
+    /// Sovereign account of Subdex on its siblings, holding the reserves of bridged assets.
+    ///
+    /// Only meaningful for siblings deriving sovereign accounts from [`Sibling`].
+    pub fn reserve_account() -> T::AccountId {
+        Sibling(T::SelfParaId::get()).into_account()
+    }
+
+    /// Bridged supply of every asset of `para_id`, which `para_id` should hold in reserve.
+    pub fn bridged_supplies(para_id: ParaId) -> Vec<(Option<T::AssetId>, BalanceOf<T>)> {
+        BridgedSupply::<T>::iter_prefix(para_id).collect()
+    }
+
+    /// Send a message to a sibling parachain, in the version negotiated with it.
+    pub fn send(dest: ParaId, message: XcmpMessageOf<T>) -> Result<(), ()> {
+        let message = VersionedXcmpMessage::new(Self::para_version(dest), message);
+        let result = T::XCMPMessageSender::send_xcmp_message(dest, &message);
+        T::MessageTracer::trace(MessageTrace::new(
+            Direction::Outbound,
+            Channel::Xcmp(dest),
+            &message,
+            message.accounts(),
+            if result.is_ok() {
+                Outcome::Sent
+            } else {
+                Outcome::NotSent
+            },
+        ));
+        result
+    }
+
+    /// Account holding the pool shares bought for `sender` of parachain `para_id`.
+    pub fn liquidity_account(para_id: ParaId, sender: &T::AccountId) -> T::AccountId {
+        T::ModuleId::get().into_sub_account(blake2_256(&(para_id, sender).encode()))
+    }
+
+    /// Messages accepted from `para_id`.
+    pub fn para_permission(para_id: ParaId) -> ParaPermission {
+        Self::para_permissions(para_id).unwrap_or_else(Self::default_para_permission)
+    }
+
+    /// Bridged supply of an asset of `para_id` after `amount` more is bridged, if within its
+    /// cap.
+    fn supply_after_deposit(
+        para_id: ParaId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+    ) -> Option<BalanceOf<T>> {
+        let supply = Self::bridged_supply(para_id, asset).checked_add(&amount)?;
+        match Self::supply_cap(para_id, asset) {
+            Some(cap) if supply > cap => None,
+            _ => Some(supply),
+        }
+    }
+
+    /// Local id of an asset bridged from `para_id`.
+    pub fn local_asset_id(para_id: ParaId, asset: Option<T::AssetId>) -> Option<T::AssetId> {
+        pallet_subdex_xcmp::Module::<T>::asset_id_by_para_asset_id(para_id, asset)
+    }
+
+    /// Credit `amount` of an asset of `para_id` to `who`, exactly like an inbound transfer
+    /// would, and return its local id.
+    ///
+    /// Fails if governance does not allow the asset or its bridged supply cap would be
+    /// exceeded.
+    pub fn deposit(
+        para_id: ParaId,
+        who: &T::AccountId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+    ) -> Result<T::AssetId, DispatchError> {
+        let mapped = Self::local_asset_id(para_id, asset);
+        ensure!(
+            mapped.is_some() || Self::para_permission(para_id) == ParaPermission::Allowed,
+            Error::<T>::AssetNotAllowed
+        );
+        let supply = Self::supply_after_deposit(para_id, asset, amount)
+            .ok_or(Error::<T>::SupplyCapExceeded)?;
+
+        let balance =
+            |id| pallet_subdex_router::Module::<T>::balance_of(who, Asset::ParachainAsset(id));
+        let before = mapped.map(balance).unwrap_or_else(Zero::zero);
+
+        <pallet_subdex_xcmp::Module<T> as XCMPMessageHandler<_>>::handle_xcmp_message(
+            para_id,
+            &XCMPMessage::TransferToken(who.clone(), amount, asset),
+        );
+
+        let local = Self::local_asset_id(para_id, asset).ok_or(Error::<T>::UnknownAsset)?;
+        ensure!(
+            balance(local).saturating_sub(before) == amount,
+            Error::<T>::DepositFailed
+        );
+        BridgedSupply::<T>::insert(para_id, asset, supply);
+        Ok(local)
+    }
+
+    /// Send `amount` of an asset bridged from `para_id` held by `who` back to `dest` on that
+    /// parachain.
+    pub fn withdraw(
+        who: &T::AccountId,
+        para_id: ParaId,
+        dest: T::AccountId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+    ) -> DispatchResult {
+        pallet_subdex_xcmp::Module::<T>::transfer_asset_balance_to_parachain_chain(
+            RawOrigin::Signed(who.clone()).into(),
+            para_id.into(),
+            dest,
+            asset,
+            amount,
+        )
+    }
+
+    fn handle_swap_and_return(src: ParaId, swap: &SwapAndReturnMessageOf<T>) {
+        let holder = Self::account_id();
+        let asset_in = match Self::deposit(src, &holder, swap.asset, swap.amount) {
+            Ok(asset_in) => asset_in,
+            Err(_) => {
+                return Self::handle_failed_transfer(src, &swap.sender, swap.asset, swap.amount)
+            }
+        };
+
+        let error = match Self::execute_swap_and_return(&holder, asset_in, swap) {
+            Ok(amount_out) => {
+                Self::deposit_event(RawEvent::SwapAndReturnExecuted(
+                    src,
+                    swap.dest_para_id,
+                    swap.dest.clone(),
+                    swap.amount,
+                    amount_out,
+                ));
+                return;
+            }
+            Err(error) => error,
+        };
+
+        let refunded = Self::refund_or_trap(&holder, src, &swap.sender, swap.asset, swap.amount);
+        let sender = swap.sender.clone();
+        Self::deposit_event(if refunded {
+            RawEvent::SwapAndReturnRefunded(src, sender, swap.amount, error)
+        } else {
+            RawEvent::SwapAndReturnFailed(src, sender, swap.amount, error)
+        });
+    }
+
+    #[transactional]
+    fn execute_swap_and_return(
+        holder: &T::AccountId,
+        asset_in: T::AssetId,
+        swap: &SwapAndReturnMessageOf<T>,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        let to_subdex = swap.dest_para_id == T::SelfParaId::get();
+        let asset_out = match swap.target_asset {
+            None if to_subdex => Asset::MainNetworkCurrency,
+            _ if to_subdex => return Err(Error::<T>::TargetAssetNotNative.into()),
+            target => Asset::ParachainAsset(
+                Self::local_asset_id(swap.dest_para_id, target)
+                    .ok_or(Error::<T>::TargetAssetNotNative)?,
+            ),
+        };
+        let amount_out = pallet_subdex_router::Module::<T>::do_swap(
+            holder,
+            Asset::ParachainAsset(asset_in),
+            swap.amount,
+            asset_out,
+        )?;
+        ensure!(
+            amount_out >= swap.min_amount_out,
+            Error::<T>::InsufficientOutputAmount
+        );
+        if to_subdex {
+            <T as pallet_subdex::Trait>::Currency::transfer(
+                holder,
+                &swap.dest,
+                amount_out,
+                ExistenceRequirement::AllowDeath,
+            )?;
+        } else {
+            Self::withdraw(
+                holder,
+                swap.dest_para_id,
+                swap.dest.clone(),
+                swap.target_asset,
+                amount_out,
+            )?;
+        }
+        Ok(amount_out)
+    }
+
+    fn handle_add_liquidity(src: ParaId, add: &AddLiquidityMessageOf<T>) {
+        let provider = Self::liquidity_account(src, &add.sender);
+        let first = Self::deposit(src, &provider, add.first_asset, add.first_amount);
+        let second = Self::deposit(src, &provider, add.second_asset, add.second_amount);
+        let result = match (&first, &second) {
+            (Ok(first), Ok(second)) => {
+                Self::execute_add_liquidity(src, &provider, *first, *second, add)
+            }
+            (Err(error), _) | (_, Err(error)) => Err(*error),
+        };
+
+        let error = match result {
+            Ok(shares) => {
+                Self::deposit_event(RawEvent::RemoteLiquidityAdded(
+                    src,
+                    add.sender.clone(),
+                    provider,
+                    shares,
+                ));
+                return;
+            }
+            Err(error) => error,
+        };
+
+        let deposits = [
+            (first.is_ok(), add.first_asset, add.first_amount),
+            (second.is_ok(), add.second_asset, add.second_amount),
+        ];
+        for &(credited, asset, amount) in deposits.iter() {
+            if credited {
+                Self::refund_or_trap(&provider, src, &add.sender, asset, amount);
+            } else {
+                Self::handle_failed_transfer(src, &add.sender, asset, amount);
+            }
+        }
+        Self::deposit_event(RawEvent::RemoteLiquidityFailed(
+            src,
+            add.sender.clone(),
+            error,
+        ));
+    }
+
+    #[transactional]
+    fn execute_add_liquidity(
+        src: ParaId,
+        provider: &T::AccountId,
+        first: T::AssetId,
+        second: T::AssetId,
+        add: &AddLiquidityMessageOf<T>,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        let (first, second) = (Asset::ParachainAsset(first), Asset::ParachainAsset(second));
+        let shares = pallet_subdex_router::Module::<T>::shares_for(
+            first,
+            add.first_amount,
+            second,
+            add.second_amount,
+        )?;
+        ensure!(
+            !shares.is_zero() && shares >= add.min_shares,
+            Error::<T>::InsufficientOutputAmount
+        );
+        let (first_paid, second_paid) =
+            pallet_subdex_router::Module::<T>::do_invest(provider, first, second, shares)?;
+
+        let first_left = add.first_amount.saturating_sub(first_paid);
+        if !first_left.is_zero() {
+            Self::withdraw(
+                provider,
+                src,
+                add.sender.clone(),
+                add.first_asset,
+                first_left,
+            )?;
+        }
+        let second_left = add.second_amount.saturating_sub(second_paid);
+        if !second_left.is_zero() {
+            Self::withdraw(
+                provider,
+                src,
+                add.sender.clone(),
+                add.second_asset,
+                second_left,
+            )?;
+        }
+        Ok(shares)
+    }
+
+    fn handle_remove_liquidity(src: ParaId, remove: &RemoveLiquidityMessageOf<T>) {
+        match Self::execute_remove_liquidity(src, remove) {
+            Ok((first_amount, second_amount)) => {
+                Self::deposit_event(RawEvent::RemoteLiquidityRemoved(
+                    src,
+                    remove.sender.clone(),
+                    remove.shares,
+                    first_amount,
+                    second_amount,
+                ))
+            }
+            Err(error) => Self::deposit_event(RawEvent::RemoteLiquidityFailed(
+                src,
+                remove.sender.clone(),
+                error,
+            )),
+        }
+    }
+
+    #[transactional]
+    fn execute_remove_liquidity(
+        src: ParaId,
+        remove: &RemoveLiquidityMessageOf<T>,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>), DispatchError> {
+        let provider = Self::liquidity_account(src, &remove.sender);
+        let first =
+            Self::local_asset_id(src, remove.first_asset).ok_or(Error::<T>::UnknownAsset)?;
+        let second =
+            Self::local_asset_id(src, remove.second_asset).ok_or(Error::<T>::UnknownAsset)?;
+
+        let (first_amount, second_amount) = pallet_subdex_router::Module::<T>::do_divest(
+            &provider,
+            Asset::ParachainAsset(first),
+            Asset::ParachainAsset(second),
+            remove.shares,
+            remove.min_first_amount,
+            remove.min_second_amount,
+        )?;
+        ensure!(
+            first_amount >= remove.min_first_amount && second_amount >= remove.min_second_amount,
+            Error::<T>::InsufficientOutputAmount
+        );
+
+        Self::withdraw(
+            &provider,
+            src,
+            remove.sender.clone(),
+            remove.first_asset,
+            first_amount,
+        )?;
+        Self::withdraw(
+            &provider,
+            src,
+            remove.sender.clone(),
+            remove.second_asset,
+            second_amount,
+        )?;
+        Ok((first_amount, second_amount))
+    }
+
+    fn handle_transfer(src: ParaId, transfer: &TransferMessageOf<T>) {
+        match transfer {
+            XCMPMessage::TransferToken(beneficiary, amount, asset) => {
+                if Self::deposit(src, beneficiary, *asset, *amount).is_err() {
+                    Self::handle_failed_transfer(src, beneficiary, *asset, *amount);
+                }
+            }
+        }
+    }
+
+    fn handle_transfer_returned(src: ParaId, returned: &ReturnedTransferMessageOf<T>) {
+        let beneficiary = &returned.beneficiary;
+        match Self::deposit(src, beneficiary, returned.asset, returned.amount) {
+            Ok(_) => Self::deposit_event(RawEvent::ReturnedTransferCredited(
+                src,
+                beneficiary.clone(),
+                returned.asset,
+                returned.amount,
+                returned.error,
+            )),
+            Err(_) => {
+                let error = Self::transfer_error(src, returned.asset, returned.amount);
+                Self::trap(src, beneficiary, returned.asset, returned.amount, error);
+            }
+        }
+    }
+
+    /// Handle assets of `src` that were meant for `beneficiary` but could not be credited,
+    /// according to the failure policy of `src`.
+    fn handle_failed_transfer(
+        src: ParaId,
+        beneficiary: &T::AccountId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+    ) {
+        let error = Self::transfer_error(src, asset, amount);
+        if Self::failure_policy(src) == TransferFailurePolicy::Return {
+            let returned = SubdexXcmpMessage::TransferReturned(ReturnedTransferMessage {
+                beneficiary: beneficiary.clone(),
+                asset,
+                amount,
+                error,
+            });
+            if Self::send(src, returned).is_ok() {
+                Self::deposit_event(RawEvent::TransferReturned(
+                    src,
+                    beneficiary.clone(),
+                    asset,
+                    amount,
+                    error,
+                ));
+                return;
+            }
+        }
+        Self::trap(src, beneficiary, asset, amount, error);
+    }
+
+    fn trap(
+        src: ParaId,
+        beneficiary: &T::AccountId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+        error: TransferError,
+    ) {
+        if amount.is_zero() {
+            return;
+        }
+        if Trapped::<T>::contains_key(beneficiary, (src, asset)) {
+            Trapped::<T>::mutate(beneficiary, (src, asset), |trapped| {
+                *trapped = trapped.saturating_add(amount)
+            });
+        } else if Self::trapped_accounts(src, asset) < T::MaxTrappedAccounts::get() {
+            Trapped::<T>::insert(beneficiary, (src, asset), amount);
+            TrappedAccounts::<T>::mutate(src, asset, |count| *count += 1);
+        } else {
+            TrappedOverflow::<T>::mutate(src, asset, |overflow| {
+                *overflow = overflow.saturating_add(amount)
+            });
+            return Self::deposit_event(RawEvent::TransferTrappedInOverflow(
+                src,
+                beneficiary.clone(),
+                asset,
+                amount,
+                error,
+            ));
+        }
+        Self::deposit_event(RawEvent::TransferTrapped(
+            src,
+            beneficiary.clone(),
+            asset,
+            amount,
+            error,
+        ));
+    }
+
+    /// Most likely reason a transfer of `amount` of an asset of `src` was not credited.
+    fn transfer_error(
+        src: ParaId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+    ) -> TransferError {
+        let mapped = Self::local_asset_id(src, asset).is_some();
+        if amount < T::MinParachainAssetAmount::get() {
+            TransferError::BelowMinimum
+        } else if !mapped && Self::para_permission(src) != ParaPermission::Allowed {
+            TransferError::AssetNotAllowed
+        } else if Self::supply_after_deposit(src, asset, amount).is_none() {
+            TransferError::SupplyCapExceeded
+        } else if !mapped {
+            TransferError::UnknownAsset
+        } else {
+            TransferError::NotCredited
+        }
+    }
+
+    /// Send a deposit held by `holder` back to `sender` on the chain it came from, or trap it
+    /// for `sender` if it cannot be sent.
+    ///
+    /// Returns whether the deposit was sent.
+    fn refund_or_trap(
+        holder: &T::AccountId,
+        src: ParaId,
+        sender: &T::AccountId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+    ) -> bool {
+        if Self::refund(holder, src, sender, asset, amount) {
+            return true;
+        }
+        let trapped = Self::uncredit(holder, src, asset, amount);
+        if !trapped.is_zero() {
+            Self::trap(src, sender, asset, trapped, TransferError::NotRefunded);
+        }
+        false
+    }
+
+    /// Take up to `amount` of a deposit back from `holder`, as if it had never been credited,
+    /// and return the amount taken.
+    fn uncredit(
+        holder: &T::AccountId,
+        src: ParaId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+    ) -> BalanceOf<T> {
+        let id = match Self::local_asset_id(src, asset) {
+            Some(id) => id,
+            None => return Zero::zero(),
+        };
+        let balance = pallet_subdex::Module::<T>::asset_balances(holder, id);
+        let taken = amount.min(balance);
+        if taken == balance {
+            pallet_subdex::AssetBalances::<T>::remove(holder, id);
+        } else {
+            pallet_subdex::AssetBalances::<T>::insert(holder, id, balance - taken);
+        }
+        BridgedSupply::<T>::mutate(src, asset, |supply| *supply = supply.saturating_sub(taken));
+        taken
+    }
+
+    /// Send a deposit held by `holder` back to `sender` on the chain it came from.
+    ///
+    /// Returns whether the deposit could be sent.
+    fn refund(
+        holder: &T::AccountId,
+        src: ParaId,
+        sender: &T::AccountId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+    ) -> bool {
+        Self::local_asset_id(src, asset).map_or(false, |id| {
+            pallet_subdex_router::Module::<T>::balance_of(holder, Asset::ParachainAsset(id))
+                >= amount
+                && Self::send_back(holder, src, sender, asset, amount).is_ok()
+        })
+    }
+
+    #[transactional]
+    fn send_back(
+        holder: &T::AccountId,
+        src: ParaId,
+        sender: &T::AccountId,
+        asset: Option<T::AssetId>,
+        amount: BalanceOf<T>,
+    ) -> DispatchResult {
+        Self::withdraw(holder, src, sender.clone(), asset, amount)
+    }
+}
+
+impl<T: Trait> XCMPMessageHandler<VersionedXcmpMessageOf<T>> for Module<T> {
+    fn handle_xcmp_message(src: ParaId, message: &VersionedXcmpMessageOf<T>) {
+        if Self::para_permission(src) == ParaPermission::Denied {
+            T::MessageTracer::trace(MessageTrace::new(
+                Direction::Inbound,
+                Channel::Xcmp(src),
+                message,
+                message.accounts(),
+                Outcome::Rejected,
+            ));
+            return Self::deposit_event(RawEvent::MessageRejected(src));
+        }
+        match message.message() {
+            SubdexXcmpMessage::Transfer(transfer) => Self::handle_transfer(src, transfer),
+            SubdexXcmpMessage::SwapAndReturn(swap) => Self::handle_swap_and_return(src, swap),
+            SubdexXcmpMessage::AddLiquidity(add) => Self::handle_add_liquidity(src, add),
+            SubdexXcmpMessage::RemoveLiquidity(remove) => {
+                Self::handle_remove_liquidity(src, remove)
+            }
+            SubdexXcmpMessage::TransferReturned(returned) => {
+                Self::handle_transfer_returned(src, returned)
+            }
+            SubdexXcmpMessage::VersionQuery => {
+                // Nothing to do if it cannot be sent, the querying chain can ask again.
+                let _ = Self::send(
+                    src,
+                    SubdexXcmpMessage::VersionAnnouncement(LATEST_XCMP_VERSION),
+                );
+            }
+            SubdexXcmpMessage::VersionAnnouncement(version) => {
+                let version = (*version).min(LATEST_XCMP_VERSION);
+                ParaVersions::insert(src, version);
+                Self::deposit_event(RawEvent::XcmpVersionSet(src, version));
+            }
+            SubdexXcmpMessage::PriceFeed(_) => {
+                Self::deposit_event(RawEvent::UnexpectedMessage(src));
+            }
+        }
+    }
+}
+
+/// Lets `pallet_subdex_xcmp` send its transfers through the router.
+///
+/// Assets sent back to their parachain no longer count towards its bridged supply.
+impl<T: Trait> XCMPMessageSender<TransferMessageOf<T>> for Module<T> {
+    fn send_xcmp_message(dest: ParaId, message: &TransferMessageOf<T>) -> Result<(), ()> {
+        Self::send(dest, SubdexXcmpMessage::Transfer(message.clone()))?;
+        match message {
+            XCMPMessage::TransferToken(_, amount, asset) => {
+                BridgedSupply::<T>::mutate(dest, asset, |supply| {
+                    *supply = supply.saturating_sub(*amount)
+                });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,552 @@
+//! Messages exchanged between Subdex and its sibling parachains.
+//!
+//! `pallet_subdex_xcmp` messages keep their original encoding, so chains that only know about
+//! asset transfers keep working. Messages added by Subdex on top of them use enum indices
+//! from [`EXTENDED_MESSAGE_INDEX`] upwards, which `XCMPMessage` never reaches.
+//!
+//! On the wire, messages are wrapped in a [`VersionedXcmpMessage`]. Chains that never
+//! negotiated a version receive and send messages unversioned, exactly as above. Chains that
+//! announced support for a version exchange messages prefixed with
+//! [`VERSIONED_MESSAGE_INDEX`] and the version, so the payload of later versions can change
+//! without being mistaken for an older one. Versions are negotiated with
+//! [`SubdexXcmpMessage::VersionQuery`] and [`SubdexXcmpMessage::VersionAnnouncement`], which
+//! chains that do not know them simply fail to decode.
+
+use codec::{Decode, Encode, EncodeLike, Error, Input, Output};
+use cumulus_primitives::ParaId;
+use pallet_subdex::Asset;
+use pallet_subdex_message_trace::TracedMessage;
+use pallet_subdex_xcmp::XCMPMessage;
+use sp_core::sr25519;
+use sp_runtime::{FixedU128, RuntimeDebug};
+use sp_std::prelude::*;
+
+/// Lowest enum index used by messages that are not part of `pallet_subdex_xcmp`.
+pub const EXTENDED_MESSAGE_INDEX: u8 = 0x80;
+
+const PRICE_FEED_INDEX: u8 = EXTENDED_MESSAGE_INDEX;
+const SWAP_AND_RETURN_INDEX: u8 = EXTENDED_MESSAGE_INDEX + 1;
+const ADD_LIQUIDITY_INDEX: u8 = EXTENDED_MESSAGE_INDEX + 2;
+const REMOVE_LIQUIDITY_INDEX: u8 = EXTENDED_MESSAGE_INDEX + 3;
+const TRANSFER_RETURNED_INDEX: u8 = EXTENDED_MESSAGE_INDEX + 4;
+const VERSION_QUERY_INDEX: u8 = EXTENDED_MESSAGE_INDEX + 5;
+const VERSION_ANNOUNCEMENT_INDEX: u8 = EXTENDED_MESSAGE_INDEX + 6;
+
+/// First byte of messages sent with an explicit version.
+pub const VERSIONED_MESSAGE_INDEX: u8 = 0xff;
+
+/// Version of the message format, 0 standing for unversioned messages.
+pub type XcmpVersion = u8;
+
+/// Latest message format version Subdex understands.
+pub const LATEST_XCMP_VERSION: XcmpVersion = 1;
+
+pub type SubscriptionId = u64;
+
+/// Price update delivered to the subscribers of a price feed.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct PriceFeedMessage<AssetId, Moment> {
+    /// Subscription the update is delivered for.
+    pub subscription: SubscriptionId,
+    /// Asset the price is given for.
+    pub base: Asset<AssetId>,
+    /// Asset the price is expressed in.
+    pub quote: Asset<AssetId>,
+    /// Price at the current pool reserves.
+    pub spot_price: FixedU128,
+    /// Time-weighted average price over `twap_window`, if the pool has enough history.
+    pub twap: Option<FixedU128>,
+    /// Window of the time-weighted average price, in milliseconds.
+    pub twap_window: Moment,
+    /// Timestamp of the Subdex block the prices were read in.
+    pub timestamp: Moment,
+}
+
+/// [`PriceFeedMessage`] signed by the Subdex price signer.
+///
+/// Subscribers should only accept updates signed by the price signer Subdex publishes.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct SignedPriceFeedMessage<AssetId, Moment> {
+    pub feed: PriceFeedMessage<AssetId, Moment>,
+    /// Key the update was signed with.
+    pub signer: sr25519::Public,
+    /// Signature of the SCALE encoded `feed`.
+    pub signature: sr25519::Signature,
+}
+
+impl<AssetId: Encode, Moment: Encode> SignedPriceFeedMessage<AssetId, Moment> {
+    /// Whether `signature` is a signature of `feed` by `signer`.
+    pub fn verify(&self) -> bool {
+        sp_io::crypto::sr25519_verify(&self.signature, &self.feed.encode(), &self.signer)
+    }
+}
+
+/// Deposit an asset, swap it on Subdex and send the result on to another parachain.
+///
+/// Assets are identified the way `pallet_subdex_xcmp` transfers identify them: by the id they
+/// have on the chain they come from, `None` standing for that chain's native currency.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct SwapAndReturnMessage<AccountId, Balance, AssetId> {
+    /// Account on the sending chain that is refunded if the swap cannot be executed.
+    pub sender: AccountId,
+    /// Deposited asset, as identified on the sending chain.
+    pub asset: Option<AssetId>,
+    /// Deposited amount.
+    pub amount: Balance,
+    /// Parachain the bought asset is sent to, or Subdex to keep it there.
+    pub dest_para_id: ParaId,
+    /// Bought asset, as identified on `dest_para_id`. It must be native to `dest_para_id`;
+    /// on Subdex, `None` is the relay chain currency and nothing else can be bought.
+    pub target_asset: Option<AssetId>,
+    /// Smallest acceptable amount of the bought asset.
+    pub min_amount_out: Balance,
+    /// Account on `dest_para_id` receiving the bought asset.
+    pub dest: AccountId,
+}
+
+/// Deposit two assets of the sending chain and buy shares of their pool with them.
+///
+/// Both assets must be native to the sending chain, as they are transferred from it with the
+/// message: pools with the relay chain currency or with assets of other chains cannot be
+/// joined this way.
+///
+/// The shares are held on Subdex by an account derived from the sending chain and `sender`.
+/// Whatever the pool ratio leaves unused is sent back to `sender`.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct AddLiquidityMessage<AccountId, Balance, AssetId> {
+    /// Account on the sending chain the shares are bought for.
+    pub sender: AccountId,
+    /// First deposited asset, as identified on the sending chain.
+    pub first_asset: Option<AssetId>,
+    pub first_amount: Balance,
+    /// Second deposited asset, as identified on the sending chain.
+    pub second_asset: Option<AssetId>,
+    pub second_amount: Balance,
+    /// Smallest acceptable number of shares.
+    pub min_shares: Balance,
+}
+
+/// Sell shares held for `sender` and send the withdrawn assets back to the sending chain.
+///
+/// Like [`AddLiquidityMessage`], only pools of two assets of the sending chain are supported.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct RemoveLiquidityMessage<AccountId, Balance, AssetId> {
+    /// Account on the sending chain the shares are held for.
+    pub sender: AccountId,
+    /// First asset of the pool, as identified on the sending chain.
+    pub first_asset: Option<AssetId>,
+    /// Second asset of the pool, as identified on the sending chain.
+    pub second_asset: Option<AssetId>,
+    /// Number of shares sold.
+    pub shares: Balance,
+    /// Smallest acceptable amount of the first asset.
+    pub min_first_amount: Balance,
+    /// Smallest acceptable amount of the second asset.
+    pub min_second_amount: Balance,
+}
+
+/// Why an inbound transfer could not be credited.
+///
+/// Sent to other chains, so new reasons must only ever be appended.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum TransferError {
+    /// The amount is below the smallest amount of a parachain asset Subdex accepts.
+    BelowMinimum,
+    /// The asset could not be registered on Subdex.
+    UnknownAsset,
+    /// The transfer was accepted but the receiving account was not credited.
+    NotCredited,
+    /// The sending chain may not bridge this asset to Subdex.
+    AssetNotAllowed,
+    /// Subdex does not accept more of this asset from the sending chain.
+    SupplyCapExceeded,
+    /// The transfer was credited for an operation that failed and could not be sent back.
+    NotRefunded,
+}
+
+/// Transfer sent back to the chain it came from because it could not be credited.
+///
+/// The receiving chain is expected to credit `amount` of `asset` to `beneficiary` again.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct ReturnedTransferMessage<AccountId, Balance, AssetId> {
+    /// Account the transfer was meant for, credited with the returned amount.
+    pub beneficiary: AccountId,
+    /// Returned asset, as identified on the chain receiving this message.
+    pub asset: Option<AssetId>,
+    pub amount: Balance,
+    pub error: TransferError,
+}
+
+#[derive(Clone, PartialEq, Eq, RuntimeDebug)]
+pub enum SubdexXcmpMessage<AccountId, Balance, AssetId, Moment> {
+    /// Asset transfer as understood by `pallet_subdex_xcmp`.
+    Transfer(XCMPMessage<AccountId, Balance, AssetId>),
+    /// Signed price update of a pair the destination chain subscribed to.
+    PriceFeed(SignedPriceFeedMessage<AssetId, Moment>),
+    /// Swap of a deposited asset, the result being sent to another parachain.
+    SwapAndReturn(SwapAndReturnMessage<AccountId, Balance, AssetId>),
+    /// Liquidity provided from another parachain.
+    AddLiquidity(AddLiquidityMessage<AccountId, Balance, AssetId>),
+    /// Liquidity withdrawn to another parachain.
+    RemoveLiquidity(RemoveLiquidityMessage<AccountId, Balance, AssetId>),
+    /// Transfer that could not be credited by the receiving chain.
+    TransferReturned(ReturnedTransferMessage<AccountId, Balance, AssetId>),
+    /// Request for the latest message format version the receiving chain understands.
+    VersionQuery,
+    /// Latest message format version the sending chain understands.
+    VersionAnnouncement(XcmpVersion),
+}
+
+impl<AccountId, Balance, AssetId, Moment> Encode
+    for SubdexXcmpMessage<AccountId, Balance, AssetId, Moment>
+where
+    AccountId: Encode,
+    Balance: Encode,
+    AssetId: Encode,
+    Moment: Encode,
+{
+    fn encode_to<W: Output>(&self, dest: &mut W) {
+        match self {
+            SubdexXcmpMessage::Transfer(message) => message.encode_to(dest),
+            SubdexXcmpMessage::PriceFeed(feed) => {
+                dest.push_byte(PRICE_FEED_INDEX);
+                feed.encode_to(dest);
+            }
+            SubdexXcmpMessage::SwapAndReturn(swap) => {
+                dest.push_byte(SWAP_AND_RETURN_INDEX);
+                swap.encode_to(dest);
+            }
+            SubdexXcmpMessage::AddLiquidity(add) => {
+                dest.push_byte(ADD_LIQUIDITY_INDEX);
+                add.encode_to(dest);
+            }
+            SubdexXcmpMessage::RemoveLiquidity(remove) => {
+                dest.push_byte(REMOVE_LIQUIDITY_INDEX);
+                remove.encode_to(dest);
+            }
+            SubdexXcmpMessage::TransferReturned(returned) => {
+                dest.push_byte(TRANSFER_RETURNED_INDEX);
+                returned.encode_to(dest);
+            }
+            SubdexXcmpMessage::VersionQuery => dest.push_byte(VERSION_QUERY_INDEX),
+            SubdexXcmpMessage::VersionAnnouncement(version) => {
+                dest.push_byte(VERSION_ANNOUNCEMENT_INDEX);
+                version.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl<AccountId, Balance, AssetId, Moment> EncodeLike
+    for SubdexXcmpMessage<AccountId, Balance, AssetId, Moment>
+where
+    AccountId: Encode,
+    Balance: Encode,
+    AssetId: Encode,
+    Moment: Encode,
+{
+}
+
+impl<AccountId, Balance, AssetId, Moment> Decode
+    for SubdexXcmpMessage<AccountId, Balance, AssetId, Moment>
+where
+    AccountId: Decode,
+    Balance: Decode,
+    AssetId: Decode,
+    Moment: Decode,
+{
+    fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+        match input.read_byte()? {
+            PRICE_FEED_INDEX => Ok(SubdexXcmpMessage::PriceFeed(Decode::decode(input)?)),
+            SWAP_AND_RETURN_INDEX => Ok(SubdexXcmpMessage::SwapAndReturn(Decode::decode(input)?)),
+            ADD_LIQUIDITY_INDEX => Ok(SubdexXcmpMessage::AddLiquidity(Decode::decode(input)?)),
+            REMOVE_LIQUIDITY_INDEX => {
+                Ok(SubdexXcmpMessage::RemoveLiquidity(Decode::decode(input)?))
+            }
+            TRANSFER_RETURNED_INDEX => {
+                Ok(SubdexXcmpMessage::TransferReturned(Decode::decode(input)?))
+            }
+            VERSION_QUERY_INDEX => Ok(SubdexXcmpMessage::VersionQuery),
+            VERSION_ANNOUNCEMENT_INDEX => Ok(SubdexXcmpMessage::VersionAnnouncement(
+                Decode::decode(input)?,
+            )),
+            index if index < EXTENDED_MESSAGE_INDEX => Ok(SubdexXcmpMessage::Transfer(
+                Decode::decode(&mut PrefixedInput {
+                    prefix: Some(index),
+                    input,
+                })?,
+            )),
+            _ => Err("Unknown Subdex XCMP message".into()),
+        }
+    }
+}
+
+impl<AccountId, Balance, AssetId, Moment> From<XCMPMessage<AccountId, Balance, AssetId>>
+    for SubdexXcmpMessage<AccountId, Balance, AssetId, Moment>
+{
+    fn from(message: XCMPMessage<AccountId, Balance, AssetId>) -> Self {
+        SubdexXcmpMessage::Transfer(message)
+    }
+}
+
+impl<AccountId: Clone, Balance, AssetId, Moment> TracedMessage<AccountId>
+    for SubdexXcmpMessage<AccountId, Balance, AssetId, Moment>
+{
+    fn accounts(&self) -> Vec<AccountId> {
+        match self {
+            SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(dest, ..)) => vec![dest.clone()],
+            SubdexXcmpMessage::SwapAndReturn(swap) => vec![swap.sender.clone(), swap.dest.clone()],
+            SubdexXcmpMessage::AddLiquidity(add) => vec![add.sender.clone()],
+            SubdexXcmpMessage::RemoveLiquidity(remove) => vec![remove.sender.clone()],
+            SubdexXcmpMessage::TransferReturned(returned) => vec![returned.beneficiary.clone()],
+            SubdexXcmpMessage::PriceFeed(_)
+            | SubdexXcmpMessage::VersionQuery
+            | SubdexXcmpMessage::VersionAnnouncement(_) => Vec::new(),
+        }
+    }
+}
+
+/// [`SubdexXcmpMessage`] as sent over XCMP.
+///
+/// Decoding accepts every version up to [`LATEST_XCMP_VERSION`]. Versions must never change
+/// once released; a new payload format gets a new version.
+#[derive(Clone, PartialEq, Eq, RuntimeDebug)]
+pub enum VersionedXcmpMessage<AccountId, Balance, AssetId, Moment> {
+    /// Message without version, understood by every chain.
+    Unversioned(SubdexXcmpMessage<AccountId, Balance, AssetId, Moment>),
+    /// Message in version 1, encoded as `[VERSIONED_MESSAGE_INDEX, 1]` and the message.
+    V1(SubdexXcmpMessage<AccountId, Balance, AssetId, Moment>),
+}
+
+impl<AccountId, Balance, AssetId, Moment>
+    VersionedXcmpMessage<AccountId, Balance, AssetId, Moment>
+{
+    /// Wrap `message` for a chain understanding up to `version`.
+    pub fn new(
+        version: XcmpVersion,
+        message: SubdexXcmpMessage<AccountId, Balance, AssetId, Moment>,
+    ) -> Self {
+        match version {
+            0 => VersionedXcmpMessage::Unversioned(message),
+            _ => VersionedXcmpMessage::V1(message),
+        }
+    }
+
+    pub fn version(&self) -> XcmpVersion {
+        match self {
+            VersionedXcmpMessage::Unversioned(_) => 0,
+            VersionedXcmpMessage::V1(_) => 1,
+        }
+    }
+
+    pub fn message(&self) -> &SubdexXcmpMessage<AccountId, Balance, AssetId, Moment> {
+        match self {
+            VersionedXcmpMessage::Unversioned(message) | VersionedXcmpMessage::V1(message) => {
+                message
+            }
+        }
+    }
+}
+
+impl<AccountId: Clone, Balance, AssetId, Moment> TracedMessage<AccountId>
+    for VersionedXcmpMessage<AccountId, Balance, AssetId, Moment>
+{
+    fn accounts(&self) -> Vec<AccountId> {
+        self.message().accounts()
+    }
+}
+
+impl<AccountId, Balance, AssetId, Moment> Encode
+    for VersionedXcmpMessage<AccountId, Balance, AssetId, Moment>
+where
+    AccountId: Encode,
+    Balance: Encode,
+    AssetId: Encode,
+    Moment: Encode,
+{
+    fn encode_to<W: Output>(&self, dest: &mut W) {
+        match self {
+            VersionedXcmpMessage::Unversioned(message) => message.encode_to(dest),
+            VersionedXcmpMessage::V1(message) => {
+                dest.push_byte(VERSIONED_MESSAGE_INDEX);
+                dest.push_byte(1);
+                message.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl<AccountId, Balance, AssetId, Moment> EncodeLike
+    for VersionedXcmpMessage<AccountId, Balance, AssetId, Moment>
+where
+    AccountId: Encode,
+    Balance: Encode,
+    AssetId: Encode,
+    Moment: Encode,
+{
+}
+
+impl<AccountId, Balance, AssetId, Moment> Decode
+    for VersionedXcmpMessage<AccountId, Balance, AssetId, Moment>
+where
+    AccountId: Decode,
+    Balance: Decode,
+    AssetId: Decode,
+    Moment: Decode,
+{
+    fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+        match input.read_byte()? {
+            VERSIONED_MESSAGE_INDEX => match input.read_byte()? {
+                1 => Ok(VersionedXcmpMessage::V1(Decode::decode(input)?)),
+                _ => Err("Unsupported Subdex XCMP message version".into()),
+            },
+            index => Ok(VersionedXcmpMessage::Unversioned(Decode::decode(
+                &mut PrefixedInput {
+                    prefix: Some(index),
+                    input,
+                },
+            )?)),
+        }
+    }
+}
+
+/// Input that yields an already consumed byte before the rest of `input`.
+struct PrefixedInput<'a, I> {
+    prefix: Option<u8>,
+    input: &'a mut I,
+}
+
+impl<'a, I: Input> Input for PrefixedInput<'a, I> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, Error> {
+        Ok(self
+            .input
+            .remaining_len()?
+            .map(|len| len + self.prefix.is_some() as usize))
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), Error> {
+        match (self.prefix.take(), into.split_first_mut()) {
+            (Some(prefix), Some((first, rest))) => {
+                *first = prefix;
+                self.input.read(rest)
+            }
+            (prefix, _) => {
+                self.prefix = prefix;
+                self.input.read(into)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Message = SubdexXcmpMessage<u64, u128, u64, u64>;
+    type Versioned = VersionedXcmpMessage<u64, u128, u64, u64>;
+
+    fn transfer() -> Message {
+        SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(1, 1_000, Some(2)))
+    }
+
+    /// Encoding of `transfer()` as `pallet_subdex_xcmp` defines it.
+    const TRANSFER_BYTES: &[u8] = &[
+        0x00, // TransferToken
+        1, 0, 0, 0, 0, 0, 0, 0, // account
+        0xe8, 0x03, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // amount
+        1, 2, 0, 0, 0, 0, 0, 0, 0, // Some(asset)
+    ];
+
+    fn assert_encoding(message: Versioned, expected: &[u8]) {
+        assert_eq!(message.encode(), expected);
+        assert_eq!(Versioned::decode(&mut &expected[..]), Ok(message));
+    }
+
+    #[test]
+    fn unversioned_transfer_keeps_legacy_encoding() {
+        assert_encoding(Versioned::new(0, transfer()), TRANSFER_BYTES);
+        assert_eq!(
+            XCMPMessage::<u64, u128, u64>::decode(&mut &TRANSFER_BYTES[..]),
+            Ok(XCMPMessage::TransferToken(1, 1_000, Some(2)))
+        );
+    }
+
+    #[test]
+    fn v1_transfer_is_prefixed_with_version() {
+        let mut expected = vec![0xff, 1];
+        expected.extend_from_slice(TRANSFER_BYTES);
+        assert_encoding(Versioned::new(1, transfer()), &expected);
+    }
+
+    #[test]
+    fn unversioned_extended_message_encoding() {
+        let returned = SubdexXcmpMessage::TransferReturned(ReturnedTransferMessage {
+            beneficiary: 1,
+            asset: Some(2),
+            amount: 1_000,
+            error: TransferError::SupplyCapExceeded,
+        });
+        assert_encoding(
+            Versioned::new(0, returned),
+            &[
+                0x84, // TransferReturned
+                1, 0, 0, 0, 0, 0, 0, 0, // beneficiary
+                1, 2, 0, 0, 0, 0, 0, 0, 0, // Some(asset)
+                0xe8, 0x03, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // amount
+                4, // SupplyCapExceeded
+            ],
+        );
+    }
+
+    #[test]
+    fn v1_swap_and_return_encoding() {
+        let swap = SubdexXcmpMessage::SwapAndReturn(SwapAndReturnMessage {
+            sender: 1,
+            asset: None,
+            amount: 500,
+            dest_para_id: 200.into(),
+            target_asset: Some(3),
+            min_amount_out: 400,
+            dest: 4,
+        });
+        assert_encoding(
+            Versioned::new(1, swap),
+            &[
+                0xff, // versioned
+                1,    // version
+                0x81, // SwapAndReturn
+                1, 0, 0, 0, 0, 0, 0, 0, // sender
+                0, // None
+                0xf4, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // amount
+                200, 0, 0, 0, // dest_para_id
+                1, 3, 0, 0, 0, 0, 0, 0, 0, // Some(target_asset)
+                0x90, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // min_amount_out
+                4, 0, 0, 0, 0, 0, 0, 0, // dest
+            ],
+        );
+    }
+
+    #[test]
+    fn version_negotiation_encoding() {
+        assert_encoding(Versioned::new(0, SubdexXcmpMessage::VersionQuery), &[0x85]);
+        assert_encoding(
+            Versioned::new(0, SubdexXcmpMessage::VersionAnnouncement(1)),
+            &[0x86, 1],
+        );
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let mut message = vec![0xff, LATEST_XCMP_VERSION + 1];
+        message.extend_from_slice(TRANSFER_BYTES);
+        assert!(Versioned::decode(&mut &message[..]).is_err());
+        assert!(Versioned::decode(&mut &[0x87][..]).is_err());
+    }
+
+    #[test]
+    fn version_is_chosen_from_negotiated_version() {
+        assert_eq!(Versioned::new(0, transfer()).version(), 0);
+        assert_eq!(Versioned::new(LATEST_XCMP_VERSION, transfer()).version(), 1);
+        assert_eq!(Versioned::new(1, transfer()).message(), &transfer());
+    }
+}
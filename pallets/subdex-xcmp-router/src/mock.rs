@@ -0,0 +1,264 @@
+use crate::{Module, Trait, VersionedXcmpMessage, VersionedXcmpMessageOf, XcmpMessageOf};
+use codec::{Decode, Encode};
+use cumulus_primitives::{
+    xcmp::{XCMPMessageHandler, XCMPMessageSender},
+    ParaId, UpwardMessageOrigin, UpwardMessageSender,
+};
+use cumulus_upward_message::BalancesMessage;
+use frame_support::{impl_outer_event, impl_outer_origin, parameter_types, weights::Weight};
+use pallet_subdex::Asset;
+use pallet_subdex_xcmp::XCMPMessage;
+use sp_core::H256;
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+    ModuleId, Perbill,
+};
+use std::cell::RefCell;
+
+pub type AccountId = u64;
+pub type Balance = u128;
+pub type AssetId = u64;
+
+pub const ALICE: AccountId = 1;
+pub const BOB: AccountId = 2;
+
+/// Para id of Subdex.
+pub const SUBDEX: u32 = 100;
+/// Parachain whose native asset is `BTC` on Subdex.
+pub const BTC_PARA: u32 = 200;
+/// Parachain whose native asset is `ETH` on Subdex.
+pub const ETH_PARA: u32 = 300;
+/// Parachain that has not bridged anything yet.
+pub const NEW_PARA: u32 = 400;
+
+pub const MAIN: Asset<AssetId> = Asset::MainNetworkCurrency;
+pub const BTC: Asset<AssetId> = Asset::ParachainAsset(1);
+pub const ETH: Asset<AssetId> = Asset::ParachainAsset(2);
+
+impl_outer_origin! {
+    pub enum Origin for Test {}
+}
+
+mod xcmp_router {
+    pub use crate::Event;
+}
+
+impl_outer_event! {
+    pub enum TestEvent for Test {
+        frame_system<T>,
+        pallet_balances<T>,
+        pallet_subdex<T>,
+        pallet_subdex_xcmp<T>,
+        pallet_subdex_router<T>,
+        xcmp_router<T>,
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Test;
+
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::one();
+}
+
+impl frame_system::Trait for Test {
+    type BaseCallFilter = ();
+    type Origin = Origin;
+    type Call = ();
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId;
+    type Lookup = IdentityLookup<AccountId>;
+    type Header = Header;
+    type Event = TestEvent;
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type ModuleToIndex = ();
+    type AccountData = pallet_balances::AccountData<Balance>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+}
+
+parameter_types! {
+    pub const ExistentialDeposit: Balance = 1;
+}
+
+impl pallet_balances::Trait for Test {
+    type Balance = Balance;
+    type Event = TestEvent;
+    type DustRemoval = ();
+    type ExistentialDeposit = ExistentialDeposit;
+    type AccountStore = System;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const MinimumPeriod: u64 = 1;
+}
+
+impl pallet_timestamp::Trait for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const FeeRateNominator: Balance = 3;
+    pub const FeeRateDenominator: Balance = 1000;
+    pub const MinMainNetworkAssetAmount: Balance = 1000;
+    pub const MinParachainAssetAmount: Balance = 1000;
+}
+
+impl pallet_subdex::Trait for Test {
+    type Event = TestEvent;
+    type Currency = Balances;
+    type IMoment = u64;
+    type AssetId = AssetId;
+    type FeeRateNominator = FeeRateNominator;
+    type FeeRateDenominator = FeeRateDenominator;
+    type MinMainNetworkAssetAmount = MinMainNetworkAssetAmount;
+    type MinParachainAssetAmount = MinParachainAssetAmount;
+}
+
+impl pallet_subdex_router::Trait for Test {
+    type Event = TestEvent;
+    type OnPoolUpdate = ();
+    type DexPaused = ();
+}
+
+/// Upward transfer as the relay chain would receive it.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct Transfer(pub AccountId, pub Balance);
+
+impl BalancesMessage<AccountId, Balance> for Transfer {
+    fn transfer(dest: AccountId, amount: Balance) -> Self {
+        Transfer(dest, amount)
+    }
+}
+
+pub struct MockUpwardMessageSender;
+
+impl UpwardMessageSender<Transfer> for MockUpwardMessageSender {
+    fn send_upward_message(_: &Transfer, _: UpwardMessageOrigin) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+impl pallet_subdex_xcmp::Trait for Test {
+    type Event = TestEvent;
+    type UpwardMessageSender = MockUpwardMessageSender;
+    type UpwardMessage = Transfer;
+    type XCMPMessageSender = XcmpRouter;
+}
+
+thread_local! {
+    static SENT: RefCell<Vec<(ParaId, VersionedXcmpMessageOf<Test>)>> = RefCell::new(Vec::new());
+    static FAIL_SEND: RefCell<bool> = RefCell::new(false);
+}
+
+pub struct MockXcmpMessageSender;
+
+impl XCMPMessageSender<VersionedXcmpMessageOf<Test>> for MockXcmpMessageSender {
+    fn send_xcmp_message(dest: ParaId, message: &VersionedXcmpMessageOf<Test>) -> Result<(), ()> {
+        if FAIL_SEND.with(|fail| *fail.borrow()) {
+            return Err(());
+        }
+        SENT.with(|sent| sent.borrow_mut().push((dest, message.clone())));
+        Ok(())
+    }
+}
+
+/// XCMP messages sent so far, unwrapped from their version.
+pub fn sent_messages() -> Vec<(ParaId, XcmpMessageOf<Test>)> {
+    SENT.with(|sent| {
+        sent.borrow()
+            .iter()
+            .map(|(dest, message)| (*dest, message.message().clone()))
+            .collect()
+    })
+}
+
+/// Make sibling parachains refuse XCMP messages, or accept them again.
+pub fn fail_sending(fail: bool) {
+    FAIL_SEND.with(|current| *current.borrow_mut() = fail);
+}
+
+parameter_types! {
+    pub const XcmpRouterModuleId: ModuleId = ModuleId(*b"sdx/xcmp");
+    pub const SelfParaId: ParaId = ParaId::from(SUBDEX);
+    pub const MaxTrappedAccounts: u32 = 2;
+}
+
+impl Trait for Test {
+    type Event = TestEvent;
+    type XCMPMessageSender = MockXcmpMessageSender;
+    type ModuleId = XcmpRouterModuleId;
+    type MessageTracer = ();
+    type SelfParaId = SelfParaId;
+    type MaxTrappedAccounts = MaxTrappedAccounts;
+}
+
+pub type System = frame_system::Module<Test>;
+pub type Balances = pallet_balances::Module<Test>;
+pub type Timestamp = pallet_timestamp::Module<Test>;
+pub type Dex = pallet_subdex::Module<Test>;
+pub type XcmpRouter = Module<Test>;
+
+/// Handle `message` as received from parachain `src`.
+pub fn receive(src: u32, message: XcmpMessageOf<Test>) {
+    XcmpRouter::handle_xcmp_message(src.into(), &VersionedXcmpMessage::Unversioned(message));
+}
+
+/// Transfer of `amount` of the native asset of the sending chain to `dest`.
+pub fn transfer(dest: AccountId, amount: Balance) -> XcmpMessageOf<Test> {
+    crate::SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(dest, amount, None))
+}
+
+/// Parachain assets held by `who`.
+pub fn asset_balance(who: AccountId, asset_id: AssetId) -> Balance {
+    pallet_subdex::Module::<Test>::asset_balances(who, asset_id)
+}
+
+/// Alice holds 1_000_000 MAIN and bridged 1_000_000 `BTC` and `ETH`, with which she created a
+/// `BTC`/`ETH` and a `MAIN`/`BTC` pool of 100_000 of each asset.
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let mut storage = frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    pallet_balances::GenesisConfig::<Test> {
+        balances: vec![(ALICE, 1_000_000)],
+    }
+    .assimilate_storage(&mut storage)
+    .unwrap();
+    pallet_subdex_xcmp::GenesisConfig::<Test> { next_asset_id: 1 }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+
+    let mut ext = sp_io::TestExternalities::new(storage);
+    ext.execute_with(|| {
+        System::set_block_number(1);
+        Timestamp::set_timestamp(1_000);
+        receive(BTC_PARA, transfer(ALICE, 1_000_000));
+        receive(ETH_PARA, transfer(ALICE, 1_000_000));
+        Dex::initialize_exchange(Origin::signed(ALICE), BTC, 100_000, ETH, 100_000)
+            .expect("the pool is created");
+        Dex::initialize_exchange(Origin::signed(ALICE), MAIN, 100_000, BTC, 100_000)
+            .expect("the pool is created");
+    });
+    ext
+}
@@ -0,0 +1,769 @@
+use crate::{
+    mock::*, AddLiquidityMessage, Error, RawEvent, RemoveLiquidityMessage, ReturnedTransferMessage,
+    Sibling, SubdexXcmpMessage, SwapAndReturnMessage, TransferError, TransferFailurePolicy,
+};
+use cumulus_primitives::ParaId;
+use frame_support::{assert_noop, assert_ok, storage::StorageDoubleMap};
+use pallet_subdex_xcmp::XCMPMessage;
+use sp_runtime::traits::AccountIdConversion;
+
+fn router_events() -> Vec<RawEvent<AccountId, AssetId, Balance>> {
+    System::events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            TestEvent::xcmp_router(event) => Some(event),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Swap of `amount` BTC sent by Bob from the BTC parachain.
+fn swap(
+    amount: Balance,
+    dest_para_id: u32,
+    target_asset: Option<AssetId>,
+    min_amount_out: Balance,
+) -> crate::XcmpMessageOf<Test> {
+    SubdexXcmpMessage::SwapAndReturn(SwapAndReturnMessage {
+        sender: BOB,
+        asset: None,
+        amount,
+        dest_para_id: dest_para_id.into(),
+        target_asset,
+        min_amount_out,
+        dest: BOB,
+    })
+}
+
+#[test]
+fn swap_and_return_sends_bought_asset_to_destination() {
+    new_test_ext().execute_with(|| {
+        let expected_out =
+            pallet_subdex_router::Module::<Test>::amount_out_for(BTC, ETH, 1_000).unwrap();
+
+        receive(BTC_PARA, swap(1_000, ETH_PARA, None, 900));
+
+        let amount_out = match router_events().pop() {
+            Some(RawEvent::SwapAndReturnExecuted(src, dest, BOB, 1_000, amount_out)) => {
+                assert_eq!((src, dest), (BTC_PARA.into(), ETH_PARA.into()));
+                amount_out
+            }
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert!(amount_out >= expected_out);
+        assert_eq!(
+            sent_messages(),
+            vec![(
+                ETH_PARA.into(),
+                SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(BOB, amount_out, None))
+            )]
+        );
+        assert_eq!(asset_balance(XcmpRouter::account_id(), 1), 0);
+        assert_eq!(
+            XcmpRouter::bridged_supply(ParaId::from(BTC_PARA), None),
+            1_001_000
+        );
+        assert_eq!(
+            XcmpRouter::bridged_supply(ParaId::from(ETH_PARA), None),
+            1_000_000 - amount_out
+        );
+    });
+}
+
+#[test]
+fn swap_and_return_buys_relay_chain_currency_on_subdex() {
+    new_test_ext().execute_with(|| {
+        let expected_out =
+            pallet_subdex_router::Module::<Test>::amount_out_for(BTC, MAIN, 1_000).unwrap();
+
+        receive(BTC_PARA, swap(1_000, SUBDEX, None, 900));
+
+        let amount_out = match router_events().pop() {
+            Some(RawEvent::SwapAndReturnExecuted(_, dest, BOB, 1_000, amount_out)) => {
+                assert_eq!(dest, SUBDEX.into());
+                amount_out
+            }
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert!(amount_out >= expected_out);
+        assert_eq!(Balances::free_balance(BOB), amount_out);
+        assert!(sent_messages().is_empty());
+    });
+}
+
+#[test]
+fn swap_and_return_rejects_assets_not_native_to_destination() {
+    new_test_ext().execute_with(|| {
+        receive(BTC_PARA, swap(1_000, SUBDEX, Some(1), 0));
+        receive(BTC_PARA, swap(1_000, ETH_PARA, Some(7), 0));
+
+        let errors: Vec<_> = router_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                RawEvent::SwapAndReturnRefunded(_, BOB, 1_000, error) => Some(error),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                Error::<Test>::TargetAssetNotNative.into(),
+                Error::<Test>::TargetAssetNotNative.into()
+            ]
+        );
+    });
+}
+
+#[test]
+fn failed_swap_refunds_exactly_the_deposit() {
+    new_test_ext().execute_with(|| {
+        // Assets held for other operations.
+        let holder = XcmpRouter::account_id();
+        pallet_subdex::AssetBalances::<Test>::insert(holder, 1, 5_000);
+
+        receive(BTC_PARA, swap(1_000, ETH_PARA, None, 100_000));
+
+        assert_eq!(asset_balance(holder, 1), 5_000);
+        assert_eq!(asset_balance(holder, 2), 0);
+        assert_eq!(
+            sent_messages(),
+            vec![(
+                BTC_PARA.into(),
+                SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(BOB, 1_000, None))
+            )]
+        );
+        assert_eq!(
+            XcmpRouter::bridged_supply(ParaId::from(BTC_PARA), None),
+            1_000_000
+        );
+        assert_eq!(
+            router_events().pop(),
+            Some(RawEvent::SwapAndReturnRefunded(
+                BTC_PARA.into(),
+                BOB,
+                1_000,
+                Error::<Test>::InsufficientOutputAmount.into()
+            ))
+        );
+    });
+}
+
+/// Second asset of the BTC parachain, bridged as local asset 3.
+const BTC_TOKEN: Option<AssetId> = Some(5);
+
+/// Bridge `BTC_TOKEN` and create a `BTC`/`BTC_TOKEN` pool of 100_000 of each asset.
+fn create_sibling_pool() {
+    receive(
+        BTC_PARA,
+        SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(ALICE, 1_000_000, BTC_TOKEN)),
+    );
+    assert_eq!(
+        XcmpRouter::local_asset_id(BTC_PARA.into(), BTC_TOKEN),
+        Some(3)
+    );
+    Dex::initialize_exchange(
+        Origin::signed(ALICE),
+        BTC,
+        100_000,
+        pallet_subdex::Asset::ParachainAsset(3),
+        100_000,
+    )
+    .expect("the pool is created");
+}
+
+fn add_liquidity(
+    first_amount: Balance,
+    second_amount: Balance,
+    min_shares: Balance,
+) -> crate::XcmpMessageOf<Test> {
+    SubdexXcmpMessage::AddLiquidity(AddLiquidityMessage {
+        sender: BOB,
+        first_asset: None,
+        first_amount,
+        second_asset: BTC_TOKEN,
+        second_amount,
+        min_shares,
+    })
+}
+
+fn remove_liquidity(shares: Balance, min_first_amount: Balance) -> crate::XcmpMessageOf<Test> {
+    SubdexXcmpMessage::RemoveLiquidity(RemoveLiquidityMessage {
+        sender: BOB,
+        first_asset: None,
+        second_asset: BTC_TOKEN,
+        shares,
+        min_first_amount,
+        min_second_amount: 0,
+    })
+}
+
+fn transfer_to_bob(
+    amount: Balance,
+    asset: Option<AssetId>,
+) -> (ParaId, crate::XcmpMessageOf<Test>) {
+    (
+        BTC_PARA.into(),
+        SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(BOB, amount, asset)),
+    )
+}
+
+/// Shares bought by the last successful `AddLiquidity`.
+fn shares_added() -> Balance {
+    router_events()
+        .into_iter()
+        .filter_map(|event| match event {
+            RawEvent::RemoteLiquidityAdded(_, BOB, _, shares) => Some(shares),
+            _ => None,
+        })
+        .last()
+        .expect("liquidity was added")
+}
+
+#[test]
+fn add_liquidity_buys_shares_and_returns_unused_deposit() {
+    new_test_ext().execute_with(|| {
+        create_sibling_pool();
+        let provider = XcmpRouter::liquidity_account(BTC_PARA.into(), &BOB);
+
+        receive(BTC_PARA, add_liquidity(1_000, 3_000, 1));
+
+        assert_eq!(
+            router_events().pop(),
+            Some(RawEvent::RemoteLiquidityAdded(
+                BTC_PARA.into(),
+                BOB,
+                provider,
+                shares_added()
+            ))
+        );
+        // The pool takes as much of the second asset as of the first, the rest goes back.
+        let returned: Balance = sent_messages()
+            .into_iter()
+            .filter_map(|(_, message)| match message {
+                SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(BOB, left, BTC_TOKEN)) => {
+                    Some(left)
+                }
+                _ => None,
+            })
+            .sum();
+        assert!(returned >= 1_000 && returned < 3_000);
+        assert_eq!(asset_balance(provider, 1), 0);
+        assert_eq!(asset_balance(provider, 3), 0);
+    });
+}
+
+#[test]
+fn remove_liquidity_sends_withdrawn_assets_back() {
+    new_test_ext().execute_with(|| {
+        create_sibling_pool();
+        receive(BTC_PARA, add_liquidity(1_000, 1_000, 1));
+        let shares = shares_added();
+        let sent_before = sent_messages().len();
+
+        receive(BTC_PARA, remove_liquidity(shares, 1));
+
+        let (first_amount, second_amount) = match router_events().pop() {
+            Some(RawEvent::RemoteLiquidityRemoved(_, BOB, removed, first, second)) => {
+                assert_eq!(removed, shares);
+                (first, second)
+            }
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(
+            sent_messages()[sent_before..],
+            [
+                transfer_to_bob(first_amount, None),
+                transfer_to_bob(second_amount, BTC_TOKEN)
+            ]
+        );
+    });
+}
+
+#[test]
+fn failed_remove_liquidity_keeps_shares() {
+    new_test_ext().execute_with(|| {
+        create_sibling_pool();
+        receive(BTC_PARA, add_liquidity(1_000, 1_000, 1));
+        let shares = shares_added();
+        let sent_before = sent_messages().len();
+
+        receive(BTC_PARA, remove_liquidity(shares, 1_000_000));
+        assert!(matches!(
+            router_events().pop(),
+            Some(RawEvent::RemoteLiquidityFailed(_, BOB, _))
+        ));
+        assert_eq!(sent_messages().len(), sent_before);
+
+        // The shares are still there to be removed.
+        receive(BTC_PARA, remove_liquidity(shares, 1));
+        assert_eq!(sent_messages().len(), sent_before + 2);
+    });
+}
+
+#[test]
+fn failed_add_liquidity_refunds_deposits() {
+    new_test_ext().execute_with(|| {
+        create_sibling_pool();
+
+        receive(BTC_PARA, add_liquidity(1_000, 2_000, 1_000_000));
+
+        assert_eq!(
+            sent_messages(),
+            vec![
+                transfer_to_bob(1_000, None),
+                transfer_to_bob(2_000, BTC_TOKEN)
+            ]
+        );
+        assert_eq!(
+            router_events().pop(),
+            Some(RawEvent::RemoteLiquidityFailed(
+                BTC_PARA.into(),
+                BOB,
+                Error::<Test>::InsufficientOutputAmount.into()
+            ))
+        );
+    });
+}
+
+#[test]
+fn deposits_that_cannot_be_refunded_leave_the_liquidity_account() {
+    new_test_ext().execute_with(|| {
+        create_sibling_pool();
+        let provider = XcmpRouter::liquidity_account(BTC_PARA.into(), &BOB);
+        fail_sending(true);
+
+        receive(BTC_PARA, add_liquidity(1_000, 2_000, 1_000_000));
+
+        assert_eq!(asset_balance(provider, 1), 0);
+        assert_eq!(asset_balance(provider, 3), 0);
+        assert_eq!(
+            XcmpRouter::trapped(BOB, (ParaId::from(BTC_PARA), None)),
+            1_000
+        );
+        assert_eq!(
+            XcmpRouter::trapped(BOB, (ParaId::from(BTC_PARA), BTC_TOKEN)),
+            2_000
+        );
+        assert_eq!(
+            XcmpRouter::bridged_supply(ParaId::from(BTC_PARA), BTC_TOKEN),
+            1_000_000
+        );
+        assert!(router_events().contains(&RawEvent::TransferTrapped(
+            BTC_PARA.into(),
+            BOB,
+            BTC_TOKEN,
+            2_000,
+            TransferError::NotRefunded
+        )));
+    });
+}
+
+#[test]
+fn failed_transfers_are_trapped_by_default() {
+    new_test_ext().execute_with(|| {
+        receive(BTC_PARA, transfer(BOB, 999));
+
+        assert_eq!(asset_balance(BOB, 1), 0);
+        assert_eq!(
+            XcmpRouter::trapped(BOB, (ParaId::from(BTC_PARA), None)),
+            999
+        );
+        assert!(sent_messages().is_empty());
+        assert_eq!(
+            router_events().pop(),
+            Some(RawEvent::TransferTrapped(
+                BTC_PARA.into(),
+                BOB,
+                None,
+                999,
+                TransferError::BelowMinimum
+            ))
+        );
+    });
+}
+
+#[test]
+fn failed_transfers_are_returned_under_return_policy() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(XcmpRouter::set_failure_policy(
+            Origin::root(),
+            BTC_PARA.into(),
+            TransferFailurePolicy::Return
+        ));
+
+        receive(BTC_PARA, transfer(BOB, 999));
+
+        assert_eq!(
+            sent_messages(),
+            vec![(
+                BTC_PARA.into(),
+                SubdexXcmpMessage::TransferReturned(ReturnedTransferMessage {
+                    beneficiary: BOB,
+                    asset: None,
+                    amount: 999,
+                    error: TransferError::BelowMinimum,
+                })
+            )]
+        );
+        assert_eq!(XcmpRouter::trapped(BOB, (ParaId::from(BTC_PARA), None)), 0);
+
+        // Assets that cannot be returned are trapped.
+        fail_sending(true);
+        receive(BTC_PARA, transfer(BOB, 999));
+        assert_eq!(
+            XcmpRouter::trapped(BOB, (ParaId::from(BTC_PARA), None)),
+            999
+        );
+    });
+}
+
+#[test]
+fn returned_transfers_are_never_returned_again() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(XcmpRouter::set_failure_policy(
+            Origin::root(),
+            BTC_PARA.into(),
+            TransferFailurePolicy::Return
+        ));
+
+        receive(
+            BTC_PARA,
+            SubdexXcmpMessage::TransferReturned(ReturnedTransferMessage {
+                beneficiary: BOB,
+                asset: None,
+                amount: 999,
+                error: TransferError::NotCredited,
+            }),
+        );
+
+        assert!(sent_messages().is_empty());
+        assert_eq!(
+            XcmpRouter::trapped(BOB, (ParaId::from(BTC_PARA), None)),
+            999
+        );
+    });
+}
+
+#[test]
+fn swap_deposit_that_cannot_be_refunded_is_trapped() {
+    new_test_ext().execute_with(|| {
+        fail_sending(true);
+
+        receive(BTC_PARA, swap(1_000, ETH_PARA, None, 0));
+
+        // Taken back without leaving a balance of zero behind.
+        assert!(!pallet_subdex::AssetBalances::<Test>::contains_key(
+            XcmpRouter::account_id(),
+            1
+        ));
+        assert_eq!(
+            XcmpRouter::trapped(BOB, (ParaId::from(BTC_PARA), None)),
+            1_000
+        );
+        assert_eq!(
+            XcmpRouter::bridged_supply(ParaId::from(BTC_PARA), None),
+            1_000_000
+        );
+        let events = router_events();
+        assert!(events.contains(&RawEvent::TransferTrapped(
+            BTC_PARA.into(),
+            BOB,
+            None,
+            1_000,
+            TransferError::NotRefunded
+        )));
+        assert!(matches!(
+            events.last(),
+            Some(RawEvent::SwapAndReturnFailed(_, BOB, 1_000, _))
+        ));
+    });
+}
+
+#[test]
+fn trapped_assets_can_be_claimed() {
+    new_test_ext().execute_with(|| {
+        fail_sending(true);
+        receive(BTC_PARA, swap(1_000, ETH_PARA, None, 0));
+        let trapped_key = (ParaId::from(BTC_PARA), None);
+
+        // Nothing is lost while the parachain cannot be reached.
+        assert_noop!(
+            XcmpRouter::claim_trapped(Origin::signed(BOB), BTC_PARA.into(), None, ALICE),
+            Error::<Test>::SendFailed
+        );
+        assert_noop!(
+            XcmpRouter::claim_trapped(Origin::signed(ALICE), BTC_PARA.into(), None, ALICE),
+            Error::<Test>::NothingTrapped
+        );
+
+        fail_sending(false);
+        assert_ok!(XcmpRouter::claim_trapped(
+            Origin::signed(BOB),
+            BTC_PARA.into(),
+            None,
+            ALICE
+        ));
+
+        assert_eq!(XcmpRouter::trapped(BOB, trapped_key), 0);
+        assert_eq!(
+            sent_messages(),
+            vec![(
+                BTC_PARA.into(),
+                SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(ALICE, 1_000, None))
+            )]
+        );
+        assert_eq!(
+            router_events().pop(),
+            Some(RawEvent::TrappedAssetsClaimed(
+                BTC_PARA.into(),
+                BOB,
+                ALICE,
+                None,
+                1_000
+            ))
+        );
+    });
+}
+
+#[test]
+fn messages_of_denied_parachains_are_dropped() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(XcmpRouter::set_para_permission(
+            Origin::root(),
+            BTC_PARA.into(),
+            Some(ParaPermission::Denied)
+        ));
+        assert_ok!(XcmpRouter::set_failure_policy(
+            Origin::root(),
+            BTC_PARA.into(),
+            TransferFailurePolicy::Return
+        ));
+
+        receive(BTC_PARA, transfer(BOB, 5_000));
+        receive(BTC_PARA, swap(1_000, ETH_PARA, None, 0));
+
+        assert_eq!(asset_balance(BOB, 1), 0);
+        assert_eq!(XcmpRouter::trapped(BOB, (ParaId::from(BTC_PARA), None)), 0);
+        assert_eq!(
+            XcmpRouter::trapped_accounts(ParaId::from(BTC_PARA), None),
+            0
+        );
+        assert!(sent_messages().is_empty());
+        assert_eq!(
+            XcmpRouter::bridged_supply(ParaId::from(BTC_PARA), None),
+            1_000_000
+        );
+        assert_eq!(
+            router_events(),
+            vec![
+                RawEvent::ParaPermissionSet(BTC_PARA.into(), Some(ParaPermission::Denied)),
+                RawEvent::FailurePolicySet(BTC_PARA.into(), TransferFailurePolicy::Return),
+                RawEvent::MessageRejected(BTC_PARA.into()),
+                RawEvent::MessageRejected(BTC_PARA.into()),
+            ]
+        );
+
+        // The permission is lifted again.
+        assert_ok!(XcmpRouter::set_para_permission(
+            Origin::root(),
+            BTC_PARA.into(),
+            None
+        ));
+        receive(BTC_PARA, transfer(BOB, 5_000));
+        assert_eq!(asset_balance(BOB, 1), 5_000);
+    });
+}
+
+#[test]
+fn trapped_accounts_are_bounded_per_asset() {
+    new_test_ext().execute_with(|| {
+        let btc_para = ParaId::from(BTC_PARA);
+        for who in &[ALICE, BOB, ALICE, 3, 4] {
+            receive(BTC_PARA, transfer(*who, 999));
+        }
+
+        assert_eq!(XcmpRouter::trapped(ALICE, (btc_para, None)), 1_998);
+        assert_eq!(XcmpRouter::trapped(BOB, (btc_para, None)), 999);
+        assert_eq!(XcmpRouter::trapped(3, (btc_para, None)), 0);
+        assert_eq!(XcmpRouter::trapped_accounts(btc_para, None), 2);
+        assert_eq!(XcmpRouter::trapped_overflow(btc_para, None), 1_998);
+        assert_eq!(
+            router_events().pop(),
+            Some(RawEvent::TransferTrappedInOverflow(
+                BTC_PARA.into(),
+                4,
+                None,
+                999,
+                TransferError::BelowMinimum
+            ))
+        );
+
+        // A claim makes room for another account.
+        assert_ok!(XcmpRouter::claim_trapped(
+            Origin::signed(BOB),
+            BTC_PARA.into(),
+            None,
+            BOB
+        ));
+        receive(BTC_PARA, transfer(3, 999));
+        assert_eq!(XcmpRouter::trapped(3, (btc_para, None)), 999);
+        assert_eq!(XcmpRouter::trapped_accounts(btc_para, None), 2);
+
+        assert_noop!(
+            XcmpRouter::return_trapped_overflow(Origin::signed(ALICE), btc_para, None, ALICE),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_ok!(XcmpRouter::return_trapped_overflow(
+            Origin::root(),
+            btc_para,
+            None,
+            ALICE
+        ));
+        assert_eq!(XcmpRouter::trapped_overflow(btc_para, None), 0);
+        assert_eq!(
+            sent_messages().pop(),
+            Some((
+                btc_para,
+                SubdexXcmpMessage::Transfer(XCMPMessage::TransferToken(ALICE, 1_998, None))
+            ))
+        );
+        assert_noop!(
+            XcmpRouter::return_trapped_overflow(Origin::root(), btc_para, None, ALICE),
+            Error::<Test>::NothingTrapped
+        );
+    });
+}
+
+#[test]
+fn only_mapped_assets_are_credited_to_restricted_parachains() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(XcmpRouter::set_default_para_permission(
+            Origin::root(),
+            ParaPermission::MappedAssetsOnly
+        ));
+
+        // Already mapped.
+        receive(BTC_PARA, transfer(BOB, 5_000));
+        assert_eq!(asset_balance(BOB, 1), 5_000);
+
+        receive(NEW_PARA, transfer(BOB, 5_000));
+        assert_eq!(XcmpRouter::local_asset_id(NEW_PARA.into(), None), None);
+        assert_eq!(
+            router_events().pop(),
+            Some(RawEvent::TransferTrapped(
+                NEW_PARA.into(),
+                BOB,
+                None,
+                5_000,
+                TransferError::AssetNotAllowed
+            ))
+        );
+
+        assert_ok!(XcmpRouter::set_para_permission(
+            Origin::root(),
+            NEW_PARA.into(),
+            Some(ParaPermission::Allowed)
+        ));
+        receive(NEW_PARA, transfer(BOB, 5_000));
+        assert_eq!(XcmpRouter::local_asset_id(NEW_PARA.into(), None), Some(3));
+        assert_eq!(asset_balance(BOB, 3), 5_000);
+    });
+}
+
+#[test]
+fn assets_can_be_mapped_before_they_are_bridged() {
+    new_test_ext().execute_with(|| {
+        let new_para = ParaId::from(NEW_PARA);
+        assert_noop!(
+            XcmpRouter::map_asset(Origin::root(), new_para, None, 2),
+            Error::<Test>::AssetIdInUse
+        );
+        assert_noop!(
+            XcmpRouter::freeze_asset_mapping(Origin::root(), new_para, None),
+            Error::<Test>::UnknownAsset
+        );
+        assert_ok!(XcmpRouter::map_asset(Origin::root(), new_para, None, 10));
+        assert_eq!(pallet_subdex_xcmp::NextAssetId::<Test>::get(), 11);
+
+        receive(NEW_PARA, transfer(BOB, 5_000));
+        assert_eq!(asset_balance(BOB, 10), 5_000);
+        assert_noop!(
+            XcmpRouter::map_asset(Origin::root(), new_para, None, 20),
+            Error::<Test>::BridgedSupplyOutstanding
+        );
+
+        // Remapped once nothing is left on Subdex, until the mapping is frozen.
+        assert_ok!(XcmpRouter::withdraw(&BOB, new_para, BOB, None, 5_000));
+        assert_ok!(XcmpRouter::map_asset(Origin::root(), new_para, None, 20));
+        assert_ok!(XcmpRouter::freeze_asset_mapping(
+            Origin::root(),
+            new_para,
+            None
+        ));
+        assert_noop!(
+            XcmpRouter::map_asset(Origin::root(), new_para, None, 30),
+            Error::<Test>::MappingFrozen
+        );
+    });
+}
+
+#[test]
+fn bridged_supply_is_capped() {
+    new_test_ext().execute_with(|| {
+        let btc_para = ParaId::from(BTC_PARA);
+        assert_ok!(XcmpRouter::set_supply_cap(
+            Origin::root(),
+            btc_para,
+            None,
+            Some(1_001_000)
+        ));
+
+        receive(BTC_PARA, transfer(BOB, 1_000));
+        assert_eq!(XcmpRouter::bridged_supply(btc_para, None), 1_001_000);
+        receive(BTC_PARA, transfer(BOB, 1_000));
+        assert_eq!(asset_balance(BOB, 1), 1_000);
+        assert_eq!(
+            router_events().pop(),
+            Some(RawEvent::TransferTrapped(
+                btc_para,
+                BOB,
+                None,
+                1_000,
+                TransferError::SupplyCapExceeded
+            ))
+        );
+
+        // Assets sent back make room again.
+        assert_ok!(XcmpRouter::withdraw(&BOB, btc_para, BOB, None, 1_000));
+        assert_eq!(XcmpRouter::bridged_supply(btc_para, None), 1_000_000);
+        receive(BTC_PARA, transfer(BOB, 1_000));
+        assert_eq!(asset_balance(BOB, 1), 1_000);
+
+        assert_ok!(XcmpRouter::set_bridged_supply(
+            Origin::root(),
+            btc_para,
+            None,
+            0
+        ));
+        assert_eq!(
+            router_events().pop(),
+            Some(RawEvent::BridgedSupplySet(btc_para, None, 1_001_000, 0))
+        );
+    });
+}
+
+#[test]
+fn reserves_to_audit_are_the_bridged_supplies() {
+    new_test_ext().execute_with(|| {
+        create_sibling_pool();
+
+        let mut supplies = XcmpRouter::bridged_supplies(BTC_PARA.into());
+        supplies.sort();
+        assert_eq!(supplies, vec![(None, 1_000_000), (BTC_TOKEN, 1_000_000)]);
+        assert!(XcmpRouter::bridged_supplies(NEW_PARA.into()).is_empty());
+        assert_eq!(
+            XcmpRouter::reserve_account(),
+            Sibling(SUBDEX.into()).into_account()
+        );
+    });
+}
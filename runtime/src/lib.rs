@@ -22,25 +22,29 @@
 #[cfg(feature = "std")]
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
-use pallet_subdex_xcmp::XCMPMessage;
 use sp_api::impl_runtime_apis;
-use sp_core::OpaqueMetadata;
+use sp_core::{
+    u32_trait::{_1, _2},
+    OpaqueMetadata,
+};
 use sp_runtime::{
     create_runtime_str, generic, impl_opaque_keys,
     traits::{BlakeTwo256, Block as BlockT, IdentifyAccount, IdentityLookup, Saturating, Verify},
-    transaction_validity::{TransactionSource, TransactionValidity},
-    ApplyExtrinsicResult, MultiSignature,
+    transaction_validity::{TransactionPriority, TransactionSource, TransactionValidity},
+    ApplyExtrinsicResult, DispatchError, DispatchResult, FixedU128, ModuleId, MultiSignature,
 };
 use sp_std::prelude::*;
 #[cfg(feature = "std")]
 use sp_version::NativeVersion;
 use sp_version::RuntimeVersion;
 
+pub mod migrations;
+
 // A few exports that help ease life for downstream crates.
 
 pub use frame_support::{
     construct_runtime, parameter_types,
-    traits::{Get, KeyOwnerProofSystem, Randomness},
+    traits::{Filter, Get, KeyOwnerProofSystem, Randomness},
     weights::{constants::WEIGHT_PER_SECOND, IdentityFee, Weight},
     StorageValue,
 };
@@ -48,6 +52,7 @@ pub use frame_support::{
 pub use pallet_balances::Call as BalancesCall;
 pub use pallet_subdex;
 pub use pallet_subdex::Call as DexPalletCall;
+pub use pallet_subdex_router::Call as DexRouterCall;
 pub use pallet_timestamp::Call as TimestampCall;
 #[cfg(any(feature = "std", test))]
 pub use sp_runtime::BuildStorage;
@@ -110,10 +115,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
     spec_name: create_runtime_str!("wasm-test-parachain"),
     impl_name: create_runtime_str!("wasm-test-parachain"),
     authoring_version: 3,
-    spec_version: 4,
-    impl_version: 4,
+    spec_version: 41,
+    impl_version: 41,
     apis: RUNTIME_API_VERSIONS,
-    transaction_version: 1,
+    transaction_version: 2,
 };
 
 pub const MILLISECS_PER_BLOCK: u64 = 6000;
@@ -191,7 +196,7 @@ impl frame_system::Trait for Runtime {
     type ExtrinsicBaseWeight = ExtrinsicBaseWeight;
     type BlockExecutionWeight = ();
     type MaximumExtrinsicWeight = MaximumExtrinsicWeight;
-    type BaseCallFilter = ();
+    type BaseCallFilter = BaseFilter;
     type SystemWeightInfo = ();
 }
 
@@ -249,18 +254,62 @@ parameter_types! {
 
 impl cumulus_message_broker::Trait for Runtime {
     type Event = Event;
-    type DownwardMessageHandlers = DexXCMP;
+    type DownwardMessageHandlers = DexMessageQueue;
     type UpwardMessage = cumulus_upward_message::RococoUpwardMessage;
     type ParachainId = ParachainId;
-    type XCMPMessage = XCMPMessage<AccountId, Balance, AssetId>;
-    type XCMPMessageHandlers = DexXCMP;
+    type XCMPMessage = XcmpMessage;
+    type XCMPMessageHandlers = DexMessageQueue;
+}
+
+parameter_types! {
+    pub MaxMessageProcessingWeight: Weight = Perbill::from_percent(10) * MaximumBlockWeight::get();
+    pub const XcmpMessageWeight: Weight = 200_000_000;
+    pub const DownwardMessageWeight: Weight = 50_000_000;
+    pub const MaxQueuedPerOrigin: u32 = 500;
+}
+
+/// XCMP messages exchanged with siblings.
+pub type XcmpMessage = pallet_subdex_xcmp_router::VersionedXcmpMessageOf<Runtime>;
+
+impl pallet_subdex_message_queue::Trait for Runtime {
+    type Event = Event;
+    type XcmpMessage = XcmpMessage;
+    type XcmpMessageHandler = XcmpRouter;
+    type DownwardMessageHandler = DexRelayCommands;
+    type MaxProcessingWeight = MaxMessageProcessingWeight;
+    type XcmpMessageWeight = XcmpMessageWeight;
+    type DownwardMessageWeight = DownwardMessageWeight;
+    type MessageTracer = DexMessageTrace;
+    type MessageFilter = InboundMessageFilter;
+    type MaxQueuedPerOrigin = MaxQueuedPerOrigin;
+    type ImmediateDownwardMessages = pallet_subdex_relay_commands::IsRelayCommand;
 }
 
+impl pallet_subdex_message_trace::Trait for Runtime {}
+
 impl pallet_subdex_xcmp::Trait for Runtime {
     type Event = Event;
-    type UpwardMessageSender = MessageBroker;
+    type UpwardMessageSender = pallet_subdex_message_trace::TracedUpwardMessageSender<
+        MessageBroker,
+        DexMessageTrace,
+        AccountId,
+    >;
     type UpwardMessage = cumulus_upward_message::RococoUpwardMessage;
+    type XCMPMessageSender = XcmpRouter;
+}
+
+parameter_types! {
+    pub const XcmpRouterModuleId: ModuleId = ModuleId(*b"sdx/xcmp");
+    pub const MaxTrappedAccounts: u32 = 1_000;
+}
+
+impl pallet_subdex_xcmp_router::Trait for Runtime {
+    type Event = Event;
     type XCMPMessageSender = MessageBroker;
+    type ModuleId = XcmpRouterModuleId;
+    type MessageTracer = DexMessageTrace;
+    type SelfParaId = ParachainId;
+    type MaxTrappedAccounts = MaxTrappedAccounts;
 }
 
 // Used to get min main network asset amount, based on its type size, set on node runtime level
@@ -284,8 +333,8 @@ const fn get_min_parachain_asset_amount() -> Balance {
 }
 
 parameter_types! {
-    // 3/1000
-    pub const FeeRateNominator: Balance = 3;
+    // 3/1000, the nominator is changed by the relay chain through `DexRelayCommands`.
+    pub storage FeeRateNominator: Balance = 3;
     pub const FeeRateDenominator: Balance = 1000;
     pub const MinMainNetworkAssetAmount: Balance = get_min_main_network_asset_amount();
     pub const MinParachainAssetAmount: Balance = get_min_parachain_asset_amount();
@@ -302,6 +351,239 @@ impl pallet_subdex::Trait for Runtime {
     type MinParachainAssetAmount = MinParachainAssetAmount;
 }
 
+/// Refuses swaps and liquidity changes while the relay chain paused the DEX, or while their
+/// `DexPallet` call is paused with `TransactionPause`.
+///
+/// The router checks it for every swap and liquidity change, so pausing e.g. `DexPallet`
+/// `swap` also stops router swaps, order fills and XCMP swaps.
+pub struct DexPaused;
+
+impl pallet_subdex_router::DexPause for DexPaused {
+    fn is_paused(operation: pallet_subdex_router::DexOperation) -> bool {
+        use pallet_subdex_router::DexOperation;
+        let call: &[u8] = match operation {
+            DexOperation::Swap => b"swap",
+            DexOperation::InvestLiquidity => b"invest_liquidity",
+            DexOperation::DivestLiquidity => b"divest_liquidity",
+        };
+        DexRelayCommands::dex_paused() || TransactionPause::is_paused(b"DexPallet", call)
+    }
+}
+
+impl pallet_subdex_router::Trait for Runtime {
+    type Event = Event;
+    type OnPoolUpdate = DexOracle;
+    type DexPaused = DexPaused;
+}
+
+parameter_types! {
+    pub const MaxPriceObservations: u32 = 4 * HOURS;
+}
+
+impl pallet_subdex_oracle::Trait for Runtime {
+    type Event = Event;
+    type MaxObservations = MaxPriceObservations;
+}
+
+/// Pays the price feed update fees to the account of the price signer key, held by the
+/// collator signing and publishing the updates.
+pub struct PayPriceSigner;
+
+impl frame_support::traits::OnUnbalanced<pallet_balances::NegativeImbalance<Runtime>>
+    for PayPriceSigner
+{
+    fn on_nonzero_unbalanced(fees: pallet_balances::NegativeImbalance<Runtime>) {
+        use frame_support::traits::Currency;
+        // Updates are only paid once signed, so the signer is set.
+        if let Some(signer) = DexPriceFeed::price_signer() {
+            Balances::resolve_creating(&AccountId::from(signer.0), fees);
+        }
+    }
+}
+
+parameter_types! {
+    pub const PriceFeedSubscriptionDeposit: Balance = 100 * get_min_main_network_asset_amount();
+    pub const MinPriceFeedInterval: BlockNumber = 10;
+    pub const MaxPriceFeedUpdatesPerBlock: u32 = 20;
+    pub const PriceFeedSigningPeriod: BlockNumber = 5;
+    pub const PriceFeedUnsignedPriority: TransactionPriority = TransactionPriority::max_value() / 2;
+}
+
+impl pallet_subdex_price_feed::Trait for Runtime {
+    type Event = Event;
+    type Currency = Balances;
+    type OnUpdateFee = PayPriceSigner;
+    type Oracle = DexOracle;
+    type SubscriptionDeposit = PriceFeedSubscriptionDeposit;
+    type MinInterval = MinPriceFeedInterval;
+    type MaxUpdatesPerBlock = MaxPriceFeedUpdatesPerBlock;
+    type SigningPeriod = PriceFeedSigningPeriod;
+    type UnsignedPriority = PriceFeedUnsignedPriority;
+}
+
+impl<C> frame_system::offchain::SendTransactionTypes<C> for Runtime
+where
+    Call: From<C>,
+{
+    type OverarchingCall = Call;
+    type Extrinsic = UncheckedExtrinsic;
+}
+
+parameter_types! {
+    // Changed by the relay chain through `DexRelayCommands`.
+    pub storage RelayWithdrawFee: Balance = get_min_main_network_asset_amount();
+    pub const MinRelayWithdrawAmount: Balance = get_min_main_network_asset_amount();
+}
+
+impl pallet_subdex_relay_withdraw::Trait for Runtime {
+    type Event = Event;
+    type Currency = Balances;
+    type UpwardMessageSender = MessageBroker;
+    type UpwardMessage = cumulus_upward_message::RococoUpwardMessage;
+    type WithdrawFee = RelayWithdrawFee;
+    type OnWithdrawFee = ();
+    type MinWithdrawAmount = MinRelayWithdrawAmount;
+    type MessageTracer = DexMessageTrace;
+}
+
+/// Applies the relay commands changing other pallets.
+pub struct RelayCommandHandler;
+
+impl pallet_subdex_relay_commands::RelayCommandHandler<Balance> for RelayCommandHandler {
+    fn upgrade_runtime(code: Vec<u8>) -> DispatchResult {
+        ParachainUpgrade::schedule_upgrade(frame_system::RawOrigin::Root.into(), code)
+    }
+
+    fn set_fee(fee: pallet_subdex_relay_commands::Fee, amount: Balance) -> DispatchResult {
+        match fee {
+            pallet_subdex_relay_commands::Fee::RelayWithdraw => {
+                RelayWithdrawFee::set(&amount);
+                Ok(())
+            }
+            pallet_subdex_relay_commands::Fee::PriceFeedUpdate => {
+                DexPriceFeed::set_update_fee(frame_system::RawOrigin::Root.into(), amount)
+            }
+            pallet_subdex_relay_commands::Fee::TradeRate => {
+                if amount >= FeeRateDenominator::get() {
+                    return Err(DispatchError::Other("fee rate must be below one"));
+                }
+                FeeRateNominator::set(&amount);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl pallet_subdex_relay_commands::Trait for Runtime {
+    type Event = Event;
+    type Balance = Balance;
+    type CommandHandler = RelayCommandHandler;
+    type DownwardMessageHandler = DexXCMP;
+}
+
+parameter_types! {
+    pub ProtectedPallets: Vec<Vec<u8>> = vec![
+        b"System".to_vec(),
+        b"Timestamp".to_vec(),
+        b"Sudo".to_vec(),
+        b"ParachainUpgrade".to_vec(),
+        b"MessageBroker".to_vec(),
+        b"TransactionPause".to_vec(),
+        b"DexRelayCommands".to_vec(),
+        b"TechnicalCommittee".to_vec(),
+    ];
+}
+
+parameter_types! {
+    pub const TechnicalMotionDuration: BlockNumber = 3 * DAYS;
+    pub const TechnicalMaxProposals: u32 = 100;
+    pub const TechnicalMaxMembers: u32 = 100;
+}
+
+type TechnicalCollective = pallet_collective::Instance1;
+
+impl pallet_collective::Trait<TechnicalCollective> for Runtime {
+    type Origin = Origin;
+    type Proposal = Call;
+    type Event = Event;
+    type MotionDuration = TechnicalMotionDuration;
+    type MaxProposals = TechnicalMaxProposals;
+    type MaxMembers = TechnicalMaxMembers;
+    type WeightInfo = ();
+}
+
+/// Root, or half of the technical committee.
+type EnsureRootOrHalfTechnicalCommittee = frame_system::EnsureOneOf<
+    AccountId,
+    frame_system::EnsureRoot<AccountId>,
+    pallet_collective::EnsureProportionAtLeast<_1, _2, AccountId, TechnicalCollective>,
+>;
+
+impl pallet_subdex_transaction_pause::Trait for Runtime {
+    type Event = Event;
+    type PauseOrigin = EnsureRootOrHalfTechnicalCommittee;
+    type ProtectedPallets = ProtectedPallets;
+}
+
+/// Refuses calls paused with `TransactionPause`, and direct `pallet_subdex` swaps and
+/// liquidity changes.
+///
+/// Those have to go through `DexRouter`, which enforces deadlines, slippage limits and the DEX
+/// pause, and keeps `DexOracle` up to date. Only `initialize_exchange` can be called directly,
+/// and not while the DEX is paused.
+pub struct BaseFilter;
+
+impl Filter<Call> for BaseFilter {
+    fn filter(call: &Call) -> bool {
+        let dex_call_allowed = match call {
+            Call::DexPallet(DexPalletCall::initialize_exchange(..)) => {
+                !DexRelayCommands::dex_paused()
+            }
+            Call::DexPallet(_) => false,
+            _ => true,
+        };
+        dex_call_allowed
+            && pallet_subdex_transaction_pause::PausedCallFilter::<Runtime>::filter(call)
+    }
+}
+
+/// Keeps inbound messages queued while their handling is paused with `TransactionPause`,
+/// under the `DexXCMP` calls `handle_xcmp_message` and `handle_downward_message`.
+///
+/// Relay commands are not queued, so they are never paused.
+pub struct InboundMessageFilter;
+
+impl Filter<pallet_subdex_message_queue::InboundMessage<XcmpMessage>> for InboundMessageFilter {
+    fn filter(message: &pallet_subdex_message_queue::InboundMessage<XcmpMessage>) -> bool {
+        use pallet_subdex_message_queue::InboundMessage;
+        match message {
+            InboundMessage::Xcmp(..) => {
+                !TransactionPause::is_paused(b"DexXCMP", b"handle_xcmp_message")
+            }
+            InboundMessage::Downward(_) => {
+                !TransactionPause::is_paused(b"DexXCMP", b"handle_downward_message")
+            }
+        }
+    }
+}
+
+parameter_types! {
+    pub const DexOrdersModuleId: ModuleId = ModuleId(*b"sdx/ordr");
+    pub const MaxOrderChecksPerBlock: u32 = 50;
+    pub const MaxOrdersPerPair: u32 = 500;
+    pub const MaxOrdersPerAccount: u32 = 50;
+    pub const MinimumOrderFill: Balance = get_min_parachain_asset_amount();
+}
+
+impl pallet_subdex_orders::Trait for Runtime {
+    type Event = Event;
+    type ModuleId = DexOrdersModuleId;
+    type MaxOrderChecksPerBlock = MaxOrderChecksPerBlock;
+    type MaxOrdersPerPair = MaxOrdersPerPair;
+    type MaxOrdersPerAccount = MaxOrdersPerAccount;
+    type MinimumFill = MinimumOrderFill;
+}
+
 construct_runtime! {
     pub enum Runtime where
         Block = Block,
@@ -318,6 +600,17 @@ construct_runtime! {
         TransactionPayment: pallet_transaction_payment::{Module, Storage},
         Balances: pallet_balances::{Module, Call, Storage, Event<T>},
         DexPallet: pallet_subdex::{Module, Call, Storage, Event<T>},
+        DexRouter: pallet_subdex_router::{Module, Call, Event<T>},
+        DexOrders: pallet_subdex_orders::{Module, Call, Storage, Event<T>},
+        DexOracle: pallet_subdex_oracle::{Module, Call, Storage, Event<T>},
+        XcmpRouter: pallet_subdex_xcmp_router::{Module, Call, Storage, Event<T>},
+        DexPriceFeed: pallet_subdex_price_feed::{Module, Call, Storage, Config<T>, Event<T>, ValidateUnsigned},
+        DexRelayWithdraw: pallet_subdex_relay_withdraw::{Module, Call, Storage, Event<T>},
+        DexMessageQueue: pallet_subdex_message_queue::{Module, Storage, Event},
+        DexMessageTrace: pallet_subdex_message_trace::{Module, Storage},
+        DexRelayCommands: pallet_subdex_relay_commands::{Module, Call, Storage, Event<T>},
+        TransactionPause: pallet_subdex_transaction_pause::{Module, Call, Storage, Event},
+        TechnicalCommittee: pallet_collective::<Instance1>::{Module, Call, Storage, Origin<T>, Event<T>, Config<T>},
     }
 }
 
@@ -339,18 +632,22 @@ pub type SignedExtra = (
     frame_system::CheckNonce<Runtime>,
     frame_system::CheckWeight<Runtime>,
     pallet_transaction_payment::ChargeTransactionPayment<Runtime>,
+    pallet_subdex_router::CheckDeadline<Runtime>,
 );
 /// Unchecked extrinsic type as expected by this runtime.
 pub type UncheckedExtrinsic = generic::UncheckedExtrinsic<Address, Call, Signature, SignedExtra>;
 /// Extrinsic type that has already been checked.
 pub type CheckedExtrinsic = generic::CheckedExtrinsic<AccountId, Call, SignedExtra>;
+/// Hooks run by `Executive` on runtime upgrades and in every block: the storage migrations
+/// first, then the pallets.
+pub type AllHooks = (migrations::Migrations, AllModules);
 /// Executive: handles dispatch to the various modules.
 pub type Executive = frame_executive::Executive<
     Runtime,
     Block,
     frame_system::ChainContext<Runtime>,
     Runtime,
-    AllModules,
+    AllHooks,
 >;
 
 impl_runtime_apis! {
@@ -413,6 +710,82 @@ impl_runtime_apis! {
         }
     }
 
+    impl pallet_subdex_orders_runtime_api::DexOrdersApi<
+        Block,
+        AccountId,
+        pallet_subdex::Asset<AssetId>,
+        pallet_subdex_orders::LimitOrderOf<Runtime>,
+    > for Runtime {
+        fn open_orders(
+            asset_sell: pallet_subdex::Asset<AssetId>,
+            asset_buy: pallet_subdex::Asset<AssetId>,
+        ) -> Vec<pallet_subdex_orders::LimitOrderOf<Runtime>> {
+            DexOrders::open_orders(asset_sell, asset_buy)
+        }
+
+        fn orders_of(who: AccountId) -> Vec<pallet_subdex_orders::LimitOrderOf<Runtime>> {
+            DexOrders::orders_of(&who)
+        }
+    }
+
+    impl pallet_subdex_oracle_runtime_api::DexOracleApi<
+        Block,
+        pallet_subdex::Asset<AssetId>,
+        u64,
+        FixedU128,
+    > for Runtime {
+        fn twap(
+            base: pallet_subdex::Asset<AssetId>,
+            quote: pallet_subdex::Asset<AssetId>,
+            window: u64,
+        ) -> Option<FixedU128> {
+            <DexOracle as pallet_subdex_oracle::Twap<_, _>>::twap(base, quote, window)
+        }
+
+        fn spot_price(
+            base: pallet_subdex::Asset<AssetId>,
+            quote: pallet_subdex::Asset<AssetId>,
+        ) -> Option<FixedU128> {
+            <DexOracle as pallet_subdex_oracle::Twap<_, _>>::spot_price(base, quote)
+        }
+    }
+
+    impl pallet_subdex_message_queue_runtime_api::DexMessageQueueApi<Block, BlockNumber> for Runtime {
+        fn queue_depth() -> u64 {
+            DexMessageQueue::queue_depth()
+        }
+
+        fn oldest_received_at() -> Option<BlockNumber> {
+            DexMessageQueue::oldest_received_at()
+        }
+    }
+
+    impl pallet_subdex_xcmp_router_runtime_api::DexSiblingApi<Block, AccountId, AssetId, Balance> for Runtime {
+        fn reserve_account() -> AccountId {
+            XcmpRouter::reserve_account()
+        }
+
+        fn bridged_supplies(
+            para_id: cumulus_primitives::ParaId,
+        ) -> Vec<(Option<AssetId>, Balance)> {
+            XcmpRouter::bridged_supplies(para_id)
+        }
+    }
+
+    impl migrations::TryUpgradeApi<Block> for Runtime {
+        fn on_runtime_upgrade() -> Weight {
+            <AllHooks as frame_support::traits::OnRuntimeUpgrade>::on_runtime_upgrade()
+        }
+
+        fn finish_migrations() -> u32 {
+            migrations::Migrations::finish()
+        }
+
+        fn checks_migrations() -> bool {
+            cfg!(feature = "try-runtime")
+        }
+    }
+
     impl sp_session::SessionKeys<Block> for Runtime {
         fn decode_session_keys(
             encoded: Vec<u8>,
@@ -427,3 +800,69 @@ impl_runtime_apis! {
 }
 
 cumulus_runtime::register_validate_block!(Block, Executive);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frame_support::assert_ok;
+    use pallet_subdex::Asset;
+
+    fn dex_calls() -> Vec<Call> {
+        vec![
+            Call::DexPallet(DexPalletCall::swap(
+                Asset::MainNetworkCurrency,
+                1_000,
+                Asset::ParachainAsset(1),
+            )),
+            Call::DexPallet(DexPalletCall::invest_liquidity(
+                Asset::MainNetworkCurrency,
+                Asset::ParachainAsset(1),
+                1_000,
+            )),
+            Call::DexPallet(DexPalletCall::divest_liquidity(
+                Asset::MainNetworkCurrency,
+                Asset::ParachainAsset(1),
+                1_000,
+                0,
+                0,
+            )),
+        ]
+    }
+
+    fn initialize_exchange() -> Call {
+        Call::DexPallet(DexPalletCall::initialize_exchange(
+            Asset::MainNetworkCurrency,
+            100_000,
+            Asset::ParachainAsset(1),
+            100_000,
+        ))
+    }
+
+    #[test]
+    fn base_filter_refuses_direct_dex_trades() {
+        sp_io::TestExternalities::default().execute_with(|| {
+            for call in dex_calls() {
+                assert!(!BaseFilter::filter(&call));
+            }
+            assert!(BaseFilter::filter(&initialize_exchange()));
+            assert!(BaseFilter::filter(&Call::DexRouter(
+                pallet_subdex_router::Call::swap_exact_in(
+                    Asset::MainNetworkCurrency,
+                    1_000,
+                    Asset::ParachainAsset(1),
+                    0,
+                    0,
+                )
+            )));
+        });
+    }
+
+    #[test]
+    fn base_filter_refuses_new_pools_while_dex_paused() {
+        sp_io::TestExternalities::default().execute_with(|| {
+            assert_ok!(DexRelayCommands::set_dex_paused(Origin::root(), true));
+
+            assert!(!BaseFilter::filter(&initialize_exchange()));
+        });
+    }
+}
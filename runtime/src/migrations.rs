@@ -0,0 +1,496 @@
+//! Storage migrations run by `Executive` on runtime upgrades.
+//!
+//! `pallet_subdex` and `pallet_subdex_xcmp` do not version their storage, so its version is
+//! kept here, under the `StorageVersion` item of their storage prefix. A [`Migration`] starts
+//! on the first upgrade that finds the version it migrates from, and bumps the version once
+//! done.
+//!
+//! Migrations visit a bounded number of storage entries per block, so that they fit in a block
+//! however large the migrated storage is. The upgrade only starts them, [`Migrations`] runs
+//! one step of every started migration in `on_initialize`, from the first block of the new
+//! runtime on. Until a migration is done its pallet sees partly migrated storage, so it must
+//! handle both versions.
+//!
+//! With the `try-runtime` feature, every migration checks the storage when it starts and once
+//! it is done, and panics if a check fails.
+
+use crate::{AccountId, AssetId, Balance, BlockNumber, Runtime};
+use codec::{Decode, Encode};
+use cumulus_primitives::ParaId;
+use frame_support::{
+    storage::{
+        migration::{get_storage_value, put_storage_value, take_storage_value},
+        unhashed, StorageDoubleMap, StoragePrefixedMap,
+    },
+    traits::{Get, OffchainWorker, OnFinalize, OnInitialize, OnRuntimeUpgrade},
+    weights::Weight,
+};
+use sp_core::hashing::twox_128;
+use sp_runtime::traits::Zero;
+use sp_std::prelude::*;
+
+pub type StorageVersion = u16;
+
+sp_api::decl_runtime_apis! {
+    /// Dry run of runtime upgrades, see the `try-upgrade` command of the collator.
+    pub trait TryUpgradeApi {
+        /// Run the upgrade hooks of `Executive` and return the weight they used.
+        fn on_runtime_upgrade() -> Weight;
+
+        /// Run the started storage migrations to the end and return the number of blocks they
+        /// take.
+        fn finish_migrations() -> u32;
+
+        /// Whether the runtime was built with the `try-runtime` feature, i.e. checks the
+        /// storage before and after its migrations.
+        fn checks_migrations() -> bool;
+    }
+}
+
+const STORAGE_VERSION: &[u8] = b"StorageVersion";
+const MIGRATION_CURSOR: &[u8] = b"MigrationCursor";
+#[cfg(feature = "try-runtime")]
+const MIGRATION_CHECK: &[u8] = b"MigrationCheck";
+
+/// Storage version of the pallet with storage prefix `module`.
+pub fn storage_version(module: &[u8]) -> StorageVersion {
+    get_storage_value(module, STORAGE_VERSION, &[]).unwrap_or_default()
+}
+
+fn set_storage_version(module: &[u8], version: StorageVersion) {
+    put_storage_value(module, STORAGE_VERSION, &[], version)
+}
+
+/// Where the next step of the migration of the pallet with storage prefix `module` starts, if
+/// one is in progress.
+pub fn migration_cursor(module: &[u8]) -> Option<Vec<u8>> {
+    get_storage_value(module, MIGRATION_CURSOR, &[])
+}
+
+/// Migration of the storage of a pallet from version [`Migration::FROM`] to the next one.
+pub trait Migration {
+    /// Version of the storage the migration applies to.
+    const FROM: StorageVersion;
+
+    /// Storage prefix of the migrated pallet.
+    fn module_prefix() -> &'static [u8];
+
+    /// Cursor the first step starts from.
+    fn first_cursor() -> Vec<u8>;
+
+    /// Migrate a bounded part of the storage from `cursor`, and return the cursor of the next
+    /// step, `None` once the migration is done.
+    fn step(cursor: Vec<u8>) -> (Option<Vec<u8>>, Weight);
+
+    /// Check the storage before the migration, and return what the check after it needs.
+    #[cfg(feature = "try-runtime")]
+    fn pre_upgrade() -> Result<Vec<u8>, &'static str>;
+
+    /// Check the storage after the migration, given what [`Migration::pre_upgrade`] returned.
+    #[cfg(feature = "try-runtime")]
+    fn post_upgrade(state: Vec<u8>) -> Result<(), &'static str>;
+}
+
+/// Start `M` if the storage has the version it migrates from.
+fn start<M: Migration>() -> Weight {
+    let db = <Runtime as frame_system::Trait>::DbWeight::get();
+    let module = M::module_prefix();
+    if storage_version(module) != M::FROM || migration_cursor(module).is_some() {
+        return db.reads(2);
+    }
+
+    #[cfg(feature = "try-runtime")]
+    {
+        let state = M::pre_upgrade().unwrap_or_else(|e| panic!("pre-upgrade check failed: {}", e));
+        put_storage_value(module, MIGRATION_CHECK, &[], state);
+    }
+    put_storage_value(module, MIGRATION_CURSOR, &[], M::first_cursor());
+    db.reads_writes(2, 1)
+}
+
+/// Run the next step of `M` if it is in progress.
+fn resume<M: Migration>() -> Weight {
+    let db = <Runtime as frame_system::Trait>::DbWeight::get();
+    let module = M::module_prefix();
+    let cursor = match migration_cursor(module) {
+        Some(cursor) => cursor,
+        None => return db.reads(1),
+    };
+
+    let (next, weight) = M::step(cursor);
+    match next {
+        Some(next) => put_storage_value(module, MIGRATION_CURSOR, &[], next),
+        None => {
+            take_storage_value::<Vec<u8>>(module, MIGRATION_CURSOR, &[]);
+            set_storage_version(module, M::FROM + 1);
+            #[cfg(feature = "try-runtime")]
+            {
+                let state = take_storage_value(module, MIGRATION_CHECK, &[]).unwrap_or_default();
+                M::post_upgrade(state)
+                    .unwrap_or_else(|e| panic!("post-upgrade check failed: {}", e));
+            }
+        }
+    }
+    weight.saturating_add(db.reads_writes(1, 2))
+}
+
+/// All migrations, oldest first.
+///
+/// Starts them on runtime upgrades, and runs a step of every started migration at the
+/// beginning of each block.
+pub struct Migrations;
+
+impl Migrations {
+    fn in_progress() -> bool {
+        migration_cursor(SeedBridgedSupply::module_prefix()).is_some()
+    }
+
+    /// Run the started migrations to the end, as the following blocks would, and return the
+    /// number of steps it took.
+    pub fn finish() -> u32 {
+        let mut steps = 0;
+        while Self::in_progress() {
+            resume::<SeedBridgedSupply>();
+            steps += 1;
+        }
+        steps
+    }
+}
+
+impl OnRuntimeUpgrade for Migrations {
+    fn on_runtime_upgrade() -> Weight {
+        start::<SeedBridgedSupply>()
+    }
+}
+
+impl OnInitialize<BlockNumber> for Migrations {
+    fn on_initialize(_: BlockNumber) -> Weight {
+        resume::<SeedBridgedSupply>()
+    }
+}
+
+impl OnFinalize<BlockNumber> for Migrations {}
+
+impl OffchainWorker<BlockNumber> for Migrations {}
+
+/// `pallet_subdex_xcmp` version 1: the supply of the assets bridged through it is tracked.
+///
+/// Up to spec version 4, `DexXCMP` bridged assets without recording how much of them siblings
+/// hold in reserve for Subdex. `XcmpRouter` tracks it in `BridgedSupply`, from zero for the
+/// assets bridged before. This migration sets the untracked supply of every asset mapped in
+/// `AssetIdByParaAssetId` to the amount of it on Subdex: its `DexPallet` asset balances plus
+/// its pool reserves. Tracked supplies are left as they are, so the supplies of chains started
+/// after spec version 4 do not change.
+///
+/// Asset balances are first summed per asset into the temporary `MigrationSupply` item of
+/// `DexXCMP`, and the pool reserves added once all are visited. The supplies are then written
+/// per mapping. The cursor is the raw key of the last visited asset balance, then of the last
+/// visited mapping.
+pub struct SeedBridgedSupply;
+
+impl SeedBridgedSupply {
+    /// Asset balances or mappings visited per block.
+    pub const ENTRIES_PER_STEP: u64 = 500;
+
+    /// Add the reserves of every pool to the supplies, and return the number of pools.
+    ///
+    /// Pools are few, so they are all visited at once.
+    fn add_pool_reserves() -> u64 {
+        let mut pools = 0;
+        for (first, second, exchange) in pallet_subdex::Exchanges::<Runtime>::iter() {
+            pools += 1;
+            if let pallet_subdex::Asset::ParachainAsset(asset) = first {
+                add_supply(asset, exchange.first_asset_pool);
+            }
+            if let pallet_subdex::Asset::ParachainAsset(asset) = second {
+                add_supply(asset, exchange.second_asset_pool);
+            }
+        }
+        pools
+    }
+}
+
+const MIGRATION_SUPPLY: &[u8] = b"MigrationSupply";
+
+fn add_supply(asset: AssetId, amount: Balance) {
+    let module = SeedBridgedSupply::module_prefix();
+    let supply: Balance =
+        get_storage_value(module, MIGRATION_SUPPLY, &asset.encode()).unwrap_or_default();
+    put_storage_value(
+        module,
+        MIGRATION_SUPPLY,
+        &asset.encode(),
+        supply.saturating_add(amount),
+    );
+}
+
+/// Keys of the double map entry at raw `key`, both hashed with `Blake2_128Concat`.
+///
+/// Callers check the keys against the hashed key of the map, so that an entry is never read
+/// with the wrong keys.
+fn double_map_keys<K1: Decode, K2: Decode>(key: &[u8]) -> Option<(K1, K2)> {
+    // The storage prefix, then every key after its 16 byte hash.
+    let mut rest = key.get(32 + 16..)?;
+    let first = K1::decode(&mut rest).ok()?;
+    let mut rest = rest.get(16..)?;
+    let second = K2::decode(&mut rest).ok()?;
+    Some((first, second)).filter(|_| rest.is_empty())
+}
+
+/// Call `f` with the raw keys following `cursor` under `prefix`, up to `ENTRIES_PER_STEP` of
+/// them, and return the cursor to continue from, `None` once all are visited, and the number
+/// of keys visited.
+fn visit(mut cursor: Vec<u8>, prefix: &[u8], mut f: impl FnMut(&[u8])) -> (Option<Vec<u8>>, u64) {
+    let mut visited = 0;
+    loop {
+        if visited == SeedBridgedSupply::ENTRIES_PER_STEP {
+            return (Some(cursor), visited);
+        }
+        cursor = match sp_io::storage::next_key(&cursor) {
+            Some(key) if key.starts_with(prefix) => key,
+            _ => return (None, visited),
+        };
+        visited += 1;
+        f(&cursor);
+    }
+}
+
+impl Migration for SeedBridgedSupply {
+    const FROM: StorageVersion = 0;
+
+    fn module_prefix() -> &'static [u8] {
+        pallet_subdex_xcmp::AssetIdByParaAssetId::<Runtime>::module_prefix()
+    }
+
+    fn first_cursor() -> Vec<u8> {
+        pallet_subdex::AssetBalances::<Runtime>::final_prefix().to_vec()
+    }
+
+    fn step(cursor: Vec<u8>) -> (Option<Vec<u8>>, Weight) {
+        let db = <Runtime as frame_system::Trait>::DbWeight::get();
+        let balances = pallet_subdex::AssetBalances::<Runtime>::final_prefix();
+        let mappings = pallet_subdex_xcmp::AssetIdByParaAssetId::<Runtime>::final_prefix();
+
+        if cursor.starts_with(&balances) {
+            let (next, visited) = visit(cursor, &balances, |key| {
+                if let Some((who, asset)) = double_map_keys::<AccountId, AssetId>(key) {
+                    if pallet_subdex::AssetBalances::<Runtime>::hashed_key_for(&who, asset) == key {
+                        add_supply(asset, unhashed::get(key).unwrap_or_default());
+                    }
+                }
+            });
+            let weight = db.reads_writes(visited.saturating_mul(3).saturating_add(1), visited);
+            return match next {
+                Some(next) => (Some(next), weight),
+                None => {
+                    let pools = Self::add_pool_reserves();
+                    let pools_weight = db.reads_writes(pools.saturating_mul(3), pools * 2);
+                    (Some(mappings.to_vec()), weight.saturating_add(pools_weight))
+                }
+            };
+        }
+
+        let (next, visited) = visit(cursor, &mappings, |key| {
+            let keys =
+                double_map_keys::<ParaId, Option<AssetId>>(key).filter(|(para_id, asset)| {
+                    pallet_subdex_xcmp::AssetIdByParaAssetId::<Runtime>::hashed_key_for(
+                        para_id, asset,
+                    ) == key
+                });
+            if let (Some((para_id, asset)), Some(id)) = (keys, unhashed::get::<AssetId>(key)) {
+                let module = SeedBridgedSupply::module_prefix();
+                let supply: Balance =
+                    take_storage_value(module, MIGRATION_SUPPLY, &id.encode()).unwrap_or_default();
+                let tracked =
+                    pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::get(para_id, asset);
+                if !supply.is_zero() && tracked.is_zero() {
+                    pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::insert(
+                        para_id, asset, supply,
+                    );
+                }
+            }
+        });
+        if next.is_none() {
+            // Supplies of assets without a mapping, which Subdex never bridged.
+            sp_io::storage::clear_prefix(
+                &[twox_128(Self::module_prefix()), twox_128(MIGRATION_SUPPLY)].concat(),
+            );
+        }
+        let weight = db.reads_writes(
+            visited.saturating_mul(3).saturating_add(1),
+            visited.saturating_mul(2).saturating_add(1),
+        );
+        (next, weight)
+    }
+
+    #[cfg(feature = "try-runtime")]
+    fn pre_upgrade() -> Result<Vec<u8>, &'static str> {
+        let tracked: Vec<(ParaId, Option<AssetId>, Balance)> =
+            pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::iter().collect();
+        Ok(tracked.encode())
+    }
+
+    #[cfg(feature = "try-runtime")]
+    fn post_upgrade(state: Vec<u8>) -> Result<(), &'static str> {
+        let tracked = Vec::<(ParaId, Option<AssetId>, Balance)>::decode(&mut &state[..])
+            .map_err(|_| "invalid pre-upgrade state")?;
+        for (para_id, asset, supply) in tracked.iter() {
+            if pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::get(para_id, asset) != *supply {
+                return Err("tracked supply changed");
+            }
+        }
+
+        let mut held = sp_std::collections::btree_map::BTreeMap::<AssetId, Balance>::new();
+        let balances = pallet_subdex::AssetBalances::<Runtime>::final_prefix();
+        let mut key = balances.to_vec();
+        while let Some(next) = sp_io::storage::next_key(&key).filter(|k| k.starts_with(&balances)) {
+            let (_, asset) = double_map_keys::<AccountId, AssetId>(&next)
+                .ok_or("undecodable asset balance key")?;
+            let balance: Balance = unhashed::get(&next).unwrap_or_default();
+            *held.entry(asset).or_default() += balance;
+            key = next;
+        }
+        for (first, second, exchange) in pallet_subdex::Exchanges::<Runtime>::iter() {
+            if let pallet_subdex::Asset::ParachainAsset(asset) = first {
+                *held.entry(asset).or_default() += exchange.first_asset_pool;
+            }
+            if let pallet_subdex::Asset::ParachainAsset(asset) = second {
+                *held.entry(asset).or_default() += exchange.second_asset_pool;
+            }
+        }
+        for (para_id, asset, id) in pallet_subdex_xcmp::AssetIdByParaAssetId::<Runtime>::iter() {
+            if tracked.iter().any(|(p, a, _)| (*p, *a) == (para_id, asset)) {
+                continue;
+            }
+            let supply = pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::get(para_id, asset);
+            if supply != held.get(&id).copied().unwrap_or_default() {
+                return Err("bridged supply differs from the amount on Subdex");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DexPallet, Origin};
+    use frame_support::traits::Currency;
+    use pallet_subdex::Asset;
+
+    fn account(seed: u8) -> AccountId {
+        AccountId::from([seed; 32])
+    }
+
+    fn is_done() -> bool {
+        let module = SeedBridgedSupply::module_prefix();
+        storage_version(module) == 1 && migration_cursor(module).is_none()
+    }
+
+    fn map(para_id: u32, asset: Option<AssetId>, id: AssetId) {
+        pallet_subdex_xcmp::AssetIdByParaAssetId::<Runtime>::insert(
+            ParaId::from(para_id),
+            asset,
+            id,
+        );
+    }
+
+    fn bridged_supply(para_id: u32, asset: Option<AssetId>) -> Balance {
+        pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::get(ParaId::from(para_id), asset)
+    }
+
+    fn upgrade(blocks: BlockNumber) {
+        Migrations::on_runtime_upgrade();
+        for block in 1..=blocks {
+            assert!(!is_done());
+            Migrations::on_initialize(block);
+        }
+    }
+
+    #[test]
+    fn seeds_the_supply_of_assets_bridged_before_it_was_tracked() {
+        sp_io::TestExternalities::default().execute_with(|| {
+            // Snapshot of version 0 storage, with a pool of asset 1.
+            map(200, None, 1);
+            map(300, None, 2);
+            map(300, Some(7), 3);
+            pallet_subdex::AssetBalances::<Runtime>::insert(account(1), 1, 500);
+            pallet_subdex::AssetBalances::<Runtime>::insert(account(1), 2, 1_000);
+            pallet_subdex::AssetBalances::<Runtime>::insert(account(2), 1, 250);
+            pallet_subdex::AssetBalances::<Runtime>::insert(account(3), 1, 10_001);
+            pallet_balances::Module::<Runtime>::make_free_balance_be(&account(3), 1_000_000);
+            DexPallet::initialize_exchange(
+                Origin::signed(account(3)),
+                Asset::ParachainAsset(1),
+                10_000,
+                Asset::MainNetworkCurrency,
+                100_000,
+            )
+            .expect("the pool is created");
+            // Tracked since the release.
+            pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::insert(
+                ParaId::from(300),
+                None::<AssetId>,
+                42,
+            );
+
+            // Balances and pools, then mappings.
+            upgrade(2);
+
+            assert!(is_done());
+            assert_eq!(bridged_supply(200, None), 500 + 250 + 10_001);
+            assert_eq!(bridged_supply(300, None), 42);
+            assert!(
+                !pallet_subdex_xcmp_router::BridgedSupply::<Runtime>::contains_key(
+                    ParaId::from(300),
+                    Some(7)
+                )
+            );
+            assert_eq!(
+                get_storage_value::<Balance>(
+                    SeedBridgedSupply::module_prefix(),
+                    MIGRATION_SUPPLY,
+                    &2u64.encode()
+                ),
+                None
+            );
+        });
+    }
+
+    #[test]
+    fn visits_a_bounded_number_of_entries_per_block() {
+        sp_io::TestExternalities::default().execute_with(|| {
+            // Snapshot of version 0 storage, with 1024 asset balances.
+            for asset in 0..4 {
+                map(200, Some(asset), asset);
+                for seed in 0..=255u8 {
+                    pallet_subdex::AssetBalances::<Runtime>::insert(account(seed), asset, 100);
+                }
+            }
+
+            // Three blocks of balances, then the mappings.
+            upgrade(4);
+
+            assert!(is_done());
+            for asset in 0..4 {
+                assert_eq!(bridged_supply(200, Some(asset)), 25_600);
+            }
+        });
+    }
+
+    #[test]
+    fn migrations_run_once() {
+        sp_io::TestExternalities::default().execute_with(|| {
+            upgrade(2);
+            assert!(is_done());
+            map(200, None, 1);
+            pallet_subdex::AssetBalances::<Runtime>::insert(account(1), 1, 500);
+
+            Migrations::on_runtime_upgrade();
+            Migrations::on_initialize(3);
+            Migrations::on_initialize(4);
+
+            assert_eq!(bridged_supply(200, None), 0);
+        });
+    }
+}